@@ -0,0 +1,42 @@
+$( A small Metamath database for testing `mm0-rs from-mm`. It contains the
+   implicational fragment of propositional logic, and proves the same theorem
+   with a normal and a compressed proof. $)
+
+$c ( ) -> wff |- $.
+$v ph ps ch $.
+
+$( $j syntax 'wff'; syntax '|-' as 'wff'; $)
+
+wph $f wff ph $.
+wps $f wff ps $.
+wch $f wff ch $.
+
+$( Implication. $)
+wi $a wff ( ph -> ps ) $.
+
+ax-1 $a |- ( ph -> ( ps -> ph ) ) $.
+ax-2 $a |- ( ( ph -> ( ps -> ch ) ) -> ( ( ph -> ps ) -> ( ph -> ch ) ) ) $.
+
+${
+  min $e |- ph $.
+  maj $e |- ( ph -> ps ) $.
+  $( Modus ponens. $)
+  ax-mp $a |- ps $.
+$}
+
+${
+  a1i.1 $e |- ph $.
+  $( Inference introducing an antecedent. $)
+  a1i $p |- ( ps -> ph ) $=
+    wph wps wph wi a1i.1 wph wps ax-1 ax-mp $.
+$}
+
+$( Principle of identity, with a normal proof. $)
+id $p |- ( ph -> ph ) $=
+  wph wph wph wi wi wph wph wi wph wph ax-1 wph wph wph wi wph wi wi
+  wph wph wph wi wi wph wph wi wi wph wph wph wi ax-1 wph wph wph wi wph ax-2
+  ax-mp ax-mp $.
+
+$( Principle of identity, with a compressed proof. $)
+idc $p |- ( ph -> ph ) $=
+  ( wi ax-1 ax-2 ax-mp ) AAABBAABZAACAFABBAFBFBAFCAFADEE $.
@@ -25,10 +25,14 @@ export const language = {
     'max', 'min', '-', '//', '%', '<', '<=', '>', '>=', '=', 'shl', 'shr',
     'band', 'bor', 'bxor', 'bnot', '==', '->string', 'string->atom',
     'string-append', 'string-len', 'string-nth', 'substr', 'string->list',
-    'list->string', 'not', 'and', 'or', 'list', 'cons', 'hd', 'tl', 'nth',
-    'map', 'bool?', 'atom?', 'pair?', 'null?', 'number?', 'string?', 'fn?',
+    'list->string', 'string-split', 'string-index-of', 'string-replace',
+    'string-upcase', 'string-downcase', 'number->string', 'format', 'not', 'and',
+    'or', 'list', 'cons', 'hd', 'tl', 'nth',
+    'map', 'for-each', 'bool?', 'atom?', 'pair?', 'null?', 'number?', 'string?', 'fn?',
     'def?', 'ref?', 'ref!', 'get!', 'set!', 'set-weak!', 'copy-span', 'stack-span',
-    'async', 'atom-map?', 'atom-map!', 'lookup', 'insert!', 'insert', 'set-timeout',
+    'async', 'atom-map?', 'atom-map!', 'hash-map?', 'hash-map!', 'lookup', 'insert!',
+    'insert', 'vec?', 'vec!', 'vec-len', 'vec-get', 'vec-set!', 'vec-push!', 'vec->list',
+    'set-timeout',
     'set-stack-limit', 'mvar?', 'goal?', 'mvar!', 'pp', 'goal', 'goal-type',
     'infer-type', 'infer-sort', 'get-mvars', 'get-goals', 'set-goals',
     'set-close-fn', 'local-ctx', 'to-expr', 'refine', 'have', 'stat', 'get-decl',
@@ -0,0 +1,35 @@
+//! The build script for `mm0-rs`. This sets the `MM0_RS_BUILD_ID` environment variable to an
+//! identifier for the build, which is part of the keys of the elaboration cache (see
+//! `src/compiler/cache.rs`), so that cache files written by another build are not reused.
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn git(args: &[&str]) -> Option<String> {
+  let out = Command::new("git").args(args).output().ok()?;
+  if !out.status.success() { return None }
+  Some(String::from_utf8(out.stdout).ok()?.trim().to_owned())
+}
+
+fn main() {
+  // A clean git checkout is identified by its commit. Otherwise we use the build time,
+  // which works because this script is rerun whenever a source file changes.
+  let id = match (git(&["rev-parse", "HEAD"]), git(&["status", "--porcelain", "."])) {
+    (Some(rev), Some(status)) if status.is_empty() => rev,
+    _ => format!("{}-{}", env!("CARGO_PKG_VERSION"),
+      SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos())),
+  };
+  println!("cargo:rustc-env=MM0_RS_BUILD_ID={}", id);
+  // Declaring any `rerun-if-changed` replaces cargo's default of rerunning on any change
+  // in the package, so we list the sources as well as the git files that record the commit:
+  // `.git/HEAD` changes on checkout, and the branch ref changes on commit.
+  for path in ["build.rs", "Cargo.toml", "Cargo.lock", "src", "components"] {
+    println!("cargo:rerun-if-changed={}", path);
+  }
+  let mut git_files = vec!["HEAD".to_owned()];
+  git_files.extend(git(&["symbolic-ref", "-q", "HEAD"]));
+  for file in git_files {
+    if let Some(path) = git(&["rev-parse", "--git-path", &file]) {
+      println!("cargo:rerun-if-changed={}", path);
+    }
+  }
+}
@@ -97,6 +97,8 @@ pub mod cmd {
   pub const STMT_DEF: u8 = 0x05;
   /// `STMT_THM = 0x06`, starts a `theorem` declaration
   pub const STMT_THM: u8 = 0x06;
+  /// `STMT_INPUT_STRING = 0x07`, starts an `input string` directive
+  pub const STMT_INPUT_STRING: u8 = 0x07;
   /// `STMT_LOCAL = 0x08`, starts a `local` declaration
   /// (a bit mask to be combined with `STMT_THM` or `STMT_DEF`)
   pub const STMT_LOCAL: u8 = 0x08;
@@ -201,6 +203,10 @@ pub enum StmtCmd {
     /// Is this not `pub theorem`?
     local: bool,
   },
+  /// An `input string` directive. This is followed by a proof sequence that constructs
+  /// a list of expressions of sort `string`, whose concatenation should be the text of
+  /// the corresponding MM0 file.
+  InputString,
 }
 
 // IMO breaking this out is preferred to making the id fields Option<A> in StmtCmd
@@ -243,6 +249,10 @@ pub enum NumdStmtCmd {
     /// Is this not `pub theorem`?
     local: bool,
   },
+  /// An `input string` directive. This is followed by a proof sequence that constructs
+  /// a list of expressions of sort `string`, whose concatenation should be the text of
+  /// the corresponding MM0 file.
+  InputString,
 }
 
 impl StmtCmd {
@@ -250,7 +260,7 @@ impl StmtCmd {
   #[must_use]
   pub fn is_local(self) -> bool {
     match self {
-      Self::Sort | Self::Axiom => false,
+      Self::Sort | Self::Axiom | Self::InputString => false,
       Self::TermDef { local } | Self::Thm { local } => local,
     }
   }
@@ -261,7 +271,7 @@ impl NumdStmtCmd {
   #[must_use]
   pub fn is_local(self) -> bool {
     match self {
-      Self::Sort { .. } | Self::Axiom { .. } => false,
+      Self::Sort { .. } | Self::Axiom { .. } | Self::InputString => false,
       Self::TermDef { local, .. } | Self::Thm { local, .. } => local,
     }
   }
@@ -277,6 +287,7 @@ impl TryFrom<u8> for StmtCmd {
       cmd::STMT_LOCAL_DEF => StmtCmd::TermDef { local: true },
       cmd::STMT_THM => StmtCmd::Thm { local: false },
       cmd::STMT_LOCAL_THM => StmtCmd::Thm { local: true },
+      cmd::STMT_INPUT_STRING => StmtCmd::InputString,
       _ => return Err(ParseError::StmtCmdConv(cmd)),
     })
   }
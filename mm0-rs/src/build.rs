@@ -0,0 +1,293 @@
+//! Build a project of MM1 files described by a manifest file.
+//!
+//! The manifest, called `mm0.toml` by default, lists a number of targets, each of which is
+//! an `.mm1` (or `.mm0`) file to elaborate along with the artifacts to produce from it:
+//! ```toml
+//! # optional: directory for the elaboration cache (see `mm0-rs compile --cache`)
+//! cache = ".mm1cache"
+//! # optional: base URL for source links in the documentation (see `mm0-rs doc --src`)
+//! src = "https://github.com/digama0/mm0/blob/master/examples/"
+//!
+//! [[target]]
+//! name = "peano"        # optional, defaults to the file stem of `source`
+//! source = "peano.mm1"  # the file to elaborate
+//! spec = "peano.mm0"    # optional, an MM0 specification to check the proof files against
+//! join = "peano_join.mm0" # optional, a file to write `spec` to with its imports joined
+//! mmb = "peano.mmb"     # optional, an MMB file to write
+//! mmu = "peano.mmu"     # optional, an MMU file to write
+//! doc = "doc/peano"     # optional, a directory to write documentation to
+//! ```
+//! All paths are relative to the directory containing the manifest, and target names
+//! must be unique. If `join` is given, the specification is joined as by `mm0-rs join`
+//! and the proof files are checked against the joined file, which can then also be
+//! passed to verifiers that do not support `import`.
+//!
+//! `mm0-rs build` elaborates all targets in parallel (sharing the work for common imports),
+//! then writes the artifacts for each target and verifies the proof files against the
+//! specification. A target is skipped if all of its artifacts are newer than its source,
+//! its specification and all the files they import.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use clap::ArgMatches;
+use serde::Deserialize;
+use mm1_parser::parse;
+use crate::compiler::{elab_for_results, get_file, write_output};
+use crate::{FileRef, FrozenEnv};
+
+/// The contents of an `mm0.toml` manifest file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+  /// The directory to use for the elaboration cache, if any.
+  cache: Option<PathBuf>,
+  /// The base URL for source links in the documentation, or `-` to disable them.
+  #[cfg_attr(not(feature = "doc"), allow(dead_code))]
+  src: Option<String>,
+  /// The list of targets.
+  #[serde(default, rename = "target")]
+  targets: Vec<Target>,
+}
+
+impl Manifest {
+  /// Read and parse the manifest file at `path`.
+  fn load(path: &Path) -> io::Result<Self> {
+    let err = |e: &dyn std::fmt::Display|
+      io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+    let man: Self = toml::from_str(&fs::read_to_string(path)?).map_err(|e| err(&e))?;
+    let mut names = HashSet::new();
+    if let Some(t) = man.targets.iter().find(|t| !names.insert(t.name())) {
+      return Err(err(&format_args!("duplicate target name '{}'", t.name())))
+    }
+    Ok(man)
+  }
+}
+
+/// A target in the manifest. See the [module documentation](self) for the meaning of the fields.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Target {
+  name: Option<String>,
+  source: PathBuf,
+  spec: Option<PathBuf>,
+  join: Option<PathBuf>,
+  mmb: Option<PathBuf>,
+  mmu: Option<PathBuf>,
+  doc: Option<PathBuf>,
+}
+
+impl Target {
+  /// The name of the target, which defaults to the file stem of the source file.
+  fn name(&self) -> &str {
+    self.name.as_deref().unwrap_or_else(||
+      self.source.file_stem().and_then(|s| s.to_str()).unwrap_or("?"))
+  }
+
+  /// The list of artifacts of this target, relative to `base`. The documentation is
+  /// represented by the `index.html` file in the documentation folder.
+  fn outputs(&self, base: &Path) -> Vec<PathBuf> {
+    let mut out: Vec<_> = self.join.iter().chain(&self.mmb).chain(&self.mmu)
+      .map(|p| base.join(p)).collect();
+    if let Some(doc) = &self.doc { out.push(base.join(doc).join("index.html")) }
+    out
+  }
+}
+
+/// Add `path` and all of the files it (transitively) imports to `deps`.
+/// Imports that cannot be resolved are skipped; they will be reported by the elaborator.
+fn collect_deps(path: &FileRef, deps: &mut HashSet<FileRef>) -> io::Result<()> {
+  if !deps.insert(path.clone()) || path.has_extension("mmb") || path.has_extension("mmu") {
+    return Ok(())
+  }
+  let text = get_file(path.clone())?;
+  let (_, ast) = parse(text.ascii().clone(), None);
+  for (_, f) in &ast.imports {
+    if let Ok(f) = std::str::from_utf8(f) {
+      let p = path.path().parent().map_or_else(|| PathBuf::from(f), |p| p.join(f));
+      if let Ok(p) = p.canonicalize() { collect_deps(&p.into(), deps)? }
+    }
+  }
+  Ok(())
+}
+
+/// Returns true if all the `outputs` exist and are newer than all the `inputs`
+/// and the files they import.
+fn up_to_date(inputs: &[&FileRef], outputs: &[PathBuf]) -> io::Result<bool> {
+  let mut oldest = None::<SystemTime>;
+  for out in outputs {
+    match fs::metadata(out) {
+      Ok(m) => { let t = m.modified()?; oldest = Some(oldest.map_or(t, |o| o.min(t))) }
+      Err(_) => return Ok(false),
+    }
+  }
+  let oldest = if let Some(t) = oldest { t } else { return Ok(false) };
+  let mut deps = HashSet::new();
+  for &input in inputs { collect_deps(input, &mut deps)? }
+  for dep in deps {
+    if fs::metadata(dep.path())?.modified()? > oldest { return Ok(false) }
+  }
+  Ok(true)
+}
+
+/// The settings shared by all the targets of a manifest.
+struct Builder<'a> {
+  /// The directory containing the manifest, which the paths in the manifest are relative to.
+  base: &'a Path,
+  /// The base URL for source links in the documentation.
+  #[cfg(feature = "doc")]
+  base_url: Option<lsp_types::Url>,
+}
+
+impl Builder<'_> {
+  /// Write the artifacts for a target, given the elaboration results for the source and
+  /// the specification. Returns false if the target failed.
+  fn target(&self, t: &Target, path: &FileRef, env: &FrozenEnv, spec: Option<&FrozenEnv>
+  ) -> io::Result<bool> {
+    let file = get_file(path.clone())?;
+    for out in t.mmb.iter().chain(&t.mmu) {
+      let out = self.base.join(out);
+      let out_str = out.to_str().ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid unicode in output path"))?;
+      write_output(path.clone(), file.try_ascii().map(|fc| &**fc), env, out_str)?;
+      if let Some(spec) = spec {
+        if !crate::mmb::verify::verify_file(&fs::canonicalize(&out)?.into(), spec)? {
+          // Don't leave a bad proof file around, or else the target would look up to date
+          fs::remove_file(out)?;
+          return Ok(false)
+        }
+      }
+    }
+    if let Some(doc) = &t.doc {
+      #[cfg(feature = "doc")]
+      crate::doc::build_docs(path, file.ascii(), env, self.base.join(doc),
+        crate::doc::ProofOrder::Post, None, self.base_url.clone())?;
+      #[cfg(not(feature = "doc"))] {
+        println!("warning: not writing docs to {}, mm0-rs was built without the 'doc' feature",
+          doc.display())
+      }
+    }
+    Ok(true)
+  }
+}
+
+/// Main entry point for `mm0-rs build` subcommand.
+///
+/// See the [module documentation](self) for the manifest format.
+///
+/// # Arguments
+///
+/// `mm0-rs build [-m mm0.toml] [-f] [TARGETS...]`, where:
+///
+/// - `mm0.toml` is the manifest file
+/// - `-f` forces all targets to be rebuilt, even if they are up to date
+/// - `TARGETS` are the names of the targets to build, or all targets if omitted
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let manifest = fs::canonicalize(args.value_of("manifest").unwrap_or("mm0.toml"))?;
+  let man = Manifest::load(&manifest)?;
+  let base = manifest.parent().expect("file has a parent");
+  let builder = Builder {
+    base,
+    #[cfg(feature = "doc")]
+    base_url: crate::doc::parse_base_url(man.src.as_deref())?,
+  };
+  if let Some(cache) = &man.cache { crate::compiler::set_cache_dir(base.join(cache)) }
+  let selected: Option<Vec<_>> = args.values_of("TARGETS").map(Iterator::collect);
+  if let Some(sel) = &selected {
+    if let Some(name) = sel.iter().find(|&&name| !man.targets.iter().any(|t| t.name() == name)) {
+      return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown target '{}'", name)))
+    }
+  }
+  let mut stale = vec![];
+  for t in &man.targets {
+    if selected.as_ref().map_or(false, |sel| !sel.contains(&t.name())) { continue }
+    let path: FileRef = fs::canonicalize(base.join(&t.source))?.into();
+    let spec = match &t.spec {
+      Some(spec) => Some(FileRef::from(fs::canonicalize(base.join(spec))?)),
+      None => None,
+    };
+    if !args.is_present("force") &&
+      up_to_date(&std::iter::once(&path).chain(&spec).collect::<Vec<_>>(), &t.outputs(base))? {
+      println!("fresh {}", t.name());
+      continue
+    }
+    let spec = match (spec, &t.join) {
+      (Some(spec), Some(join)) => {
+        let join = base.join(join);
+        crate::joiner::join_with_header(true, true, fs::File::create(&join)?, spec)?;
+        Some(fs::canonicalize(join)?.into())
+      }
+      (spec, _) => spec,
+    };
+    stale.push((t, path, spec));
+  }
+  let paths: Vec<_> = stale.iter()
+    .flat_map(|(_, path, spec)| std::iter::once(path).chain(spec).cloned()).collect();
+  let mut results = elab_for_results(&paths)?.into_iter();
+  let mut failed = vec![];
+  for (t, path, spec) in stale {
+    let env = results.next().expect("impossible");
+    let spec_env = spec.map(|_| results.next().expect("impossible"));
+    let ok = match (env, spec_env) {
+      (Some((env, false)), None) => builder.target(t, &path, &env, None)?,
+      (Some((env, false)), Some(Some((spec, false)))) =>
+        builder.target(t, &path, &env, Some(&spec))?,
+      _ => false,
+    };
+    if !ok { failed.push(t.name()) }
+  }
+  if !failed.is_empty() {
+    let msg = format!("failed to build {}", failed.join(", "));
+    return Err(io::Error::new(io::ErrorKind::Other, msg))
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use std::fs;
+  use clap::{App, Arg};
+  use crate::compiler::test_file;
+  use super::{main, Manifest};
+
+  /// Write the files of a project with a joined spec, and build it with `mm0-rs build`.
+  fn build(spec: &str) -> (std::path::PathBuf, std::io::Result<()>) {
+    let manifest = test_file("mm0.toml", b"[[target]]\nsource = \"a.mm1\"\n\
+      spec = \"a.mm0\"\njoin = \"a_join.mm0\"\nmmb = \"a.mmb\"\n");
+    let dir = manifest.path().parent().unwrap().to_owned();
+    fs::write(dir.join("b.mm0"),
+      "delimiter $ ( ) $;\nprovable sort wff;\nterm im: wff > wff > wff;\n").unwrap();
+    fs::write(dir.join("a.mm0"), format!("import \"b.mm0\";\n{}", spec)).unwrap();
+    fs::write(dir.join("a.mm1"), "import \"b.mm0\";\naxiom ax (a b: wff): $ im a (im b a) $;\n")
+      .unwrap();
+    let args = App::new("build").arg(Arg::with_name("manifest").short("m").takes_value(true))
+      .get_matches_from(vec!["build", "-m", manifest.path().to_str().unwrap()]);
+    (dir, main(&args))
+  }
+
+  #[test]
+  fn joined_spec() {
+    let (dir, res) = build("axiom ax (a b: wff): $ im a (im b a) $;\n");
+    res.unwrap();
+    let joined = fs::read_to_string(dir.join("a_join.mm0")).unwrap();
+    assert!(joined.contains("provable sort wff;") && !joined.contains("\nimport"), "{}", joined);
+    assert!(dir.join("a.mmb").exists());
+    let (dir, res) = build("axiom ax (a b: wff): $ im b (im a b) $;\n");
+    assert_eq!(res.unwrap_err().to_string(), "failed to build a");
+    assert!(!dir.join("a.mmb").exists());
+  }
+
+  #[test]
+  fn duplicate_target_names() {
+    let ok = test_file("mm0.toml", b"[[target]]\nsource = \"a.mm1\"\n\
+      [[target]]\nname = \"b\"\nsource = \"a.mm1\"\n");
+    assert_eq!(Manifest::load(ok.path()).unwrap().targets.len(), 2);
+    let dup = test_file("mm0.toml", b"[[target]]\nsource = \"a.mm1\"\n\
+      [[target]]\nname = \"a\"\nsource = \"b.mm1\"\n");
+    let err = Manifest::load(dup.path()).unwrap_err();
+    assert!(err.to_string().ends_with("duplicate target name 'a'"), "{}", err);
+  }
+}
@@ -6,10 +6,23 @@
 //! Additionally, unlike the server, the MM1 compiler will go on and generate MMB or MMU proofs,
 //! which can then be checked using an external MM0 checker such as [`mm0-c`].
 //!
+//! With `--message-format=json`, diagnostics are instead printed as JSON records, one per line,
+//! for consumption by other tools. See [`JsonMessage`] for the format.
+//!
+//! With `--cache DIR`, the environment resulting from each successfully elaborated file is
+//! saved in `DIR`, and reused on later runs if the file and its imports have not changed.
+//! See the [`cache`] module for details.
+//!
+//! With `--profile`, a report of the time spent elaborating each statement is printed for
+//! each elaborated file. See the [`profile`](crate::elab::profile) module for details.
+//!
 //! [`mm0_rs::server`]: crate::server
 //! [`mm0-c`]: https://github.com/digama0/mm0/tree/master/mm0-c
+mod cache;
+
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
-use std::collections::{HashMap, hash_map::Entry};
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 use std::{io, fs};
 use futures::{FutureExt, future::BoxFuture};
 use futures::channel::oneshot::{Sender as FSender, channel};
@@ -20,8 +33,9 @@ use annotate_snippets::{
   display_list::{DisplayList, FormatOptions}};
 use typed_arena::Arena;
 use clap::ArgMatches;
-use mm1_parser::{parse, ErrorLevel, ParseError};
-use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder};
+use mm1_parser::{ast::Ast, parse, ErrorLevel, ParseError};
+use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder, Elaborator,
+  lisp::debugger::Debugger};
 use crate::{ArcList, FileRef, FileSpan, FrozenEnv, LinedString, MutexExt, Position, Range, Span};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
@@ -33,9 +47,21 @@ lazy_static! {
   /// The virtual file system of files that have been included via
   /// transitive imports, protected for concurrent access by a mutex.
   static ref VFS: Vfs = Vfs(Mutex::new(HashMap::new()));
+  /// The directory for the elaboration cache, if enabled by `--cache`.
+  static ref CACHE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
 }
 
 static QUIET: AtomicBool = AtomicBool::new(false);
+/// True in `--watch` mode, where we keep track of file dependencies, and keep
+/// the results of previous elaborations around for incremental elaboration.
+static WATCH: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "server")]
+static JSON: AtomicBool = AtomicBool::new(false);
+/// True in `--profile` mode, where we print a profiling report for each elaborated file.
+static PROFILE: AtomicBool = AtomicBool::new(false);
+/// True if nothing should be printed to stdout, because it is used for another purpose
+/// (like the debug adapter protocol). Diagnostics in imported files are not reported.
+static SILENT: AtomicBool = AtomicBool::new(false);
 
 /// The cached [`Environment`](crate::elab::Environment) representing a
 /// completed parse, or an incomplete parse.
@@ -50,9 +76,11 @@ enum FileCache {
   /// [`Environment`]: crate::elab::Environment
   /// [`Sender`]: FSender
   /// [`Receiver`]: futures::channel::oneshot::Receiver
-  InProgress(Vec<FSender<ElabResult<()>>>),
-  /// The file has been elaborated and the result is ready.
-  Ready(FrozenEnv),
+  InProgress(Vec<FSender<ElabResult<Option<u64>>>>),
+  /// The file has been elaborated and the result is ready. The first argument is the
+  /// cache key of the file (see [`cache::key`]), if caching is enabled and the file
+  /// elaborated without errors.
+  Ready(Option<u64>, FrozenEnv),
 }
 
 #[derive(DeepSizeOf, Clone)]
@@ -124,6 +152,11 @@ impl std::ops::Deref for FileContents {
   }
 }
 
+/// The result of a previous elaboration of a file, which is kept in `--watch` mode so that
+/// the file can be elaborated incrementally after it is edited. It contains the source text,
+/// the parsed AST, and the errors and environment resulting from elaboration.
+type OldElab = (FileContents, Arc<Ast>, Option<Arc<[ElabError]>>, FrozenEnv);
+
 /// A file that has been loaded from disk, along with the
 /// parsed representation of the file (which may be in progress on another thread).
 #[derive(DeepSizeOf)]
@@ -135,12 +168,33 @@ struct VirtualFile {
     /// elaboration job to represent dependency relations. A result of `None`
     /// means that the file parse job has not yet been started.
     parsed: FMutex<Option<FileCache>>,
+    /// The files imported by this file, as of the last elaboration (only in watch mode).
+    deps: Mutex<Vec<FileRef>>,
+    /// The files that import this file (only in watch mode).
+    downstream: Mutex<HashSet<FileRef>>,
+    /// The last elaboration of this file, if it can be reused (only in watch mode).
+    old: Mutex<Option<OldElab>>,
 }
 
 impl VirtualFile {
   /// Constructs a new [`VirtualFile`] from source text.
   fn new(text: FileContents) -> VirtualFile {
-    VirtualFile { text, parsed: FMutex::new(None) }
+    VirtualFile {
+      text,
+      parsed: FMutex::new(None),
+      deps: Mutex::new(vec![]),
+      downstream: Mutex::new(HashSet::new()),
+      old: Mutex::new(None),
+    }
+  }
+
+  /// Read the file at `path` from disk.
+  fn load(path: &FileRef) -> io::Result<FileContents> {
+    if path.has_extension("mmb") {
+      FileContents::new_bin_from_file(path.path())
+    } else {
+      Ok(FileContents::new(fs::read_to_string(path.path())?))
+    }
   }
 }
 
@@ -159,16 +213,56 @@ impl Vfs {
       Entry::Occupied(e) => Ok((e.key().clone(), e.get().clone())),
       Entry::Vacant(e) => {
         let path = e.key().clone();
-        let fc = if path.has_extension("mmb") {
-          FileContents::new_bin_from_file(path.path())?
-        } else {
-          FileContents::new(fs::read_to_string(path.path())?)
-        };
-        let val = e.insert(Arc::new(VirtualFile::new(fc))).clone();
+        let val = e.insert(Arc::new(VirtualFile::new(VirtualFile::load(&path)?))).clone();
         Ok((path, val))
       }
     }
   }
+
+  /// Record that `to` now imports `deps` instead of `old_deps`.
+  /// (This is the same as the server's `Vfs::update_downstream`.)
+  fn update_downstream(&self, old_deps: &[FileRef], deps: &[FileRef], to: &FileRef) {
+    for from in old_deps {
+      if !deps.contains(from) {
+        let file = self.0.ulock().get(from).expect("missing file").clone();
+        file.downstream.ulock().remove(to);
+      }
+    }
+    for from in deps {
+      if !old_deps.contains(from) {
+        let file = self.0.ulock().get(from).expect("missing file").clone();
+        file.downstream.ulock().insert(to.clone());
+      }
+    }
+  }
+
+  /// Reload `path` from disk, and invalidate it and all files that (transitively) import
+  /// it, so that they will be elaborated again on the next call to [`elaborate`].
+  /// Files that are invalidated because of a dependency change are elaborated from
+  /// scratch, while an edited file is elaborated incrementally from the first change.
+  fn invalidate(&self, path: &FileRef) -> io::Result<()> {
+    let mut new = VirtualFile::new(VirtualFile::load(path)?);
+    let old = self.0.ulock().get(path).cloned();
+    let mut todo = vec![];
+    if let Some(old) = old {
+      new.deps = Mutex::new(old.deps.ulock().clone());
+      new.downstream = Mutex::new(old.downstream.ulock().clone());
+      new.old = Mutex::new(old.old.ulock().take());
+      todo.extend(old.downstream.ulock().iter().cloned());
+    }
+    self.0.ulock().insert(path.clone(), Arc::new(new));
+    let mut done = HashSet::new();
+    while let Some(path) = todo.pop() {
+      if !done.insert(path.clone()) { continue }
+      let file = self.0.ulock().get(&path).cloned();
+      if let Some(file) = file {
+        *block_on(file.parsed.lock()) = None;
+        *file.old.ulock() = None;
+        todo.extend(file.downstream.ulock().iter().cloned());
+      }
+    }
+    Ok(())
+  }
 }
 
 fn mk_to_range() -> impl FnMut(&FileSpan) -> Option<Range> {
@@ -255,7 +349,9 @@ fn make_snippet<'a>(path: &'a FileRef, file: &'a LinedString, pos: Span,
 ///
 /// - `msg`: The error message
 /// - `level`: The error level
-fn make_snippet_no_source(msg: &str, level: ErrorLevel) -> Snippet<'_> {
+/// - `footer`: The snippet footer (calculated by [`ElabErrorKind::to_footer`])
+fn make_snippet_no_source<'a>(msg: &'a str, level: ErrorLevel,
+    footer: Vec<Annotation<'a>>) -> Snippet<'a> {
   let annotation_type = level.to_annotation_type();
   Snippet {
     title: Some(Annotation {
@@ -264,7 +360,7 @@ fn make_snippet_no_source(msg: &str, level: ErrorLevel) -> Snippet<'_> {
       annotation_type,
     }),
     slices: vec![],
-    footer: vec![],
+    footer,
     opt: FormatOptions { color: true, anonymized_line_numbers: false, margin: None }
   }
 }
@@ -295,15 +391,17 @@ impl ElabError {
   /// # Parameters
   ///
   /// - `path`: The location of the error
+  /// - `to_range`: a function for converting (index-based) spans to (line/col) ranges
   /// - `f`: The function to pass the constructed snippet
   fn to_snippet_no_source<T>(&self, path: &FileRef, span: Span,
+      to_range: impl FnMut(&FileSpan) -> Option<Range>,
       f: impl for<'a> FnOnce(Snippet<'a>) -> T) -> T {
     let s = if span.end == span.start {
       format!("{}:{:#x}: {}", path, span.start, self.kind.msg())
     } else {
       format!("{}:{:#x}-{:#x}: {}", path, span.start, span.end, self.kind.msg())
     };
-    f(make_snippet_no_source(&s, self.level))
+    f(make_snippet_no_source(&s, self.level, self.kind.to_footer(&Arena::new(), to_range)))
   }
 }
 
@@ -314,7 +412,79 @@ fn to_snippet<T>(err: &ParseError, path: &FileRef, file: &LinedString,
   f(make_snippet(path, file, err.pos, &format!("{}", err.msg), err.level, vec![]))
 }
 
+/// A diagnostic message, as printed by `--message-format=json`.
+/// Each message is printed as a single line of JSON.
+#[cfg(feature = "server")]
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonMessage<'a> {
+  /// The file containing the error, relative to the current directory
+  file: &'a str,
+  /// The location of the error, or `null` if the file is not a text file
+  /// or the error has no location
+  range: Option<Range>,
+  /// The severity of the error: `"error"`, `"warning"` or `"info"`
+  level: String,
+  /// The error message
+  message: String,
+  /// Related locations (possibly in other files), along with short messages
+  related_information: Option<Vec<lsp_types::DiagnosticRelatedInformation>>,
+}
+
+#[cfg(feature = "server")]
+impl JsonMessage<'_> {
+  fn print(&self) {
+    println!("{}", serde_json::to_string(self).expect("failed to serialize"))
+  }
+}
+
+/// Print an elaboration error in the file at `path` to stdout, either as a [`Snippet`]
+/// or as a [`JsonMessage`] if `--message-format=json` is set. If `text` is `None`
+/// (for example if it is an MMB file), the error is printed with a byte offset.
+fn emit_error(path: &FileRef, text: Option<&LinedString>, e: &ElabError,
+  to_range: &mut impl FnMut(&FileSpan) -> Option<Range>
+) {
+  fn print(s: Snippet<'_>) { println!("{}\n", DisplayList::from(s)) }
+  if SILENT.load(Ordering::Relaxed) { return }
+  #[cfg(feature = "server")]
+  if JSON.load(Ordering::Relaxed) {
+    let to_loc = |fsp: &FileSpan| lsp_types::Location {
+      uri: fsp.file.url().clone(),
+      range: to_range(fsp).unwrap_or_default(),
+    };
+    let (range, message, related_information) = if let Some(text) = text {
+      let diag = e.to_diag(text, to_loc);
+      (Some(diag.range), diag.message, diag.related_information)
+    } else {
+      (None, e.kind.msg(), e.kind.to_related_info(to_loc))
+    };
+    let level = e.level.to_string();
+    return JsonMessage {file: path.rel(), range, level, message, related_information}.print()
+  }
+  if let Some(text) = text {
+    e.to_snippet(path, text, to_range, print)
+  } else {
+    e.to_snippet_no_source(path, e.pos, to_range, print)
+  }
+}
+
+/// Print a parse error in the file at `path` to stdout, either as a [`Snippet`]
+/// or as a [`JsonMessage`] if `--message-format=json` is set.
+fn emit_parse_error(path: &FileRef, file: &LinedString, e: &ParseError) {
+  if SILENT.load(Ordering::Relaxed) { return }
+  #[cfg(feature = "server")]
+  if JSON.load(Ordering::Relaxed) {
+    let diag = e.to_diag(file);
+    return JsonMessage {
+      file: path.rel(), range: Some(diag.range), level: e.level.to_string(),
+      message: diag.message, related_information: None
+    }.print()
+  }
+  to_snippet(e, path, file, |s| println!("{}", DisplayList::from(s)))
+}
+
 fn log_msg(#[allow(unused_mut)] mut s: String) {
+  if SILENT.load(Ordering::Relaxed) { return }
   #[cfg(feature = "memory")]
   match crate::get_memory_usage() {
     0 => {}
@@ -343,8 +513,13 @@ fn log_msg(#[allow(unused_mut)] mut s: String) {
 /// which will later be joined when the result is required.
 /// (**Note**: This can result in deadlock if the import graph has a cycle.)
 ///
+/// If the elaboration cache is enabled, the imports are elaborated first in order to
+/// calculate the cache key of this file, and if there is a cache entry for this key
+/// it is loaded instead of elaborating the file. The key is returned in the result
+/// (it is `None` if caching is disabled or there were errors).
+///
 /// [`Ast`]: crate::parser::Ast
-async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult<()>> {
+async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult<Option<u64>>> {
   let (path, file) = VFS.get_or_insert(path)?;
   {
     let mut g = file.parsed.lock().await;
@@ -356,78 +531,166 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
         drop(g);
         return Ok(recv.await.unwrap_or(ElabResult::Canceled))
       }
-      Some(FileCache::Ready(env)) => return Ok(ElabResult::Ok((), None, env.clone()))
+      Some(FileCache::Ready(key, env)) => return Ok(ElabResult::Ok(*key, None, env.clone()))
     }
   }
   let text = file.text.clone();
-  let (cyc, errors, env) = if path.has_extension("mmb") {
-    let (error, env) = mmb_elab(&path, &text);
-    (None, if let Err(e) = error {vec![e]} else {vec![]}, FrozenEnv::new(env))
-  } else if path.has_extension("mmu") {
-    let (error, env) = mmu_elab(&path, &text);
+  let cache_dir = CACHE_DIR.ulock().clone();
+  let watch = WATCH.load(Ordering::Relaxed);
+  let mut key = None;
+  let mut deps = Vec::new();
+  let mut new_ast = None;
+  let mut cached = false;
+  let mut profile = None;
+  let (cyc, errors, mut env) = if path.has_extension("mmb") || path.has_extension("mmu") {
+    // Binary files are fast to load, so they are not cached,
+    // but we still need a key for files that import them
+    key = cache_dir.as_ref().map(|_| cache::key(&path, &text, &[]));
+    let (error, env) = if path.has_extension("mmb") {
+      mmb_elab(&path, &text)
+    } else {
+      mmu_elab(&path, &text)
+    };
     (None, if let Err(e) = error {vec![e]} else {vec![]}, FrozenEnv::new(env))
   } else {
-    let (_, ast) = parse(text.ascii().clone(), None);
-    if !ast.errors.is_empty() {
-      for e in &ast.errors {
-        to_snippet(e, &path, &ast.source,
-          |s| println!("{}", DisplayList::from(s)))
+    // In watch mode, if the file was edited (and none of its imports changed), then the
+    // previous parse and elaboration can be reused up to the first changed character.
+    let old = if watch { file.old.ulock().take() } else { None };
+    let (old_ast, old) = match old {
+      None => (None, None),
+      Some((old_text, ast, errors, env)) => {
+        let (old_text, new_text) = (old_text.ascii().as_bytes(), text.ascii().as_bytes());
+        let idx = old_text.iter().zip(new_text).position(|(a, b)| a != b)
+          .unwrap_or_else(|| old_text.len().min(new_text.len()));
+        (Some((text.ascii().to_pos(idx), ast)), Some((errors, env)))
       }
-    }
-    let ast = Arc::new(ast);
-    let mut deps = Vec::new();
-    if !QUIET.load(Ordering::Relaxed) { log_msg(format!("elab {}", path)) }
+    };
+    let (idx, ast) = parse(text.ascii().clone(), old_ast);
+    for e in &ast.errors { emit_parse_error(&path, &ast.source, e) }
+    let ast = &*new_ast.insert(Arc::new(ast));
     let rd = rd.push(path.clone());
-    let fut =
-      ElaborateBuilder {
-        ast: &ast,
-        path: path.clone(),
-        mm0_mode: path.has_extension("mm0"),
-        check_proofs: crate::get_check_proofs(),
-        report_upstream_errors: false,
-        cancel: Arc::default(),
-        old: None,
-        recv_dep: |p| {
-          let p = VFS.get_or_insert(p)?.0;
-          let (send, recv) = channel();
-          if rd.contains(&p) {
-            send.send(ElabResult::ImportCycle(rd.clone())).expect("failed to send");
-          } else {
-            POOL.spawn_ok(elaborate_and_send(p.clone(), send, rd.clone()));
-            deps.push(p);
-          }
-          Ok(recv)
-        },
-        recv_goal: None,
-      }.elab();
-    let (cyc, _, errors, env) = fut.await;
-    (cyc, errors, env)
+    let loaded = match &cache_dir {
+      Some(dir) => {
+        if ast.errors.is_empty() {
+          key = import_keys(&path, ast, &rd, &mut deps).await
+            .map(|keys| cache::key(&path, &text, &keys));
+        }
+        key.and_then(|key| cache::load(dir, key))
+      }
+      None => None
+    };
+    if let Some(env) = loaded {
+      if !QUIET.load(Ordering::Relaxed) { log_msg(format!("cached {}", path)) }
+      cached = true;
+      (None, vec![], env)
+    } else {
+      deps.clear();
+      if !QUIET.load(Ordering::Relaxed) { log_msg(format!("elab {}", path)) }
+      let fut =
+        ElaborateBuilder {
+          ast,
+          path: path.clone(),
+          mm0_mode: path.has_extension("mm0"),
+          check_proofs: crate::get_check_proofs(),
+          report_upstream_errors: false,
+          cancel: Arc::default(),
+          old: old.map(|(errors, env)| (idx, errors, env)),
+          recv_dep: |p| {
+            let p = VFS.get_or_insert(p)?.0;
+            let (send, recv) = channel();
+            if rd.contains(&p) {
+              send.send(ElabResult::ImportCycle(rd.clone())).expect("failed to send");
+            } else {
+              POOL.spawn_ok(elaborate_and_send(p.clone(), send, rd.clone()));
+              deps.push(p);
+            }
+            Ok(recv)
+          },
+          recv_goal: None,
+          profile: PROFILE.load(Ordering::Relaxed),
+          debugger: None,
+        }.elab();
+      let (cyc, _, errors, env, prof) = fut.await;
+      profile = prof;
+      (cyc, errors, env)
+    }
   };
-  if !QUIET.load(Ordering::Relaxed) { log_msg(format!("elabbed {}", path)) }
+  if !QUIET.load(Ordering::Relaxed) && !cached { log_msg(format!("elabbed {}", path)) }
+  if let Some(profile) = profile {
+    println!("{}", profile.report(&path, text.ascii()))
+  }
   let errors: Option<Arc<[_]>> = if errors.is_empty() { None } else {
-    fn print(s: Snippet<'_>) { println!("{}\n", DisplayList::from(s)) }
     let mut to_range = mk_to_range();
-    if let FileContents::Ascii(text) = &file.text {
-      for e in &errors { e.to_snippet(&path, text, &mut to_range, print) }
-    } else {
-      for e in &errors { e.to_snippet_no_source(&path, e.pos, print) }
-    }
+    for e in &errors { emit_error(&path, file.text.try_ascii().map(|t| &**t), e, &mut to_range) }
     Some(errors.into())
   };
+  let key = if cyc.is_some() || errors.is_some() { None } else { key };
+  let binary = path.has_extension("mmb") || path.has_extension("mmu");
+  if let (Some(dir), Some(key), false, false) = (&cache_dir, key, binary, cached) {
+    match cache::store(dir, key, &mut env) {
+      Ok(true) => {}
+      Ok(false) => if !QUIET.load(Ordering::Relaxed) {
+        log_msg(format!("not caching {}: the environment cannot be serialized", path))
+      },
+      Err(e) => log_msg(format!("failed to write cache for {}: {}", path, e)),
+    }
+  }
+  if watch {
+    let old_deps = std::mem::replace(&mut *file.deps.ulock(), deps.clone());
+    VFS.update_downstream(&old_deps, &deps, &path);
+    // An environment loaded from the cache has no span information, so it is not reusable
+    if let (Some(ast), false) = (new_ast, cached) {
+      *file.old.ulock() = Some((text, ast, errors.clone(), env.clone()))
+    }
+  }
   let res = match cyc {
-    None => ElabResult::Ok((), errors, env.clone()),
+    None => ElabResult::Ok(key, errors, env.clone()),
     Some(cyc) => ElabResult::ImportCycle(cyc),
   };
-  {
-    let mut g = file.parsed.lock().await;
-    if let Some(FileCache::InProgress(senders)) = g.take() {
-      for s in senders {
-        drop(s.send(res.clone()));
-      }
+  finish_elab(&file, res.clone(), key, env).await;
+  Ok(res)
+}
+
+/// Elaborate the imports of `path` (which has already been parsed into `ast`), and return
+/// their cache keys, or `None` if any of them could not be elaborated without errors.
+/// This is used to calculate the cache key for `path` before elaborating it.
+/// The imported files are added to `deps`.
+async fn import_keys(path: &FileRef, ast: &Ast, rd: &ArcList<FileRef>,
+  deps: &mut Vec<FileRef>
+) -> Option<Vec<u64>> {
+  let mut recvs = vec![];
+  for (_, f) in &ast.imports {
+    let f = std::str::from_utf8(f).ok()?;
+    let p = path.path().parent().map_or_else(|| PathBuf::from(f), |p| p.join(f));
+    let p = VFS.get_or_insert(p.canonicalize().ok()?.into()).ok()?.0;
+    if rd.contains(&p) { return None }
+    let (send, recv) = channel();
+    POOL.spawn_ok(elaborate_and_send(p.clone(), send, rd.clone()));
+    deps.push(p);
+    recvs.push(recv);
+  }
+  let mut keys = vec![];
+  for recv in recvs {
+    match recv.await {
+      Ok(ElabResult::Ok(Some(key), _, _)) => keys.push(key),
+      _ => return None
     }
-    *g = Some(FileCache::Ready(env));
   }
-  Ok(res)
+  Some(keys)
+}
+
+/// Store the environment `env` resulting from elaborating `file`, and broadcast the
+/// result `res` to all waiting tasks.
+async fn finish_elab(file: &VirtualFile,
+  res: ElabResult<Option<u64>>, key: Option<u64>, env: FrozenEnv
+) {
+  let mut g = file.parsed.lock().await;
+  if let Some(FileCache::InProgress(senders)) = g.take() {
+    for s in senders {
+      drop(s.send(res.clone()));
+    }
+  }
+  *g = Some(FileCache::Ready(key, env));
 }
 
 /// Elaborate a file, and pass the [`Environment`](crate::elab::Environment)
@@ -436,7 +699,9 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
 /// See [`elaborate`] for details on elaboration. This function encapsulates
 /// the `async fn` into a [`BoxFuture`], in order to avoid a recursion between
 /// this function and [`elaborate`] resulting in infinite sized futures.
-fn elaborate_and_send(path: FileRef, send: FSender<ElabResult<()>>, rd: ArcList<FileRef>) ->
+fn elaborate_and_send(path: FileRef,
+  send: FSender<ElabResult<Option<u64>>>, rd: ArcList<FileRef>
+) ->
   BoxFuture<'static, ()> {
   async {
     if let Ok(env) = elaborate(path, rd).await {
@@ -456,6 +721,99 @@ pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option
   Ok((file.text.clone(), env))
 }
 
+/// Elaborate the file at `path` (or an empty file, if `path` is `None`), reporting any
+/// errors, and return the [`Elaborator`] in its final state. The imports are elaborated
+/// as usual. This is used by the [`repl`](crate::elab::repl).
+pub(crate) fn elab_for_repl(path: Option<FileRef>) -> io::Result<Elaborator> {
+  let (path, text, errors, elab) = elab_keep(path, None)?;
+  let mut to_range = mk_to_range();
+  for e in &errors { emit_error(&path, Some(&*text), e, &mut to_range) }
+  Ok(elab)
+}
+
+/// Elaborate the file at `path` (or an empty file, if `path` is `None`) with `debugger`
+/// attached, and return the path and contents of the file, the parse and elaboration errors
+/// (which are not reported), and the [`Elaborator`] in its final state.
+/// The imports are elaborated as usual.
+#[allow(clippy::type_complexity)]
+pub(crate) fn elab_keep(path: Option<FileRef>, debugger: Option<Debugger>
+) -> io::Result<(FileRef, Arc<LinedString>, Vec<ElabError>, Elaborator)> {
+  let (path, text) = match path {
+    Some(path) => {
+      let (path, file) = VFS.get_or_insert(path)?;
+      let text = file.text.try_ascii().cloned().ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidInput, "expected an .mm1 or .mm0 file"))?;
+      (path, text)
+    }
+    None => (std::env::current_dir()?.join("<repl>").into(), Arc::default()),
+  };
+  let (_, ast) = parse(text.clone(), None);
+  let mut errors: Vec<ElabError> = ast.errors.iter().cloned().map(Into::into).collect();
+  let ast = Arc::new(ast);
+  let rd = ArcList::default().push(path.clone());
+  let (_, _, errs, elab) = block_on(ElaborateBuilder {
+    ast: &ast,
+    path: path.clone(),
+    mm0_mode: path.has_extension("mm0"),
+    check_proofs: crate::get_check_proofs(),
+    report_upstream_errors: true,
+    cancel: Arc::default(),
+    old: None,
+    recv_dep: |p| {
+      let p = VFS.get_or_insert(p)?.0;
+      let (send, recv) = channel();
+      if rd.contains(&p) {
+        send.send(ElabResult::ImportCycle(rd.clone())).expect("failed to send");
+      } else {
+        POOL.spawn_ok(elaborate_and_send(p, send, rd.clone()));
+      }
+      Ok(recv)
+    },
+    recv_goal: None,
+    profile: false,
+    debugger,
+  }.elab_keep());
+  errors.extend(errs);
+  Ok((path, text, errors, elab))
+}
+
+/// Suppress all output to stdout from the compiler (see [`SILENT`]).
+pub(crate) fn set_silent() { SILENT.store(true, Ordering::Relaxed) }
+
+/// Elaborate several files in parallel on the thread pool [`struct@POOL`], and return
+/// the completed [`FrozenEnv`] results in the same order, along with a flag that is
+/// true if elaboration reported any errors (not including warnings and info messages).
+pub(crate) fn elab_for_results(paths: &[FileRef]) -> io::Result<Vec<Option<(FrozenEnv, bool)>>> {
+  let mut recvs = vec![];
+  for path in paths {
+    let path = VFS.get_or_insert(path.clone())?.0;
+    let (send, recv) = channel();
+    POOL.spawn_ok(elaborate_and_send(path, send, Default::default()));
+    recvs.push(recv);
+  }
+  Ok(recvs.into_iter().map(|recv| match block_on(recv) {
+    Ok(ElabResult::Ok(_, errors, env)) => Some((env,
+      errors.map_or(false, |es| es.iter().any(|e| e.level == ErrorLevel::Error)))),
+    _ => None
+  }).collect())
+}
+
+/// Set the directory for the elaboration cache (see [`cache`]).
+pub(crate) fn set_cache_dir(dir: PathBuf) { *CACHE_DIR.ulock() = Some(dir) }
+
+/// Get the contents of the file at `path`, loading it into the [`VFS`] if necessary.
+pub(crate) fn get_file(path: FileRef) -> io::Result<FileContents> {
+  Ok(VFS.get_or_insert(path)?.1.text.clone())
+}
+
+/// Print an error in the file at `path` to stdout. If the file is not a text file
+/// (for example if it is an MMB file), the error is printed with a byte offset.
+pub(crate) fn print_error(path: &FileRef, e: &ElabError) -> io::Result<()> {
+  let file = VFS.get_or_insert(path.clone())?.1;
+  emit_error(path, file.text.try_ascii().map(|t| &**t), e, &mut mk_to_range());
+  Ok(())
+}
+
 /// Main entry point for `mm0-rs compile` subcommand.
 ///
 /// # Arguments
@@ -466,47 +824,149 @@ pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option
 /// - `out.mmb` (or `out.mmu`) is the MMB file to generate, if the elaboration is
 ///   successful. The file extension is used to determine if we are outputting
 ///   binary. If this argument is omitted, the input is only elaborated.
+///
+/// If `--cache DIR` is passed, elaborated files are cached in `DIR` (see [`cache`]).
+///
+/// If `--message-format=json` is passed, the `elab` progress messages are suppressed
+/// and all diagnostics are printed as [`JsonMessage`]s. This option is only accepted
+/// with the `server` feature.
+///
+/// If `--profile` is passed, a profiling report is printed for each elaborated file.
 pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   let path = args.value_of("INPUT").expect("required arg");
   let path: FileRef = fs::canonicalize(path)?.into();
+  #[cfg(feature = "server")]
+  if args.value_of("message_format") == Some("json") {
+    JSON.store(true, Ordering::Relaxed);
+    QUIET.store(true, Ordering::Relaxed);
+  }
+  if let Some(dir) = args.value_of_os("cache") { set_cache_dir(dir.into()) }
+  PROFILE.store(args.is_present("profile"), Ordering::Relaxed);
+  if args.is_present("watch") {
+    WATCH.store(true, Ordering::Relaxed);
+    if let Err(e) = compile(&path, args) { eprintln!("error: {}", e) }
+    watch(&path, args)
+  } else {
+    if !compile(&path, args)? { std::process::exit(1) }
+    Ok(())
+  }
+}
+
+/// Elaborate `path` and write the outputs requested in `args`.
+/// Returns false if elaboration or output failed.
+fn compile(path: &FileRef, args: &ArgMatches<'_>) -> io::Result<bool> {
   let (file, env) = elab_for_result(path.clone())?;
-  let env = env.unwrap_or_else(|| std::process::exit(1));
-  QUIET.store(args.is_present("quiet"), Ordering::Relaxed);
+  let env = if let Some(env) = env { env } else { return Ok(false) };
+  if args.is_present("quiet") { QUIET.store(true, Ordering::Relaxed) }
   if let Some(s) = args.value_of_os("output") {
     if let Err((fsp, e)) =
       if s == "-" { env.run_output(io::stdout()) }
       else { env.run_output(fs::File::create(s)?) }
     {
-      let e = ElabError::new_e(fsp.span, e);
-      let file = VFS.get_or_insert(fsp.file.clone())?.1;
-      e.to_snippet(&fsp.file, file.text.ascii(), &mut mk_to_range(),
-        |s| println!("{}\n", DisplayList::from(s)));
-      std::process::exit(1);
+      print_error(&fsp.file, &ElabError::new_e(fsp.span, e))?;
+      return Ok(false)
     }
   }
   if let Some(out) = args.value_of("OUTPUT") {
-    use {fs::File, io::BufWriter};
-    let w = BufWriter::new(File::create(out)?);
-    if out.rsplit('.').next().map_or(false, |ext| ext.eq_ignore_ascii_case("mmu")) {
-      env.export_mmu(w)?;
-    } else {
-      fn report(lvl: ErrorLevel, err: &str) {
-        println!("{}\n", DisplayList::from(Snippet {
-          title: Some(Annotation {
-            label: Some(err),
-            id: None,
-            annotation_type: lvl.to_annotation_type(),
-          }),
-          footer: vec![],
-          slices: vec![],
-          opt: FormatOptions { color: true, ..Default::default() },
-        }))
-      }
-      let mut report = report;
-      let mut ex = MmbExporter::new(path, file.try_ascii().map(|fc| &**fc), &env, &mut report, w);
-      ex.run(true)?;
-      ex.finish()?;
+    write_output(path.clone(), file.try_ascii().map(|fc| &**fc), &env, out)?
+  }
+  Ok(true)
+}
+
+/// Poll the files loaded so far for changes, and [`compile`] again after each change.
+/// Only the files that changed and the files that (transitively) import them are
+/// elaborated again; edited files are elaborated incrementally.
+fn watch(path: &FileRef, args: &ArgMatches<'_>) -> io::Result<()> {
+  fn mtimes() -> HashMap<FileRef, Option<std::time::SystemTime>> {
+    VFS.0.ulock().keys().map(|p|
+      (p.clone(), fs::metadata(p.path()).and_then(|m| m.modified()).ok())).collect()
+  }
+  let quiet = QUIET.load(Ordering::Relaxed);
+  let mut times = mtimes();
+  loop {
+    if !quiet { log_msg("watching for changes...".into()) }
+    let changed = loop {
+      std::thread::sleep(std::time::Duration::from_millis(300));
+      let new = mtimes();
+      let changed: Vec<_> = new.iter()
+        .filter(|&(p, t)| times.get(p).map_or(false, |t2| t != t2))
+        .map(|(p, _)| p.clone()).collect();
+      times = new;
+      if !changed.is_empty() { break changed }
+    };
+    for p in &changed {
+      if !quiet { log_msg(format!("changed {}", p)) }
+      // If a file can't be read (for example because it is being written), keep the old
+      // version; we will see it again when its modification time changes
+      if let Err(e) = VFS.invalidate(p) { eprintln!("error: {}: {}", p, e) }
     }
+    if let Err(e) = compile(path, args) { eprintln!("error: {}", e) }
+    // Add any files that were imported for the first time
+    for (p, t) in mtimes() { times.entry(p).or_insert(t); }
+  }
+}
+
+/// Write `env` to the file `out`, as an MMU file if it has the `.mmu` extension and
+/// as an MMB file otherwise. `path` and `source` are the input file, which is used
+/// for the debugging data in the MMB file.
+pub(crate) fn write_output(path: FileRef, source: Option<&LinedString>,
+  env: &FrozenEnv, out: &str
+) -> io::Result<()> {
+  use {fs::File, io::BufWriter};
+  let w = BufWriter::new(File::create(out)?);
+  if out.rsplit('.').next().map_or(false, |ext| ext.eq_ignore_ascii_case("mmu")) {
+    env.export_mmu(w)?;
+  } else {
+    #[cfg(feature = "server")]
+    let file = path.clone();
+    let mut report = move |lvl: ErrorLevel, err: &str| {
+      #[cfg(feature = "server")]
+      if JSON.load(Ordering::Relaxed) {
+        return JsonMessage {
+          file: file.rel(), range: None, level: lvl.to_string(),
+          message: err.to_owned(), related_information: None
+        }.print()
+      }
+      println!("{}\n", DisplayList::from(Snippet {
+        title: Some(Annotation {
+          label: Some(err),
+          id: None,
+          annotation_type: lvl.to_annotation_type(),
+        }),
+        footer: vec![],
+        slices: vec![],
+        opt: FormatOptions { color: true, ..Default::default() },
+      }))
+    };
+    let mut ex = MmbExporter::new(path, source, env, &mut report, w);
+    ex.run(true)?;
+    ex.finish()?;
   }
   Ok(())
-}
\ No newline at end of file
+}
+
+/// Write `text` to a file called `name` in a fresh subdirectory of the system temporary
+/// directory, and return its path. Each call gets a new directory, because the [`VFS`]
+/// caches files by path.
+#[cfg(test)]
+pub(crate) fn test_file(name: &str, text: &[u8]) -> FileRef {
+  use std::sync::atomic::AtomicUsize;
+  static COUNTER: AtomicUsize = AtomicUsize::new(0);
+  let dir = std::env::temp_dir().join(format!("mm0-rs-test-{}-{}",
+    std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+  fs::create_dir_all(&dir).expect("failed to create test directory");
+  let path = dir.join(name);
+  fs::write(&path, text).expect("failed to write test file");
+  fs::canonicalize(path).expect("failed to find test file").into()
+}
+
+/// Elaborate `text` as the file `name` (see [`test_file`]) and return the environment,
+/// panicking if elaboration reports any errors.
+#[cfg(test)]
+pub(crate) fn elab_test_file(name: &str, text: &str) -> FrozenEnv {
+  let path = test_file(name, text.as_bytes());
+  match elab_for_results(&[path]).expect("failed to read test file").pop() {
+    Some(Some((env, false))) => env,
+    _ => panic!("elaboration of {} failed", name),
+  }
+}
@@ -0,0 +1,622 @@
+//! The on-disk elaboration cache used by `mm0-rs compile --cache DIR`.
+//!
+//! Each elaborated file is stored as a serialized [`Environment`] in `DIR`, under a key that
+//! hashes the file contents together with the keys of all of its imports (see [`key`]), so
+//! that a change to a file invalidates the cache entries of everything that depends on it.
+//!
+//! The format is a simple binary serialization of the environment, including all lisp
+//! global data (including user-defined procedures, which are stored as [`Ir`] code along
+//! with their captured environment). Lisp values are deduplicated by pointer, in the same
+//! way as the [`Remapper`](crate::Remapper) does it, so that shared values are restored
+//! faithfully. The contents of references are written at the end of the file, after all
+//! the values they can point to, so that cyclic references can be restored as well.
+//! The [`Spans`](crate::elab::spans::Spans) used for hovers and go-to-definition are not
+//! stored, because the CLI compiler does not use them.
+//! Some values, such as the `mmc` compiler object, cannot be serialized; files with such
+//! values in their environment are not cached.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::fs;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use num::BigInt;
+use crate::elab::lisp::{Annot, BuiltinProc, InferTarget, LispWeak, Proc, ProcPos, ProcSpec, Syntax,
+  parser::{Branch, Ir, MVarPattern, Pattern}};
+use crate::{ArcString, AtomData, AtomId, AtomVec, Coe, DeclKey, Delims, DocComment, Environment,
+  Expr, ExprNode, FileRef, FileSpan, FrozenEnv, LispData, LispKind, LispVal, Literal,
+  MergeStrategyInner, Modifiers, NotaInfo, OutputString, ParserEnv, Prec, Proof,
+  ProofNode, Sort, SortId, Span, StmtTrace, Term, TermId, TermKind, Thm, ThmId, ThmKind, Type};
+
+/// The magic number at the start of every cache file.
+const MAGIC: [u8; 4] = *b"MM1C";
+/// The version of the cache format, which should be bumped whenever the format changes.
+const VERSION: u32 = 2;
+
+/// A 64-bit FNV-1a hash. Unlike [`DefaultHasher`](std::collections::hash_map::DefaultHasher),
+/// the algorithm is fixed, so the keys do not change between runs or Rust versions.
+struct Fnv(u64);
+
+impl Fnv {
+  const fn new() -> Self { Self(0xcbf2_9ce4_8422_2325) }
+
+  fn bytes(&mut self, bs: &[u8]) -> &mut Self {
+    for &b in bs { self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3) }
+    self
+  }
+
+  /// Hash a length-prefixed byte string.
+  fn field(&mut self, bs: &[u8]) -> &mut Self {
+    self.bytes(&(bs.len() as u64).to_le_bytes()).bytes(bs)
+  }
+}
+
+/// Calculate the cache key for a file, given its contents and the keys of its imports.
+/// The key also depends on the build of `mm0-rs` and the `--no-proofs` setting.
+pub(super) fn key(path: &FileRef, text: &[u8], imports: &[u64]) -> u64 {
+  let mut h = Fnv::new();
+  h.field(env!("MM0_RS_BUILD_ID").as_bytes())
+    .bytes(&VERSION.to_le_bytes())
+    .bytes(&[crate::get_check_proofs().into()])
+    .field(path.path().to_string_lossy().as_bytes())
+    .field(text);
+  for k in imports { h.bytes(&k.to_le_bytes()); }
+  h.0
+}
+
+fn cache_path(dir: &Path, key: u64) -> PathBuf { dir.join(format!("{:016x}.mm1c", key)) }
+
+/// Load the environment with key `key` from the cache directory `dir`, if it exists.
+/// Cache files that are corrupted or in an old format are ignored.
+pub(super) fn load(dir: &Path, key: u64) -> Option<FrozenEnv> {
+  let buf = fs::read(cache_path(dir, key)).ok()?;
+  let mut r = Decoder {buf: &buf, files: vec![], lisp: vec![], refs: vec![]};
+  if r.bytes(4).ok()? != MAGIC || r.u32().ok()? != VERSION || r.u64().ok()? != key { return None }
+  r.files = Vec::<PathBuf>::decode(&mut r).ok()?.into_iter().map(FileRef::from).collect();
+  Some(FrozenEnv::new(r.env().ok()?))
+}
+
+/// Store `env` in the cache directory `dir` under key `key`.
+/// Returns `Ok(false)` if the environment has already been shared (so that it cannot be
+/// safely read from this thread), or if it contains values that cannot be cached.
+pub(super) fn store(dir: &Path, key: u64, env: &mut FrozenEnv) -> io::Result<bool> {
+  let env = if let Some(env) = env.get_mut() { env } else { return Ok(false) };
+  let mut w = Encoder::default();
+  w.env(env);
+  if !w.ok { return Ok(false) }
+  fs::create_dir_all(dir)?;
+  // Write to a temporary file and then rename, so that concurrent or interrupted
+  // runs never observe a partially written cache file.
+  let path = cache_path(dir, key);
+  let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+  let mut f = io::BufWriter::new(fs::File::create(&tmp)?);
+  f.write_all(&MAGIC)?;
+  f.write_u32::<LE>(VERSION)?;
+  f.write_u64::<LE>(key)?;
+  let mut header = Encoder::default();
+  w.files.encode(&mut header);
+  f.write_all(&header.buf)?;
+  f.write_all(&w.buf)?;
+  f.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+  fs::rename(tmp, path)?;
+  Ok(true)
+}
+
+/// The state of the serializer.
+#[derive(Default)]
+struct Encoder {
+  /// The output buffer (not including the header and file table).
+  buf: Vec<u8>,
+  /// The file table, indexed by the numbers in `file_idx`.
+  files: Vec<PathBuf>,
+  /// A map from files to indexes in the file table.
+  file_idx: HashMap<FileRef, u32>,
+  /// A map from lisp values (by pointer) to the index of their first occurrence.
+  lisp: HashMap<*const LispKind, u32>,
+  /// The contents of the references that have been encoded so far. These are written
+  /// at the end, after everything else, so that cycles through references can be resolved.
+  refs: Vec<LispVal>,
+  /// False if we encountered a value that cannot be serialized.
+  ok: bool,
+}
+
+impl Encoder {
+  fn u8(&mut self, n: u8) { self.buf.push(n) }
+  fn u32(&mut self, n: u32) { self.buf.write_u32::<LE>(n).expect("writing to a vec") }
+  fn u64(&mut self, n: u64) { self.buf.write_u64::<LE>(n).expect("writing to a vec") }
+  fn usize(&mut self, n: usize) { self.u64(n as u64) }
+  fn bytes(&mut self, s: &[u8]) { self.usize(s.len()); self.buf.extend_from_slice(s) }
+
+  fn env(&mut self, env: &Environment) {
+    self.ok = true;
+    env.data.0.encode(self);
+    env.sorts.0.encode(self);
+    env.pe.encode(self);
+    env.terms.0.encode(self);
+    env.thms.0.encode(self);
+    env.stmts.encode(self);
+    let mut i = 0;
+    while let Some(e) = self.refs.get(i).cloned() {
+      self.lisp(&e);
+      i += 1;
+    }
+  }
+
+  /// Encode a lisp value, or a back-reference to it if it has been seen before.
+  fn lisp(&mut self, e: &LispKind) {
+    let ptr: *const LispKind = e;
+    if let Some(&i) = self.lisp.get(&ptr) { self.u8(0); return self.u32(i) }
+    #[allow(clippy::cast_possible_truncation)]
+    let n = self.lisp.len() as u32;
+    self.lisp.insert(ptr, n);
+    match e {
+      LispKind::Atom(a) => { self.u8(1); a.encode(self) }
+      LispKind::List(es) => { self.u8(2); es.encode(self) }
+      LispKind::DottedList(es, r) => { self.u8(3); es.encode(self); r.encode(self) }
+      LispKind::Annot(Annot::Span(fsp), e) => { self.u8(4); fsp.encode(self); e.encode(self) }
+      LispKind::Number(n) => { self.u8(5); n.encode(self) }
+      LispKind::String(s) => { self.u8(6); s.encode(self) }
+      &LispKind::Bool(b) => { self.u8(7); b.encode(self) }
+      LispKind::Syntax(s) => { self.u8(8); self.bytes(s.to_byte_str()) }
+      LispKind::Undef => self.u8(9),
+      LispKind::Proc(p) => { self.u8(10); p.encode(self) }
+      LispKind::AtomMap(m) => { self.u8(11); m.encode(self) }
+      LispKind::Ref(m) => match &*m.get_weak() {
+        LispWeak::Strong(e) => { self.u8(12); self.refs.push(e.clone()) }
+        LispWeak::Weak(e) if e.strong_count() == 0 => self.u8(13),
+        LispWeak::Weak(_) => { self.u8(14); self.refs.push(m.unref()) }
+      },
+      &LispKind::MVar(n, it) => { self.u8(15); n.encode(self); it.encode(self) }
+      LispKind::Goal(e) => { self.u8(16); e.encode(self) }
+      LispKind::Vector(v) => { self.u8(17); v.encode(self) }
+      LispKind::HashMap(m) => { self.u8(18); m.encode(self) }
+    }
+  }
+}
+
+/// The state of the deserializer.
+struct Decoder<'a> {
+  /// The remainder of the input.
+  buf: &'a [u8],
+  /// The file table.
+  files: Vec<FileRef>,
+  /// The lisp values that have been decoded so far, indexed by order of first occurrence.
+  /// A value of `None` means the value is still being constructed.
+  lisp: Vec<Option<LispVal>>,
+  /// The references whose contents have not yet been read, and whether they are weak.
+  refs: Vec<(LispVal, bool)>,
+}
+
+type Result<T> = io::Result<T>;
+
+fn bad<T>() -> Result<T> { Err(io::ErrorKind::InvalidData.into()) }
+
+impl Decoder<'_> {
+  fn u8(&mut self) -> Result<u8> { self.buf.read_u8() }
+  fn u32(&mut self) -> Result<u32> { self.buf.read_u32::<LE>() }
+  fn u64(&mut self) -> Result<u64> { self.buf.read_u64::<LE>() }
+  fn usize(&mut self) -> Result<usize> { self.u64()?.try_into().or_else(|_| bad()) }
+  fn bytes(&mut self, n: usize) -> Result<&[u8]> {
+    if self.buf.len() < n { return bad() }
+    let (s, rest) = self.buf.split_at(n);
+    self.buf = rest;
+    Ok(s)
+  }
+  fn byte_str(&mut self) -> Result<&[u8]> { let n = self.usize()?; self.bytes(n) }
+
+  fn env(&mut self) -> Result<Environment> {
+    let mut env = Environment::new();
+    env.data = AtomVec(Decode::decode(self)?);
+    env.atoms = env.data.enum_iter().map(|(a, ad)| (ad.name.clone(), a)).collect();
+    env.sorts.0 = Decode::decode(self)?;
+    env.pe = Decode::decode(self)?;
+    env.terms.0 = Decode::decode(self)?;
+    env.thms.0 = Decode::decode(self)?;
+    env.stmts = Decode::decode(self)?;
+    let mut i = 0;
+    while let Some((r, weak)) = self.refs.get(i).cloned() {
+      let e = self.lisp()?;
+      r.as_lref(|m| if weak { m.set_weak(&e) } else { *m.get_mut_weak() = LispWeak::Strong(e) });
+      i += 1;
+    }
+    if !self.buf.is_empty() { return bad() }
+    Ok(env)
+  }
+
+  fn lisp(&mut self) -> Result<LispVal> {
+    let tag = self.u8()?;
+    if tag == 0 {
+      let i = self.u32()? as usize;
+      return self.lisp.get(i).cloned().flatten().map_or_else(bad, Ok)
+    }
+    let n = self.lisp.len();
+    self.lisp.push(None);
+    let e = match tag {
+      1 => LispVal::atom(Decode::decode(self)?),
+      2 => LispVal::list(Box::<[LispVal]>::decode(self)?),
+      3 => {
+        let es = Box::<[LispVal]>::decode(self)?;
+        LispVal::dotted_list(es, self.lisp()?)
+      }
+      4 => {
+        let fsp = FileSpan::decode(self)?;
+        self.lisp()?.span(fsp)
+      }
+      5 => LispVal::number(Decode::decode(self)?),
+      6 => LispVal::string(Decode::decode(self)?),
+      7 => LispVal::bool(Decode::decode(self)?),
+      8 => LispVal::syntax(Syntax::from_bytes(self.byte_str()?).map_or_else(bad, Ok)?),
+      9 => LispVal::undef(),
+      10 => LispVal::proc(Decode::decode(self)?),
+      11 => LispVal::new(LispKind::AtomMap(Decode::decode(self)?)),
+      12 | 14 => {
+        // The contents are filled in at the end, see `Decoder::env`
+        let r = LispVal::new_ref(LispVal::undef());
+        self.refs.push((r.clone(), tag == 14));
+        r
+      }
+      13 => {
+        let r = LispVal::new_ref(LispVal::undef());
+        r.as_lref(|m| *m.get_mut_weak() = LispWeak::Weak(std::rc::Weak::new()));
+        r
+      }
+      15 => LispVal::new(LispKind::MVar(Decode::decode(self)?, Decode::decode(self)?)),
+      16 => LispVal::new(LispKind::Goal(self.lisp()?)),
+      17 => LispVal::new(LispKind::Vector(Decode::decode(self)?)),
+      18 => LispVal::new(LispKind::HashMap(Decode::decode(self)?)),
+      _ => return bad()
+    };
+    self.lisp[n] = Some(e.clone());
+    Ok(e)
+  }
+}
+
+/// A type that can be written to the cache.
+trait Encode {
+  fn encode(&self, w: &mut Encoder);
+}
+
+/// A type that can be read from the cache.
+trait Decode: Sized {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self>;
+}
+
+/// Implements [`Encode`] and [`Decode`] for a type by converting to and from a simpler type.
+macro_rules! via {
+  ($ty:ty: $via:ty, |$x:ident| $enc:expr, |$y:pat_param| $dec:expr) => {
+    impl Encode for $ty {
+      fn encode(&self, w: &mut Encoder) { let $x = self; Encode::encode(&$enc, w) }
+    }
+    impl Decode for $ty {
+      fn decode(r: &mut Decoder<'_>) -> Result<Self> { let $y = <$via>::decode(r)?; $dec }
+    }
+  }
+}
+
+impl Encode for u8 { fn encode(&self, w: &mut Encoder) { w.u8(*self) } }
+impl Decode for u8 { fn decode(r: &mut Decoder<'_>) -> Result<Self> { r.u8() } }
+impl Encode for u32 { fn encode(&self, w: &mut Encoder) { w.u32(*self) } }
+impl Decode for u32 { fn decode(r: &mut Decoder<'_>) -> Result<Self> { r.u32() } }
+impl Encode for u64 { fn encode(&self, w: &mut Encoder) { w.u64(*self) } }
+impl Decode for u64 { fn decode(r: &mut Decoder<'_>) -> Result<Self> { r.u64() } }
+impl Encode for usize { fn encode(&self, w: &mut Encoder) { w.usize(*self) } }
+impl Decode for usize { fn decode(r: &mut Decoder<'_>) -> Result<Self> { r.usize() } }
+via!(bool: u8, |b| u8::from(*b), |n| match n { 0 => Ok(false), 1 => Ok(true), _ => bad() });
+via!(AtomId: u32, |a| a.0, |n| Ok(AtomId(n)));
+via!(SortId: u8, |s| s.0, |n| Ok(SortId(n)));
+via!(TermId: u32, |t| t.0, |n| Ok(TermId(n)));
+via!(ThmId: u32, |t| t.0, |n| Ok(ThmId(n)));
+via!(Modifiers: u8, |m| m.bits(), |n| Modifiers::from_bits(n).map_or_else(bad, Ok));
+via!(Span: (usize, usize), |sp| (sp.start, sp.end), |(start, end)| Ok(Span {start, end}));
+via!(Prec: Option<u32>, |p| match *p { Prec::Prec(n) => Some(n), Prec::Max => None },
+  |p| Ok(p.map_or(Prec::Max, Prec::Prec)));
+via!(ProcSpec: (bool, usize),
+  |sp| match *sp { ProcSpec::Exact(n) => (false, n), ProcSpec::AtLeast(n) => (true, n) },
+  |(b, n)| Ok(if b { ProcSpec::AtLeast(n) } else { ProcSpec::Exact(n) }));
+via!(Delims: Vec<u8>, |d| (0..=u8::MAX).filter(|&c| d.get(c)).collect::<Vec<_>>(),
+  |v| Ok({ let mut d = Delims::default(); for c in v { d.set(c) } d }));
+
+via!(BigInt: Vec<u8>, |n| n.to_signed_bytes_le(), |v| Ok(BigInt::from_signed_bytes_le(&v)));
+via!(ArcString: Vec<u8>, |s| **s, |v| Ok(ArcString::new(v.into())));
+impl Encode for str { fn encode(&self, w: &mut Encoder) { w.bytes(self.as_bytes()) } }
+impl Decode for String {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+    String::from_utf8(r.byte_str()?.to_owned()).or_else(|_| bad())
+  }
+}
+impl Encode for PathBuf {
+  fn encode(&self, w: &mut Encoder) { self.to_str().expect("bad unicode in file path").encode(w) }
+}
+impl Decode for PathBuf {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok(String::decode(r)?.into()) }
+}
+impl Encode for FileRef {
+  fn encode(&self, w: &mut Encoder) {
+    #[allow(clippy::cast_possible_truncation)]
+    let n = w.file_idx.len() as u32;
+    let i = *w.file_idx.entry(self.clone()).or_insert_with(|| {
+      w.files.push(self.path().to_owned());
+      n
+    });
+    w.u32(i)
+  }
+}
+impl Decode for FileRef {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+    let i = r.u32()? as usize;
+    r.files.get(i).cloned().map_or_else(bad, Ok)
+  }
+}
+
+impl<T: Encode> Encode for Option<T> {
+  fn encode(&self, w: &mut Encoder) {
+    match self { None => w.u8(0), Some(e) => { w.u8(1); e.encode(w) } }
+  }
+}
+impl<T: Decode> Decode for Option<T> {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+    match r.u8()? { 0 => Ok(None), 1 => Ok(Some(T::decode(r)?)), _ => bad() }
+  }
+}
+impl<T: Encode> Encode for [T] {
+  fn encode(&self, w: &mut Encoder) { w.usize(self.len()); for e in self { e.encode(w) } }
+}
+impl<T: Encode> Encode for Vec<T> { fn encode(&self, w: &mut Encoder) { (**self).encode(w) } }
+impl<T: Decode> Decode for Vec<T> {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+    let n = r.usize()?;
+    // Don't trust the length for preallocation, in case the file is corrupted
+    let mut v = Vec::with_capacity(n.min(1 << 16));
+    for _ in 0..n { v.push(T::decode(r)?) }
+    Ok(v)
+  }
+}
+impl<T: Encode + ?Sized> Encode for Box<T> { fn encode(&self, w: &mut Encoder) { (**self).encode(w) } }
+impl<T: Decode> Decode for Box<T> { fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok(Box::new(T::decode(r)?)) } }
+impl<T: Decode> Decode for Box<[T]> {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok(Vec::decode(r)?.into()) }
+}
+impl<T: Encode + ?Sized> Encode for Arc<T> { fn encode(&self, w: &mut Encoder) { (**self).encode(w) } }
+impl<T: Decode> Decode for Arc<T> { fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok(Arc::new(T::decode(r)?)) } }
+impl Decode for DocComment { fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok(String::decode(r)?.into()) } }
+impl<T: Encode + ?Sized> Encode for Rc<T> { fn encode(&self, w: &mut Encoder) { (**self).encode(w) } }
+impl<T: Decode> Decode for Rc<T> { fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok(Rc::new(T::decode(r)?)) } }
+impl<A: Encode, B: Encode> Encode for (A, B) {
+  fn encode(&self, w: &mut Encoder) { self.0.encode(w); self.1.encode(w) }
+}
+impl<A: Decode, B: Decode> Decode for (A, B) {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok((A::decode(r)?, B::decode(r)?)) }
+}
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+  fn encode(&self, w: &mut Encoder) { self.0.encode(w); self.1.encode(w); self.2.encode(w) }
+}
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok((A::decode(r)?, B::decode(r)?, C::decode(r)?)) }
+}
+impl<K: Encode, V: Encode, S> Encode for HashMap<K, V, S> {
+  fn encode(&self, w: &mut Encoder) {
+    w.usize(self.len());
+    for (k, v) in self { k.encode(w); v.encode(w) }
+  }
+}
+impl<K: Decode + Hash + Eq, V: Decode> Decode for HashMap<K, V> {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> { Ok(Vec::<(K, V)>::decode(r)?.into_iter().collect()) }
+}
+impl<A: Encode, B: Encode, C: Encode, D: Encode> Encode for (A, B, C, D) {
+  fn encode(&self, w: &mut Encoder) { self.0.encode(w); self.1.encode(w); self.2.encode(w); self.3.encode(w) }
+}
+impl<A: Decode, B: Decode, C: Decode, D: Decode> Decode for (A, B, C, D) {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+    Ok((A::decode(r)?, B::decode(r)?, C::decode(r)?, D::decode(r)?))
+  }
+}
+
+impl Encode for LispVal { fn encode(&self, w: &mut Encoder) { w.lisp(self) } }
+impl Decode for LispVal { fn decode(r: &mut Decoder<'_>) -> Result<Self> { r.lisp() } }
+
+/// Implements [`Encode`] and [`Decode`] for a struct, field by field.
+macro_rules! structs {
+  ($($ty:ident {$($f:ident),*};)*) => {$(
+    impl Encode for $ty {
+      fn encode(&self, w: &mut Encoder) { $(self.$f.encode(w);)* }
+    }
+    impl Decode for $ty {
+      fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+        Ok(Self { $($f: Decode::decode(r)?),* })
+      }
+    }
+  )*}
+}
+
+/// Implements [`Encode`] and [`Decode`] for an enum, using the given tag for each variant.
+macro_rules! enums {
+  ($($ty:ident {$($n:literal: $v:ident $(($($x:ident),*))? $({$($f:ident),*})?,)*})*) => {$(
+    impl Encode for $ty {
+      fn encode(&self, w: &mut Encoder) {
+        match self {$(
+          Self::$v $(($($x),*))? $({$($f),*})? => {
+            w.u8($n); $($($x.encode(w);)*)? $($($f.encode(w);)*)?
+          }
+        )*}
+      }
+    }
+    impl Decode for $ty {
+      fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+        Ok(match r.u8()? {
+          $($n => Self::$v $(($({ let $x = Decode::decode(r)?; $x }),*))?
+            $({$($f: Decode::decode(r)?),*})?,)*
+          _ => return bad()
+        })
+      }
+    }
+  )*}
+}
+
+via!(BuiltinProc: String, |p| *p.to_str(), |s| BuiltinProc::from_str(&s).map_or_else(bad, Ok));
+
+impl Encode for Proc {
+  fn encode(&self, w: &mut Encoder) {
+    match self {
+      Proc::Builtin(p) => { w.u8(0); p.encode(w) }
+      Proc::Lambda {pos, env, spec, code} => {
+        w.u8(1); pos.encode(w); env.encode(w); spec.encode(w); code.encode(w)
+      }
+      Proc::MatchCont(valid) => { w.u8(2); valid.get().encode(w) }
+      Proc::RefineCallback => w.u8(3),
+      Proc::MergeMap(m) => { w.u8(4); m.encode(w) }
+      Proc::ProofThunk(a, e) => {
+        w.u8(5); a.encode(w);
+        match &*e.borrow() {
+          Ok(e) => { w.u8(0); e.encode(w) }
+          Err(es) => { w.u8(1); es.encode(w) }
+        }
+      }
+      #[cfg(feature = "mmc")]
+      Proc::MmcCompiler(_) => w.ok = false,
+    }
+  }
+}
+
+impl Decode for Proc {
+  fn decode(r: &mut Decoder<'_>) -> Result<Self> {
+    Ok(match r.u8()? {
+      0 => Proc::Builtin(Decode::decode(r)?),
+      1 => Proc::Lambda {
+        pos: Decode::decode(r)?, env: Decode::decode(r)?,
+        spec: Decode::decode(r)?, code: Decode::decode(r)?
+      },
+      2 => Proc::MatchCont(Rc::new(Cell::new(Decode::decode(r)?))),
+      3 => Proc::RefineCallback,
+      4 => Proc::MergeMap(Decode::decode(r)?),
+      5 => {
+        let a = Decode::decode(r)?;
+        Proc::ProofThunk(a, RefCell::new(match r.u8()? {
+          0 => Ok(Decode::decode(r)?),
+          1 => Err(Decode::decode(r)?),
+          _ => return bad()
+        }))
+      }
+      _ => return bad()
+    })
+  }
+}
+
+structs! {
+  FileSpan {file, span};
+  Branch {vars, cont, pat, eval};
+  LispData {src, doc, val, merge};
+  AtomData {name, lisp, graveyard, sort, decl};
+  Sort {atom, name, span, full, doc, mods};
+  Expr {heap, head};
+  Term {atom, span, vis, full, doc, args, ret, kind};
+  Proof {heap, hyps, head};
+  Thm {atom, span, vis, full, doc, args, heap, hyps, ret, kind};
+  OutputString {span, heap, exprs};
+  NotaInfo {span, term, nargs, rassoc, lits};
+  ParserEnv {delims_l, delims_r, consts, prec_assoc, prefixes, infixes, coes, coe_prov, decl_nota};
+}
+
+enums! {
+  InferTarget { 0: Unknown, 1: Provable, 2: Bound(a), 3: Reg(a), }
+  ProcPos { 0: Named(fsp, sp, a), 1: Unnamed(fsp), }
+  MergeStrategyInner { 0: AtomMap(m), 1: Custom(f), }
+  Ir {
+    0: Local(i), 1: Global(sp, a), 2: Const(e), 3: List(sp, es), 4: DottedList(es, e),
+    5: App(sp1, sp2, f, es), 6: If(es), 7: Focus(sp, es), 8: SetMergeStrategy(sp, a, e),
+    9: Def(n, x, e), 10: Eval(b, es), 11: NoTailRec, 12: Lambda(sp, n, spec, e),
+    13: Match(sp, e, brs),
+  }
+  Pattern {
+    0: Skip, 1: Atom(i), 2: QuoteAtom(a), 3: String(s), 4: Bool(b), 5: Undef, 6: Number(n),
+    7: MVar(p), 8: Goal(p), 9: DottedList(ps, p), 10: List(ps, n), 11: And(ps), 12: Or(ps),
+    13: Not(ps), 14: Test(sp, f, ps), 15: QExprAtom(a),
+  }
+  MVarPattern { 0: Unknown, 1: Any, 2: Simple(p), }
+  Type { 0: Bound(s), 1: Reg(s, deps), }
+  ExprNode { 0: Ref(i), 1: Dummy(a, s), 2: App(t, es), }
+  TermKind { 0: Term, 1: Def(e), }
+  ProofNode {
+    0: Ref(i), 1: Dummy(a, s), 2: Term {term, args}, 3: Hyp(i, e), 4: Thm {thm, args, res},
+    5: Conv(p), 6: Refl(p), 7: Sym(p), 8: Cong {term, args}, 9: Unfold {term, args, res},
+  }
+  ThmKind { 0: Axiom, 1: Thm(p), }
+  StmtTrace { 0: Sort(a), 1: Decl(a), 2: Global(a), 3: OutputString(s), 4: InputString(s), }
+  DeclKey { 0: Term(t), 1: Thm(t), }
+  Literal { 0: Var(i, p), 1: Const(c), }
+  Coe { 0: One(fsp, t), 1: Trans(c1, s, c2), }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use std::fs;
+  use std::path::PathBuf;
+  use crate::compiler::{elab_keep, test_file};
+  use crate::FrozenEnv;
+  use super::{cache_path, load, store, Encoder, Fnv};
+
+  const TEXT: &str = "delimiter $ ( ) $;\nprovable sort wff;\nterm im: wff > wff > wff;\n\
+    infixr im: $->$ prec 25;\naxiom ax (a b: wff): $ a -> b -> a $;\n\
+    theorem t (a b: wff): $ a -> b -> a $ = 'ax;\n\
+    do {\n  (def r (ref! 1)) (set! r (list r 'x \"s\" #t))\n  (def (f x) (+ x 1))\n\
+      (def m (atom-map! '[a 1])) (def v (vec! 1 2))\n};\n";
+
+  /// Elaborate `TEXT` into an environment that has not been shared yet,
+  /// and return it along with a fresh cache directory.
+  fn elab() -> (FrozenEnv, PathBuf) {
+    let path = test_file("cache.mm1", TEXT.as_bytes());
+    let (_, _, errors, mut elab) = elab_keep(Some(path.clone()), None).unwrap();
+    assert!(errors.is_empty(), "{:?}", errors);
+    let env = FrozenEnv::new(std::mem::take(&mut *elab));
+    (env, path.path().parent().unwrap().join("cache"))
+  }
+
+  fn encode(env: &mut FrozenEnv) -> Vec<u8> {
+    let mut w = Encoder::default();
+    w.env(env.get_mut().unwrap());
+    assert!(w.ok);
+    w.buf
+  }
+
+  #[test]
+  fn round_trip() {
+    let (mut env, dir) = elab();
+    assert!(store(&dir, 1, &mut env).unwrap());
+    let mut env2 = load(&dir, 1).unwrap();
+    assert_eq!(encode(&mut env), encode(&mut env2));
+    let t = env2.data()[env2.get_atom(b"t").unwrap()].decl();
+    assert!(matches!(t, Some(crate::DeclKey::Thm(_))));
+    // a shared environment is not stored
+    let shared = env.clone();
+    assert!(!store(&dir, 2, &mut env).unwrap());
+    drop(shared);
+  }
+
+  #[test]
+  fn bad_files_rejected() {
+    let (mut env, dir) = elab();
+    assert!(store(&dir, 1, &mut env).unwrap());
+    let buf = fs::read(cache_path(&dir, 1)).unwrap();
+    fs::write(cache_path(&dir, 2), &buf[..buf.len() - 1]).unwrap();
+    assert!(load(&dir, 2).is_none(), "truncated file");
+    // A file written by a different build has a different key, which is checked on load
+    // in case the file was renamed or the hash collides
+    fs::write(cache_path(&dir, 3), &buf).unwrap();
+    assert!(load(&dir, 3).is_none(), "stale key");
+    let mut old = buf;
+    old[4] ^= 1;
+    fs::write(cache_path(&dir, 1), &old).unwrap();
+    assert!(load(&dir, 1).is_none(), "old version");
+  }
+
+  #[test]
+  fn fnv_test_vectors() {
+    assert_eq!(Fnv::new().bytes(b"").0, 0xcbf2_9ce4_8422_2325);
+    assert_eq!(Fnv::new().bytes(b"a").0, 0xaf63_dc4c_8601_ec8c);
+    assert_eq!(Fnv::new().bytes(b"foobar").0, 0x8594_4171_f739_67e8);
+  }
+}
@@ -0,0 +1,550 @@
+//! A debugger for MM1 lisp, the `mm0-rs debug-adapter` subcommand.
+//!
+//! The debug adapter talks to the editor on stdin/stdout using the
+//! [Debug Adapter Protocol](https://microsoft.github.io/debug-adapter-protocol/).
+//! On a `launch` request, it elaborates the requested file with a [`Debugger`] attached.
+//! Breakpoints are set on lines, and evaluation pauses before the first function
+//! application evaluated on a line with a breakpoint. While paused, the editor can
+//! inspect the call stack, the local variables in each frame, and the current proof
+//! goals, and step into, over or out of the current application.
+//!
+//! Only the main file is debugged; imported files are elaborated as usual, although
+//! breakpoints in procedures defined in imported files are still hit when the
+//! procedures are called from the main file.
+//!
+//! When the editor disconnects, the session stops pausing and the rest of the file is
+//! elaborated without interruption before the adapter exits.
+
+use std::collections::{HashMap, hash_map::Entry};
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use clap::ArgMatches;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use mm1_parser::parse;
+use crate::elab::lisp::debugger::{DebugHook, DebugState, Debugger};
+use crate::{ErrorLevel, FileRef, FileSpan, LinedString, MutexExt, Position};
+
+/// The single thread reported to the editor.
+const THREAD_ID: u32 = 1;
+/// The variables reference of the "Goals" scope. The "Locals" scope of frame `i`
+/// has reference `i + 2`.
+const GOALS_REF: usize = 1;
+
+/// An incoming DAP message. We only act on messages of type `request`.
+#[derive(Deserialize)]
+struct Request {
+  seq: i64,
+  #[serde(rename = "type")]
+  ty: String,
+  #[serde(default)]
+  command: String,
+  #[serde(default)]
+  arguments: Value,
+}
+
+/// The arguments to the `launch` request.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchArgs {
+  /// The file to elaborate.
+  program: String,
+  /// Disable proof checking until `(check-proofs #t)`.
+  #[serde(default)]
+  no_proofs: bool,
+  /// Pause before the first function application.
+  #[serde(default)]
+  stop_on_entry: bool,
+}
+
+/// Reads a message in the `Content-Length` framing used by DAP, or returns `None` at EOF.
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<Request>> {
+  let mut len = None;
+  loop {
+    let mut line = String::new();
+    if r.read_line(&mut line)? == 0 { return Ok(None) }
+    let line = line.trim_end();
+    if line.is_empty() { break }
+    if let Some(n) = line.strip_prefix("Content-Length:") { len = n.trim().parse().ok() }
+  }
+  let len = len.ok_or_else(||
+    io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header"))?;
+  let mut buf = vec![0; len];
+  r.read_exact(&mut buf)?;
+  Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+/// Returns the (0-based) first and last lines of each statement in `text`.
+/// Breakpoints are verified if they are on one of these lines.
+fn stmt_lines(text: String) -> Vec<(u32, u32)> {
+  let text = Arc::new(LinedString::from(text));
+  let (_, ast) = parse(text.clone(), None);
+  ast.stmts.iter().map(|s| (text.to_pos(s.span.start).line, text.to_pos(s.span.end).line))
+    .collect()
+}
+
+/// When to pause next.
+#[derive(Clone, Copy)]
+enum Mode {
+  /// Pause only at breakpoints.
+  Continue,
+  /// Pause at the next function application.
+  Step,
+  /// Pause at the next function application when the stack depth is at most this value.
+  Until(usize),
+}
+
+/// The state of the debug session.
+struct Session {
+  /// The incoming requests, read on a separate thread.
+  recv: Receiver<Request>,
+  /// The stream the outgoing messages are written to.
+  out: Box<dyn Write + Send>,
+  /// True if the editor has disconnected or closed the connection. We no longer pause
+  /// or wait for requests after this.
+  closed: bool,
+  /// The sequence number of the last message we sent.
+  seq: i64,
+  /// The (0-based) lines with breakpoints, in each file.
+  breakpoints: HashMap<FileRef, Vec<u32>>,
+  /// The file contents, used to map spans to lines. `None` if the file could not be read.
+  files: HashMap<FileRef, Option<LinedString>>,
+  /// When to pause next.
+  mode: Mode,
+  /// The reason for the next pause, if it is caused by `mode`.
+  reason: &'static str,
+  /// The file and line of the last function application, so that we only stop at
+  /// a breakpoint when we enter the line.
+  last_line: Option<(FileRef, u32)>,
+}
+
+impl Session {
+  fn new(recv: Receiver<Request>, out: Box<dyn Write + Send>) -> Self {
+    Self {
+      recv, out, closed: false, seq: 0,
+      breakpoints: HashMap::new(),
+      files: HashMap::new(),
+      mode: Mode::Continue,
+      reason: "entry",
+      last_line: None,
+    }
+  }
+
+  fn send(&mut self, mut msg: Value) {
+    self.seq += 1;
+    msg["seq"] = self.seq.into();
+    let msg = msg.to_string();
+    let msg = format!("Content-Length: {}\r\n\r\n{}", msg.len(), msg);
+    drop(self.out.write_all(msg.as_bytes()).and_then(|_| self.out.flush()))
+  }
+
+  fn respond(&mut self, req: &Request, body: Value) {
+    self.send(json!({"type": "response", "request_seq": req.seq,
+      "success": true, "command": req.command, "body": body}))
+  }
+
+  fn respond_err(&mut self, req: &Request, msg: &str) {
+    self.send(json!({"type": "response", "request_seq": req.seq,
+      "success": false, "command": req.command, "message": msg}))
+  }
+
+  fn event(&mut self, event: &str, body: Value) {
+    self.send(json!({"type": "event", "event": event, "body": body}))
+  }
+
+  /// Waits for the next request, or returns `None` if the session is closed.
+  fn recv(&mut self) -> Option<Request> {
+    if self.closed { return None }
+    let req = self.recv.recv().ok();
+    self.closed = req.is_none();
+    req
+  }
+
+  /// Handles the requests that are valid at any point in the session, and returns
+  /// false if `req` is not one of them.
+  fn handle_common(&mut self, req: &Request) -> bool {
+    match &*req.command {
+      _ if req.ty != "request" => {}
+      "initialize" => {
+        self.respond(req, json!({"supportsConfigurationDoneRequest": true}));
+        self.event("initialized", json!({}))
+      }
+      "setBreakpoints" => {
+        let path = req.arguments["source"]["path"].as_str().unwrap_or_default();
+        let lines: Vec<u32> = req.arguments["breakpoints"].as_array().map_or(vec![], |bps|
+          bps.iter().filter_map(|bp| bp["line"].as_u64()?.checked_sub(1)?.try_into().ok())
+            .collect());
+        let (path, stmts) = match std::fs::canonicalize(path) {
+          Ok(path) => {
+            let stmts = std::fs::read_to_string(&path).map_or(vec![], stmt_lines);
+            (Some(FileRef::from(path)), stmts)
+          }
+          Err(_) => (None, vec![]),
+        };
+        let mut verified = vec![];
+        let bps: Vec<_> = lines.iter().map(|&l| {
+          let ok = stmts.iter().any(|&(start, end)| start <= l && l <= end);
+          if ok { verified.push(l) }
+          json!({"verified": ok, "line": l + 1})
+        }).collect();
+        if let Some(path) = path { self.breakpoints.insert(path, verified); }
+        self.respond(req, json!({"breakpoints": bps}))
+      }
+      "setExceptionBreakpoints" => self.respond(req, json!({})),
+      "threads" => self.respond(req, json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+      "disconnect" | "terminate" => {
+        self.respond(req, json!({}));
+        self.closed = true
+      }
+      _ => return false
+    }
+    true
+  }
+
+  /// Returns the (0-based) position of the start of `loc`.
+  fn pos(&mut self, loc: &FileSpan) -> Option<Position> {
+    let file = match self.files.entry(loc.file.clone()) {
+      Entry::Occupied(e) => e.into_mut(),
+      Entry::Vacant(e) => e.insert(
+        std::fs::read_to_string(loc.file.path()).ok().map(LinedString::from)),
+    };
+    Some(file.as_ref()?.to_pos(loc.span.start))
+  }
+
+  /// Handles requests until the editor sends `launch` and `configurationDone`,
+  /// or returns `None` if the session is closed first.
+  fn configure(&mut self) -> Option<LaunchArgs> {
+    let (mut args, mut configured) = (None, false);
+    while args.is_none() || !configured {
+      let req = self.recv()?;
+      if self.handle_common(&req) { continue }
+      match &*req.command {
+        "launch" => match LaunchArgs::deserialize(&req.arguments) {
+          Ok(a) => { args = Some(a); self.respond(&req, json!({})) }
+          Err(e) => self.respond_err(&req, &format!("invalid launch arguments: {}", e)),
+        },
+        "configurationDone" => { configured = true; self.respond(&req, json!({})) }
+        _ => self.respond_err(&req, "not launched"),
+      }
+    }
+    args
+  }
+
+  /// Handles requests while paused at `state`, until the editor resumes evaluation
+  /// or the session is closed.
+  fn paused(&mut self, state: &DebugState) {
+    while let Some(req) = self.recv() {
+      if self.handle_common(&req) { continue }
+      match &*req.command {
+        "stackTrace" => {
+          let frames: Vec<_> = state.frames.iter().enumerate().map(|(i, f)| {
+            let pos = self.pos(&f.loc);
+            json!({
+              "id": i,
+              "name": f.name,
+              "source": {"name": f.loc.file.rel(), "path": f.loc.file.path()},
+              "line": pos.map_or(0, |p| p.line + 1),
+              "column": pos.map_or(0, |p| p.character + 1),
+            })
+          }).collect();
+          self.respond(&req, json!({"stackFrames": frames, "totalFrames": state.frames.len()}))
+        }
+        "scopes" => {
+          let i = req.arguments["frameId"].as_u64().unwrap_or(0);
+          self.respond(&req, json!({"scopes": [
+            {"name": "Locals", "variablesReference": i + 2, "expensive": false},
+            {"name": "Goals", "variablesReference": GOALS_REF, "expensive": false},
+          ]}))
+        }
+        "variables" => {
+          let r = req.arguments["variablesReference"].as_u64().and_then(|r| r.try_into().ok());
+          let vars = match r {
+            Some(GOALS_REF) => &*state.goals,
+            Some(r) => r.checked_sub(2).and_then(|i| state.frames.get(i))
+              .map_or(&[][..], |f| &*f.locals),
+            None => &[],
+          };
+          let vars: Vec<_> = vars.iter().map(|(name, value)|
+            json!({"name": name, "value": value, "variablesReference": 0})).collect();
+          self.respond(&req, json!({"variables": vars}))
+        }
+        "continue" => {
+          self.mode = Mode::Continue;
+          self.respond(&req, json!({"allThreadsContinued": true}));
+          break
+        }
+        "next" => { self.mode = Mode::Until(state.depth); self.respond(&req, json!({})); break }
+        "stepIn" => { self.mode = Mode::Step; self.respond(&req, json!({})); break }
+        "stepOut" => {
+          self.mode = state.ret_depth.map_or(Mode::Continue, Mode::Until);
+          self.respond(&req, json!({}));
+          break
+        }
+        "pause" => self.respond(&req, json!({})),
+        _ => self.respond_err(&req, "unsupported request"),
+      }
+    }
+    self.reason = "step"
+  }
+
+  /// Handles the requests received while running, and returns true if evaluation
+  /// should pause at `loc`. See [`DebugHook::check`].
+  fn check(&mut self, loc: &FileSpan, depth: usize) -> bool {
+    while !self.closed {
+      match self.recv.try_recv() {
+        Ok(req) => if !self.handle_common(&req) {
+          match &*req.command {
+            "pause" => {
+              self.mode = Mode::Step;
+              self.reason = "pause";
+              self.respond(&req, json!({}))
+            }
+            _ => self.respond_err(&req, "not paused"),
+          }
+        },
+        Err(TryRecvError::Empty) => break,
+        Err(TryRecvError::Disconnected) => self.closed = true,
+      }
+    }
+    if self.closed { return false }
+    let new_line = self.pos(loc).and_then(|Position {line: l, ..}| {
+      let cur = (loc.file.clone(), l);
+      if self.last_line.as_ref() == Some(&cur) { return None }
+      self.last_line = Some(cur);
+      Some(l)
+    });
+    match self.mode {
+      Mode::Step => return true,
+      Mode::Until(d) if depth <= d => return true,
+      _ => {}
+    }
+    if let Some(l) = new_line {
+      if self.breakpoints.get(&loc.file).map_or(false, |bps| bps.contains(&l)) {
+        self.reason = "breakpoint";
+        return true
+      }
+    }
+    false
+  }
+}
+
+/// The [`DebugHook`] for the debug adapter, which forwards to the [`Session`].
+struct DapHook(Arc<Mutex<Session>>);
+
+impl DebugHook for DapHook {
+  fn check(&mut self, loc: &FileSpan, depth: usize) -> bool { self.0.ulock().check(loc, depth) }
+
+  fn pause(&mut self, state: DebugState) {
+    let mut s = self.0.ulock();
+    let reason = s.reason;
+    s.event("stopped", json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true}));
+    s.paused(&state)
+  }
+}
+
+/// Main entry point for the `mm0-rs debug-adapter` subcommand.
+///
+/// This is not intended to be run directly; the editor starts it and communicates
+/// with it on stdin/stdout. The file to debug is passed in the `launch` request.
+pub fn main(_: &ArgMatches<'_>) -> io::Result<()> {
+  crate::compiler::set_silent();
+  let (send, recv) = channel();
+  std::thread::spawn(move || {
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    while let Ok(Some(req)) = read_message(&mut stdin) {
+      if send.send(req).is_err() { break }
+    }
+  });
+  run(Session::new(recv, Box::new(io::stdout())));
+  Ok(())
+}
+
+/// Runs the debug session: waits for the `launch` request, elaborates the program
+/// with the debugger attached, and handles requests until the session is closed.
+fn run(session: Session) {
+  let session = Arc::new(Mutex::new(session));
+  let args = session.ulock().configure();
+  let args = if let Some(args) = args {args} else {return};
+  if args.stop_on_entry { session.ulock().mode = Mode::Step }
+  if args.no_proofs { crate::set_check_proofs(false) }
+  let debugger = Debugger::new(DapHook(session.clone()));
+  let res = std::fs::canonicalize(&args.program)
+    .and_then(|path| crate::compiler::elab_keep(Some(path.into()), Some(debugger)));
+  let mut s = session.ulock();
+  if s.closed { return }
+  let code = match res {
+    Ok((path, text, errors, _)) => {
+      for e in &errors {
+        let pos = text.to_pos(e.pos.start);
+        let output = format!("{}:{}:{}: {}: {}\n",
+          path.rel(), pos.line + 1, pos.character + 1, e.level, e.kind.msg());
+        let category = if e.level == ErrorLevel::Error {"stderr"} else {"console"};
+        s.event("output", json!({"category": category, "output": output}))
+      }
+      i32::from(errors.iter().any(|e| e.level == ErrorLevel::Error))
+    }
+    Err(e) => {
+      let output = format!("{}: {}\n", args.program, e);
+      s.event("output", json!({"category": "stderr", "output": output}));
+      1
+    }
+  };
+  s.event("exited", json!({"exitCode": code}));
+  s.event("terminated", json!({}));
+  while let Some(req) = s.recv() {
+    if !s.handle_common(&req) { s.respond_err(&req, "not running") }
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use std::io::{self, Write};
+  use std::sync::mpsc::{channel, Receiver, Sender};
+  use std::thread::JoinHandle;
+  use std::time::Duration;
+  use serde_json::{json, Value};
+  use crate::compiler::test_file;
+  use super::{run, Request, Session};
+
+  /// The session output, which sends each message to the [`Client`].
+  struct Out(Sender<Value>);
+
+  impl Write for Out {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      let (_, body) = std::str::from_utf8(buf).unwrap().split_once("\r\n\r\n").unwrap();
+      drop(self.0.send(serde_json::from_str(body).unwrap()));
+      Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+  }
+
+  /// The editor side of a session running on another thread.
+  struct Client {
+    seq: i64,
+    send: Sender<Request>,
+    recv: Receiver<Value>,
+    thread: JoinHandle<()>,
+  }
+
+  impl Client {
+    fn start() -> Self {
+      let (send, recv) = channel();
+      let (out, out_recv) = channel();
+      let thread = std::thread::spawn(move || run(Session::new(recv, Box::new(Out(out)))));
+      Self {seq: 0, send, recv: out_recv, thread}
+    }
+
+    /// Sends a request and returns the response, which should be the next message.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+      self.seq += 1;
+      let ty = "request".into();
+      self.send.send(Request {seq: self.seq, ty, command: command.into(), arguments}).unwrap();
+      let resp = self.next();
+      assert_eq!((&resp["type"], &resp["request_seq"]), (&json!("response"), &json!(self.seq)));
+      resp
+    }
+
+    /// The next message sent by the session.
+    fn next(&self) -> Value { self.recv.recv_timeout(Duration::from_secs(30)).unwrap() }
+
+    /// The name and body of the next message, which should be an event.
+    fn event(&self) -> (String, Value) {
+      let msg = self.next();
+      assert_eq!(msg["type"], "event");
+      (msg["event"].as_str().unwrap().to_owned(), msg["body"].clone())
+    }
+
+    /// Waits for the session to finish, and checks that it sent nothing else.
+    fn join(self) {
+      let Self {send, recv, thread, ..} = self;
+      drop(send);
+      thread.join().unwrap();
+      assert_eq!(recv.try_iter().collect::<Vec<_>>(), [] as [Value; 0]);
+    }
+  }
+
+  #[test]
+  fn breakpoints() {
+    let path = test_file("bps.mm1",
+      b"-- comment\ndo {\n  (display 1)\n};\n\ndo { (display 2) };\n");
+    let mut c = Client::start();
+    let resp = c.request("setBreakpoints", json!({
+      "source": {"path": path.path()},
+      "breakpoints": [{"line": 1}, {"line": 3}, {"line": 5}, {"line": 6}, {"line": 9}],
+    }));
+    assert_eq!(resp["success"], true);
+    assert_eq!(resp["body"]["breakpoints"], json!([
+      {"verified": false, "line": 1},
+      {"verified": true, "line": 3},
+      {"verified": false, "line": 5},
+      {"verified": true, "line": 6},
+      {"verified": false, "line": 9},
+    ]));
+    let resp = c.request("setBreakpoints", json!({
+      "source": {"path": "/nonexistent/file.mm1"}, "breakpoints": [{"line": 3}],
+    }));
+    assert_eq!(resp["body"]["breakpoints"], json!([{"verified": false, "line": 3}]));
+    c.join()
+  }
+
+  #[test]
+  fn session() {
+    let path = test_file("session.mm1", b"do {\n  (def (f x)\n    (list x x))\n  (f 1)\n};\n");
+    let mut c = Client::start();
+    let resp = c.request("initialize", json!({}));
+    assert_eq!(resp["body"]["supportsConfigurationDoneRequest"], true);
+    assert_eq!(c.event().0, "initialized");
+    let source = json!({"path": path.path()});
+    c.request("setBreakpoints", json!({"source": source, "breakpoints": [{"line": 3}]}));
+    assert_eq!(c.request("stackTrace", json!({}))["message"], "not launched");
+    assert_eq!(c.request("launch", json!({"program": path.path()}))["success"], true);
+    assert_eq!(c.request("configurationDone", json!({}))["success"], true);
+
+    let (event, body) = c.event();
+    assert_eq!((&*event, &body["reason"]), ("stopped", &json!("breakpoint")));
+    let resp = c.request("stackTrace", json!({"threadId": 1}));
+    let frames = resp["body"]["stackFrames"].as_array().unwrap().iter()
+      .map(|f| (f["name"].as_str().unwrap(), f["line"].as_u64().unwrap())).collect::<Vec<_>>();
+    assert_eq!(frames, [("f", 3), ("[top level]", 4)]);
+    let resp = c.request("scopes", json!({"frameId": 0}));
+    assert_eq!(resp["body"]["scopes"][0]["variablesReference"], 2);
+    let resp = c.request("variables", json!({"variablesReference": 2}));
+    assert_eq!(resp["body"]["variables"],
+      json!([{"name": "x", "value": "1", "variablesReference": 0}]));
+    assert_eq!(c.request("continue", json!({}))["success"], true);
+
+    let (event, body) = c.event();
+    assert_eq!((&*event, &body["category"]), ("output", &json!("console")));
+    assert!(body["output"].as_str().unwrap().ends_with("session.mm1:4:3: info: (1 1)\n"));
+    let (event, body) = c.event();
+    assert_eq!((&*event, &body["exitCode"]), ("exited", &json!(0)));
+    assert_eq!(c.event().0, "terminated");
+    assert_eq!(c.request("continue", json!({}))["message"], "not running");
+    assert_eq!(c.request("disconnect", json!({}))["success"], true);
+    c.join()
+  }
+
+  #[test]
+  fn disconnect_while_paused() {
+    let path = test_file("disconnect.mm1", b"do {\n  (display 1)\n  (display 2)\n};\n");
+    let mut c = Client::start();
+    c.request("launch", json!({"program": path.path(), "stopOnEntry": true}));
+    c.request("configurationDone", json!({}));
+    let (event, body) = c.event();
+    assert_eq!((&*event, &body["reason"]), ("stopped", &json!("entry")));
+    assert_eq!(c.request("disconnect", json!({}))["success"], true);
+    // the rest of the file is elaborated without pausing, and the session ends
+    c.join()
+  }
+
+  #[test]
+  fn closed_before_launch() {
+    let mut c = Client::start();
+    c.request("initialize", json!({}));
+    assert_eq!(c.event().0, "initialized");
+    c.join()
+  }
+}
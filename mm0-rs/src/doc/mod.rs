@@ -8,7 +8,7 @@ use std::fs::{self, File};
 use std::io::{self, BufWriter, Write};
 use std::mem;
 use crate::{lisp::pretty::Annot, ArcString, AtomData, AtomId, DeclKey, DocComment, EnvMergeIter,
-  Environment, ExprNode, FileRef, FormatEnv, LinedString, LispVal, Proof, ProofNode, SliceUninit,
+  Environment, ExprNode, FileRef, FormatEnv, FrozenEnv, LinedString, LispVal, Proof, ProofNode, SliceUninit,
   StmtTrace, TermId, Thm, ThmId, ThmKind, Type};
 
 const PP_WIDTH: usize = 160;
@@ -395,8 +395,14 @@ impl PartialEq for CaseInsensitiveName {
 }
 impl Eq for CaseInsensitiveName {}
 
+/// The order in which the steps of a proof are displayed.
 #[derive(Clone, Copy)]
-enum ProofOrder { Pre, Post }
+pub(crate) enum ProofOrder {
+  /// Display each step before its subproofs.
+  Pre,
+  /// Display each step after its subproofs (the default).
+  Post,
+}
 
 struct BuildDoc<'a, W> {
   thm_folder: PathBuf,
@@ -587,7 +593,7 @@ impl<'a, W: Write> BuildDoc<'a, W> {
       let fe = FormatEnv {source: self.source, env: &self.env};
       match *s {
         StmtTrace::Global(_) |
-        StmtTrace::OutputString(_) => {}
+        StmtTrace::OutputString(_) | StmtTrace::InputString(_) => {}
         StmtTrace::Sort(a) => {
           let ad = &self.env.data[a];
           write!(file, "    <div id=\"")?;
@@ -620,7 +626,7 @@ impl<'a, W: Write> BuildDoc<'a, W> {
               render_doc(&mut file, &td.doc)?;
               write!(file, "      <pre>")?;
               let w = &mut HtmlPrinter::new(fe.env, &mut self.mangler, file, "");
-              fe.pretty(|pr| pr.thm(tid).render_raw(PP_WIDTH, w))?;
+              fe.pretty(|pr| pr.thm(tid, false).render_raw(PP_WIDTH, w))?;
               writeln!(file, "</pre>\n    </div>")?;
               let next = tid.0.checked_add(1).map(ThmId)
                 .filter(|&tid| self.env.thms.get(tid).is_some());
@@ -648,11 +654,36 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   let path: FileRef = fs::canonicalize(path)?.into();
   let (fc, old) = crate::compiler::elab_for_result(path.clone())?;
   let old = old.unwrap_or_else(|| std::process::exit(1));
+  let dir = PathBuf::from(args.value_of("OUTPUT").unwrap_or("doc"));
+  let order = match args.value_of("order") {
+    Some("pre") => ProofOrder::Pre,
+    Some("post") => ProofOrder::Post,
+    _ => unreachable!(),
+  };
+  let base_url = parse_base_url(args.value_of("src"))?;
+  build_docs(&path, fc.ascii(), &old, dir, order, args.value_of("only"), base_url)
+}
+
+/// Parse the base URL for source links, as passed to `--src`. `-` disables source links,
+/// and if `src` is `None` the default URL (the `examples` directory on GitHub) is used.
+pub(crate) fn parse_base_url(src: Option<&str>) -> io::Result<Option<Url>> {
+  match src {
+    Some("-") => Ok(None),
+    src => Ok(Some(Url::parse(src.unwrap_or("https://github.com/digama0/mm0/blob/master/examples/"))
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)),
+  }
+}
+
+/// Write the documentation for `old`, the result of elaborating the file `path` with
+/// contents `source`, to the folder `dir`. If `only` is set, only the pages for the given
+/// (comma separated) theorems are written, otherwise all theorems and the index are written.
+pub(crate) fn build_docs(path: &FileRef, source: &LinedString, old: &FrozenEnv,
+  mut dir: PathBuf, order: ProofOrder, only: Option<&str>, base_url: Option<Url>
+) -> io::Result<()> {
   println!("writing docs");
   let mut env = Environment::new();
   assert!(matches!(
-    EnvMergeIter::new(&mut env, &old, (0..0).into()).next(&mut env, &mut vec![]), Ok(None)));
-  let mut dir = PathBuf::from(args.value_of("OUTPUT").unwrap_or("doc"));
+    EnvMergeIter::new(&mut env, old, (0..0).into()).next(&mut env, &mut vec![]), Ok(None)));
   fs::create_dir_all(&dir)?;
   macro_rules! import {($($str:expr),*) => {$({
     let mut file = dir.to_owned();
@@ -662,12 +693,6 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
     }
   })*}}
   import!("stylesheet.css", "proof.js");
-  let order = match args.value_of("order") {
-    Some("pre") => ProofOrder::Pre,
-    Some("post") => ProofOrder::Post,
-    _ => unreachable!(),
-  };
-  let only = args.value_of("only");
   let index = if only.is_some() {None} else {
     let mut file = dir.clone();
     file.push("index.html");
@@ -675,13 +700,8 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   };
   dir.push("thms");
   fs::create_dir_all(&dir)?;
-  let base_url = match args.value_of("src") {
-    Some("-") => None,
-    src => Some(Url::parse(src.unwrap_or("https://github.com/digama0/mm0/blob/master/examples/"))
-      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
-  };
   let mut bd = BuildDoc {
-    source: fc.ascii(),
+    source,
     base_url, order,
     axuse: AxiomUse::new(&env),
     thm_folder: dir, env, index,
@@ -702,7 +722,7 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
       bd.thm_doc(i.checked_sub(1).map(|j| thms[j]), tid, thms.get(i+1).copied())?;
     }
   } else {
-    bd.write_all(&path, old.stmts())?;
+    bd.write_all(path, old.stmts())?;
   }
   Ok(())
 }
\ No newline at end of file
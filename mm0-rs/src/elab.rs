@@ -15,6 +15,8 @@ pub mod local_context;
 pub mod refine;
 pub mod proof;
 pub mod inout;
+pub mod profile;
+pub mod repl;
 
 
 use std::collections::HashMap;
@@ -25,13 +27,15 @@ use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
 use std::{future::Future, pin::Pin, task::{Context, Poll}};
 use std::time::{Duration, Instant};
 use futures::channel::oneshot::Receiver;
+use serde::Serialize;
 use owning_ref::{ArcRef, OwningRef};
 use spans::Spans;
 use crate::ast::{self, Ast, DeclKind, Delimiter, GenNota, Literal as ALiteral,
   LocalKind, SExpr, SExprKind, SimpleNota, SimpleNotaKind, Stmt, StmtKind};
 use inout::InoutHandlers;
+use profile::Profile;
 use environment::Literal as ELiteral;
-use lisp::LispVal;
+use lisp::{LispVal, debugger::Debugger};
 use local_context::try_get_span_opt;
 use crate::{ArcList, ArcString, AtomId, BoxError, Coe, DeclKey, DocComment, EnvMergeIter,
   Environment, ErrorLevel, Expr, ExprNode, FileRef, FileSpan, FrozenEnv,
@@ -50,13 +54,31 @@ pub enum ElabErrorKind {
   Boxed(BoxError, Option<Vec<(FileSpan, BoxError)>>),
   /// This is an error from a file upstream. The `usize` is the number of
   /// number of upstream errors after the first one.
-  Upstream(FileRef, ArcRef<[ElabError], ElabError>, usize)
+  Upstream(FileRef, ArcRef<[ElabError], ElabError>, usize),
+  /// A reference to a sort, term or theorem which has not been declared. The [`BoxError`]
+  /// is the error message, and the [`ArcString`] is the name that was not found.
+  UnknownIdent(BoxError, ArcString),
+  /// A disjoint variable violation in an application of a theorem. The [`FileSpan`] is the
+  /// name of the applied theorem, and each pair `(x, a)` in the list says that the binder
+  /// `a` of the theorem is substituted with an expression containing the variable
+  /// substituted for the bound variable `x`, so `a` would need to depend on `x`.
+  DisjointVars(BoxError, FileSpan, Box<[(ArcString, ArcString)]>),
+  /// A definition whose value depends on the given bound variables, which are missing from
+  /// the dependencies of its return type.
+  MissingDeps(BoxError, Box<[ArcString]>),
+  /// An unsolved goal or `?` hole in a proof. The [`BoxError`] is the error message, and
+  /// the [`ArcString`] is the name of a hypothesis which proves the goal, if there is one.
+  Hole(BoxError, Option<ArcString>),
 }
 impl ElabErrorKind {
   /// Converts the error message to a [`String`].
   #[must_use] pub fn raw_msg(&self) -> String {
     match self {
-      ElabErrorKind::Boxed(e, _) => format!("{}", e),
+      ElabErrorKind::Boxed(e, _) |
+      ElabErrorKind::UnknownIdent(e, _) |
+      ElabErrorKind::DisjointVars(e, ..) |
+      ElabErrorKind::MissingDeps(e, _) |
+      ElabErrorKind::Hole(e, _) => format!("{}", e),
       ElabErrorKind::Upstream(_, e, _) => e.kind.raw_msg(),
     }
   }
@@ -65,13 +87,13 @@ impl ElabErrorKind {
   #[must_use] pub fn msg(&self) -> String {
     use std::fmt::Write;
     match self {
-      ElabErrorKind::Boxed(e, _) => format!("{}", e),
       &ElabErrorKind::Upstream(ref file, ref e, n) => {
         let mut s = format!("file contains errors:\n{}:{:#x}: {}",
           file, e.pos.start, e.kind.raw_msg());
         if n != 0 { write!(&mut s, "\n + {} more", n).unwrap() }
         s
       }
+      _ => self.raw_msg(),
     }
   }
 
@@ -111,7 +133,7 @@ pub struct ElabError {
   pub pos: Span,
   /// The severity of the error or message
   pub level: ErrorLevel,
-  /// The type of error (currently there is only [`ElabErrorKind::Boxed`])
+  /// The type of error
   pub kind: ElabErrorKind,
 }
 
@@ -130,6 +152,11 @@ impl ElabError {
     ElabError::new(pos, ElabErrorKind::Boxed(e.into(), None))
   }
 
+  /// Make an error for a reference to `name`, which is not a declared sort, term or theorem.
+  pub fn unknown(pos: impl Into<Span>, e: impl Into<BoxError>, name: &ArcString) -> ElabError {
+    ElabError::new(pos, ElabErrorKind::UnknownIdent(e.into(), name.clone()))
+  }
+
   /// Make an elaboration error from a position, a message, and a list of related info
   pub fn with_info(pos: impl Into<Span>, msg: BoxError, v: Vec<(FileSpan, BoxError)>) -> ElabError {
     ElabError::new(pos, ElabErrorKind::Boxed(msg, Some(v)))
@@ -171,7 +198,12 @@ impl From<mm1_parser::ParseError> for ElabError {
 }
 
 impl From<mm0b_parser::ParseError> for ElabError {
-  fn from(e: mm0b_parser::ParseError) -> Self { Self::new_e(0, format!("{:?}", e)) }
+  fn from(e: mm0b_parser::ParseError) -> Self {
+    match e {
+      mm0b_parser::ParseError::StrError(s, pos) => Self::new_e(pos..pos, s),
+      e => Self::new_e(0, format!("{:?}", e)),
+    }
+  }
 }
 
 /// Records the current reporting setting. A report that is suppressed by the reporting mode
@@ -202,12 +234,26 @@ impl ReportMode {
 }
 
 /// A function that gets called on goal view events.
+///
+/// By default the listener is called whenever a proof or `focus` block ends with unsolved
+/// goals. A listener created with [`GoalListener::at`] is instead called once, at the first
+/// tactic boundary at or after the given position in the statement containing it.
 #[allow(clippy::type_complexity)]
-pub struct GoalListener(Box<dyn for<'a> FnMut(&'a Elaborator, &'a str)>);
+pub struct GoalListener {
+  pos: Option<usize>,
+  f: Box<dyn for<'a> FnMut(&'a Elaborator, &'a str)>,
+}
 
 impl GoalListener {
   /// Creates a new [`GoalListener`] from a callback.
-  pub fn new(f: impl for<'a> FnMut(&'a Elaborator, &'a str) + 'static) -> Self { Self(Box::new(f)) }
+  pub fn new(f: impl for<'a> FnMut(&'a Elaborator, &'a str) + 'static) -> Self {
+    Self {pos: None, f: Box::new(f)}
+  }
+
+  /// Creates a [`GoalListener`] that reports the proof state at byte position `pos`.
+  pub fn at(pos: usize, f: impl for<'a> FnMut(&'a Elaborator, &'a str) + 'static) -> Self {
+    Self {pos: Some(pos), f: Box::new(f)}
+  }
 }
 
 impl std::fmt::Debug for GoalListener {
@@ -216,6 +262,28 @@ impl std::fmt::Debug for GoalListener {
   }
 }
 
+/// A named entry in a [`GoalState`], such as a hypothesis or a metavariable.
+#[derive(Debug, Serialize)]
+pub struct GoalHyp {
+  /// The name of the hypothesis or metavariable.
+  pub name: String,
+  /// The pretty printed type.
+  #[serde(rename = "type")]
+  pub ty: String,
+}
+
+/// A snapshot of the proof state, with all expressions pretty printed,
+/// for rendering in a goal view. See [`Elaborator::goal_state`].
+#[derive(Debug, Serialize)]
+pub struct GoalState {
+  /// The hypotheses and subproofs in the local context, in order.
+  pub hyps: Vec<GoalHyp>,
+  /// The goals, in order. Tactics apply to the first goal.
+  pub goals: Vec<String>,
+  /// The unassigned metavariables, with their sorts.
+  pub mvars: Vec<GoalHyp>,
+}
+
 /// The [`Elaborator`] struct contains the working data for elaboration, and is the
 /// main interface to MM1 operations (along with [`Evaluator`](lisp::eval::Evaluator),
 /// which a lisp execution context).
@@ -256,6 +324,10 @@ pub struct Elaborator {
   arena: lisp::LispArena,
   /// A listener for goal view events.
   recv_goal: Option<GoalListener>,
+  /// The profiling data, if profiling is enabled.
+  profile: Option<Profile>,
+  /// The attached lisp debugger, if any.
+  debugger: Option<Debugger>,
 }
 
 impl Deref for Elaborator {
@@ -301,9 +373,31 @@ impl Elaborator {
       reporting: ReportMode::new(),
       arena: Default::default(),
       recv_goal,
+      profile: None,
+      debugger: None,
     }
   }
 
+  /// Elaborates the statement of `ast` containing the position `pos` again, starting from
+  /// `env`, the result of a previous elaboration of the same [`Ast`], and reports the proof
+  /// state at `pos` to `recv_goal`. This is used by the server to produce the goal view
+  /// without elaborating the whole file. Because `env` is the environment at the end of the
+  /// file, declarations that come after the statement are also visible to it.
+  pub fn elab_stmt_at(ast: &Arc<Ast>, path: FileRef, env: &FrozenEnv, pos: usize,
+    recv_goal: GoalListener,
+  ) {
+    let s = if let Some(s) = ast.stmts.iter().find(|s| s.span.contains(&pos)) {s} else {return};
+    let mm0_mode = path.has_extension("mm0");
+    let mut elab = Elaborator::new(ast.clone(), path, mm0_mode, true,
+      Arc::default(), Some(recv_goal));
+    elab.arena.install_thread_local();
+    // Merging into an empty environment can't produce any conflicts, and the errors of
+    // this run are discarded anyway, since the caller only wants the goals
+    drop(EnvMergeIter::new(&mut elab.env, env, s.span).next(&mut elab.env, &mut vec![]));
+    drop(elab.elab_stmt(String::new(), s, s.span));
+    lisp::LispArena::uninstall_thread_local();
+  }
+
   fn span(&self, s: Span) -> &[u8] { self.ast.span(s) }
 
   /// Converts a [`Span`] in the current elaboration file to a [`FileSpan`].
@@ -320,12 +414,26 @@ impl Elaborator {
   }
 
   fn call_goal_listener(&mut self, stat: &str) {
-    if let Some(mut listener) = self.recv_goal.take() {
-      listener.0(self, stat);
+    if matches!(self.recv_goal, Some(GoalListener {pos: None, ..})) {
+      let mut listener = self.recv_goal.take().expect("impossible");
+      (listener.f)(self, stat);
       self.recv_goal = Some(listener);
     }
   }
 
+  /// Called at each tactic boundary, where `next` is the position of the next tactic
+  /// (or the end of the enclosing block). This triggers a [`GoalListener::at`] listener
+  /// whose position is in the current statement and not after `next`.
+  fn goal_boundary(&mut self, next: usize) {
+    if let Some(GoalListener {pos: Some(pos), ..}) = self.recv_goal {
+      if pos <= next && self.spans.stmt().contains(&pos) {
+        let mut listener = self.recv_goal.take().expect("impossible");
+        let stat = self.stat();
+        (listener.f)(self, &stat);
+      }
+    }
+  }
+
   fn name_of(&mut self, stmt: &Stmt) -> LispVal {
     match &stmt.k {
       StmtKind::Annot(_, s) => self.name_of(s),
@@ -648,6 +756,10 @@ pub struct ElaborateBuilder<'a, F> {
   /// to transfer an [`Environment`] containing the elaborated theorems, as well as any
   /// extra data `T`, which is collected and passed through the function.
   pub recv_goal: Option<GoalListener>,
+  /// If true, the time spent on each statement is recorded, and returned in a [`Profile`].
+  pub profile: bool,
+  /// A debugger to attach to lisp evaluation in this file (but not its imports).
+  pub debugger: Option<Debugger>,
 }
 
 impl<'a, T: Send, F> ElaborateBuilder<'a, F>
@@ -656,18 +768,49 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
   ///
   /// # Returns
   ///
-  /// A [`Future`] which returns `(cyc, toks, errs, env)` with
+  /// A [`Future`] which returns `(cyc, toks, errs, env, profile)` with
   ///
   /// - `cyc`: An import cycle that forced this elaboration to halt, if one was found.
   /// - `toks`: The accumulated `T` values passed from `mk` (in the order that `import` statements
   ///   appeared in the file)
   /// - `errs`: The elaboration errors found
   /// - `env`: The final environment
+  /// - `profile`: The profiling data, if [`profile`](Self::profile) is set
   ///
   /// If elaboration of an individual statement fails, the error is pushed and then elaboration
   /// continues at the next statement, so the overall elaboration process cannot fail and an
   /// environment is always produced.
-  pub fn elab(self) -> impl Future<Output=(Option<ArcList<FileRef>>, Vec<T>, Vec<ElabError>, FrozenEnv)> + Send {
+  #[allow(clippy::type_complexity)]
+  pub fn elab(self) -> impl Future<Output=(
+    Option<ArcList<FileRef>>, Vec<T>, Vec<ElabError>, FrozenEnv, Option<Profile>
+  )> + Send {
+    self.elab_with(|cyc, toks, elab| {
+      elab.arena.clear();
+      (cyc, toks, elab.errors, FrozenEnv::new(elab.env), elab.profile)
+    })
+  }
+
+  /// Like [`elab`](Self::elab), but the [`Elaborator`] is returned in its final state
+  /// instead of being frozen, so that it can be used to evaluate further lisp expressions
+  /// in the resulting environment. This is used by the [`repl`] subcommand.
+  ///
+  /// The future returns `(cyc, toks, errs, elab)`, where `cyc`, `toks` and `errs` are
+  /// as in [`elab`](Self::elab).
+  #[allow(clippy::type_complexity)]
+  pub fn elab_keep(self) -> impl Future<Output=(
+    Option<ArcList<FileRef>>, Vec<T>, Vec<ElabError>, Elaborator
+  )> {
+    self.elab_with(|cyc, toks, mut elab| {
+      let errs = mem::take(&mut elab.errors);
+      (cyc, toks, errs, elab)
+    })
+  }
+
+  /// The implementation of [`elab`](Self::elab) and [`elab_keep`](Self::elab_keep).
+  /// The `finish` function is called on the elaborator once all statements are done.
+  fn elab_with<R>(self,
+    finish: fn(Option<ArcList<FileRef>>, Vec<T>, Elaborator) -> R
+  ) -> impl Future<Output=R> + Send {
 
     type ImportMap<D> = HashMap<Span, (FileRef, D)>;
     struct FrozenElaborator(Elaborator);
@@ -689,12 +832,15 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
       progress: UnfinishedStmt<T>
     }
 
-    struct ElabFuture<T>(Option<ElabFutureInner<T>>);
+    struct ElabFuture<T, R>(
+      Option<ElabFutureInner<T>>,
+      fn(Option<ArcList<FileRef>>, Vec<T>, Elaborator) -> R,
+    );
 
-    impl<T> Future for ElabFuture<T> {
-      type Output = (Option<ArcList<FileRef>>, Vec<T>, Vec<ElabError>, FrozenEnv);
-      fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = &mut unsafe { self.get_unchecked_mut() }.0;
+    impl<T, R> Future for ElabFuture<T, R> {
+      type Output = R;
+      fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let ElabFuture(this, finish) = unsafe { self.get_unchecked_mut() };
         let ElabFutureInner {
           elab: FrozenElaborator(elab),
           cyc, toks, recv, idx, progress, report_upstream_errors
@@ -760,7 +906,10 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
           let ast = elab.ast.clone();
           while let Some(s) = ast.stmts.get(*idx) {
             if elab.cancel.load(Ordering::Relaxed) {break}
-            match elab.elab_stmt(String::new(), s, s.span) {
+            let start = elab.profile_start(s);
+            let res = elab.elab_stmt(String::new(), s, s.span);
+            elab.profile_finish(start);
+            match res {
               Ok(ElabStmt::Ok) => {}
               Ok(ElabStmt::Import(sp)) => {
                 if let Some((file, recv)) = recv.remove(&sp) {
@@ -780,8 +929,7 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
         lisp::LispArena::uninstall_thread_local();
         let ElabFutureInner {elab: FrozenElaborator(elab), cyc, toks, ..} =
           this.take().expect("impossible");
-        elab.arena.clear();
-        Poll::Ready((cyc, toks, elab.errors, FrozenEnv::new(elab.env)))
+        Poll::Ready(finish(cyc, toks, elab))
       }
     }
 
@@ -789,6 +937,8 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
     let mut recv = HashMap::new();
     let mut elab = Elaborator::new(self.ast.clone(),
       self.path, self.mm0_mode, self.check_proofs, self.cancel, self.recv_goal);
+    if self.profile { elab.profile = Some(Profile::default()) }
+    elab.debugger = self.debugger;
     elab.arena.install_thread_local();
     for &(sp, ref f) in &self.ast.imports {
       (|| -> Result<_> {
@@ -809,6 +959,78 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
       recv,
       idx: 0,
       progress: UnfinishedStmt::None,
-    }))
+    }), finish)
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::wildcard_imports)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn elab_stmt_at_reports_goals() {
+    let text = "delimiter $ ( ) $;\n\
+      provable sort wff;\n\
+      term im (a b: wff): wff; infixr im: $->$ prec 25;\n\
+      axiom mp (a b: wff): $ a -> b $ > $ a $ > $ b $;\n\
+      theorem t (a b: wff) (h1: $ a -> b $) (h2: $ a $): $ b $ =\n\
+      (focus (refine '(mp h1 _)) 'h2);\n";
+    let env = crate::compiler::elab_test_file("goals.mm1", text);
+    let path = crate::compiler::test_file("goals.mm1", text.as_bytes());
+    let ast = Arc::new(mm1_parser::parse(Arc::new(text.to_owned().into()), None).1);
+    let pos = text.find("(refine").unwrap();
+    let res = Arc::new(std::sync::Mutex::new(None));
+    Elaborator::elab_stmt_at(&ast, path, &env, pos, GoalListener::at(pos, {
+      let res = res.clone();
+      move |elab: &Elaborator, _| *res.lock().unwrap() = Some(elab.goal_state())
+    }));
+    let state = res.lock().unwrap().take().expect("no goals reported");
+    assert_eq!(state.hyps.iter().map(|h| &*h.name).collect::<Vec<_>>(), ["h1", "h2"]);
+    assert_eq!(state.goals, ["b"]);
+  }
+
+  fn elab_errors(text: &str) -> Vec<ElabError> {
+    let ast = Arc::new(mm1_parser::parse(Arc::new(text.to_owned().into()), None).1);
+    futures::executor::block_on(ElaborateBuilder {
+      ast: &ast,
+      path: crate::compiler::test_file("errors.mm1", text.as_bytes()),
+      mm0_mode: false,
+      check_proofs: true,
+      report_upstream_errors: false,
+      cancel: Arc::default(),
+      old: None,
+      recv_dep: |_| -> Result<Receiver<ElabResult<()>>, BoxError> { Err("unexpected import".into()) },
+      recv_goal: None,
+      profile: false,
+      debugger: None,
+    }.elab()).2
+  }
+
+  #[test]
+  fn error_kinds() {
+    let text = "delimiter $ ( ) $;\n\
+      provable sort wff; sort set;\n\
+      term al {x: set} (a: wff x): wff;\n\
+      axiom ax {x: set} (a: wff): $ al x a $;\n\
+      theorem t1 (a: wff): $ a $ = 'foo;\n\
+      def d {x: set} (a: wff x): wff = $ a $;\n\
+      theorem t2 {x: set} (a: wff x): $ al x a $ = 'ax;\n\
+      theorem t3 (a: wff) (h: $ a $): $ a $ = '?;\n";
+    let errs = elab_errors(text);
+    assert_eq!(errs.len(), 4, "{:?}", errs);
+    assert!(matches!(&errs[0].kind, ElabErrorKind::UnknownIdent(_, n) if **n == *b"foo"), "{}", errs[0].kind.msg());
+    match &errs[1].kind {
+      ElabErrorKind::MissingDeps(_, vars) => assert_eq!(vars.iter().map(ArcString::as_str).collect::<Vec<_>>(), ["x"]),
+      k => panic!("unexpected error {}", k.msg()),
+    }
+    match &errs[2].kind {
+      ElabErrorKind::DisjointVars(_, fsp, missing) => {
+        assert_eq!(&text[fsp.span.start..fsp.span.end], "ax");
+        assert_eq!(missing.iter().map(|(x, a)| (x.as_str(), a.as_str())).collect::<Vec<_>>(), [("x", "a")]);
+      }
+      k => panic!("unexpected error {}", k.msg()),
+    }
+    assert_eq!(errs[3].kind.msg(), "|- a");
+    assert!(matches!(&errs[3].kind, ElabErrorKind::Hole(_, Some(h)) if **h == *b"h"));
+  }
+}
@@ -269,15 +269,16 @@ pub struct Thm {
   pub kind: ThmKind,
 }
 
-/// An `output string` directive, which is anonymous and hence stored directly
-/// in the [`StmtTrace`] list.
+/// An `output string` or `input string` directive, which is anonymous and hence
+/// stored directly in the [`StmtTrace`] list.
 #[derive(Clone, Debug, DeepSizeOf)]
 pub struct OutputString {
   /// The span of the full statement.
   pub span: FileSpan,
   /// The heap of expressions used in the `exprs`.
   pub heap: Box<[ExprNode]>,
-  /// The expressions to output.
+  /// The expressions to output (or for `input string`, whose concatenation
+  /// should be the text of the MM0 file).
   pub exprs: Box<[ExprNode]>,
 }
 
@@ -292,7 +293,9 @@ pub enum StmtTrace {
   /// A global lisp declaration in a `do` block, i.e. `do { (def foo 1) };`
   Global(AtomId),
   /// An `output string` directive.
-  OutputString(Box<OutputString>)
+  OutputString(Box<OutputString>),
+  /// An `input string` directive.
+  InputString(Box<OutputString>),
 }
 
 /// A declaration is either a [`Term`] or a [`Thm`]. This is done because in MM1
@@ -497,6 +500,12 @@ pub enum ObjectKind {
   RefineSyntax(RefineSyntax),
   /// This is an import; hovering does nothing and go-to-definition goes to the file
   Import(FileRef),
+  /// These are the binders of a theorem application which were inferred by `refine`,
+  /// with their values; they are shown as inlay hints after the theorem name
+  InferredArgs(Box<[(AtomId, FrozenLispVal)]>),
+  /// This is the inferred statement of a `have` step;
+  /// it is shown as an inlay hint after the subproof name
+  HaveType(FrozenLispVal),
 }
 
 impl ObjectKind {
@@ -514,6 +523,20 @@ impl ObjectKind {
   #[must_use] pub fn proof(e: LispVal) -> ObjectKind {
     ObjectKind::Proof(unsafe {FrozenLispVal::new(e)})
   }
+  /// Create an [`ObjectKind`] for the [`InferredArgs`](Self::InferredArgs) of an application.
+  /// # Safety
+  /// Because this function calls [`FrozenLispVal::new`],
+  /// the resulting object must not be examined before the elaborator is frozen.
+  #[must_use] pub fn inferred_args(args: Vec<(AtomId, LispVal)>) -> ObjectKind {
+    ObjectKind::InferredArgs(args.into_iter().map(|(a, e)| (a, unsafe {FrozenLispVal::new(e)})).collect())
+  }
+  /// Create an [`ObjectKind`] for the [`HaveType`](Self::HaveType) of a `have` step.
+  /// # Safety
+  /// Because this function calls [`FrozenLispVal::new`],
+  /// the resulting object must not be examined before the elaborator is frozen.
+  #[must_use] pub fn have_type(e: LispVal) -> ObjectKind {
+    ObjectKind::HaveType(unsafe {FrozenLispVal::new(e)})
+  }
 }
 
 /// The main environment struct, containing all permanent data to be exported from an MM1 file.
@@ -1231,6 +1254,7 @@ impl Environment {
       },
       StmtTrace::Global(_) => {}
       StmtTrace::OutputString(ref e) => self.stmts.push(StmtTrace::OutputString(e.remap(remap))),
+      StmtTrace::InputString(ref e) => self.stmts.push(StmtTrace::InputString(e.remap(remap))),
     }
     Ok(())
   }
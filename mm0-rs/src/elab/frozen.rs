@@ -62,6 +62,11 @@ impl FrozenEnv {
   /// [`Rc::clone()`] should be avoided because it could race with other readers.
   #[must_use] pub unsafe fn thaw(&self) -> &Environment { &self.0 }
 
+  /// Get the underlying [`Environment`], if this is the only reference to it.
+  /// Because the environment has not been shared with other threads, it is safe to
+  /// use in the usual (thread-unsafe) way.
+  pub fn get_mut(&mut self) -> Option<&mut Environment> { Arc::get_mut(&mut self.0) }
+
   /// Create a [`FormatEnv`] object, which can be used to print objects.
   /// # Safety
   /// TODO: this gives out an `&Environment`, even though it is frozen. Don't abuse it
@@ -366,6 +371,9 @@ impl Remap for FrozenLispKind {
       FrozenLispKind::Annot(sp, m) => LispVal::new(LispKind::Annot(sp.clone(), m.remap(r))),
       FrozenLispKind::Proc(f) => LispVal::proc(f.remap(r)),
       FrozenLispKind::AtomMap(m) => LispVal::new(LispKind::AtomMap(m.remap(r))),
+      FrozenLispKind::Vector(v) => LispVal::new(LispKind::Vector(v.remap(r))),
+      FrozenLispKind::HashMap(m) => LispVal::new(LispKind::HashMap(
+        m.iter().map(|(k, v)| (k.remap(r), v.remap(r))).collect())),
       FrozenLispKind::Ref(m) => match r.refs.entry(m as *const _) {
         Entry::Occupied(e) => e.get().clone(),
         Entry::Vacant(e) => {
@@ -3,7 +3,8 @@
 use std::io;
 use super::proof::{Dedup, NodeHasher, ProofKind, build};
 use crate::{DeclKey, SortId, TermId, Type, Expr, ExprNode,
-  TermKind, OutputString, StmtTrace, Environment, FileSpan, BoxError};
+  TermKind, OutputString, StmtTrace, Environment, FileSpan, BoxError, ArcString};
+use crate::ast::{Atom, SExprKind};
 use super::{ElabError, Elaborator, Span, HashMap, Result as EResult, SExpr,
   lisp::{InferTarget, LispVal}, local_context::try_get_span, FrozenEnv};
 
@@ -321,6 +322,17 @@ impl Environment {
   }
 }
 
+impl Environment {
+  /// Evaluate the expressions of an `output string` or `input string` directive in this
+  /// environment, unfolding all definitions, and return the resulting string.
+  pub(crate) fn eval_string_directive(&self, os: &OutputString) -> Result<Vec<u8>, OutputError> {
+    let (_, terms) = self.new_string_handler().map_err(OutputError::String)?;
+    let mut w = StringWriter::default();
+    self.write_output_string(&terms, &mut w, &os.heap, &os.exprs)?;
+    Ok(w.w)
+  }
+}
+
 impl Elaborator {
   fn get_string_handler(&mut self, sp: Span) -> EResult<(Sorts, &mut HashMap<TermId, InoutStringType>)> {
     if self.inout.string.is_none() {
@@ -330,12 +342,23 @@ impl Elaborator {
     let_unchecked!(Some((s, map)) = &mut self.inout.string, Ok((*s, map)))
   }
 
-  fn elab_output_string(&mut self, sp: Span, hs: &[SExpr]) -> EResult<()> {
+  /// Elaborate the arguments to an `output string` or `input string` command,
+  /// which are math expressions of type `string`.
+  fn elab_string_directive(&mut self, sp: Span, hs: &[SExpr]) -> EResult<OutputString> {
     let (sorts, _) = self.get_string_handler(sp)?;
     let fsp = self.fspan(sp);
     let mut es = Vec::with_capacity(hs.len());
     for f in hs {
-      let e = self.eval_lisp(f)?;
+      // A bare identifier refers to a term or definition, as in `input string: foo;`
+      let e = if matches!(f.k, SExprKind::Atom(Atom::Ident)) {
+        let name = self.ast.span(f.span);
+        if let Some(&a) = self.env.atoms.get(name) { LispVal::atom(a) } else {
+          let name = ArcString::new(name.into());
+          return Err(ElabError::unknown(f.span, format!("unknown term '{}'", name), &name))
+        }
+      } else {
+        self.eval_lisp(f)?
+      };
       let val = self.elaborate_term(f.span, &e,
         InferTarget::Reg(self.sorts[sorts.str].atom))?;
       let s = self.infer_sort(sp, &val)?;
@@ -351,8 +374,22 @@ impl Elaborator {
       .collect::<EResult<Vec<_>>>()?;
     let (mut ids, heap) = build(&de);
     let exprs = is.into_iter().map(|i| ids[i].take()).collect();
-    self.stmts.push(StmtTrace::OutputString(
-      Box::new(OutputString {span: fsp, heap, exprs})));
+    Ok(OutputString {span: fsp, heap, exprs})
+  }
+
+  fn elab_output_string(&mut self, sp: Span, hs: &[SExpr]) -> EResult<()> {
+    let os = self.elab_string_directive(sp, hs)?;
+    self.stmts.push(StmtTrace::OutputString(Box::new(os)));
+    Ok(())
+  }
+
+  /// Elaborate an `input string` command. The string usually refers to definitions
+  /// that are only given a value in the proof file, so it is not checked here; instead the
+  /// verifier checks that it evaluates to the text of the MM0 file (see
+  /// [`verify_file`](crate::mmb::verify::verify_file)).
+  fn elab_input_string(&mut self, sp: Span, hs: &[SExpr]) -> EResult<()> {
+    let os = self.elab_string_directive(sp, hs)?;
+    self.stmts.push(StmtTrace::InputString(Box::new(os)));
     Ok(())
   }
 
@@ -398,11 +435,13 @@ impl Elaborator {
     }
   }
 
-  /// Elaborate an `input` command. This is not implemented, as it needs to work with the
-  /// final MM0 file, which is not available. More design work is needed.
-  #[allow(clippy::unused_self)]
-  pub fn elab_input(&mut self, _: Span, kind: Span, _: &[SExpr]) -> EResult<()> {
-    Err(ElabError::new_e(kind, "unsupported input kind"))
+  /// Elaborate an `input` command. Currently only `input string` is supported,
+  /// see [`elab_input_string`](Self::elab_input_string).
+  pub fn elab_input(&mut self, sp: Span, kind: Span, hs: &[SExpr]) -> EResult<()> {
+    match self.span(kind) {
+      b"string" => self.elab_input_string(sp, hs),
+      _ => Err(ElabError::new_e(kind, "unsupported input kind")),
+    }
   }
 }
 
@@ -428,4 +467,21 @@ impl FrozenEnv {
     }
     Ok(())
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use crate::compiler::{elab_keep, test_file};
+
+  #[test]
+  fn unknown_ident_in_string_directive() {
+    let text = include_str!("../../../examples/string.mm0")
+      .replace("output string: hello;", "output string: hallo;");
+    let path = test_file("string.mm0", text.as_bytes());
+    let (_, _, errors, elab) = elab_keep(Some(path), None).unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind.msg(), "unknown term 'hallo'");
+    // the name is not interned by the lookup
+    assert!(!elab.atoms.contains_key(&b"hallo"[..]));
+  }
+}
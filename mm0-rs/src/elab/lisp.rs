@@ -7,11 +7,13 @@
 pub mod parser;
 pub mod eval;
 pub mod debug;
+pub mod debugger;
 pub mod print;
 pub mod pretty;
 
 use std::ops::{Deref, DerefMut};
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::rc::{Rc, Weak};
 use std::cell::{Cell, RefCell};
 use std::sync::{Arc, Mutex};
@@ -34,7 +36,7 @@ macro_rules! str_enum {
   (@inner $to_str:expr, $from_str:expr, $from_bytes:expr;
       $(#[$doc:meta])* enum $name:ident {$($(#[doc=$doc2:expr])* $(#[cfg($($cfgs:tt)*)])* $e:ident: $s:expr,)*}) => {
     $(#[$doc])*
-    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
     pub enum $name { $($(#[doc=$doc2])* $(#[cfg($($cfgs)*)])* $e),* }
     crate::deep_size_0!($name);
 
@@ -101,6 +103,9 @@ str_enum! {
     /// and then closes the goal.
     Focus: "focus",
     /// `let`, aka `let*` in other lisps: define a sequence of variable declarations.
+    /// The named let `(let loop ([x e]) exprs)` defines a local recursive function `loop`
+    /// and calls it on the initial values. Like any other tail call, a call to `loop` in
+    /// tail position of `exprs` does not grow the stack.
     Let: "let",
     /// `letrec`: define a set of mutually recursive variable declarations.
     Letrec: "letrec",
@@ -276,6 +281,14 @@ macro_rules! mk_lisp_kind {
       /// A map from atoms to values. This can be used as a mutable map if it is behind a
       /// [`Ref`](Self::Ref).
       AtomMap(HashMap<AtomId, $val>),
+      /// A vector of values. This can be used as a mutable vector if it is behind a
+      /// [`Ref`](Self::Ref).
+      Vector(Vec<$val>),
+      /// A map from arbitrary hashable values to values. This can be used as a mutable
+      /// map if it is behind a [`Ref`](Self::Ref). Keys are compared structurally, like
+      /// `==`, and they may not contain mutable references, procedures or other
+      /// objects that have only reference equality (see [`LispKind::is_hashable`]).
+      HashMap(HashMap<$val, $val>),
       /// A mutable reference. This is the only way to have mutable values in
       /// client code.
       Ref($ref_),
@@ -672,6 +685,28 @@ impl LispKind {
   pub fn is_map(&self) -> bool {
     self.unwrapped(|e| matches!(e, LispKind::AtomMap(_)))
   }
+  /// Returns true if this value is a vector.
+  pub fn is_vec(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::Vector(_)))
+  }
+  /// Returns true if this value is a hash map.
+  pub fn is_hash_map(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::HashMap(_)))
+  }
+  /// Returns true if this value can be used as a key in a hash map. Keys are built from
+  /// atoms, numbers, strings, booleans, syntax and `#undef` using lists, and
+  /// may not contain mutable references.
+  pub fn is_hashable(&self) -> bool {
+    match self {
+      LispKind::Atom(_) | LispKind::Number(_) | LispKind::String(_) |
+      LispKind::Bool(_) | LispKind::Syntax(_) | LispKind::Undef => true,
+      LispKind::List(es) => es.iter().all(|e| e.is_hashable()),
+      LispKind::DottedList(es, r) => es.iter().all(|e| e.is_hashable()) && r.is_hashable(),
+      LispKind::Annot(_, e) => e.is_hashable(),
+      LispKind::Proc(_) | LispKind::AtomMap(_) | LispKind::Vector(_) | LispKind::HashMap(_) |
+      LispKind::Ref(_) | LispKind::MVar(..) | LispKind::Goal(_) => false,
+    }
+  }
   /// Returns true if this value is not `#undef` or a reference to `#undef`.
   pub fn is_def(&self) -> bool {
     self.unwrapped(|e| !matches!(e, LispKind::Undef))
@@ -833,12 +868,46 @@ impl PartialEq<LispKind> for LispKind {
           }
         }
       }
-      _ => false // Goal, Proc, MVar, AtomMap all have only reference equality
+      _ => false // Goal, Proc, MVar, AtomMap, Vector, HashMap all have only reference equality
     }))
   }
 }
 impl Eq for LispKind {}
 
+impl LispKind {
+  /// Hashes the elements of a list (which may be split over several
+  /// [`DottedList`](LispKind::DottedList) nodes), and the tail if it is not `()`,
+  /// so that equal lists in different representations have the same hash.
+  fn hash_list<H: Hasher>(&self, state: &mut H) {
+    self.unwrapped(|e| match e {
+      LispKind::List(es) => for e in &**es { e.hash(state) },
+      LispKind::DottedList(es, r) => {
+        for e in &**es { e.hash(state) }
+        r.hash_list(state)
+      }
+      _ => e.hash(state),
+    })
+  }
+}
+
+impl Hash for LispKind {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.unwrapped(|e| match e {
+      LispKind::Atom(a) => (0_u8, a).hash(state),
+      LispKind::List(_) | LispKind::DottedList(..) => { 1_u8.hash(state); e.hash_list(state) }
+      LispKind::Number(n) => (2_u8, n).hash(state),
+      LispKind::String(s) => (3_u8, s).hash(state),
+      LispKind::Bool(b) => (4_u8, b).hash(state),
+      LispKind::Syntax(s) => (5_u8, s).hash(state),
+      // The remaining values have only reference equality, or are not hashable
+      _ => mem::discriminant(e).hash(state),
+    })
+  }
+}
+impl Hash for LispVal {
+  fn hash<H: Hasher>(&self, state: &mut H) { (**self).hash(state) }
+}
+
 /// An annotation, which is a tag placed on lisp values that is ignored by all
 /// the basic functions.
 #[derive(Clone, Debug, EnvDebug, DeepSizeOf)]
@@ -1108,6 +1177,61 @@ str_enum! {
     /// (list->string '(98 97 114)) -- "bar"
     /// ```
     ListToString: "list->string",
+    /// `(string-split sep s)` splits the string `s` at each occurrence of the
+    /// (nonempty) string `sep`, and returns the list of pieces.
+    /// ```metamath-zero
+    /// (string-split ", " "a, b, c") -- ("a" "b" "c")
+    /// (string-split "," ",a,")      -- ("" "a" "")
+    /// ```
+    StringSplit: "string-split",
+    /// * `(string-index-of pat s)` returns the index of the first occurrence of the
+    ///   string `pat` in `s`, or `#undef` if it does not occur.
+    /// * `(string-index-of pat s start)` returns the index of the first occurrence
+    ///   at or after `start`.
+    /// ```metamath-zero
+    /// (string-index-of "o" "hello world")   -- 4
+    /// (string-index-of "o" "hello world" 5) -- 7
+    /// (string-index-of "x" "hello world")   -- #undef
+    /// ```
+    StringIndexOf: "string-index-of",
+    /// `(string-replace from to s)` replaces every occurrence of the (nonempty)
+    /// string `from` in `s` with `to`.
+    /// ```metamath-zero
+    /// (string-replace "o" "0" "foo bar") -- "f00 bar"
+    /// ```
+    StringReplace: "string-replace",
+    /// `(string-upcase s)` converts the ASCII letters in `s` to upper case.
+    /// ```metamath-zero
+    /// (string-upcase "foo-Bar") -- "FOO-BAR"
+    /// ```
+    StringUpcase: "string-upcase",
+    /// `(string-downcase s)` converts the ASCII letters in `s` to lower case.
+    /// ```metamath-zero
+    /// (string-downcase "foo-Bar") -- "foo-bar"
+    /// ```
+    StringDowncase: "string-downcase",
+    /// * `(number->string n)` converts the number `n` to a string in base 10.
+    /// * `(number->string n radix)` uses base `radix` instead, which must be
+    ///   between 2 and 36.
+    /// ```metamath-zero
+    /// (number->string 255)       -- "255"
+    /// (number->string 255 16)    -- "ff"
+    /// (number->string (- 5) 2)   -- "-101"
+    /// ```
+    NumberToString: "number->string",
+    /// `(format fmt e1 e2 e3)` builds a string from the template `fmt`, replacing
+    /// the directives in it with the arguments in order:
+    /// * `~a` inserts the next argument as by `->string`,
+    /// * `~s` inserts the next argument as printed by `print`
+    ///   (so strings are quoted),
+    /// * `~%` inserts a newline, and `~~` inserts a `~`.
+    ///
+    /// It is an error if the number of arguments does not match the directives.
+    /// ```metamath-zero
+    /// (format "~a is ~s" 'foo "bar") -- "foo is \"bar\""
+    /// (format "~a~~~a" 1 2)          -- "1~2"
+    /// ```
+    Format: "format",
     /// `(not e1 e2 e3)` returns `#f` if any argument is truthy, and `#t` otherwise.
     /// It is not short-circuiting.
     Not: "not",
@@ -1138,6 +1262,10 @@ str_enum! {
     /// calling `f` on the heads of all the arguments, then the second elements and so on.
     /// All lists must be the same length.
     Map: "map",
+    /// `(for-each f '(a1 a2) '(b1 b2))` calls `(f a1 b1)` and then `(f a2 b2)`, discarding
+    /// the results, and returns `#undef`. All lists must be the same length.
+    /// Unlike `map`, it does not build a list of the results.
+    ForEach: "for-each",
     /// `(bool? e)` is true if the argument is a boolean, `#t` or `#f`.
     IsBool: "bool?",
     /// `(atom? e)` is true if the argument is an atom (also known as a symbol), `'x`.
@@ -1182,18 +1310,30 @@ str_enum! {
     IsAtomMap: "atom-map?",
     /// `(atom-map! [k1 v1] [k2 v2] ...)` creates a new mutable atom map, a key-value store.
     NewAtomMap: "atom-map!",
-    /// * `(lookup m k)` gets the value stored in the atom map `m` at `k`, or `#undef` if not present.
+    /// `(hash-map? m)` is true if the argument is a hash map.
+    IsHashMap: "hash-map?",
+    /// `(hash-map! [k1 v1] [k2 v2] ...)` creates a new mutable hash map. Unlike atom maps,
+    /// the keys can be any values built from atoms, numbers, strings, booleans, syntax forms
+    /// and `#undef` using lists, and they are compared structurally. An entry `[k]` without
+    /// a value removes `k` from the entries before it.
+    NewHashMap: "hash-map!",
+    /// * `(lookup m k)` gets the value stored in the atom map or hash map `m` at `k`,
+    ///   or `#undef` if not present. If `m` is an atom map and `k` is not an atom or string,
+    ///   the result is `#undef`, even if a default `v` is given.
     /// * `(lookup m k v)` will return `v` instead if the key is not present,
     ///   unless `v` is a procedure, in which case it will be called with no arguments on lookup failure.
     Lookup: "lookup",
-    /// * `(insert! m k v)` inserts the value `v` at key `k` in the mutable map `m`,
-    ///   and returns `#undef`.
+    /// * `(insert! m k v)` inserts the value `v` at key `k` in the mutable atom map
+    ///   or hash map `m`, and returns `#undef`.
     /// * `(insert! m k)` "undefines" the value at key `k` in `m`, that is,
     ///   it erases whatever is there.
     Insert: "insert!",
-    /// * `(insert m k v)` returns an immutable map based on the immutable map `m`,
-    ///   with the value `v` inserted at key `k`.
+    /// * `(insert m k v)` returns an immutable map based on the immutable atom map
+    ///   or hash map `m`, with the value `v` inserted at key `k`.
     /// * `(insert m k)` returns `k` erased from `m`.
+    ///
+    /// `m` itself is not modified. (Before hash maps were added, `insert` returned `#undef`
+    /// instead of the new map, which made it unusable.)
     InsertNew: "insert",
     /// This function is intended for use in `set-merge-strategy`, and will merge atom-maps.
     ///
@@ -1203,11 +1343,29 @@ str_enum! {
     /// * `(merge-map f old new)` or `((merge-map f) old new)` will use
     ///   `(f oldval newval)` to resolve keys that are present in both maps.
     MergeMap: "merge-map",
+    /// `(vec? v)` is true if the argument is a vector.
+    IsVec: "vec?",
+    /// `(vec! e1 e2 e3)` creates a new mutable vector containing `e1 e2 e3`.
+    NewVec: "vec!",
+    /// `(vec-len v)` returns the number of elements in the vector `v`.
+    VecLen: "vec-len",
+    /// `(vec-get v n)` returns the `n`th element of the vector `v` (starting from 0),
+    /// or `#undef` if out of range.
+    VecGet: "vec-get",
+    /// `(vec-set! v n e)` sets the `n`th element of the mutable vector `v` to `e`,
+    /// and returns `#undef`. It fails if `n` is out of range.
+    VecSet: "vec-set!",
+    /// `(vec-push! v e1 e2)` appends `e1` and `e2` to the end of the mutable vector `v`,
+    /// and returns `#undef`.
+    VecPush: "vec-push!",
+    /// `(vec->list v)` returns a list of the elements of the vector `v`.
+    VecToList: "vec->list",
     /// `(set-timeout n)` sets the timeout for running individual theorems and
     /// `do` blocks to `n` milliseconds. The default is 5 seconds.
     SetTimeout: "set-timeout",
     /// `(set-stack-limit n)` sets the maximum number of stack frames used during
     /// evaluation of theorems and `do` blocks to `n`. The default is 1024.
+    /// Tail calls reuse the stack frame of the caller, so they do not count against the limit.
     SetStackLimit: "set-stack-limit",
     /// `(mvar? e)` returns `#t` if `e` is an unsolved metavariable value.
     /// *Note:* Holes in expressions are *not* represented as raw metavariables,
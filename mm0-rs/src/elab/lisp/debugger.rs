@@ -0,0 +1,113 @@
+//! Hooks for stepping through lisp evaluation in a debugger.
+//!
+//! A [`Debugger`] is attached to an elaboration using
+//! [`ElaborateBuilder::debugger`](crate::elab::ElaborateBuilder::debugger). Before each
+//! function application, the [`Evaluator`](super::eval::Evaluator) asks the
+//! [`DebugHook`] whether to pause, and if so it passes a snapshot of the evaluation
+//! stack to [`DebugHook::pause`], which blocks until evaluation should resume.
+//! The `mm0-rs debug-adapter` front end implements the hook using the
+//! Debug Adapter Protocol.
+
+use std::collections::HashMap;
+use std::fmt;
+use crate::{AtomId, FileSpan};
+
+/// A frame in the [`DebugState`] call stack.
+#[derive(Debug)]
+pub struct StackFrame {
+  /// The name of the procedure, or `[fn]` for anonymous procedures.
+  pub name: String,
+  /// The location currently being evaluated in this frame. For all frames except
+  /// the innermost one, this is the call to the next frame.
+  pub loc: FileSpan,
+  /// The local variables in scope, and their values.
+  pub locals: Vec<(String, String)>,
+}
+
+/// A snapshot of the evaluator state, passed to [`DebugHook::pause`].
+#[derive(Debug)]
+pub struct DebugState {
+  /// The call stack, innermost frame first.
+  pub frames: Vec<StackFrame>,
+  /// The hypotheses and goals of the current proof state.
+  pub goals: Vec<(String, String)>,
+  /// The current depth of the evaluation stack.
+  pub depth: usize,
+  /// The depth of the evaluation stack after the innermost procedure returns,
+  /// or `None` if evaluation is not inside a procedure call.
+  pub ret_depth: Option<usize>,
+}
+
+/// The interface between the evaluator and a debugger front end. Hooks are `Send`,
+/// like the rest of the [`Elaborator`](crate::elab::Elaborator) state, so that
+/// elaboration can run on a worker thread.
+pub trait DebugHook: Send {
+  /// Called before evaluating the function application at `loc`, when the evaluation
+  /// stack has `depth` entries. Returns true if evaluation should pause here.
+  fn check(&mut self, loc: &FileSpan, depth: usize) -> bool;
+
+  /// Called when evaluation is paused at `state.frames[0].loc`.
+  /// Evaluation resumes when this function returns.
+  fn pause(&mut self, state: DebugState);
+}
+
+/// A [`DebugHook`] attached to an elaboration, together with the debug information
+/// collected while parsing lisp code.
+pub struct Debugger {
+  pub(crate) hook: Box<dyn DebugHook>,
+  /// The names of the local variables in scope at each function application in the
+  /// current file, indexed by the start of the application span.
+  pub(crate) locals: HashMap<usize, Box<[AtomId]>>,
+}
+
+impl Debugger {
+  /// Creates a new [`Debugger`] from a [`DebugHook`].
+  pub fn new(hook: impl DebugHook + 'static) -> Self {
+    Self {hook: Box::new(hook), locals: HashMap::new()}
+  }
+}
+
+impl fmt::Debug for Debugger {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { "Debugger".fmt(f) }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::wildcard_imports)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use crate::compiler::{elab_keep, test_file};
+  use crate::MutexExt;
+  use super::*;
+
+  /// The name and locals of the innermost frame, and the number of frames.
+  type Frame = (String, Vec<(String, String)>, usize);
+
+  /// A hook that pauses at every function application and records the innermost frame.
+  struct Recorder(Arc<Mutex<Vec<Frame>>>);
+
+  impl DebugHook for Recorder {
+    fn check(&mut self, _: &FileSpan, _: usize) -> bool { true }
+    fn pause(&mut self, state: DebugState) {
+      let frame = &state.frames[0];
+      self.0.ulock().push((frame.name.clone(), frame.locals.clone(), state.frames.len()))
+    }
+  }
+
+  #[test]
+  fn pauses_with_locals() {
+    let path = test_file("debug.mm1", b"do { (def (f x) (list x x)) (f 1) };");
+    let log = Arc::new(Mutex::new(vec![]));
+    let debugger = Debugger::new(Recorder(log.clone()));
+    let (_, _, errors, _) = elab_keep(Some(path), Some(debugger)).unwrap();
+    assert!(errors.iter().all(|e| e.level != crate::ErrorLevel::Error), "{:?}", errors);
+    let frame = |name: &str, locals: &[(&str, &str)], depth| (name.to_owned(),
+      locals.iter().map(|&(x, v)| (x.to_owned(), v.to_owned())).collect::<Vec<_>>(), depth);
+    assert_eq!(*log.ulock(), [frame("[top level]", &[], 1), frame("f", &[("x", "1")], 2)]);
+  }
+
+  #[test]
+  fn debugger_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Debugger>();
+  }
+}
@@ -9,15 +9,17 @@ use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
+use memchr::memmem;
 use num::{BigInt, Signed, ToPrimitive, Zero};
 use crate::{ast::SExpr, ArcString, AtomData, AtomId, BoxError, DeclKey, DocComment, ElabError,
   Elaborator, Environment, ErrorLevel, FileRef, FileSpan, LispData,
   MergeStrategy, MergeStrategyInner, ObjectKind, SliceExt, Span, StmtTrace,
-  ExprNode, ProofNode, TermKind, ThmKind, ThmId};
-use crate::elab::local_context::{try_get_span, try_get_span_from, AwaitingProof, InferSort};
+  TermKind, ThmKind, ThmId};
+use crate::elab::local_context::{hole_error, try_get_span, try_get_span_from, AwaitingProof, InferSort};
 use crate::elab::{
   refine::{RStack, RState, RefineResult},
-  ElabErrorKind, ReportMode, Result};
+  ElabErrorKind, GoalHyp, GoalState, ReportMode, Result};
+use super::debugger::{DebugState, StackFrame};
 use super::parser::{Branch, DefTarget, Ir, MVarPattern, Pattern};
 use super::print::{EnvDisplay, FormatEnv};
 use super::{Arc, BuiltinProc, Cell, InferTarget, LispKind, LispRef, LispVal, Modifiers, Proc,
@@ -44,12 +46,13 @@ enum Stack<'a> {
   MatchCont(Span, LispVal, std::slice::Iter<'a, Branch>, Rc<Cell<bool>>),
   SetMergeStrategy(Span, AtomId),
   MapProc(Span, Span, LispVal, Box<[Uncons]>, Vec<LispVal>),
+  ForEachProc(Span, Span, LispVal, Box<[Uncons]>),
   MergeMap(Span, LispVal, MergeStrategy, std::vec::IntoIter<(AtomId, LispVal, LispVal)>, HashMap<AtomId, LispVal>, AtomId),
   AddThmProc(FileSpan, Box<AwaitingProof>),
   Refines(Span, Option<Span>, std::slice::Iter<'a, Ir>),
   Refine {sp: Span, stack: Vec<RStack>},
   Focus(Span, bool, Vec<LispVal>),
-  Have(Span, LispVal, AtomId),
+  Have(Span, LispVal, AtomId, bool),
 }
 
 impl<'a> EnvDisplay for Stack<'a> {
@@ -84,12 +87,14 @@ impl<'a> EnvDisplay for Stack<'a> {
       Stack::SetMergeStrategy(_, a) => write!(f, "(set-merge-strategy {}\n  _)", fe.to(a)),
       Stack::MapProc(_, _, e, us, es) => write!(f, "(map {}\n  {})\n  ->{} _",
         fe.to(e), fe.to(&**us), fe.to(es)),
+      Stack::ForEachProc(_, _, e, us) => write!(f, "(for-each {}\n  {})",
+        fe.to(e), fe.to(&**us)),
       Stack::MergeMap(..) => write!(f, "(merge-map)"),
       Stack::AddThmProc(_, ap) => write!(f, "(add-thm {} _)", fe.to(&ap.atom())),
       Stack::Refines(_, _, irs) => write!(f, "(refine _ {})", fe.to(irs.as_slice())),
       Stack::Refine {..} => write!(f, "(refine _)"),
       &Stack::Focus(_, cl, ref es) => write!(f, "(focus {} _)\n  ->{}", cl, fe.to(es)),
-      Stack::Have(_, _, a) => write!(f, "(have {} _)", fe.to(a)),
+      Stack::Have(_, _, a, _) => write!(f, "(have {} _)", fe.to(a)),
     }
   }
 }
@@ -107,6 +112,7 @@ enum State<'a> {
   Pattern(Span, LispVal, std::slice::Iter<'a, Branch>,
     &'a Branch, Vec<PatternStack<'a>>, Box<[LispVal]>, PatternState<'a>),
   MapProc(Span, Span, LispVal, Box<[Uncons]>, Vec<LispVal>),
+  ForEachProc(Span, Span, LispVal, Box<[Uncons]>),
   MergeMap(Span, LispVal, MergeStrategy, std::vec::IntoIter<(AtomId, LispVal, LispVal)>, HashMap<AtomId, LispVal>),
   Refine {sp: Span, stack: Vec<RStack>, state: RState},
 }
@@ -131,6 +137,8 @@ impl<'a> EnvDisplay for State<'a> {
         fe.to(e), fe.to(br), fe.to(bs.as_slice()), fe.to(st)),
       State::MapProc(_, _, e, us, es) => write!(f, "(map {}\n  {})\n  ->{}",
         fe.to(e), fe.to(&**us), fe.to(es)),
+      State::ForEachProc(_, _, e, us) => write!(f, "(for-each {}\n  {})",
+        fe.to(e), fe.to(&**us)),
       State::MergeMap(..) => write!(f, "(merge-map)"),
       State::Refine {state, ..} => state.fmt(fe, f),
     }
@@ -146,37 +154,65 @@ impl LispKind {
     }
   }
 
-  fn make_map_mut<T>(&self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> (Option<T>, Option<LispVal>) {
+  fn make_coll_mut<C: Collection, T>(&self, f: impl FnOnce(&mut C) -> T) -> (Option<T>, Option<LispVal>) {
     match self {
-      LispKind::AtomMap(m) => {
-        let mut m = m.clone();
-        (Some(f(&mut m)), Some(LispVal::new(LispKind::AtomMap(m))))
-      }
-      LispKind::Annot(sp, e) => match e.make_map_mut(f) {
+      LispKind::Annot(sp, e) => match e.make_coll_mut(f) {
         (r, None) => (r, None),
         (r, Some(e)) => (r, Some(LispVal::new(LispKind::Annot(sp.clone(), e)))),
       },
-      LispKind::Ref(m) => (m.get_mut(|e| e.as_map_mut(f)), None),
-      _ => (None, None)
+      LispKind::Ref(m) => (m.get_mut(|e| e.as_coll_mut(f)), None),
+      _ => match C::get(self) {
+        Some(m) => {
+          let mut m = m.clone();
+          (Some(f(&mut m)), Some(LispVal::new(m.into_lisp())))
+        }
+        None => (None, None)
+      }
     }
   }
 }
 impl LispVal {
-  fn as_map_mut<T>(&mut self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> Option<T> {
+  fn as_coll_mut<C: Collection, T>(&mut self, f: impl FnOnce(&mut C) -> T) -> Option<T> {
     match self.get_mut() {
       None => {
-        let (r, new) = self.make_map_mut(f);
+        let (r, new) = self.make_coll_mut(f);
         if let Some(e) = new {*self = e}
         r
       }
-      Some(LispKind::AtomMap(m)) => Some(f(m)),
-      Some(LispKind::Annot(_, e)) => Self::as_map_mut(e, f),
-      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_map_mut(e, f)),
-      Some(_) => None
+      Some(LispKind::Annot(_, e)) => Self::as_coll_mut(e, f),
+      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_coll_mut(e, f)),
+      Some(e) => C::get_mut(e).map(f)
     }
   }
 }
 
+/// A collection type stored in a [`LispKind`], which can be modified in place
+/// if it is not shared, and is copied on write otherwise.
+trait Collection: Clone {
+  fn get(e: &LispKind) -> Option<&Self>;
+  fn get_mut(e: &mut LispKind) -> Option<&mut Self>;
+  fn into_lisp(self) -> LispKind;
+}
+
+macro_rules! impl_collection {
+  ($($ty:ty => $kind:ident,)*) => {$(
+    impl Collection for $ty {
+      fn get(e: &LispKind) -> Option<&Self> {
+        if let LispKind::$kind(m) = e {Some(m)} else {None}
+      }
+      fn get_mut(e: &mut LispKind) -> Option<&mut Self> {
+        if let LispKind::$kind(m) = e {Some(m)} else {None}
+      }
+      fn into_lisp(self) -> LispKind { LispKind::$kind(self) }
+    }
+  )*}
+}
+impl_collection! {
+  HashMap<AtomId, LispVal> => AtomMap,
+  HashMap<LispVal, LispVal> => HashMap,
+  Vec<LispVal> => Vector,
+}
+
 #[derive(Debug)]
 enum Dot<'a> { List(Option<usize>), DottedList(&'a Pattern) }
 #[derive(Debug)]
@@ -410,10 +446,34 @@ impl Elaborator {
     self.as_lref(e, |m| m.get_mut(f))
   }
 
-  fn as_map<T>(&self, e: &LispKind, f: impl FnOnce(&HashMap<AtomId, LispVal>) -> SResult<T>) -> SResult<T> {
+  /// Inserts `v` at key `k` in the atom map or hash map `m`, or removes `k` if `v` is `None`.
+  /// Returns `None` if `m` is not a map.
+  fn map_insert(&mut self, m: &mut LispVal, k: &LispVal, v: Option<LispVal>) -> Option<SResult<()>> {
+    if m.is_map() {
+      let a = match self.as_string_atom(k) {
+        Some(a) => a,
+        None => return Some(Err(format!("expected an atom, got {}", self.print(k)))),
+      };
+      m.as_coll_mut(|m: &mut HashMap<AtomId, LispVal>| match v {
+        Some(v) => {m.insert(a, v);}
+        None => {m.remove(&a);}
+      }).map(Ok)
+    } else {
+      m.as_coll_mut(|m: &mut HashMap<LispVal, LispVal>| {
+        if !k.is_hashable() { return Err(format!("not a valid hash map key: {}", self.print(k))) }
+        match v {
+          Some(v) => {m.insert(k.clone(), v);}
+          None => {m.remove(k);}
+        }
+        Ok(())
+      })
+    }
+  }
+
+  fn as_vec<T>(&self, e: &LispKind, f: impl FnOnce(&[LispVal]) -> T) -> SResult<T> {
     e.unwrapped(|e| match e {
-      LispKind::AtomMap(m) => f(m),
-      _ => Err(format!("not an atom map: {}", self.print(e)))
+      LispKind::Vector(v) => Ok(f(v)),
+      _ => Err(format!("not a vector: {}", self.print(e)))
     })
   }
 
@@ -428,6 +488,32 @@ impl Elaborator {
     }
   }
 
+  /// Fills in the template `fmt` with `args`, for the `format` builtin.
+  fn format(&self, fmt: &[u8], args: &[LispVal]) -> SResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(fmt.len());
+    let mut args = args.iter();
+    let mut it = fmt.iter();
+    while let Some(&c) = it.next() {
+      if c != b'~' { out.push(c); continue }
+      match it.next() {
+        Some(b'a' | b'A') => {
+          let e = args.next().ok_or("not enough arguments for format string")?;
+          out.extend_from_slice(&self.to_string(e))
+        }
+        Some(b's' | b'S') => {
+          let e = args.next().ok_or("not enough arguments for format string")?;
+          out.extend_from_slice(format!("{}", self.print(e)).as_bytes())
+        }
+        Some(b'%') => out.push(b'\n'),
+        Some(b'~') => out.push(b'~'),
+        Some(&c) => return Err(format!("unknown format directive ~{}", c as char)),
+        None => return Err("unterminated format directive".into()),
+      }
+    }
+    if args.next().is_some() { return Err("too many arguments for format string".into()) }
+    Ok(out)
+  }
+
   fn int_bool_binop(&self, mut f: impl FnMut(&BigInt, &BigInt) -> bool, args: &[LispVal]) -> SResult<bool> {
     let mut it = args.iter();
     let mut last = self.as_int(it.next().expect("int_bool_binop([])"))?;
@@ -454,6 +540,23 @@ impl Elaborator {
     s
   }
 
+  /// Get the current proof state, for display in a goal view.
+  pub fn goal_state(&self) -> GoalState {
+    let fe = self.format_env();
+    GoalState {
+      hyps: self.lc.proof_order.iter().map(|(a, e, _)| GoalHyp {
+        name: self.print(a).to_string(),
+        ty: fe.pp(e, 80).to_string(),
+      }).collect(),
+      goals: self.lc.goals.iter().filter_map(|g| g.goal_type())
+        .map(|e| fe.pp(&e, 80).to_string()).collect(),
+      mvars: self.lc.mvars.iter().filter_map(|m| m.unwrapped(|r|
+        if let LispKind::MVar(_, tgt) = *r {
+          Some(GoalHyp {name: self.print(m).to_string(), ty: fe.to(&tgt).to_string()})
+        } else {None})).collect(),
+    }
+  }
+
   fn head_err(&self, e: &LispKind) -> SResult<LispVal> {
     e.unwrapped(|e| match e {
       LispKind::List(es) if es.is_empty() => Err("evaluating 'hd ()'".into()),
@@ -492,46 +595,6 @@ impl Elaborator {
     })
   }
 
-  fn proof_node(&self, hyps: &[(Option<AtomId>, ExprNode)],
-    heap: &[LispVal], ds: &mut Vec<LispVal>, p: &ProofNode) -> LispVal {
-    match *p {
-      ProofNode::Ref(n) => heap[n].clone(),
-      ProofNode::Dummy(a, s) => {
-        let a = LispVal::atom(a);
-        ds.push(LispVal::list(vec![a.clone(), LispVal::atom(self.env.sorts[s].atom)]));
-        a
-      }
-      ProofNode::Term {term, args: ref es} |
-      ProofNode::Cong {term, args: ref es} => {
-        let mut args = vec![LispVal::atom(self.terms[term].atom)];
-        args.extend(es.iter().map(|e| self.proof_node(hyps, heap, ds, e)));
-        LispVal::list(args)
-      }
-      ProofNode::Hyp(h, _) => LispVal::atom(hyps[h].0.unwrap_or(AtomId::UNDER)),
-      ProofNode::Thm {thm, args: ref es, ..} => {
-        let mut args = vec![LispVal::atom(self.thms[thm].atom)];
-        args.extend(es.iter().map(|e| self.proof_node(hyps, heap, ds, e)));
-        LispVal::list(args)
-      }
-      ProofNode::Conv(ref es) => {
-        let (t, c, p) = &**es;
-        LispVal::list(vec![LispVal::atom(AtomId::CONV),
-          self.proof_node(hyps, heap, ds, t),
-          self.proof_node(hyps, heap, ds, c),
-          self.proof_node(hyps, heap, ds, p),
-        ])
-      }
-      ProofNode::Refl(ref p) => self.proof_node(hyps, heap, ds, p),
-      ProofNode::Sym(ref p) =>
-        LispVal::list(vec![LispVal::atom(AtomId::SYM), self.proof_node(hyps, heap, ds, p)]),
-      ProofNode::Unfold {term, ref args, ref res} =>
-        LispVal::list(vec![LispVal::atom(AtomId::UNFOLD),
-          LispVal::atom(self.terms[term].atom),
-          LispVal::list(args.iter().map(|e| self.proof_node(hyps, heap, ds, e)).collect::<Vec<_>>()),
-          self.proof_node(hyps, heap, ds, &res.1)]),
-    }
-  }
-
   fn get_proof(&self, t: ThmId, mut heap: Vec<LispVal>) -> LispVal {
     let tdata = &self.thms[t];
     match &tdata.kind {
@@ -666,6 +729,9 @@ pub struct Evaluator<'a> {
   /// The evaluation stack. This is a structured object containing a stack of continuations
   /// each of which represent a context which awaiting a value from a sub-computation.
   stack: Vec<Stack<'a>>,
+  /// The named procedures currently being called, when profiling. Each entry contains the
+  /// position of the `Ret` frame of the call in `stack`, the name and the start time.
+  active_procs: Vec<(usize, AtomId, Instant)>,
 }
 impl<'a> Deref for Evaluator<'a> {
   type Target = Elaborator;
@@ -678,7 +744,7 @@ impl<'a> DerefMut for Evaluator<'a> {
 impl<'a> Evaluator<'a> {
   fn new(elab: &'a mut Elaborator, orig_span: Span) -> Evaluator<'a> {
     let file = elab.path.clone();
-    Evaluator {elab, ctx: vec![], file, orig_span, stack: vec![]}
+    Evaluator {elab, ctx: vec![], file, orig_span, stack: vec![], active_procs: vec![]}
   }
 
   fn fspan_base(&mut self, sp: Span) -> FileSpan {
@@ -752,7 +818,7 @@ impl<'a> Evaluator<'a> {
       LispKind::Undef => Ok(State::Ret(old)),
       LispKind::AtomMap(newmap) => {
         if newmap.is_empty() {return Ok(State::Ret(old))}
-        let mut opt = Some(old.as_map_mut(mem::take).ok_or_else(||
+        let mut opt = Some(old.as_coll_mut(mem::take::<HashMap<AtomId, LispVal>>).ok_or_else(||
           self.err(Some((sp, false)), "merge-map: not an atom-map"))?);
         let oldmap = opt.as_mut().expect("impossible");
         let mut todo = vec![];
@@ -768,7 +834,7 @@ impl<'a> Evaluator<'a> {
         }
         if todo.is_empty() {
           Ok(State::Ret({
-            if old.is_ref() && old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
+            if old.is_ref() && old.as_coll_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
             else { LispVal::new(LispKind::AtomMap(opt.take().expect("impossible"))) }
           }))
         } else {
@@ -1048,6 +1114,65 @@ make_builtins! { self, sp1, sp2, args,
     }
     LispVal::string(out.into())
   },
+  StringSplit: Exact(2) => {
+    let sep = try1!(self.as_string(&args[0]));
+    let s = try1!(self.as_string(&args[1]));
+    if sep.is_empty() { try1!(Err("empty separator")) }
+    let mut out = vec![];
+    let mut start = 0;
+    for i in memmem::find_iter(&s, &*sep) {
+      out.push(LispVal::string(s[start..i].into()));
+      start = i + sep.len();
+    }
+    out.push(LispVal::string(s[start..].into()));
+    LispVal::list(out)
+  },
+  StringIndexOf: AtLeast(2) => {
+    if args.len() > 3 { try1!(Err("expected 2 or 3 arguments")) }
+    let pat = try1!(self.as_string(&args[0]));
+    let s = try1!(self.as_string(&args[1]));
+    let start: usize = match args.get(2) {
+      None => 0,
+      Some(e) => try1!(self.with_int(e, |n| n.try_into().ok().filter(|&i| i <= s.len())
+        .ok_or_else(|| format!("index out of range: start {}, length {}", n, s.len())))),
+    };
+    match memmem::find(&s[start..], &pat) {
+      Some(i) => LispVal::number((start + i).into()),
+      None => LispVal::undef(),
+    }
+  },
+  StringReplace: Exact(3) => {
+    let from = try1!(self.as_string(&args[0]));
+    let to = try1!(self.as_string(&args[1]));
+    let s = try1!(self.as_string(&args[2]));
+    if from.is_empty() { try1!(Err("empty search string")) }
+    let mut out = Vec::with_capacity(s.len());
+    let mut start = 0;
+    for i in memmem::find_iter(&s, &*from) {
+      out.extend_from_slice(&s[start..i]);
+      out.extend_from_slice(&to);
+      start = i + from.len();
+    }
+    out.extend_from_slice(&s[start..]);
+    LispVal::string(out.into())
+  },
+  StringUpcase: Exact(1) =>
+    LispVal::string(try1!(self.as_string(&args[0])).to_ascii_uppercase().into()),
+  StringDowncase: Exact(1) =>
+    LispVal::string(try1!(self.as_string(&args[0])).to_ascii_lowercase().into()),
+  NumberToString: AtLeast(1) => {
+    if args.len() > 2 { try1!(Err("expected 1 or 2 arguments")) }
+    let radix = match args.get(1) {
+      None => 10,
+      Some(e) => try1!(self.with_int(e, |n| n.to_u32().filter(|r| (2..=36).contains(r))
+        .ok_or_else(|| format!("invalid radix {}, expected 2 to 36", n)))),
+    };
+    LispVal::string(try1!(self.with_int(&args[0], |n| Ok(n.to_str_radix(radix)))).into())
+  },
+  Format: AtLeast(1) => {
+    let fmt = try1!(self.as_string(&args[0]));
+    LispVal::string(try1!(self.format(&fmt, &args[1..])).into())
+  },
   Not: AtLeast(0) => LispVal::bool(!args.iter().any(|e| e.truthy())),
   And: AtLeast(0) => LispVal::bool(args.iter().all(|e| e.truthy())),
   Or: AtLeast(0) => LispVal::bool(args.iter().any(|e| e.truthy())),
@@ -1075,6 +1200,12 @@ make_builtins! { self, sp1, sp2, args,
     return Ok(State::MapProc(sp1, sp, proc,
       it.map(Uncons::from).collect(), vec![]))
   },
+  ForEach: AtLeast(2) => {
+    let mut it = args.into_iter();
+    let proc = it.next().unwrap();
+    let sp = proc.fspan().map_or(sp2, |fsp| fsp.span);
+    return Ok(State::ForEachProc(sp1, sp, proc, it.map(Uncons::from).collect()))
+  },
   IsBool: Exact(1) => LispVal::bool(args[0].is_bool()),
   IsAtom: Exact(1) => LispVal::bool(args[0].is_atom()),
   IsPair: Exact(1) => LispVal::bool(args[0].at_least(1)),
@@ -1128,50 +1259,53 @@ make_builtins! { self, sp1, sp2, args,
     }
     LispVal::new_ref(LispVal::new(LispKind::AtomMap(m)))
   },
+  IsHashMap: Exact(1) => LispVal::bool(args[0].is_hash_map()),
+  NewHashMap: AtLeast(0) => {
+    let mut m = HashMap::new();
+    for e in args {
+      let mut u = Uncons::from(e);
+      let k = try1!(u.next().ok_or("invalid arguments"));
+      if !k.is_hashable() {try1!(Err(format!("not a valid hash map key: {}", self.print(&k))))}
+      let ret = u.next();
+      if !u.exactly(0) {try1!(Err("invalid arguments"))}
+      if let Some(v) = ret {m.insert(k, v);} else {m.remove(&k);}
+    }
+    LispVal::new_ref(LispVal::new(LispKind::HashMap(m)))
+  },
   Lookup: AtLeast(2) => {
-    match self.as_string_atom(&args[1]) {
-      None => LispVal::undef(),
-      Some(k) => {
-        let e = try1!(self.as_map(&args[0], |m| Ok(m.get(&k).cloned())));
-        if let Some(e) = e {e} else {
-          let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
-          if v.is_proc() {
-            let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
-            return Ok(State::App(sp1, sp, v, vec![], [].iter()))
-          }
-          v
-        }
+    let a = if args[0].is_map() {
+      // a key that is not an atom is not in any atom map, and the default is not used
+      match self.as_string_atom(&args[1]) {
+        None => return Ok(State::Ret(LispVal::undef())),
+        a => a,
+      }
+    } else { None };
+    let e = try1!(args[0].unwrapped(|e| match (e, a) {
+      (LispKind::AtomMap(m), Some(a)) => Ok(m.get(&a).cloned()),
+      (LispKind::HashMap(m), _) => Ok(m.get(&args[1]).cloned()),
+      _ => Err(format!("not a map: {}", self.print(e)))
+    }));
+    if let Some(e) = e {e} else {
+      let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
+      if v.is_proc() {
+        let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
+        return Ok(State::App(sp1, sp, v, vec![], [].iter()))
       }
+      v
     }
   },
   Insert: AtLeast(2) => {
-    try1!(try1!(args[0].as_ref_mut(|r| {
-      r.as_map_mut(|m| -> SResult<_> {
-        let k = self.as_string_atom(&args[1])
-          .ok_or_else(|| format!("expected an atom, got {}", self.print(&args[1])))?;
-        match args.get(2) {
-          Some(v) => {m.insert(k, v.clone());}
-          None => {m.remove(&k);}
-        }
-        Ok(())
-      })
-    }).unwrap_or(None).ok_or("expected a mutable map")));
+    let v = args.get(2).cloned();
+    try1!(try1!(args[0].as_ref_mut(|r| self.map_insert(r, &args[1], v))
+      .unwrap_or(None).ok_or("expected a mutable map")));
     LispVal::undef()
   },
   InsertNew: AtLeast(2) => {
     let mut it = args.into_iter();
     let mut m = it.next().unwrap();
     let k = it.next().unwrap();
-    let k = self.as_string_atom(&k)
-      .ok_or_else(|| format!("expected an atom, got {}", self.print(&k)));
-    try1!(try1!(m.as_map_mut(|m| -> SResult<_> {
-      match it.next() {
-        Some(v) => {m.insert(k?, v);}
-        None => {m.remove(&k?);}
-      }
-      Ok(())
-    }).ok_or("expected a map")));
-    LispVal::undef()
+    try1!(try1!(self.map_insert(&mut m, &k, it.next()).ok_or("expected a map")));
+    m
   },
   MergeMap: AtLeast(0) => {
     let mut it = args.drain(..);
@@ -1185,6 +1319,30 @@ make_builtins! { self, sp1, sp2, args,
       } else {LispVal::proc(Proc::MergeMap(arg1.into_merge_strategy()))}
     } else {LispVal::proc(Proc::MergeMap(None))}
   },
+  IsVec: Exact(1) => LispVal::bool(args[0].is_vec()),
+  NewVec: AtLeast(0) => LispVal::new_ref(LispVal::new(LispKind::Vector(args))),
+  VecLen: Exact(1) => LispVal::number(try1!(self.as_vec(&args[0], |v| v.len())).into()),
+  VecGet: Exact(2) => {
+    let n = try1!(args[1].as_int(|n| n.to_usize().unwrap_or(usize::MAX)).ok_or("expected a number"));
+    try1!(self.as_vec(&args[0], |v| v.get(n).cloned())).unwrap_or_else(LispVal::undef)
+  },
+  VecSet: Exact(3) => {
+    let n = try1!(args[1].as_int(|n| n.to_usize().unwrap_or(usize::MAX)).ok_or("expected a number"));
+    let e = args.pop().unwrap();
+    try1!(try1!(args[0].as_ref_mut(|r| r.as_coll_mut(|v: &mut Vec<LispVal>| match v.get_mut(n) {
+      Some(x) => { *x = e; Ok(()) }
+      None => Err(format!("index {} out of range", self.print(&args[1]))),
+    })).unwrap_or(None).ok_or("expected a mutable vector")));
+    LispVal::undef()
+  },
+  VecPush: AtLeast(2) => {
+    let mut it = args.into_iter();
+    let vec = it.next().unwrap();
+    try1!(vec.as_ref_mut(|r| r.as_coll_mut(|v: &mut Vec<LispVal>| v.extend(it)))
+      .unwrap_or(None).ok_or("expected a mutable vector"));
+    LispVal::undef()
+  },
+  VecToList: Exact(1) => LispVal::list(try1!(self.as_vec(&args[0], <[_]>::to_vec))),
   SetTimeout: Exact(1) => {
     match try1!(args[0].as_int(BigInt::to_u64).ok_or("expected a number")) {
       None | Some(0) => {self.timeout = None; self.cur_timeout = None},
@@ -1255,7 +1413,7 @@ make_builtins! { self, sp1, sp2, args,
     let xarg = args.next().unwrap();
     let a = try1!(xarg.as_atom().ok_or("expected an atom"));
     let x_sp = try_get_span(&self.fspan(sp1), &xarg);
-    self.stack.push(Stack::Have(sp1, xarg, a));
+    self.stack.push(Stack::Have(sp1, xarg, a, args.len() == 1));
     let mut stack = vec![RStack::DeferGoals(mem::take(&mut self.lc.goals))];
     let state = match (args.next().unwrap(), args.next()) {
       (p, None) => {
@@ -1362,8 +1520,111 @@ impl<'a> Evaluator<'a> {
     }
   }
 
+  /// Records a call to the procedure at `pos`, if it is named and profiling is enabled.
+  /// `depth` is the position of the `Ret` frame of the call in the stack.
+  fn profile_enter(&mut self, depth: usize, pos: &ProcPos) {
+    self.profile_exit(depth);
+    if let (Some(p), &ProcPos::Named(_, _, a)) = (&mut self.elab.profile, pos) {
+      p.procs.entry(a).or_default().calls += 1;
+      self.active_procs.push((depth, a, Instant::now()))
+    }
+  }
+
+  /// Records the return of all procedure calls whose `Ret` frame is at or above `depth`.
+  fn profile_exit(&mut self, depth: usize) {
+    while let Some(&(d, a, start)) = self.active_procs.last() {
+      if d < depth { break }
+      self.active_procs.pop();
+      // Recursive calls are already included in the time of the outer call
+      if self.active_procs.iter().all(|&(_, b, _)| a != b) {
+        if let Some(p) = &mut self.elab.profile {
+          p.procs.entry(a).or_default().time += start.elapsed()
+        }
+      }
+    }
+  }
+
+  /// Asks the debugger whether to pause before evaluating the application at `sp`,
+  /// and if so, waits until it resumes.
+  fn debug_hook(&mut self, sp: Span) {
+    let loc = FileSpan {file: self.file.clone(), span: sp};
+    let depth = self.stack.len();
+    if !self.elab.debugger.as_mut().map_or(false, |d| d.hook.check(&loc, depth)) { return }
+    let state = self.debug_state(loc);
+    let start = Instant::now();
+    if let Some(d) = &mut self.elab.debugger { d.hook.pause(state) }
+    // The time spent paused does not count against the timeout
+    if let Some(t) = &mut self.elab.cur_timeout { *t += start.elapsed() }
+  }
+
+  /// Takes a snapshot of the evaluation state for the debugger, paused at `loc`.
+  fn debug_state(&self, loc: FileSpan) -> DebugState {
+    let frame = |name: String, loc: FileSpan, ctx: &[LispVal]| {
+      let names = self.debugger.as_ref()
+        .filter(|_| loc.file == self.path)
+        .and_then(|d| d.locals.get(&loc.span.start));
+      let locals = ctx.iter().enumerate().filter_map(|(i, v)| {
+        let name = match names.and_then(|ns| ns.get(i)) {
+          Some(&AtomId::UNDER) => return None,
+          Some(&a) => self.data[a].name.to_string(),
+          None => format!("#{}", i),
+        };
+        Some((name, self.print(v).to_string()))
+      }).collect();
+      StackFrame {name, loc, locals}
+    };
+    let mut frames = vec![];
+    let (mut loc, mut ctx) = (loc, &self.ctx);
+    let mut ret_depth = None;
+    for (i, s) in self.stack.iter().enumerate().rev() {
+      if let Stack::Ret(fsp, pos, old, _) = s {
+        ret_depth.get_or_insert(i);
+        let name = match *pos {
+          ProcPos::Named(_, _, a) => self.data[a].name.to_string(),
+          ProcPos::Unnamed(_) => "[fn]".into(),
+        };
+        frames.push(frame(name, mem::replace(&mut loc, fsp.clone()), ctx));
+        ctx = old;
+      }
+    }
+    frames.push(frame("[top level]".into(), loc, ctx));
+    let fe = self.format_env();
+    let mut goals: Vec<_> = self.lc.proof_order.iter()
+      .map(|(a, e, _)| (self.print(a).to_string(), fe.pp(e, 80).to_string())).collect();
+    goals.extend(self.lc.goals.iter().filter_map(|g| g.goal_type())
+      .map(|e| ("|-".into(), fe.pp(&e, 80).to_string())));
+    DebugState {frames, goals, depth: self.stack.len(), ret_depth}
+  }
+
+  /// Returns the index of the `Ret` frame of the enclosing procedure call, if the
+  /// application about to be performed is a tail call of that procedure. This is the case
+  /// when everything above the frame is a `Drop`, which covers calls in tail position of a
+  /// `fn` body, either branch of an `if`, the body of a `match` clause, and the last
+  /// expression of a `begin`-list or `let`. A tail call reuses the `Ret` frame instead of
+  /// pushing a new one, so tail recursive procedures run in constant stack space.
+  ///
+  /// The exceptions are the body of a `letrec` (which needs to keep its bindings alive,
+  /// see [`Ir::NoTailRec`]) and a `match` clause that binds a continuation `(=> k)`,
+  /// because `k` must remain callable until the clause returns.
+  fn tail_call_frame(&self) -> Option<usize> {
+    for (i, s) in self.stack.iter().enumerate().rev() {
+      match s {
+        Stack::Ret(..) => return Some(i),
+        Stack::Drop(_) => {}
+        _ => break
+      }
+    }
+    None
+  }
+
+  fn run(&mut self, active: State<'a>) -> Result<LispVal> {
+    let res = self.run_core(active);
+    self.profile_exit(0);
+    res
+  }
+
   #[allow(clippy::never_loop)]
-  fn run(&mut self, mut active: State<'a>) -> Result<LispVal> {
+  fn run_core(&mut self, mut active: State<'a>) -> Result<LispVal> {
     macro_rules! throw {($sp:expr, $e:expr) => {{
       let err = $e;
       return Err(self.err(Some(($sp, false)), err))
@@ -1388,6 +1649,7 @@ impl<'a> Evaluator<'a> {
       if self.stack.len() >= self.stack_limit {
         return Err(self.err(None, "stack overflow"))
       }
+      if let Some(p) = &mut self.elab.profile { p.steps += 1 }
       // if self.check_proofs {
       //   if self.stack.len() < stacklen {
       //     println!("stack -= {}", stacklen - self.stack.len());
@@ -1422,7 +1684,10 @@ impl<'a> Evaluator<'a> {
           Ir::Const(val) => State::Ret(val.clone()),
           Ir::List(sp, ls) => State::List(*sp, vec![], ls.iter()),
           Ir::DottedList(ls, e) => State::DottedList(vec![], ls.iter(), e),
-          Ir::App(sp1, sp2, f, es) => push!(App(*sp1, *sp2, es); Eval(f)),
+          Ir::App(sp1, sp2, f, es) => {
+            if self.elab.debugger.is_some() { self.debug_hook(*sp1) }
+            push!(App(*sp1, *sp2, es); Eval(f))
+          }
           Ir::If(e) => push!(If(&e.1, &e.2); Eval(&e.0)),
           Ir::NoTailRec => match self.stack.pop() {
             None => State::Ret(LispVal::undef()),
@@ -1531,7 +1796,12 @@ impl<'a> Evaluator<'a> {
           Some(Stack::TestPattern(sp, e, it, br, pstack, vars)) =>
             State::Pattern(sp, e, it, br, pstack, vars, PatternState::Ret(ret.truthy())),
           Some(Stack::Drop(n)) => {self.ctx.truncate(n); State::Ret(ret)}
-          Some(Stack::Ret(fsp, _, old, _)) => {self.file = fsp.file; self.ctx = old; State::Ret(ret)}
+          Some(Stack::Ret(fsp, _, old, _)) => {
+            self.file = fsp.file;
+            self.ctx = old;
+            self.profile_exit(self.stack.len());
+            State::Ret(ret)
+          }
           Some(Stack::MatchCont(_, _, _, valid)) => {
             if let Err(valid) = Rc::try_unwrap(valid) {valid.set(false)}
             State::Ret(ret)
@@ -1548,6 +1818,7 @@ impl<'a> Evaluator<'a> {
             vec.push(ret);
             State::MapProc(sp1, sp2, f, us, vec)
           }
+          Some(Stack::ForEachProc(sp1, sp2, f, us)) => State::ForEachProc(sp1, sp2, f, us),
           Some(Stack::MergeMap(sp, old, strat, it, mut map, k)) => {
             map.insert(k, ret);
             State::MergeMap(sp, old, strat, it, map)
@@ -1572,8 +1843,8 @@ impl<'a> Evaluator<'a> {
                 self.call_goal_listener(&stat);
                 let span = self.fspan(sp);
                 for g in mem::take(&mut self.lc.goals) {
-                  let err = ElabError::new_e(try_get_span(&span, &g),
-                    format!("|- {}", self.format_env().pp(&g.goal_type().expect("expected a goal"), 80)));
+                  let err = hole_error(self.format_env(), &self.lc, try_get_span(&span, &g),
+                    &g.goal_type().expect("expected a goal"));
                   self.report(err)
                 }
                 throw!(sp, format!("focused goal has not been solved\n\n{}", stat))
@@ -1584,13 +1855,16 @@ impl<'a> Evaluator<'a> {
           },
           Some(Stack::Refine {sp, stack}) =>
             State::Refine {sp, stack, state: RState::Ret(ret)},
-          Some(Stack::Have(sp, x, a)) => {
+          Some(Stack::Have(sp, x, a, inferred)) => {
             let e = self.infer_type(sp, &ret)?;
             let span = try_get_span(&self.fspan(sp), &x);
-            self.lc.add_proof(a, e, ret.clone());
             if span != sp {
               self.spans.insert_if(span, || ObjectKind::proof(x));
+              if inferred {
+                self.spans.insert_hint_if(span, || ObjectKind::have_type(e.clone()));
+              }
             }
+            self.lc.add_proof(a, e, ret.clone());
             State::Ret(LispVal::undef())
           },
         },
@@ -1621,25 +1895,18 @@ impl<'a> Evaluator<'a> {
             Ok(match func {
               &Proc::Builtin(func) => self.evaluate_builtin(sp1, sp2, func, args)?,
               Proc::Lambda {pos, env, code, ..} => {
-                let tail_call = (|| {
-                  for (i, s) in self.stack.iter().enumerate().rev() {
-                    match s {
-                      Stack::Ret(..) => return Some(i),
-                      Stack::Drop(_) => {}
-                      _ => break
-                    }
-                  }
-                  None
-                })();
-                if let Some(i) = tail_call { // tail call
+                let depth = if let Some(i) = self.tail_call_frame() { // tail call
                   let s = self.stack.drain(i..).next();
                   let_unchecked!((fsp, old) as Some(Stack::Ret(fsp, _, old, _)) = s);
                   self.ctx = (**env).into();
                   self.stack.push(Stack::Ret(fsp, pos.clone(), old, code.clone()));
+                  i
                 } else {
                   self.stack.push(Stack::Ret(self.fspan(sp1), pos.clone(),
                     mem::replace(&mut self.ctx, (**env).into()), code.clone()));
-                }
+                  self.stack.len() - 1
+                };
+                self.profile_enter(depth, pos);
                 self.file = pos.fspan().file.clone();
                 self.stack.push(Stack::Drop(self.ctx.len()));
                 match spec {
@@ -1671,7 +1938,11 @@ impl<'a> Evaluator<'a> {
                       }
                     }
                     Some(Stack::Drop(n)) => {self.ctx.truncate(n);}
-                    Some(Stack::Ret(fsp, _, old, _)) => {self.file = fsp.file; self.ctx = old},
+                    Some(Stack::Ret(fsp, _, old, _)) => {
+                      self.file = fsp.file;
+                      self.ctx = old;
+                      self.profile_exit(self.stack.len());
+                    }
                     Some(_) => {}
                     None => throw!(sp2, "continuation has expired")
                   }
@@ -1766,11 +2037,31 @@ impl<'a> Evaluator<'a> {
             }
           }
         }
+        State::ForEachProc(sp1, sp2, f, mut us) => {
+          let mut it = us.iter_mut();
+          let u0 = it.next().expect("impossible");
+          match u0.next() {
+            None => {
+              if !(u0.exactly(0) && it.all(|u| u.exactly(0))) {
+                throw!(sp1, "mismatched input length")
+              }
+              State::Ret(LispVal::undef())
+            }
+            Some(e0) => {
+              let mut args = vec![e0];
+              for u in it {
+                if let Some(e) = u.next() {args.push(e)}
+                else {throw!(sp1, "mismatched input length")}
+              }
+              push!(ForEachProc(sp1, sp2, f.clone(), us); App(sp1, sp2, f, args, [].iter()))
+            }
+          }
+        }
         State::MergeMap(sp, mut old, strat, mut it, map) => match it.next() {
           None => {
             let mut opt = Some(map);
             State::Ret({
-              if old.is_ref() && old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
+              if old.is_ref() && old.as_coll_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
               else { LispVal::new(LispKind::AtomMap(opt.take().expect("impossible"))) }
             })
           }
@@ -1780,12 +2071,28 @@ impl<'a> Evaluator<'a> {
           }
         },
         State::Refines(sp, mut it) => match it.next() {
-          None => State::Ret(LispVal::undef()),
-          Some(e) => push!(Refines(sp, Some(e.span().unwrap_or(sp)), it); Eval(e))
+          None => {
+            self.goal_boundary(sp.end);
+            State::Ret(LispVal::undef())
+          }
+          Some(e) => {
+            let esp = e.span().unwrap_or(sp);
+            self.goal_boundary(esp.start);
+            push!(Refines(sp, Some(esp), it); Eval(e))
+          }
         },
         State::Refine {sp, mut stack, state} => {
-          let res = self.elab.run_refine(self.orig_span, &mut stack, state)
-            .map_err(|e| self.err(Some((e.pos, true)), e.kind.msg()))?;
+          let orig_span = self.orig_span;
+          let res = self.elab.profiled(|p| &mut p.refine,
+            |elab| elab.run_refine(orig_span, &mut stack, state))
+            .map_err(|e| {
+              let err = self.err(Some((e.pos, true)), e.kind.msg());
+              match e.kind {
+                ElabErrorKind::Boxed(..) | ElabErrorKind::Upstream(..) => err,
+                // Keep the data of the error for the server's code actions
+                kind => ElabError {kind, ..err}
+              }
+            })?;
           match res {
             RefineResult::Ret(e) => {self.lc.clean_mvars(); State::Ret(e)}
             RefineResult::RefineExtraArgs(tgt, e, u) => {
@@ -1807,4 +2114,165 @@ impl<'a> Evaluator<'a> {
       }
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::wildcard_imports)]
+mod tests {
+  use std::fs;
+  use crate::ErrorLevel;
+  use crate::compiler::{elab_keep, test_file};
+
+  /// Elaborates `text` as `test.mm1`, with `deps` in the same directory, and returns
+  /// the reported messages.
+  fn elab(deps: &[(&str, &str)], text: &str) -> Vec<(ErrorLevel, String)> {
+    let path = test_file("test.mm1", text.as_bytes());
+    let dir = path.path().parent().unwrap();
+    for (name, text) in deps { fs::write(dir.join(name), text).unwrap() }
+    let (_, _, errors, _) = elab_keep(Some(path), None).unwrap();
+    errors.into_iter().map(|e| (e.level, e.kind.msg())).collect()
+  }
+
+  /// Evaluates the lisp expressions `es` in a `do` block, and returns the printed values
+  /// of the expressions that do not return `#undef`.
+  fn eval(es: &str) -> Vec<String> {
+    elab(&[], &format!("do {{ {} }};", es)).into_iter().map(|(level, msg)| {
+      assert_eq!(level, ErrorLevel::Info, "{}", msg);
+      msg
+    }).collect()
+  }
+
+  /// Evaluates the lisp expressions `es` in a `do` block, and returns the error they report.
+  fn error(es: &str) -> String {
+    match &*elab(&[], &format!("do {{ {} }};", es)) {
+      [(ErrorLevel::Error, msg)] => msg.clone(),
+      msgs => panic!("expected one error, got {:?}", msgs),
+    }
+  }
+
+  #[test]
+  fn vectors() {
+    assert_eq!(eval("(def v (vec! 1 2)) (vec? v) (vec? '(1 2)) v"), ["#t", "#f", "(vec! 1 2)"]);
+    assert_eq!(eval("(def v (vec! 1 2)) (vec-push! v 3 4) (vec-set! v 0 'a)
+      (vec-len v) (vec-get v 1) (def? (vec-get v 4)) (vec->list v) (vec->list (vec!))"),
+      ["4", "2", "#f", "(a 2 3 4)", "()"]);
+    assert_eq!(error("(vec-set! (vec! 1) 1 2)"), "index 1 out of range");
+  }
+
+  #[test]
+  fn hash_maps() {
+    let m = "(def m (hash-map! '[(a b) 1] '[\"x\" 2] '[#undef 3] '[y 4] '[y]))";
+    assert_eq!(eval(&format!("{} (hash-map? m) (hash-map? (atom-map!))", m)), ["#t", "#f"]);
+    // keys are compared structurally, and strings are not atoms
+    assert_eq!(eval(&format!("{} (lookup m (list 'a 'b)) (lookup m \"x\") (lookup m #undef)
+      (def? (lookup m 'x)) (def? (lookup m 'y)) (lookup m 'x 5) (lookup m 'x (fn () 6))", m)),
+      ["1", "2", "3", "#f", "#f", "5", "6"]);
+    assert_eq!(eval(&format!("{} (insert! m 5 'five) (insert! m '(a b) 6)
+      (lookup m 5) (lookup m '(a b)) (insert! m 5) (def? (lookup m 5))", m)), ["five", "6", "#f"]);
+    // `insert` returns a new map, leaving the original unchanged
+    assert_eq!(eval(&format!("{} (def m2 (insert (get! m) '(c) 7))
+      (hash-map? m2) (lookup m2 '(c)) (def? (lookup m '(c)))", m)), ["#t", "7", "#f"]);
+    assert_eq!(error("(hash-map! (list (ref! 1) 2))"), "not a valid hash map key: 1");
+    assert_eq!(error("(insert! (atom-map!) '(a) 1)"), "expected an atom, got (a)");
+  }
+
+  #[test]
+  fn atom_map_lookup() {
+    assert_eq!(eval("(def m (atom-map! '[a 1])) (lookup m 'a) (lookup m \"a\") (lookup m 'b 2)"),
+      ["1", "1", "2"]);
+    // a key that is not an atom is never found, and the default is not used
+    assert_eq!(eval("(def? (lookup (atom-map! '[a 1]) '(a) 2))"), ["#f"]);
+    assert_eq!(eval("(def m (insert (atom-map!) 'a 1)) (atom-map? m) (lookup m 'a)"),
+      ["#t", "1"]);
+  }
+
+  #[test]
+  fn collections_across_imports() {
+    let dep = "do { (def v (vec! 1 2)) (def m (hash-map! (list '(1 2) v))) };";
+    // the map still holds the same vector
+    let msgs = elab(&[("dep.mm1", dep)], "import \"dep.mm1\";
+      do { (vec-push! v 3) (vec->list v) (vec->list (lookup m '(1 2))) };");
+    assert_eq!(msgs, [(ErrorLevel::Info, "(1 2 3)".into()), (ErrorLevel::Info, "(1 2 3)".into())]);
+  }
+
+  #[test]
+  fn string_split() {
+    assert_eq!(eval(r#"(string-split ", " "a, b, c") (string-split "," ",a,")
+      (string-split "," "")"#), [r#"("a" "b" "c")"#, r#"("" "a" "")"#, r#"("")"#]);
+    assert_eq!(error(r#"(string-split "" "abc")"#), "empty separator");
+  }
+
+  #[test]
+  fn string_search_and_replace() {
+    assert_eq!(eval(r#"(string-index-of "o" "hello world") (string-index-of "o" "hello world" 5)
+      (string-index-of "" "abc" 3) (def? (string-index-of "x" "hello world"))"#),
+      ["4", "7", "3", "#f"]);
+    assert_eq!(error(r#"(string-index-of "a" "abc" 4)"#),
+      "index out of range: start 4, length 3");
+    // replacements are not rescanned
+    assert_eq!(eval(r#"(string-replace "o" "0" "foo bar") (string-replace "aa" "a" "aaaa")"#),
+      [r#""f00 bar""#, r#""aa""#]);
+    assert_eq!(error(r#"(string-replace "" "x" "abc")"#), "empty search string");
+  }
+
+  #[test]
+  fn string_case_and_numbers() {
+    assert_eq!(eval(r#"(string-upcase "foo-Bar") (string-downcase "foo-Bar")"#),
+      [r#""FOO-BAR""#, r#""foo-bar""#]);
+    assert_eq!(eval("(number->string 255) (number->string 255 16) (number->string (- 5) 2)"),
+      [r#""255""#, r#""ff""#, r#""-101""#]);
+    assert_eq!(error("(number->string 5 37)"), "invalid radix 37, expected 2 to 36");
+  }
+
+  #[test]
+  fn format() {
+    assert_eq!(eval(r#"(format "~a is ~s" 'foo "bar") (format "~a~~~a~%" 1 2)"#),
+      [r#""foo is \"bar\"""#, r#""1~2\n""#]);
+    assert_eq!(error(r#"(format "~a ~a" 1)"#), "not enough arguments for format string");
+    assert_eq!(error(r#"(format "~a" 1 2)"#), "too many arguments for format string");
+    assert_eq!(error(r#"(format "~q" 1)"#), "unknown format directive ~q");
+  }
+
+  #[test]
+  fn named_let() {
+    assert_eq!(eval("(let loop ([n 5] [acc 1]) (if (= n 0) acc (loop (- n 1) (* n acc))))"),
+      ["120"]);
+    assert_eq!(eval("(let loop () 'done)"), ["done"]);
+    // the name is not in scope in the initializers
+    assert_eq!(eval("(def loop 2) (let loop ([n loop] [acc ()]) (if (= n 0) acc
+      (loop (- n 1) (cons n acc))))"), ["(1 2)"]);
+    // the loop runs in constant stack
+    assert_eq!(eval("(set-stack-limit 100)
+      (let loop ([n 10000]) (if (= n 0) 'done (loop (- n 1))))"), ["done"]);
+    assert_eq!(error("(let loop ([n 1]) (loop))"), "expected 1 argument(s)");
+  }
+
+  #[test]
+  fn for_each() {
+    assert_eq!(eval("(def r (ref! ()))
+      (for-each (fn (a b) (set! r (cons (list a b) (get! r)))) '(1 2) '(x y)) r"),
+      ["((2 y) (1 x))"]);
+    assert_eq!(eval("(def? (for-each (fn (x) x) '(1)))"), ["#f"]);
+    assert_eq!(eval("(set-stack-limit 100) (def n (ref! 0))
+      (def xs (let loop ([i 0] [acc ()]) (if (= i 10000) acc (loop {i + 1} (cons i acc)))))
+      (for-each (fn (_) (set! n {n + 1})) xs) n"), ["10000"]);
+    assert_eq!(error("(for-each (fn (a b) a) '(1 2) '(1))"), "mismatched input length");
+  }
+
+  #[test]
+  fn deep_tail_calls() {
+    // each of these recurses far more than the stack limit, but only in tail position
+    let limit = "(set-stack-limit 100)";
+    assert_eq!(eval(&format!("{} (def (count n) (if (= n 0) 'done (count (- n 1))))
+      (count 10000)", limit)), ["done"]);
+    assert_eq!(eval(&format!("{} (def (count n) (match n [0 'done] [_ (begin (count (- n 1)))]))
+      (count 10000)", limit)), ["done"]);
+    assert_eq!(eval(&format!("{} (def (count n) (let ([m (- n 1)]) (if (< m 0) 'done (count m))))
+      (count 10000)", limit)), ["done"]);
+    assert_eq!(eval(&format!("{} (def (even? n) (if (= n 0) #t (odd? (- n 1))))
+      (def (odd? n) (if (= n 0) #f (even? (- n 1)))) (even? 10001)", limit)), ["#f"]);
+    // but calls that are not in tail position still count against the limit
+    assert!(error(&format!("{} (def (sum n) (if (= n 0) 0 {{n + (sum (- n 1))}})) (sum 1000)",
+      limit)).starts_with("stack overflow"));
+  }
+}
@@ -444,6 +444,14 @@ enum Item<'a> {
 type Var<'a> = (Span, AtomId, Vec<Item<'a>>);
 
 impl<'a> LispParser<'a> {
+  /// Records the names of the local variables in scope at the application at `sp`,
+  /// if a debugger is attached.
+  fn debug_locals(&mut self, sp: Span) {
+    if let Some(d) = &mut self.elab.debugger {
+      d.locals.insert(sp.start, self.ctx.ctx.clone().into());
+    }
+  }
+
   #[allow(clippy::vec_init_then_push)] // bug: rust-clippy#6615
   fn def_var<'c>(&mut self, mut e: &'c SExpr) -> Result<Var<'c>, ElabError> {
     let mut stack = vec![];
@@ -570,6 +578,10 @@ impl<'a> LispParser<'a> {
 
   fn let_(&mut self, rec: bool, es: &[SExpr]) -> Result<Ir, ElabError> {
     if es.is_empty() {return Ok(Ir::Const(LispVal::undef()))}
+    if let (false, &SExprKind::Atom(a)) = (rec, &es[0].k) {
+      let x = self.parse_atom(es[0].span, a)?;
+      return self.named_let(es[0].span, x, &es[1..])
+    }
     let ls = if let SExprKind::List(ls) = &es[0].k {ls} else {
       return Err(ElabError::new_e(es[0].span, "let: invalid spec"))
     };
@@ -609,6 +621,42 @@ impl<'a> LispParser<'a> {
     Ok(Ir::Eval(true, cs.into()))
   }
 
+  /// Compiles the named let `(let loop ([x1 e1] [x2 e2]) exprs)`, which is equivalent to
+  /// `(letrec ([(loop x1 x2) exprs]) (loop e1 e2))` except that `e1` and `e2` are
+  /// evaluated outside the scope of `loop`. Calls to `loop` in tail position of `exprs`
+  /// do not grow the stack.
+  fn named_let(&mut self, sp: Span, x: AtomId, es: &[SExpr]) -> Result<Ir, ElabError> {
+    let ls = match es.first() {
+      Some(SExpr {k: SExprKind::List(ls), ..}) => ls,
+      _ => return Err(ElabError::new_e(sp, "let: invalid spec"))
+    };
+    let (mut cs, mut xs, mut args) = (vec![], vec![], vec![]);
+    for l in ls {
+      let ((sp, x, stk), e2) = self.let_var(l)?;
+      let v = Ir::eval(self.def_ir(sp, e2, stk)?);
+      // the initial values are stored in anonymous locals, so that they are not in scope
+      // in the following initializers
+      let i = self.ctx.push(AtomId::UNDER);
+      cs.push(Ir::Def(i, None, v.into()));
+      xs.push(x);
+      args.push(Ir::Local(i));
+    }
+    let sps = if x == AtomId::UNDER {None} else {Some((sp, sp, None, x))};
+    let n = self.ctx.push(x);
+    cs.push(Ir::Def(n, sps.clone(),
+      Box::new(Ir::new_ref(sp, sp, Ir::Const(LispVal::undef())))));
+    let start = self.ctx.push_list(&xs);
+    let body = Ir::eval(self.exprs(false, &es[1..])?);
+    self.ctx.restore(start);
+    let m = self.ctx.push(x);
+    cs.push(Ir::Def(m, sps,
+      Box::new(Ir::Lambda(sp, start, ProcSpec::Exact(xs.len()), body.into()))));
+    cs.push(Ir::set_weak(sp, sp, Ir::Local(n), Ir::Local(m)));
+    cs.push(Ir::NoTailRec);
+    cs.push(Ir::App(sp, sp, Box::new(Ir::Local(m)), args.into()));
+    Ok(Ir::Eval(true, cs.into()))
+  }
+
   fn list_pattern(&mut self, ctx: &mut LocalCtx, code: &mut Vec<Ir>,
       quote: bool, mut es: &[SExpr]) -> Result<Pattern, ElabError> {
     let mut pfx = vec![];
@@ -861,9 +909,11 @@ impl<'a> LispParser<'a> {
       } else if let SExprKind::Atom(a) = es[0].k {
         match self.parse_ident_or_syntax(es[0].span, a) {
           Ok(AtomId::UNDER) => return Err(ElabError::new_e(es[0].span, "'_' is not a function")),
-          Ok(x) =>
+          Ok(x) => {
+            self.debug_locals(e.span);
             Ok(Ir::App(e.span, es[0].span,
-              Box::new(self.eval_atom(es[0].span, x)), self.exprs(false, &es[1..])?.into())),
+              Box::new(self.eval_atom(es[0].span, x)), self.exprs(false, &es[1..])?.into()))
+          }
           Err(stx) => {
             self.spans.insert_if(es[0].span, || ObjectKind::Syntax(stx));
             match stx {
@@ -950,6 +1000,7 @@ impl<'a> LispParser<'a> {
           }
         }
       } else {
+        self.debug_locals(e.span);
         Ok(Ir::App(e.span, es[0].span, Box::new(self.expr(false, &es[0])?),
           self.exprs(false, &es[1..])?.into()))
       },
@@ -978,4 +1029,45 @@ impl Elaborator {
   pub fn parse_qexpr(&mut self, e: QExpr) -> Result<Ir, ElabError> {
     LispParser {elab: &mut *self, ctx: LocalCtx::new()}.qexpr(e)
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use crate::ast::StmtKind;
+  use crate::compiler::{elab_keep, test_file};
+
+  /// Compiles the expressions in the `do` block `text`, and prints the results.
+  fn compile(text: &str) -> Vec<String> {
+    let path = test_file("parser.mm1", text.as_bytes());
+    let (_, _, _, mut elab) = elab_keep(Some(path), None).unwrap();
+    let ast = elab.ast.clone();
+    let es = match &ast.stmts[..] {
+      [stmt] => if let StmtKind::Do(es) = &stmt.k {es} else {panic!("expected a do block")},
+      _ => panic!("expected one statement"),
+    };
+    es.iter().map(|e| {
+      let ir = elab.parse_lisp(e).unwrap();
+      format!("{}", elab.print(&ir))
+    }).collect()
+  }
+
+  #[test]
+  fn named_let() {
+    assert_eq!(compile("do {
+      (let loop () 1)
+      (let loop ([n 3]) (if n (loop #f) n))
+      (let loop ([loop loop] [x loop]) (loop x))
+    };"), [
+      // with no bindings, the loop function takes no arguments
+      "(begin (def 0:loop (ref! #undef)) (def 1:loop (lambda 1:0 1)) \
+        (set-weak! x0 x1) (no-tail-rec) (x1))",
+      // the body calls the loop through the ref-cell `x1`
+      "(begin (def 0:_ 3) (def 1:loop (ref! #undef)) (def 2:loop (lambda 2:1 (if x2 (x1 #f) x2))) \
+        (set-weak! x1 x2) (no-tail-rec) (x2 x0))",
+      // the initializers see neither the loop nor the earlier bindings, and the
+      // argument `loop` shadows the loop in the body
+      "(begin (def 0:_ loop) (def 1:_ loop) (def 2:loop (ref! #undef)) \
+        (def 3:loop (lambda 3:2 (x3 x4))) (set-weak! x2 x3) (no-tail-rec) (x3 x0 x1))",
+    ]);
+  }
+}
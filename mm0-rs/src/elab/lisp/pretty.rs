@@ -13,6 +13,7 @@ use crate::{LispVal, LispKind, Uncons, FormatEnv,
   Prec, DeclKey, Literal, TermKind, ThmKind, Modifiers,
   Environment, NotaInfo, AtomData, AtomId, TermId, ThmId, SortId, Thm, Type,
   APP_PREC};
+use crate::lisp::Syntax;
 
 /// The possible annotations around subparts of a pretty printed display.
 /// These are ignored under usual printing settings, but they are used in
@@ -77,6 +78,8 @@ impl LispKind {
       LispKind::List(es) => es.is_empty(),
       LispKind::DottedList(..) |
       LispKind::AtomMap(..) |
+      LispKind::Vector(..) |
+      LispKind::HashMap(..) |
       LispKind::Goal(..) => false,
       LispKind::Atom(..) |
       LispKind::MVar(..) |
@@ -338,12 +341,20 @@ impl<'a> Pretty<'a> {
   }
 
   /// Pretty-prints a lisp expression.
-  pub fn pp_lisp(&'a self, e: &LispVal) -> RefDoc<'a> {
+  pub fn pp_lisp(&'a self, e: &LispVal) -> RefDoc<'a> { self.pp_lisp_core(e, true) }
+
+  /// Pretty-prints a lisp expression, without using math notation for the term arguments
+  /// of theorem applications, so that the result can be read back (inside a quotation)
+  /// as the same lisp expression.
+  pub fn pp_sexpr(&'a self, e: &LispVal) -> RefDoc<'a> { self.pp_lisp_core(e, false) }
+
+  fn pp_lisp_core(&'a self, e: &LispVal, math: bool) -> RefDoc<'a> {
     e.unwrapped(|r| match r {
       LispKind::List(_) | LispKind::DottedList(_, _) => {
         let mut u = Uncons::from(e.clone());
         let mut args = vec![];
-        let mut doc = if let Some((ad, td)) = self.get_thm_args(&mut u, &mut args) {
+        let thm = if math { self.get_thm_args(&mut u, &mut args) } else { u.next(); None };
+        let mut doc = if let Some((ad, td)) = thm {
           let doc = self.alloc(Doc::BorrowedText(ad.name.as_str()));
           let doc = self.app_doc(doc, td.args.iter().zip(&args).map(|((_, ty), e)| {
             match ty {
@@ -354,21 +365,24 @@ impl<'a> Pretty<'a> {
           self.alloc(Doc::Group(doc))
         } else {
           let mut u = Uncons::from(e.clone());
-          if let Some(e) = u.next() { self.pp_lisp(&e) }
+          if let Some(e) = u.next() { self.pp_lisp_core(&e, math) }
           else if u.exactly(0) { return s!("()") }
-          else { return self.pp_lisp(&u.into()) }
+          else { return self.pp_lisp_core(&u.into(), math) }
         };
         for e in &mut u {
-          doc = self.append_doc(doc, self.append_doc(Self::line(), self.pp_lisp(&e)));
+          doc = self.append_doc(doc, self.append_doc(Self::line(), self.pp_lisp_core(&e, math)));
         }
         if !u.exactly(0) {
           doc = self.append_doc(doc,
             self.append_doc(s!(" ."),
-              self.append_doc(Self::line(), self.pp_lisp(&u.into()))));
+              self.append_doc(Self::line(), self.pp_lisp_core(&u.into(), math))));
         }
         let doc = self.append_doc(self.lparen, self.append_doc(doc, self.rparen));
         self.alloc(Doc::Group(self.alloc(Doc::Nest(2, doc))))
       }
+      // Atoms like `if` would be read back as keywords, so we construct them explicitly
+      &LispKind::Atom(a) if !math && Syntax::from_bytes(&self.fe.env.data[a].name).is_some() =>
+        self.alloc(Doc::text(format!(",(string->atom \"{}\")", self.fe.to(&a)))),
       _ => self.alloc(Doc::text(format!("{}", self.fe.to(e)))),
     })
   }
@@ -428,6 +442,17 @@ impl<'a> Pretty<'a> {
   /// Pretty-prints a `term` or `def` declaration, for example
   /// `def foo (x y: nat): nat = $ x + y $;`.
   pub fn term(&'a self, tid: TermId, show_def: bool) -> RefDoc<'a> {
+    self.term_core(tid, show_def, false)
+  }
+
+  /// Pretty-prints a `term` or `def` declaration with the value of the definition, like
+  /// [`term`](Self::term), but also declaring the dummy variables used in the value,
+  /// as in `def foo (x: nat) (.y: nat): wff = $ A. y y = x $;`.
+  pub fn term_with_dummies(&'a self, tid: TermId) -> RefDoc<'a> {
+    self.term_core(tid, true, true)
+  }
+
+  fn term_core(&'a self, tid: TermId, show_def: bool, show_dummies: bool) -> RefDoc<'a> {
     let t = &self.fe.env.terms[tid];
     let mut doc = self.annot(Annot::Keyword,
       if matches!(t.kind, TermKind::Term) {s!("term")} else {s!("def")});
@@ -439,7 +464,21 @@ impl<'a> Pretty<'a> {
     let doc = self.append_annot(doc, Annot::TermName(tid),
       self.alloc(Doc::text(format!("{}", self.fe.to(&t.atom)))));
     let mut bvars = vec![];
-    let doc = self.grouped_binders(doc, &t.args, &mut bvars);
+    let mut doc = self.grouped_binders(doc, &t.args, &mut bvars);
+    let val = if let (true, TermKind::Def(Some(expr))) = (show_def, &t.kind) {
+      let mut bvars = Vec::new();
+      let mut heap = Vec::new();
+      let mut ds = Vec::new();
+      let mut dummies = if show_dummies { Some(&mut ds) } else { None };
+      self.fe.binders(&t.args, &mut heap, &mut bvars);
+      for e in &expr.heap[heap.len()..] {
+        let e = self.fe.expr_node(&heap, &mut dummies, e);
+        heap.push(e)
+      }
+      let val = self.fe.expr_node(&heap, &mut dummies, &expr.head);
+      doc = self.dummies(doc, &ds);
+      Some(val)
+    } else {None};
     let doc = self.append_doc(doc, s!(":"));
     let doc = self.alloc(Doc::Group(doc));
     let mut buf = self.annot(
@@ -447,19 +486,11 @@ impl<'a> Pretty<'a> {
       self.alloc(Doc::text(self.fe.env.sorts[t.ret.0].name.to_string()))
     );
     buf = self.dep_type(&bvars, t.ret.1, buf);
-    if let (true, TermKind::Def(Some(expr))) = (show_def, &t.kind) {
+    if let Some(val) = val {
       buf = self.append_doc(buf, s!(" ="));
       let doc = self.append_doc(doc, self.append_doc(Self::softline(), buf));
-      let mut bvars = Vec::new();
-      let mut heap = Vec::new();
-      self.fe.binders(&t.args, &mut heap, &mut bvars);
-      for e in &expr.heap[heap.len()..] {
-        let e = self.fe.expr_node(&heap, &mut None, e);
-        heap.push(e)
-      }
-      let val = &self.fe.expr_node(&heap, &mut None, &expr.head);
       let doc = self.append_doc(doc, self.append_doc(Self::line(),
-        self.expr_delimited(val, "$ ", " $;")));
+        self.expr_delimited(&val, "$ ", " $;")));
       self.alloc(Doc::Group(doc))
     } else {
       buf = self.append_doc(buf, s!(";"));
@@ -467,6 +498,23 @@ impl<'a> Pretty<'a> {
     }
   }
 
+  /// Appends dummy variable binders like `(.x: set)` to `doc`, given a list of
+  /// `(x set)` pairs as produced by [`expr_node`](Environment::expr_node).
+  /// Duplicate entries are skipped.
+  fn dummies(&'a self, mut doc: RefDoc<'a>, ds: &[LispVal]) -> RefDoc<'a> {
+    let mut seen = vec![];
+    for d in ds {
+      let mut u = Uncons::from(d.clone());
+      if let (Some(x), Some(s)) = (u.next().and_then(|x| x.as_atom()), u.next().and_then(|s| s.as_atom())) {
+        if seen.contains(&x) { continue }
+        seen.push(x);
+        let buf = self.alloc(Doc::text(format!("(.{}: {})", self.fe.to(&x), self.fe.to(&s))));
+        doc = self.append_doc(doc, self.append_doc(Self::softline(), buf));
+      }
+    }
+    doc
+  }
+
   /// Pretty-prints the hypotheses and return of an `axiom` or `theorem`, for example
   /// `$ a $ > $ a -> b $ > $ b $`. This is appended to the input `doc` on the right.
   pub fn hyps_and_ret(&'a self, mut doc: RefDoc<'a>,
@@ -481,7 +529,6 @@ impl<'a> Pretty<'a> {
 
   /// Pretty print a sort, with annotations.
   /// Basic form is just `<modifiers> sort <name>;`
-  #[allow(unused)]
   pub(crate) fn sort(&'a self, sid: SortId) -> RefDoc<'a> {
     let s = &self.fe.env.sorts[sid];
     let mut doc = self.annot(Annot::SortModifiers(s.mods),
@@ -495,8 +542,11 @@ impl<'a> Pretty<'a> {
 
   /// Pretty-prints an `axiom` or `theorem` declaration, for example
   /// `theorem mp (a b: wff): $ a $ > $ a -> b $ > $ b $;`.
-  /// The proof of the theorem is omitted.
-  pub fn thm(&'a self, tid: ThmId) -> RefDoc<'a> {
+  /// The proof of the theorem is omitted unless `show_proof` is set, in which case
+  /// the hypotheses are printed as named binders and the proof is printed as a
+  /// fully explicit `:verb` proof term, for example
+  /// `theorem foo (a b: wff) (h1: $ a $) (h2: $ a -> b $): $ b $ = '(:verb (mp a b h1 h2));`.
+  pub fn thm(&'a self, tid: ThmId, show_proof: bool) -> RefDoc<'a> {
     let t = &self.fe.env.thms[tid];
     let doc = self.annot(Annot::Visibility(t.vis),
       self.alloc(Doc::text(t.vis.to_string())));
@@ -506,20 +556,44 @@ impl<'a> Pretty<'a> {
     let doc = self.append_annot(doc, Annot::ThmName(tid),
       self.alloc(Doc::text(format!("{}", self.fe.to(&t.atom)))));
     let mut bvars = vec![];
-    let doc = self.grouped_binders(doc, &t.args, &mut bvars);
-    let doc = self.append_doc(doc, s!(":"));
-    let doc = self.append_doc(self.alloc(Doc::Group(doc)), Self::line());
+    let mut doc = self.grouped_binders(doc, &t.args, &mut bvars);
     let mut bvars = Vec::new();
     let mut heap = Vec::new();
     self.fe.binders(&t.args, &mut heap, &mut bvars);
-    for e in &t.heap[heap.len()..] {
+    let nargs = heap.len();
+    for e in &t.heap[nargs..] {
       let e = self.fe.expr_node(&heap, &mut None, e);
       heap.push(e)
     }
-    let doc = self.hyps_and_ret(doc,
-      t.hyps.iter().map(|(_, e)| self.fe.expr_node(&heap, &mut None, e)),
-      &self.fe.expr_node(&heap, &mut None, &t.ret));
-    let doc = self.append_doc(doc, s!(";"));
+    let ret = self.fe.expr_node(&heap, &mut None, &t.ret);
+    let pf = if let (true, ThmKind::Thm(Some(pf))) = (show_proof, &t.kind) { pf } else {
+      let doc = self.append_doc(doc, s!(":"));
+      let doc = self.append_doc(self.alloc(Doc::Group(doc)), Self::line());
+      let doc = self.hyps_and_ret(doc,
+        t.hyps.iter().map(|(_, e)| self.fe.expr_node(&heap, &mut None, e)), &ret);
+      let doc = self.append_doc(doc, s!(";"));
+      return self.alloc(Doc::Group(self.alloc(Doc::Nest(2, doc))))
+    };
+    let mut ds = Vec::new();
+    let mut pheap = heap[..nargs].to_vec();
+    for e in &pf.heap[nargs..] {
+      let e = self.fe.proof_node(&t.hyps, &pheap, &mut ds, e);
+      pheap.push(e)
+    }
+    let val = self.fe.proof_node(&t.hyps, &pheap, &mut ds, &pf.head);
+    let val = LispVal::list(vec![LispVal::atom(AtomId::VERB), val]);
+    doc = self.dummies(doc, &ds);
+    for (h, e) in &*t.hyps {
+      let buf = self.append_doc(
+        self.alloc(Doc::text(format!("({}: ", self.fe.to(&h.unwrap_or(AtomId::UNDER))))),
+        self.append_doc(self.expr(&self.fe.expr_node(&heap, &mut None, e)), s!(")")));
+      doc = self.append_doc(doc, self.append_doc(Self::softline(), self.alloc(Doc::Group(buf))));
+    }
+    let doc = self.append_doc(doc, s!(":"));
+    let doc = self.append_doc(self.alloc(Doc::Group(doc)), Self::line());
+    let doc = self.append_doc(doc, self.append_doc(self.expr(&ret), s!(" =")));
+    let doc = self.append_doc(doc, self.append_doc(Self::line(),
+      self.append_doc(s!("'"), self.append_doc(self.pp_sexpr(&val), s!(";")))));
     self.alloc(Doc::Group(self.alloc(Doc::Nest(2, doc))))
   }
 
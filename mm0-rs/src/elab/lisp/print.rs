@@ -181,6 +181,16 @@ impl EnvDisplay for LispKind {
         for (a, v) in m {write!(f, " [{} {}]", fe.data[*a].name, fe.to(v))?}
         write!(f, ")")
       }
+      LispKind::Vector(v) => {
+        write!(f, "(vec!")?;
+        for e in v {write!(f, " {}", fe.to(e))?}
+        write!(f, ")")
+      }
+      LispKind::HashMap(m) => {
+        write!(f, "(hash-map!")?;
+        for (k, v) in m {write!(f, " [{} {}]", fe.to(k), fe.to(v))?}
+        write!(f, ")")
+      }
       LispKind::Ref(m) if m.too_many_readers() => write!(f, "#[ref]"),
       LispKind::Ref(m) => m.get(|e| e.fmt(fe, f)),
       &LispKind::MVar(n, _) => write!(f, "?{}", alphanumber(n)),
@@ -252,7 +262,7 @@ impl EnvDisplay for Term {
 impl EnvDisplay for Thm {
   fn fmt(&self, fe: FormatEnv<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     if let Some(DeclKey::Thm(tid)) = fe.env.data[self.atom].decl {
-      fe.pretty(|p| p.thm(tid).render_fmt(80, f))
+      fe.pretty(|p| p.thm(tid, false).render_fmt(80, f))
     } else { panic!("undeclared theorem") }
   }
 }
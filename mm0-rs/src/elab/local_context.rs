@@ -7,7 +7,7 @@ use std::collections::{HashMap, hash_map::Entry};
 use itertools::Itertools;
 use crate::{AtomId, TermKind, ThmKind, Type as EType, Span, FileSpan, BoxError, MAX_BOUND_VARS};
 use crate::ast::{Decl, Type, DepType, LocalKind};
-use super::{Coe, DeclKind, DerefMut, DocComment, ElabError, Elaborator, Environment,
+use super::{Coe, DeclKind, DerefMut, DocComment, ElabError, ElabErrorKind, Elaborator, Environment,
   Expr, Modifiers, ObjectKind, Proof, Result, SExprKind, SortId, Term, TermId, Thm};
 use super::lisp::{LispVal, LispKind, Uncons, InferTarget, print::FormatEnv};
 use super::proof::{NodeHasher, ProofKind, ProofHash, build, Dedup};
@@ -245,6 +245,13 @@ pub fn try_get_span(fsp: &FileSpan, e: &LispKind) -> Span {
   }
 }
 
+/// Construct the error for an unsolved goal of type `ty` at `sp`, which records a
+/// hypothesis of the local context `lc` that proves the goal, if there is one.
+#[must_use] pub fn hole_error(fe: FormatEnv<'_>, lc: &LocalContext, sp: Span, ty: &LispVal) -> ElabError {
+  let fill = lc.proof_order.iter().find(|(_, e, _)| e == ty).map(|(a, _, _)| fe.data[*a].name.clone());
+  ElabError::new(sp, ElabErrorKind::Hole(format!("|- {}", fe.pp(ty, 80)).into(), fill))
+}
+
 impl Environment {
   /// Construct the proof term corresponding to a coercion `c`.
   #[must_use] pub fn apply_coe(&self, fsp: &Option<FileSpan>, c: &Coe, res: LispVal) -> LispVal {
@@ -306,8 +313,9 @@ impl<'a> ElabTerm<'a> {
       },
       LispKind::List(es) if !es.is_empty() => {
         let a = es[0].as_atom().ok_or_else(|| self.err(&es[0], "expected an atom"))?;
-        let tid = self.fe.term(a).ok_or_else(||
-          self.err(&es[0], format!("term '{}' not declared", self.fe.data[a].name)))?;
+        let name = &self.fe.data[a].name;
+        let tid = self.fe.term(a).ok_or_else(|| ElabError::unknown(self.try_get_span(&es[0]),
+          format!("term '{}' not declared", name), name))?;
         Ok(self.fe.terms[tid].ret.0)
       }
       _ => Err(self.err(e, "invalid expression"))
@@ -380,8 +388,10 @@ impl<'a> ElabTermMut<'a> {
       return Err(self.as_ref().err(&t,
         format!("term '{}' is shadowed by a local variable", self.env.data[a].name)))
     }
-    let tid = self.env.term(a).ok_or_else(||
-      self.as_ref().err(&t, format!("term '{}' not declared", self.env.data[a].name)))?;
+    let tid = self.env.term(a).ok_or_else(|| {
+      let name = &self.env.data[a].name;
+      ElabError::unknown(self.as_ref().try_get_span(&t), format!("term '{}' not declared", name), name)
+    })?;
     let sp1 = self.as_ref().try_get_span(e);
     self.spans_insert(&t, || ObjectKind::Term(tid, sp1));
     let tdata = &self.env.terms[tid];
@@ -798,12 +808,14 @@ impl Elaborator {
                 }
                 let n = ba.deps(deps2);
                 if deps & !n != 0 {
-                  return Err(ElabError::new_e(sp, format!("variables {{{}}} missing from dependencies",
-                    ba.map.iter().filter_map(|(&a, &i)| {
-                      if let InferSort::Bound {..} = self.lc.vars[&a].1 {
-                        if i & deps & !n == 0 {None} else {Some(self.data[a].name.as_str())}
-                      } else {None}
-                    }).sorted().format(", "))))
+                  let vars: Box<[_]> = ba.map.iter().filter_map(|(&a, &i)| {
+                    if let InferSort::Bound {..} = self.lc.vars[&a].1 {
+                      if i & deps & !n == 0 {None} else {Some(self.data[a].name.clone())}
+                    } else {None}
+                  }).sorted_by(|a, b| a[..].cmp(&b[..])).collect();
+                  let msg = format!("variables {{{}}} missing from dependencies",
+                    vars.iter().format(", "));
+                  return Err(ElabError::new(sp, ElabErrorKind::MissingDeps(msg.into(), vars)))
                 }
                 ((s2, n), TermKind::Def(Some(val)))
               }
@@ -895,14 +907,17 @@ impl Elaborator {
                 }
                 let g = LispVal::new_ref(LispVal::goal(self.fspan(e.span), e_ret));
                 self.lc.goals = vec![g.clone()];
-                self.elab_lisp(e)?;
+                let res = self.elab_lisp(e);
+                // If the proof failed or the position is after it, report the final state
+                self.goal_boundary(usize::MAX);
+                res?;
                 if !self.lc.goals.is_empty() {
                   let stat = self.stat();
                   self.call_goal_listener(&stat);
                 }
                 for g in mem::take(&mut self.lc.goals) {
-                  report!(try_get_span(&span, &g),
-                    format!("|- {}", self.format_env().pp(&g.goal_type().expect("expected a goal"), 80)))
+                  report!(hole_error(self.format_env(), &self.lc, try_get_span(&span, &g),
+                    &g.goal_type().expect("expected a goal")))
                 }
                 if error {return Ok(None)}
                 let nh = NodeHasher {var_map, fsp, fe: self.format_env(), lc: &self.lc};
@@ -968,8 +983,8 @@ fn dummies(fe: FormatEnv<'_>, fsp: &FileSpan, lc: &mut LocalContext, e: &LispVal
   macro_rules! sp {($e:expr) => {$e.fspan().unwrap_or(fsp.clone()).span}}
   let mut dummy = |x: AtomId, es: &LispKind| -> Result<()> {
     let s = es.as_atom().ok_or_else(|| ElabError::new_e(sp!(es), "expected an atom"))?;
-    let sort = fe.data[s].sort.ok_or_else(|| ElabError::new_e(sp!(es),
-      format!("unknown sort '{}'", fe.to(&s))))?;
+    let sort = fe.data[s].sort.ok_or_else(|| ElabError::unknown(sp!(es),
+      format!("unknown sort '{}'", fe.to(&s)), &fe.data[s].name))?;
     if x != AtomId::UNDER {lc.vars.insert(x, (true, InferSort::Bound(sort)));}
     Ok(())
   };
@@ -1018,8 +1033,8 @@ impl Elaborator {
         let a = ea.as_atom().ok_or_else(|| ElabError::new_e(sp!(ea), "expected an atom"))?;
         let a = if a == AtomId::UNDER {None} else {Some(a)};
         let s = es.as_atom().ok_or_else(|| ElabError::new_e(sp!(es), "expected an atom"))?;
-        let sort = self.data[s].sort.ok_or_else(|| ElabError::new_e(sp!(es),
-          format!("unknown sort '{}'", self.print(&s))))?;
+        let sort = self.data[s].sort.ok_or_else(|| ElabError::unknown(sp!(es),
+          format!("unknown sort '{}'", self.print(&s)), &self.data[s].name))?;
         let (is, ty) = match u.next() {
           None => {
             if let Some(a) = a {
@@ -1075,15 +1090,15 @@ impl Elaborator {
     let mut vars = (HashMap::new(), 1);
     let (mut lc, args) = self.binders(fsp, Uncons::from(args.clone()), &mut vars)?;
     let ret = if let Some(s) = ret.as_atom() {
-      let s = self.data[s].sort.ok_or_else(|| ElabError::new_e(sp!(ret),
-        format!("unknown sort '{}'", self.print(&s))))?;
+      let s = self.data[s].sort.ok_or_else(|| ElabError::unknown(sp!(ret),
+        format!("unknown sort '{}'", self.print(&s)), &self.data[s].name))?;
       (s, 0)
     } else {
       let mut u = Uncons::from(ret.clone());
       if let (Some(e), Some(vs)) = (u.next(), u.next()) {
         let s = e.as_atom().ok_or_else(|| ElabError::new_e(sp!(e), "expected an atom"))?;
-        let s = self.data[s].sort.ok_or_else(|| ElabError::new_e(sp!(e),
-          format!("unknown sort '{}'", self.print(&s))))?;
+        let s = self.data[s].sort.ok_or_else(|| ElabError::unknown(sp!(e),
+          format!("unknown sort '{}'", self.print(&s)), &self.data[s].name))?;
         (s, self.deps(fsp, &vars.0, vs)?.1)
       } else {
         return Err(ElabError::new_e(sp!(ret), format!("syntax error: {}", self.print(ret))))
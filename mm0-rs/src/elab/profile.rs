@@ -0,0 +1,251 @@
+//! Per-statement elaboration profiling.
+//!
+//! When profiling is enabled (see [`ElaborateBuilder::profile`]), the elaborator records
+//! the wall time and the number of lisp evaluation steps for each top level statement,
+//! as well as the time spent in `refine`, `unify` and named lisp procedures during the
+//! statement. This is used by `mm0-rs compile --profile` and the server's code lenses.
+//!
+//! [`ElaborateBuilder::profile`]: super::ElaborateBuilder::profile
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use crate::ast::{Stmt, StmtKind};
+use crate::{ArcString, AtomId, FileRef, LinedString, Span};
+use super::Elaborator;
+
+/// The time spent in a named lisp procedure.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ProcProfile {
+  /// The number of calls to the procedure.
+  pub calls: u64,
+  /// The time spent in the procedure, including the procedures it calls.
+  /// Time spent in recursive calls is only counted once.
+  pub time: Duration,
+}
+
+/// The profiling data for a single top level statement.
+#[derive(Clone, Debug, Default)]
+pub struct StmtProfile {
+  /// The span of the statement.
+  pub span: Span,
+  /// The name of the declared sort, term or theorem, if any.
+  pub name: Option<ArcString>,
+  /// The wall time spent elaborating the statement.
+  pub time: Duration,
+  /// The number of lisp evaluation steps.
+  pub steps: u64,
+  /// The time spent in the `refine` state machine, including `unify` but not including
+  /// the tactics it calls.
+  pub refine: Duration,
+  /// The time spent unifying expressions.
+  pub unify: Duration,
+  /// The time spent in named lisp procedures, slowest first.
+  pub procs: Vec<(ArcString, ProcProfile)>,
+}
+
+/// Formats a duration in seconds, with millisecond precision.
+fn secs(d: Duration) -> String { format!("{:.3}s", d.as_secs_f64()) }
+
+impl StmtProfile {
+  /// A one line summary of the profile, like
+  /// `0.120s (refine 0.050s, unify 0.010s, 1234 steps; foo 0.070s)`.
+  #[must_use] pub fn summary(&self) -> String {
+    let mut s = format!("{} (refine {}, unify {}, {} steps",
+      secs(self.time), secs(self.refine), secs(self.unify), self.steps);
+    if let Some((name, p)) = self.procs.first() {
+      write!(s, "; {} {}", name, secs(p.time)).expect("writing to a string")
+    }
+    s.push(')');
+    s
+  }
+}
+
+/// The profiling data for a file.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+  /// The profiles of the statements that were elaborated, in file order.
+  pub stmts: Vec<StmtProfile>,
+  /// The total number of lisp evaluation steps so far.
+  pub(crate) steps: u64,
+  /// The time spent in each named procedure during the current statement.
+  pub(crate) procs: HashMap<AtomId, ProcProfile>,
+}
+#[cfg(feature = "memory")]
+mm0_deepsize::deep_size_0!({!Copy} Profile);
+
+impl Profile {
+  /// The statement profiles, slowest first.
+  #[must_use] pub fn sorted(&self) -> Vec<&StmtProfile> {
+    let mut res: Vec<_> = self.stmts.iter().collect();
+    res.sort_by_key(|s| Reverse(s.time));
+    res
+  }
+
+  /// Renders a profiling report for the file `path` with contents `text`. The statements
+  /// are listed slowest first, followed by the named procedures, slowest first.
+  #[must_use] pub fn report(&self, path: &FileRef, text: &LinedString) -> String {
+    let mut total = StmtProfile::default();
+    let mut procs = HashMap::<&ArcString, ProcProfile>::new();
+    for s in &self.stmts {
+      total.time += s.time;
+      total.refine += s.refine;
+      total.unify += s.unify;
+      total.steps += s.steps;
+      for (name, p) in &s.procs {
+        let q = procs.entry(name).or_default();
+        q.calls += p.calls;
+        q.time += p.time;
+      }
+    }
+    let mut out = format!("profile of {}: {} total, {} refine, {} unify, {} lisp steps\n",
+      path, secs(total.time), secs(total.refine), secs(total.unify), total.steps);
+    out.push_str("      time    refine     unify       steps  statement\n");
+    for s in self.sorted() {
+      let pos = text.to_pos(s.span.start);
+      let desc = match &s.name {
+        Some(name) => name.to_string(),
+        None => {
+          let line = text.str_at(s.span).lines().next().unwrap_or("");
+          match line.char_indices().nth(40) {
+            Some((i, _)) => format!("{}...", &line[..i]),
+            None => line.into(),
+          }
+        }
+      };
+      writeln!(out, "{:>10}{:>10}{:>10}{:>12}  {}:{}: {}", secs(s.time), secs(s.refine),
+        secs(s.unify), s.steps, path, pos.line + 1, desc).expect("writing to a string");
+      for (name, p) in s.procs.iter().take(3) {
+        writeln!(out, "{:>10} in {} call(s) to {}", secs(p.time), p.calls, name)
+          .expect("writing to a string");
+      }
+    }
+    let mut procs: Vec<_> = procs.into_iter().collect();
+    procs.sort_by_key(|(_, p)| Reverse(p.time));
+    if !procs.is_empty() { out.push_str("      time       calls  procedure\n") }
+    for (name, p) in procs {
+      writeln!(out, "{:>10}{:>12}  {}", secs(p.time), p.calls, name).expect("writing to a string");
+    }
+    out
+  }
+}
+
+/// The span of the name of the sort, term or theorem declared by `stmt`, if any.
+fn decl_name(stmt: &Stmt) -> Option<Span> {
+  match &stmt.k {
+    StmtKind::Annot(_, s) | StmtKind::DocComment(_, s) => decl_name(s),
+    StmtKind::Decl(d) => Some(d.id),
+    &StmtKind::Sort(id, _) => Some(id),
+    _ => None,
+  }
+}
+
+impl Elaborator {
+  /// Starts a new statement profile for `stmt`, if profiling is enabled.
+  /// The result should be passed to [`Elaborator::profile_finish`].
+  pub(crate) fn profile_start(&mut self, stmt: &Stmt) -> Option<(Instant, u64)> {
+    let p = self.profile.as_mut()?;
+    let name = decl_name(stmt).map(|sp| self.ast.span(sp).into());
+    p.stmts.push(StmtProfile {span: stmt.span, name, ..Default::default()});
+    Some((Instant::now(), p.steps))
+  }
+
+  /// Finishes the statement profile started by [`Elaborator::profile_start`].
+  pub(crate) fn profile_finish(&mut self, start: Option<(Instant, u64)>) {
+    let (start, steps) = if let Some(start) = start {start} else {return};
+    let p = if let Some(p) = &mut self.profile {p} else {return};
+    if let Some(s) = p.stmts.last_mut() {
+      s.time = start.elapsed();
+      s.steps = p.steps - steps;
+      let data = &self.env.data;
+      s.procs = p.procs.drain().map(|(a, pp)| (data[a].name.clone(), pp)).collect();
+      s.procs.sort_by_key(|(_, p)| Reverse(p.time));
+    }
+  }
+
+  /// Runs `f`, and if profiling is enabled, adds the time it took to the `field`
+  /// of the current statement profile.
+  pub(crate) fn profiled<T>(&mut self,
+    field: fn(&mut StmtProfile) -> &mut Duration,
+    f: impl FnOnce(&mut Self) -> T
+  ) -> T {
+    if self.profile.is_none() { return f(self) }
+    let start = Instant::now();
+    let res = f(self);
+    if let Some(s) = self.profile.as_mut().and_then(|p| p.stmts.last_mut()) {
+      *field(s) += start.elapsed()
+    }
+    res
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use std::time::Duration;
+  use crate::{LinedString, Span};
+  use super::{ProcProfile, Profile, StmtProfile};
+
+  fn stmt(text: &str, line: &str, name: Option<&str>, ms: u64, procs: &[(&str, u64)]
+  ) -> StmtProfile {
+    let start = text.find(line).unwrap();
+    StmtProfile {
+      span: Span {start, end: start + line.len()},
+      name: name.map(|s| s.as_bytes().into()),
+      time: Duration::from_millis(ms),
+      steps: ms,
+      procs: procs.iter().map(|&(name, ms)| (name.as_bytes().into(),
+        ProcProfile {calls: 1, time: Duration::from_millis(ms)})).collect(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn summary() {
+    let s = StmtProfile {
+      time: Duration::from_millis(120),
+      refine: Duration::from_millis(50),
+      unify: Duration::from_millis(10),
+      steps: 1234,
+      procs: vec![(b"foo"[..].into(), ProcProfile {calls: 2, time: Duration::from_millis(70)})],
+      ..Default::default()
+    };
+    assert_eq!(s.summary(), "0.120s (refine 0.050s, unify 0.010s, 1234 steps; foo 0.070s)");
+  }
+
+  #[test]
+  fn report() {
+    let long = "do { (a-long-lisp-expression-that-keeps-going 1 2 3) };";
+    let wide = "do { (display \"ééééééééééééééééééééééééééééééééééééé\") };";
+    let text = format!("theorem fast: $ a $;\n{}\n{}\n", long, wide);
+    let path = crate::compiler::test_file("profile.mm1", text.as_bytes());
+    let profile = Profile {
+      stmts: vec![
+        stmt(&text, "theorem fast: $ a $;", Some("fast"), 10, &[]),
+        stmt(&text, long, None, 30, &[("foo", 20), ("bar", 5)]),
+        stmt(&text, wide, None, 20, &[("foo", 10)]),
+      ],
+      ..Default::default()
+    };
+    let times = profile.sorted().into_iter().map(|s| s.time.as_millis()).collect::<Vec<_>>();
+    assert_eq!(times, [30, 20, 10]);
+    let report = profile.report(&path, &LinedString::from(text));
+    // statements slowest first, with long lines truncated to 40 characters,
+    // followed by the procedures over all statements
+    let trunc = |s: &str| s.chars().take(40).collect::<String>();
+    assert_eq!(report.lines().collect::<Vec<_>>(), [
+      &*format!("profile of {}: 0.060s total, 0.000s refine, 0.000s unify, 60 lisp steps", path),
+      "      time    refine     unify       steps  statement",
+      &format!("    0.030s    0.000s    0.000s          30  {}:2: {}...", path, trunc(long)),
+      "    0.020s in 1 call(s) to foo",
+      "    0.005s in 1 call(s) to bar",
+      &format!("    0.020s    0.000s    0.000s          20  {}:3: {}...", path, trunc(wide)),
+      "    0.010s in 1 call(s) to foo",
+      &format!("    0.010s    0.000s    0.000s          10  {}:1: fast", path),
+      "      time       calls  procedure",
+      "    0.030s           2  foo",
+      "    0.005s           1  bar",
+    ]);
+  }
+}
@@ -7,10 +7,10 @@ use std::ops::Index;
 use std::mem;
 use std::collections::{HashMap, hash_map::Entry};
 use crate::{AtomId, Type};
-use super::{LocalContext, ElabError, Result, Environment,
+use super::{LocalContext, ElabError, ElabErrorKind, Result, Environment,
   SortId, TermId, ThmId, ExprNode, ProofNode, DeclKey, Modifiers};
 use super::lisp::{LispVal, LispKind, Uncons, InferTarget, print::FormatEnv};
-use super::local_context::{InferSort, try_get_span_from};
+use super::local_context::{InferSort, hole_error, try_get_span_from};
 use crate::{BoxError, FileSpan};
 
 /// This struct represents the context for the hash-consing step of proof compaction
@@ -379,8 +379,10 @@ impl NodeHash for ExprHash {
         let head = u.next().ok_or_else(||
           nh.err_sp(fsp, format!("bad expression {}", nh.fe.to(r))))?;
         let a = head.as_atom().ok_or_else(|| nh.err(&head, "expected an atom"))?;
-        let tid = nh.fe.term(a).ok_or_else(||
-          nh.err(&head, format!("term '{}' not declared", nh.fe.data[a].name)))?;
+        let name = &nh.fe.data[a].name;
+        let tid = nh.fe.term(a).ok_or_else(|| ElabError::unknown(
+          try_get_span_from(&nh.fsp, head.fspan().as_ref()),
+          format!("term '{}' not declared", name), name))?;
         let mut ns = Vec::new();
         for e in &mut u { ns.push(de.dedup(nh, ProofKind::Expr, &e)?) }
         if !u.exactly(0) {
@@ -463,6 +465,50 @@ impl Environment {
       }
     }
   }
+
+  /// Convert a [`ProofNode`] object to a [`LispVal`], under a context `heap`
+  /// (which should contain the variables and the previous proof heap elements).
+  /// Hypotheses are converted to their names in `hyps`, and any
+  /// [`Dummy`](ProofNode::Dummy) nodes that are encountered are added to `ds`.
+  pub fn proof_node(&self, hyps: &[(Option<AtomId>, ExprNode)],
+    heap: &[LispVal], ds: &mut Vec<LispVal>, p: &ProofNode) -> LispVal {
+    match *p {
+      ProofNode::Ref(n) => heap[n].clone(),
+      ProofNode::Dummy(a, s) => {
+        let a = LispVal::atom(a);
+        ds.push(LispVal::list(vec![a.clone(), LispVal::atom(self.sorts[s].atom)]));
+        a
+      }
+      ProofNode::Term {term, args: ref es} |
+      ProofNode::Cong {term, args: ref es} => {
+        let mut args = vec![LispVal::atom(self.terms[term].atom)];
+        args.extend(es.iter().map(|e| self.proof_node(hyps, heap, ds, e)));
+        LispVal::list(args)
+      }
+      ProofNode::Hyp(h, _) => LispVal::atom(hyps[h].0.unwrap_or(AtomId::UNDER)),
+      ProofNode::Thm {thm, args: ref es, ..} => {
+        let mut args = vec![LispVal::atom(self.thms[thm].atom)];
+        args.extend(es.iter().map(|e| self.proof_node(hyps, heap, ds, e)));
+        LispVal::list(args)
+      }
+      ProofNode::Conv(ref es) => {
+        let (t, c, p) = &**es;
+        LispVal::list(vec![LispVal::atom(AtomId::CONV),
+          self.proof_node(hyps, heap, ds, t),
+          self.proof_node(hyps, heap, ds, c),
+          self.proof_node(hyps, heap, ds, p),
+        ])
+      }
+      ProofNode::Refl(ref p) => self.proof_node(hyps, heap, ds, p),
+      ProofNode::Sym(ref p) =>
+        LispVal::list(vec![LispVal::atom(AtomId::SYM), self.proof_node(hyps, heap, ds, p)]),
+      ProofNode::Unfold {term, ref args, ref res} =>
+        LispVal::list(vec![LispVal::atom(AtomId::UNFOLD),
+          LispVal::atom(self.terms[term].atom),
+          LispVal::list(args.iter().map(|e| self.proof_node(hyps, heap, ds, e)).collect::<Vec<_>>()),
+          self.proof_node(hyps, heap, ds, &res.1)]),
+    }
+  }
 }
 
 /// The [`NodeHash`] version of [`ProofNode`]. It has the same structure except that
@@ -596,7 +642,8 @@ impl NodeHash for ProofHash {
       },
       LispKind::MVar(_, tgt) => return Err(nh.err_sp(fsp,
         format!("{}: {}", nh.fe.to(r), nh.fe.to(tgt)))),
-      LispKind::Goal(tgt) => return Err(nh.err_sp(fsp, format!("|- {}", nh.fe.to(tgt)))),
+      LispKind::Goal(tgt) =>
+        return Err(hole_error(nh.fe, nh.lc, try_get_span_from(&nh.fsp, fsp), tgt)),
       _ => {
         let mut u = Uncons::from(r.clone());
         let th_head = u.next().ok_or_else(||
@@ -658,6 +705,7 @@ impl NodeHash for ProofHash {
                 }
                 let mut err = format!("disjoint variable violation at {}", adata.name);
                 let args: Vec<_> = Uncons::from(r.clone()).skip(1).collect();
+                let mut missing = vec![];
                 for (i, j) in dvs {
                   if de.vec[ns[i]].2 & de.vec[ns[j]].2 != 0 {
                     use std::fmt::Write;
@@ -665,9 +713,14 @@ impl NodeHash for ProofHash {
                       nh.fe.to(&td.args[i].0.unwrap_or(AtomId::UNDER)),
                       nh.fe.to(&td.args[j].0.unwrap_or(AtomId::UNDER)),
                       nh.fe.pp(&args[i], 80), nh.fe.pp(&args[j], 80)).unwrap();
+                    // Only a regular variable can be fixed by adding a dependency
+                    if let ((Some(x), _), (Some(a), Type::Reg(..))) = (&td.args[i], &td.args[j]) {
+                      missing.push((nh.fe.data[*x].name.clone(), nh.fe.data[*a].name.clone()))
+                    }
                   }
                 }
-                return Err(nh.err(&th_head, err))
+                return Err(ElabError::new(try_get_span_from(&nh.fsp, th_head.fspan().as_ref()),
+                  ElabErrorKind::DisjointVars(err.into(), td.span.clone(), missing.into())))
               }
             }
             let rhs = Self::subst(de, &td.heap, &mut heap, &td.ret);
@@ -707,7 +760,9 @@ impl NodeHash for ProofHash {
               let l2 = Self::conv_side(de, c, false);
               ProofHash::Unfold(tid, ns.into(), lhs, l2, c)
             },
-            _ => return Err(nh.err(&th_head, format!("term/theorem '{}' not declared", adata.name)))
+            _ => return Err(ElabError::unknown(
+              try_get_span_from(&nh.fsp, th_head.fspan().as_ref()),
+              format!("term/theorem '{}' not declared", adata.name), &adata.name))
           }
         }
       }
@@ -475,8 +475,9 @@ impl Elaborator {
         let mut u = Uncons::from(e.clone());
         let head = u.next().ok_or_else(|| err!(e, "not a term"))?;
         let a = head.as_atom().ok_or_else(|| err!(head, "expected an atom"))?;
-        let tid = self.term(a).ok_or_else(||
-          err!(head, format!("unknown term '{}'", self.data[a].name)))?;
+        let name = &self.data[a].name;
+        let tid = self.term(a).ok_or_else(|| ElabError::unknown(
+          try_get_span(&self.fspan(sp), &head), format!("unknown term '{}'", name), name))?;
         let sort = self.env.terms[tid].ret.0;
         InferTarget::Reg(self.sorts[sort].atom)
       }
@@ -501,8 +502,9 @@ impl Elaborator {
         match head.as_atom().ok_or_else(|| err!(head, "expected an atom"))? {
           AtomId::CONV => u.next().ok_or_else(|| err!(e, "bad :conv"))?,
           a => {
-            let tid = self.thm(a).ok_or_else(||
-              err!(head, format!("unknown theorem '{}'", self.data[a].name)))?;
+            let name = &self.data[a].name;
+            let tid = self.thm(a).ok_or_else(|| ElabError::unknown(
+              try_get_span(&self.fspan(sp), &head), format!("unknown theorem '{}'", name), name))?;
             let tdata = &self.env.thms[tid];
             let num_args = tdata.args.len();
             let mut args = Vec::with_capacity(num_args);
@@ -579,7 +581,8 @@ impl Elaborator {
   /// Unify expressions `e1` and `e2`. Returns a conversion proof
   /// `u: e1 = e2`, with `#undef` meaning that `e1` and `e2` are equal after unification.
   fn unify(&mut self, sp: Span, e1: &LispVal, e2: &LispVal) -> Result<LispVal> {
-    self.unify1(e1, e2).map_err(|e| ElabError::new_e(sp, e))
+    self.profiled(|p| &mut p.unify, |this| this.unify1(e1, e2))
+      .map_err(|e| ElabError::new_e(sp, e))
   }
 
   /// Unify expressions `e1` and `e2`. Returns a conversion proof
@@ -751,8 +754,9 @@ impl Elaborator {
             } else if let Some(DeclKey::Thm(t)) = self.data[a].decl {
               RState::RefineBis {sp, sp2, tgt, im, t, args: vec![head], u}
             } else {
-              return Err(ElabError::new_e(sp2, format!(
-                "unknown theorem/hypothesis '{}'", self.data[a].name)))
+              let name = &self.data[a].name;
+              return Err(ElabError::unknown(sp2,
+                format!("unknown theorem/hypothesis '{}'", name), name))
             }
           }
           RefineExpr::Typed {ty, e: q} => {
@@ -789,7 +793,8 @@ impl Elaborator {
               self.lc.vars.insert(a, (true, InferSort::Bound(sort)));
               RState::Ret(head)
             } else {
-              return Err(ElabError::new_e(sp, format!("unknown term '{}'", self.data[a].name)))
+              let name = &self.data[a].name;
+              return Err(ElabError::unknown(sp, format!("unknown term '{}'", name), name))
             }
           }
           Ok(RefineExpr::Typed {ty: s, e}) => {
@@ -850,6 +855,13 @@ impl Elaborator {
               }
               args.push(self.lc.new_mvar(tgt1, Some(self.fspan(sp2))))
             }
+            // The binders which are still metavariables here are the ones to be inferred
+            // by unification; record them so that their final values can be shown
+            let inferred = tdata.args.iter().zip(&args[1..]).filter(|(_, e)| e.is_mvar())
+              .map(|(&(x, _), e)| (x.unwrap_or(AtomId::UNDER), e.clone())).collect::<Vec<_>>();
+            if !inferred.is_empty() {
+              self.spans.insert_hint_if(sp2, || ObjectKind::inferred_args(inferred));
+            }
             let mut subst = Subst::new(&self.env, &tdata.heap, Vec::from(&args[1..]));
             let hyps = tdata.hyps.iter().map(|(_, h)| subst.subst(h)).collect::<Vec<_>>();
             let ret = subst.subst(&tdata.ret);
@@ -0,0 +1,205 @@
+//! An interactive read-eval-print loop for MM1 lisp, the `mm0-rs repl` subcommand.
+//!
+//! The REPL elaborates the input file (if any) using [`ElaborateBuilder::elab_keep`], and then
+//! reads lisp expressions from standard input and evaluates them in the resulting environment,
+//! as if they were written in a `do` block at the end of the file. The session state,
+//! including lisp definitions and the proof state, persists between inputs.
+//! Input lines starting with `:` are REPL commands; see [`HELP`].
+//!
+//! [`ElaborateBuilder::elab_keep`]: super::ElaborateBuilder::elab_keep
+
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::sync::Arc;
+use std::time::Instant;
+use clap::ArgMatches;
+use mm1_parser::Parser;
+use crate::ast::Ast;
+use crate::{DeclKey, ErrorLevel, FileRef};
+use super::{Elaborator, ElabError, spans::Spans};
+
+/// The help message for the `:help` command.
+const HELP: &str = "\
+Enter lisp expressions to evaluate them. Commands:
+  :goals          Show the current proof state
+  :decl NAME      Show the statement of the sort, term or theorem NAME
+  :get-decl NAME  Show the result of (get-decl 'NAME)
+  :help           Show this message
+  :quit           Exit the REPL (or use Ctrl-D)";
+
+/// Returns true if `s` has unclosed parentheses, brackets, braces, strings or math
+/// formulas, meaning that the expression continues on the next line.
+fn incomplete(s: &str) -> bool {
+  let mut depth = 0_i32;
+  let mut delim = None;
+  let mut it = s.bytes().peekable();
+  while let Some(c) = it.next() {
+    match (delim, c) {
+      (Some(b'"'), b'\\') => { it.next(); }
+      (Some(d), _) if c == d => delim = None,
+      (None, b'"' | b'$') => delim = Some(c),
+      (None, b'(' | b'[' | b'{') => depth += 1,
+      (None, b')' | b']' | b'}') => depth -= 1,
+      (None, b'-') if it.peek() == Some(&b'-') =>
+        while !matches!(it.next(), None | Some(b'\n')) {},
+      _ => {}
+    }
+  }
+  delim.is_some() || depth > 0
+}
+
+/// Removes leading whitespace and line comments from `s`.
+fn skip_ws(mut s: &str) -> &str {
+  loop {
+    s = s.trim_start();
+    if !s.starts_with("--") { return s }
+    s = s.find('\n').map_or("", |i| &s[i..])
+  }
+}
+
+impl Elaborator {
+  /// Prints the messages reported since the last call, and clears them.
+  fn repl_flush_errors(&mut self) {
+    for e in mem::take(&mut self.errors) {
+      match e.level {
+        ErrorLevel::Info => println!("{}", e.kind.msg()),
+        level => println!("{}: {}", level, e.kind.msg()),
+      }
+    }
+  }
+
+  /// Appends `input` to the source text, and parses and evaluates the lisp expressions in it,
+  /// printing each result. The source text is only copied if it is shared, so it is normally
+  /// extended in place.
+  fn repl_eval(&mut self, input: &str) {
+    let mut source = match Arc::try_unwrap(mem::take(&mut self.ast)) {
+      Ok(ast) => ast.source,
+      Err(ast) => ast.source.clone(),
+    };
+    let text = Arc::make_mut(&mut source);
+    text.extend("\n");
+    let start = text.len();
+    text.extend(skip_ws(input));
+    self.ast = Arc::new(Ast {source: source.clone(), ..Ast::default()});
+    let mut p = Parser {
+      source: source.as_bytes(), errors: vec![], imports: vec![], idx: start, restart_pos: None
+    };
+    self.spans.set_stmt((start..source.len()).into());
+    while p.idx < source.len() {
+      let e = p.sexpr();
+      for e in p.errors.drain(..) { println!("{}: {}", e.level, e.msg) }
+      let e = match e {
+        Ok(e) => e,
+        Err(e) => { println!("{}: {}", e.level, e.msg); break }
+      };
+      self.cur_timeout = self.timeout.and_then(|d| Instant::now().checked_add(d));
+      let res = self.eval_lisp(&e);
+      self.repl_flush_errors();
+      match res {
+        Ok(val) => if val.is_def() { println!("{}", self.print(&val)) },
+        Err(ElabError {level, kind, ..}) => println!("{}: {}", level, kind.msg()),
+      }
+    }
+    self.spans = Spans::new();
+  }
+
+  /// Prints the statement of the sort, term or theorem `name`.
+  fn repl_decl(&self, name: &str) {
+    let ad = if let Some(&a) = self.env.atoms.get(name.as_bytes()) { &self.data[a] } else {
+      println!("error: unknown declaration '{}'", name);
+      return
+    };
+    if let Some(s) = ad.sort { println!("{}", self.sorts[s]) }
+    match ad.decl {
+      Some(DeclKey::Term(t)) => println!("{}", self.print(&self.terms[t])),
+      Some(DeclKey::Thm(t)) => println!("{}", self.print(&self.thms[t])),
+      None if ad.sort.is_none() => println!("error: unknown declaration '{}'", name),
+      None => {}
+    }
+  }
+
+  /// Prints the current proof state.
+  fn repl_goals(&self) {
+    let stat = self.stat();
+    if stat.is_empty() { println!("no goals") } else { print!("{}", stat) }
+  }
+}
+
+/// Main entry point for the `mm0-rs repl` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs repl [file.mm1]`, where `file.mm1` is the file to elaborate before starting
+/// the REPL. If it is omitted, the REPL starts in an empty environment.
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let path = match args.value_of("INPUT") {
+    Some(path) => Some(FileRef::from(std::fs::canonicalize(path)?)),
+    None => None,
+  };
+  let mut elab = crate::compiler::elab_for_repl(path)?;
+  elab.arena.install_thread_local();
+  println!("MM1 lisp REPL. Type :help for a list of commands.");
+  let stdin = io::stdin();
+  let mut lines = stdin.lock().lines();
+  loop {
+    let mut input = String::new();
+    loop {
+      print!("{}", if input.is_empty() {"> "} else {"| "});
+      io::stdout().flush()?;
+      match lines.next() {
+        None => { println!(); return Ok(()) }
+        Some(line) => { input.push_str(&line?); input.push('\n') }
+      }
+      if !incomplete(&input) { break }
+    }
+    let cmd = input.trim();
+    if let Some(cmd) = cmd.strip_prefix(':') {
+      let (cmd, arg) = cmd.split_once(char::is_whitespace).map_or((cmd, ""), |(c, a)| (c, a.trim()));
+      match cmd {
+        "q" | "quit" => return Ok(()),
+        "h" | "help" => println!("{}", HELP),
+        "goals" => elab.repl_goals(),
+        "decl" | "get-decl" if arg.is_empty() => println!("error: :{} expects a name", cmd),
+        "decl" => elab.repl_decl(arg),
+        "get-decl" => elab.repl_eval(&format!("(get-decl '{})", arg)),
+        _ => println!("error: unknown command ':{}', type :help for a list of commands", cmd),
+      }
+    } else if !skip_ws(cmd).is_empty() {
+      elab.repl_eval(cmd)
+    }
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::wildcard_imports)]
+mod tests {
+  use crate::compiler::{elab_keep, test_file};
+  use super::*;
+
+  fn lisp_int(elab: &Elaborator, name: &str) -> Option<i64> {
+    let a = *elab.env.atoms.get(name.as_bytes())?;
+    elab.data[a].lisp.as_ref()?.as_int(|n| i64::try_from(n).unwrap())
+  }
+
+  #[test]
+  fn eval_keeps_state() {
+    let path = test_file("repl.mm1", b"do { (def x 1) };");
+    let (_, _, errors, mut elab) = elab_keep(Some(path), None).unwrap();
+    assert!(errors.is_empty());
+    elab.arena.install_thread_local();
+    elab.repl_eval("(def y {x + 1})");
+    let source = Arc::as_ptr(&elab.ast.source);
+    elab.repl_eval("-- a comment\n(def z {y * 2}) (def w z)");
+    elab.repl_eval("(def (f a) (list a a)) (f w)");
+    assert_eq!(lisp_int(&elab, "z"), Some(4));
+    assert_eq!(lisp_int(&elab, "w"), Some(4));
+    // the source text is extended in place, not copied
+    assert_eq!(Arc::as_ptr(&elab.ast.source), source);
+    let source: &str = &elab.ast.source;
+    assert!(source.ends_with("\n(def z {y * 2}) (def w z)\n(def (f a) (list a a)) (f w)"), "{}", source);
+    // looking up an unknown name does not intern it
+    elab.repl_decl("no-such-decl");
+    assert!(!elab.env.atoms.contains_key(&b"no-such-decl"[..]));
+    crate::elab::lisp::LispArena::uninstall_thread_local();
+  }
+}
@@ -31,6 +31,10 @@ pub struct Spans<T> {
   /// The actual data associated to spans. They are indexed by span start, and one
   /// start point can contain many spans, even multiple data elements at the same span.
   data: BTreeMap<usize, Vec<(Span, T)>>,
+  /// Data that is displayed inline at the end of its span, rather than on hover. These are
+  /// kept separate from `data` because they usually share a span with another data element.
+  /// They are indexed by span start, and there is at most one hint per start point.
+  hints: BTreeMap<usize, (Span, T)>,
 }
 
 impl<'a, T> IntoIterator for &'a Spans<T> {
@@ -59,7 +63,8 @@ impl<T> Spans<T> {
       stmt: MaybeUninit::uninit(),
       decl: MaybeUninit::uninit(),
       lc: None,
-      data: BTreeMap::new()
+      data: BTreeMap::new(),
+      hints: BTreeMap::new(),
     }
   }
 
@@ -128,6 +133,18 @@ impl<T> Spans<T> {
     }
   }
 
+  /// Insert a hint at a given span, if it lies within the current statement's extent.
+  /// Unlike [`insert_if`](Self::insert_if), this replaces any previous hint at the same
+  /// position, because when a tactic is re-run only the last run is relevant.
+  pub fn insert_hint_if(&mut self, sp: Span, val: impl FnOnce() -> T) {
+    if sp.start >= self.stmt().start {
+      self.hints.insert(sp.start, (sp, val()));
+    }
+  }
+
+  /// Returns an iterator over all hints in the statement, ordered by position.
+  pub fn hints(&self) -> impl Iterator<Item=&(Span, T)> { self.hints.values() }
+
   /// Get the data at a given [`Span`].
   /// If multiple data elements exist at this span, only the first will be returned.
   #[must_use] pub fn get(&self, sp: Span) -> Option<&T> {
@@ -0,0 +1,661 @@
+//! Source formatter for MM0 and MM1 files, used by `mm0-rs fmt` and by the
+//! `textDocument/formatting` request of the server.
+//!
+//! Statements are re-emitted from the [`Ast`] with canonical spacing: binder groups are
+//! kept as written but normalized to `(x y: ty)` form, math strings are re-wrapped to the
+//! line width, and lisp expressions (such as proofs and `do` blocks) are laid out in the
+//! same style as [`lisp::pretty`](crate::elab::lisp::pretty). Since the AST does not record
+//! comments, lisp code is laid out from a lossless token tree of the source instead,
+//! and comments between statements are kept with only their whitespace normalized.
+//! A statement containing a comment that the formatter cannot place, for example
+//! between two binders, is left exactly as written. Files with an `input` statement are
+//! not formatted at all, because such a statement checks the text of the file itself.
+
+use std::{fs, io};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::io::Write;
+use std::sync::Arc;
+use clap::ArgMatches;
+use pretty::DocAllocator;
+use mm1_parser::ast::{Binder, Decl, DeclKind, Delimiter, GenNota, Literal, LocalKind,
+  SimpleNota, SimpleNotaKind, Stmt, StmtKind, Type};
+use mm1_parser::whitespace;
+use crate::{ast::Ast, LinedString, Span};
+
+/// The default line width of formatted output.
+pub const WIDTH: usize = 80;
+
+type Doc<'a> = pretty::Doc<'a, RefDoc<'a>, ()>;
+type RefDoc<'a> = pretty::RefDoc<'a, ()>;
+type Arena<'a> = pretty::Arena<'a, ()>;
+
+/// A token in the source, as seen by the formatter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Tok {
+  /// An opening bracket `(`, `[` or `{`.
+  Open,
+  /// A closing bracket `)`, `]` or `}`.
+  Close,
+  /// A quotation prefix `'`, `` ` `` or `,`.
+  Prefix,
+  /// An atom, number, string, math string or `;`.
+  Word,
+  /// A line comment or doc comment, not including the newline.
+  Comment,
+}
+
+/// Split the source in `sp` into tokens, dropping whitespace.
+fn lex(src: &[u8], sp: Span) -> Vec<(Tok, Span)> {
+  let mut toks = vec![];
+  let mut i = sp.start;
+  while i < sp.end {
+    let start = i;
+    let tk = match src[i] {
+      c if whitespace(c) => { i += 1; continue }
+      b'(' | b'[' | b'{' => { i += 1; Tok::Open }
+      b')' | b']' | b'}' => { i += 1; Tok::Close }
+      b'\'' | b'`' | b',' => { i += 1; Tok::Prefix }
+      b';' => { i += 1; Tok::Word }
+      b'-' if src.get(i + 1) == Some(&b'-') => {
+        while i < sp.end && src[i] != b'\n' { i += 1 }
+        Tok::Comment
+      }
+      b'"' => {
+        i += 1;
+        while i < sp.end {
+          match (src[i], i += 1).0 {
+            b'\\' => i += 1,
+            b'"' => break,
+            _ => {}
+          }
+        }
+        Tok::Word
+      }
+      b'$' => {
+        i += 1;
+        while i < sp.end && (src[i], i += 1).0 != b'$' {}
+        Tok::Word
+      }
+      _ => {
+        while i < sp.end && !whitespace(src[i]) && !b"()[]{}'`,;\"$".contains(&src[i]) { i += 1 }
+        Tok::Word
+      }
+    };
+    toks.push((tk, (start..i.min(sp.end)).into()))
+  }
+  toks
+}
+
+/// A node of the token tree used to lay out lisp code.
+#[derive(Debug)]
+enum Node {
+  /// An atom, number, string or math string.
+  Word(Span),
+  /// A quotation prefix applied to an expression.
+  Prefix(Span, Box<Node>),
+  /// A bracketed list, with the spans of the open and close brackets.
+  List(Span, Vec<Node>, Span),
+  /// A comment. The flag is true if the comment starts its own line.
+  Comment(Span, bool),
+}
+
+impl Node {
+  fn span(&self) -> Span {
+    match *self {
+      Node::Word(sp) | Node::Comment(sp, _) => sp,
+      Node::Prefix(sp, ref e) => (sp.start..e.span().end).into(),
+      Node::List(open, _, close) => (open.start..close.end).into(),
+    }
+  }
+}
+
+/// Builds a [`Node`] tree out of a token stream.
+struct Tree<'a> {
+  src: &'a [u8],
+  toks: Vec<(Tok, Span)>,
+  idx: usize,
+}
+
+impl<'a> Tree<'a> {
+  /// Parse the source in `sp` as a sequence of nodes. Returns `None` if the brackets
+  /// are unbalanced or a quotation prefix is not followed by an expression.
+  fn parse(src: &'a [u8], sp: Span) -> Option<Vec<Node>> {
+    let mut tree = Tree { src, toks: lex(src, sp), idx: 0 };
+    let es = tree.nodes()?;
+    if tree.idx == tree.toks.len() { Some(es) } else { None }
+  }
+
+  fn nodes(&mut self) -> Option<Vec<Node>> {
+    let mut es = vec![];
+    while let Some(&(tk, _)) = self.toks.get(self.idx) {
+      if tk == Tok::Close { break }
+      es.push(self.node()?)
+    }
+    Some(es)
+  }
+
+  fn node(&mut self) -> Option<Node> {
+    let (tk, sp) = *self.toks.get(self.idx)?;
+    self.idx += 1;
+    Some(match tk {
+      Tok::Word => Node::Word(sp),
+      Tok::Comment => Node::Comment(sp, self.idx == 1 ||
+        self.src[self.toks[self.idx - 2].1.end..sp.start].contains(&b'\n')),
+      Tok::Prefix => match self.toks.get(self.idx)?.0 {
+        Tok::Close | Tok::Comment => return None,
+        _ => Node::Prefix(sp, Box::new(self.node()?)),
+      },
+      Tok::Open => {
+        let es = self.nodes()?;
+        let &(_, close) = self.toks.get(self.idx)?;
+        self.idx += 1;
+        Node::List(sp, es, close)
+      }
+      Tok::Close => return None,
+    })
+  }
+}
+
+/// Returns true if there is a blank line in the given whitespace.
+fn has_blank_line(s: &str) -> bool { s.matches('\n').nth(1).is_some() }
+
+struct Printer<'a> {
+  src: &'a str,
+  alloc: &'a Arena<'a>,
+  /// The number of comments emitted in the current statement.
+  comments: Cell<usize>,
+}
+
+impl<'a> Printer<'a> {
+  fn alloc(&self, doc: Doc<'a>) -> RefDoc<'a> { self.alloc.alloc(doc) }
+  fn str(&self, sp: Span) -> &'a str { &self.src[sp.start..sp.end] }
+  fn text(&self, s: impl Into<Cow<'a, str>>) -> RefDoc<'a> { self.alloc(Doc::text(s)) }
+  fn span(&self, sp: Span) -> RefDoc<'a> { self.text(self.str(sp)) }
+  fn nil(&self) -> RefDoc<'a> { self.alloc(Doc::Nil) }
+  fn hardline(&self) -> RefDoc<'a> { self.alloc(Doc::Line) }
+  fn line(&self) -> RefDoc<'a> { self.alloc(Doc::FlatAlt(self.hardline(), self.text(" "))) }
+  fn softline(&self) -> RefDoc<'a> { self.group(self.line()) }
+  fn group(&self, doc: RefDoc<'a>) -> RefDoc<'a> { self.alloc(Doc::Group(doc)) }
+  fn nest(&self, doc: RefDoc<'a>) -> RefDoc<'a> { self.alloc(Doc::Nest(2, doc)) }
+
+  fn cat(&self, docs: impl IntoIterator<Item=RefDoc<'a>>) -> RefDoc<'a> {
+    docs.into_iter().fold(self.nil(), |a, b| self.alloc(Doc::Append(a, b)))
+  }
+
+  fn comment(&self, sp: Span) -> RefDoc<'a> {
+    self.comments.set(self.comments.get() + 1);
+    self.text(self.str(sp).trim_end())
+  }
+
+  /// A math string with the given tokens, `$ a b c $`, filling lines to the width.
+  fn math(&self, words: impl IntoIterator<Item=Cow<'a, str>>) -> RefDoc<'a> {
+    let mut words = words.into_iter();
+    let mut doc = match words.next() {
+      None => return self.text("$ $"),
+      Some(w) => self.cat([self.text("$ "), self.text(w)]),
+    };
+    for w in words { doc = self.cat([doc, self.softline(), self.text(w)]) }
+    self.group(self.nest(self.cat([doc, self.text(" $")])))
+  }
+
+  /// Re-wrap the math string at `sp`, which includes the `$` delimiters.
+  fn formula(&self, sp: Span) -> RefDoc<'a> {
+    self.math(self.src[sp.start + 1..sp.end - 1].split(|c: char| c == ' ' || c == '\n')
+      .filter(|w| !w.is_empty()).map(Cow::Borrowed))
+  }
+
+  /// Returns true if `e` is an atom, number or string, possibly quoted.
+  fn is_atom(&self, e: &Node) -> bool {
+    match e {
+      &Node::Word(sp) => self.src.as_bytes()[sp.start] != b'$',
+      Node::Prefix(_, e) => self.is_atom(e),
+      Node::List(..) | Node::Comment(..) => false,
+    }
+  }
+
+  /// Returns true if `es` are all atoms and there is no `@`, so that the list
+  /// `(f x y)` is small but `(f @ g x)` is not.
+  fn is_small(&self, es: &[Node]) -> bool {
+    es.iter().all(|e| self.is_atom(e) && !matches!(*e, Node::Word(sp) if self.str(sp) == "@"))
+  }
+
+  /// Lay out a node of the token tree, returning true if the result is small enough
+  /// to keep on the line of the enclosing list head. These are atoms, and lists of atoms
+  /// like `(f x y)`.
+  fn node(&self, e: &Node) -> (bool, RefDoc<'a>) {
+    match e {
+      &Node::Word(sp) if self.src.as_bytes()[sp.start] == b'$' => (false, self.formula(sp)),
+      &Node::Word(sp) => (true, self.span(sp)),
+      Node::Prefix(sp, e) => {
+        let (small, doc) = self.node(e);
+        (small, self.cat([self.span(*sp), doc]))
+      }
+      &Node::Comment(sp, _) => (false, self.comment(sp)),
+      Node::List(open, es, close) => (self.is_small(es), self.list(*open, es, *close)),
+    }
+  }
+
+  /// Lay out a list like [`lisp::pretty`](crate::elab::lisp::pretty) does: if the list does
+  /// not fit on the line, the first argument and small arguments are kept on the line of
+  /// the head, and after the first argument that is not small, the remaining arguments
+  /// go on their own lines.
+  /// Comments end the line they are on.
+  fn list(&self, open: Span, es: &[Node], close: Span) -> RefDoc<'a> {
+    let (args, tail, after_comment) = self.body(es);
+    let doc = self.nest(self.cat([self.span(open), args, tail]));
+    let doc = if after_comment { self.cat([doc, self.hardline()]) } else { doc };
+    self.group(self.cat([doc, self.span(close)]))
+  }
+
+  /// Lay out the elements of a list, returning true if it ends with a comment. An `@`
+  /// starts a new list that extends to the end, so in `(f x @ g y @ h z)` each of
+  /// `g y @ h z` and `h z` is laid out as if it were a list of its own, which is small
+  /// if all its elements are atoms. The elements before the `@` are returned separately
+  /// from the `@` and the elements after it, because a tail that starts a line indents
+  /// its arguments but not its own tail.
+  fn body(&self, es: &[Node]) -> (RefDoc<'a>, RefDoc<'a>, bool) {
+    let mut doc = self.nil();
+    let (mut first, mut broken, mut after_comment) = (true, false, false);
+    let mut prefix_small = true;
+    for (i, e) in es.iter().enumerate() {
+      let tail = !first && matches!(*e, Node::Word(sp) if self.str(sp) == "@") &&
+        matches!(es.get(i + 1), Some(e2) if !matches!(e2, Node::Comment(..)));
+      let (small, doc2) = if tail {
+        (self.is_small(&es[i + 1..]), None)
+      } else {
+        let (small, doc2) = self.node(e);
+        (small, Some(doc2))
+      };
+      let sep = if let Node::Comment(_, own_line) = *e {
+        after_comment = true;
+        if own_line && !first { self.hardline() } else { self.text(" ") }
+      } else {
+        let sep = if first {
+          self.nil()
+        } else if after_comment {
+          self.hardline()
+        } else if i == 1 && self.is_atom(&es[0]) || self.is_atom(e) {
+          self.softline()
+        } else if broken || !small && (!tail || !prefix_small) {
+          broken = true;
+          self.line()
+        } else {
+          self.softline()
+        };
+        after_comment = false;
+        sep
+      };
+      first = false;
+      prefix_small &= small;
+      if let Some(doc2) = doc2 {
+        doc = self.cat([doc, sep, doc2]);
+      } else {
+        let (args, tail, after_comment) = self.body(&es[i + 1..]);
+        let alloc = self.alloc;
+        let tail = alloc.column(move |col| alloc.nesting(move |ind| {
+          let args = if col == ind { alloc.alloc(Doc::Nest(2, args)) } else { args };
+          let body = alloc.alloc(Doc::Group(alloc.alloc(Doc::Append(args, tail))));
+          alloc.alloc(Doc::Append(alloc.alloc(Doc::text("@ ")), body))
+        }).into_doc()).into_doc();
+        return (doc, self.cat([sep, tail]), after_comment)
+      }
+    }
+    (doc, self.nil(), after_comment)
+  }
+
+  /// Lay out the lisp expression at `sp`.
+  fn sexpr(&self, sp: Span) -> Option<RefDoc<'a>> {
+    match &*Tree::parse(self.src.as_bytes(), sp)? {
+      [e] if !matches!(e, Node::Comment(..)) => Some(self.node(e).1),
+      _ => None,
+    }
+  }
+
+  fn ty(&self, ty: &Type) -> RefDoc<'a> {
+    match ty {
+      Type::DepType(d) => {
+        let mut s = self.str(d.sort).to_owned();
+        for &x in &*d.deps { s.push(' '); s.push_str(self.str(x)) }
+        self.text(s)
+      }
+      Type::Formula(f) => self.formula(f.0),
+    }
+  }
+
+  /// Append the named binder groups in `bis` to `doc`. Binders that were written
+  /// together, like `(x y: nat)`, share a span and are kept together.
+  fn binders(&self, mut doc: RefDoc<'a>, bis: &[Binder]) -> RefDoc<'a> {
+    let local = |bi: &Binder| {
+      let x = self.str(bi.local.expect("named binder"));
+      if bi.kind == LocalKind::Dummy { self.text(format!(".{}", x)) } else { self.text(x) }
+    };
+    let mut it = bis.iter().filter(|bi| bi.local.is_some()).peekable();
+    while let Some(bi) = it.next() {
+      let curly = self.src.as_bytes()[bi.span.start] == b'{';
+      let mut group = self.cat([self.text(if curly {"{"} else {"("}), local(bi)]);
+      while let Some(bi2) = it.next_if(|bi2| bi2.span == bi.span) {
+        group = self.cat([group, self.text(" "), local(bi2)])
+      }
+      if let Some(ty) = &bi.ty { group = self.cat([group, self.text(": "), self.ty(ty)]) }
+      group = self.cat([group, self.text(if curly {"}"} else {")"})]);
+      doc = self.cat([doc, self.softline(), group])
+    }
+    doc
+  }
+
+  fn decl(&self, d: &Decl) -> Option<RefDoc<'a>> {
+    let kw = match d.k {
+      DeclKind::Term => "term",
+      DeclKind::Axiom => "axiom",
+      DeclKind::Thm => "theorem",
+      DeclKind::Def => "def",
+    };
+    let head = self.cat([self.text(format!("{}{} ", d.mods, kw)), self.span(d.id)]);
+    let head = self.binders(head, &d.bis);
+    let mut tys = d.bis.iter().filter(|bi| bi.local.is_none())
+      .filter_map(|bi| bi.ty.as_ref()).chain(&d.ty);
+    let mut doc = match tys.next() {
+      None => self.group(self.nest(head)),
+      Some(ty) => {
+        let mut ret = self.ty(ty);
+        for ty in tys { ret = self.cat([ret, self.text(" >"), self.line(), self.ty(ty)]) }
+        let head = self.group(self.nest(self.cat([head, self.text(":")])));
+        self.group(self.nest(self.cat([head, self.line(), self.group(ret)])))
+      }
+    };
+    if let Some(val) = &d.val {
+      let val = self.group(self.nest(self.cat([self.line(), self.sexpr(val.span)?])));
+      doc = self.cat([doc, self.text(" ="), val])
+    }
+    Some(self.cat([doc, self.text(";")]))
+  }
+
+  fn notation(&self, n: &GenNota) -> RefDoc<'a> {
+    let head = self.binders(self.cat([self.text("notation "), self.span(n.id)]), &n.bis);
+    let head = match &n.ty {
+      None => head,
+      Some(ty) => self.cat([head, self.text(": "), self.ty(ty)]),
+    };
+    let mut lits = self.nil();
+    for (i, lit) in n.lits.iter().enumerate() {
+      let lit = match *lit {
+        Literal::Const(c, prec) => self.text(format!("(${}$:{})", self.str(c.trim), prec)),
+        Literal::Var(x) => self.span(x),
+      };
+      lits = if i == 0 { lit } else { self.cat([lits, self.softline(), lit]) }
+    }
+    let prec = match n.prec {
+      None => self.nil(),
+      Some((prec, r)) => self.text(format!(": {} {}", prec, if r {"rassoc"} else {"lassoc"})),
+    };
+    let head = self.group(self.nest(self.cat([head, self.text(" =")])));
+    self.group(self.nest(self.cat([head, self.line(), self.group(lits), prec, self.text(";")])))
+  }
+
+  fn delims(&self, cs: &[u8]) -> RefDoc<'a> {
+    self.math(cs.iter().map(|&c| Cow::Owned(char::from(c).to_string())))
+  }
+
+  /// Lay out a `do` block. The items go on their own lines, keeping single blank lines,
+  /// except that items written on the same line stay there.
+  fn do_block(&self, sp: Span) -> Option<RefDoc<'a>> {
+    let nodes = Tree::parse(self.src.as_bytes(), sp)?;
+    let es = match &*nodes {
+      [Node::Word(_), Node::List(open, es, _), Node::Word(_)]
+      if self.src.as_bytes()[open.start] == b'{' => es,
+      [Node::Word(_), e, Node::Word(_)] if !matches!(e, Node::Comment(..)) =>
+        return Some(self.cat([self.text("do "), self.node(e).1, self.text(";")])),
+      _ => return None,
+    };
+    if es.is_empty() { return Some(self.text("do {};")) }
+    let mut body = self.nil();
+    let mut prev_end = None;
+    for e in es {
+      let sp = e.span();
+      body = match (e, prev_end) {
+        (Node::Comment(_, false), Some(_)) => self.cat([body, self.text(" "), self.node(e).1]),
+        _ => {
+          let gap = prev_end.map(|end| &self.src[end..sp.start]);
+          let sep = match gap {
+            Some(gap) if !gap.contains('\n') => self.text(" "),
+            Some(gap) if has_blank_line(gap) => self.cat([self.hardline(), self.hardline()]),
+            _ => self.hardline(),
+          };
+          self.cat([body, sep, self.node(e).1])
+        }
+      };
+      prev_end = Some(sp.end);
+    }
+    Some(self.cat([self.text("do {"), self.nest(body), self.hardline(), self.text("};")]))
+  }
+
+  fn stmt(&self, stmt: &Stmt) -> Option<RefDoc<'a>> {
+    Some(match &stmt.k {
+      &StmtKind::Sort(id, mods) => self.text(format!("{}sort {};", mods, self.str(id))),
+      StmtKind::Decl(d) => self.decl(d)?,
+      StmtKind::Delimiter(Delimiter::Both(cs)) =>
+        self.cat([self.text("delimiter "), self.delims(cs), self.text(";")]),
+      StmtKind::Delimiter(Delimiter::LeftRight(l, r)) => self.group(self.nest(self.cat([
+        self.text("delimiter "), self.delims(l), self.line(), self.delims(r), self.text(";")]))),
+      &StmtKind::SimpleNota(SimpleNota {k, id, c, prec}) => {
+        let kw = match k {
+          SimpleNotaKind::Prefix => "prefix",
+          SimpleNotaKind::Infix {right: false} => "infixl",
+          SimpleNotaKind::Infix {right: true} => "infixr",
+        };
+        self.text(format!("{} {}: ${}$ prec {};", kw, self.str(id), self.str(c.trim), prec))
+      }
+      &StmtKind::Coercion {id, from, to} => self.text(format!("coercion {}: {} > {};",
+        self.str(id), self.str(from), self.str(to))),
+      StmtKind::Notation(n) => self.notation(n),
+      StmtKind::Inout {out, k, hs} => {
+        let mut doc = self.cat([
+          self.text(if *out {"output "} else {"input "}), self.span(*k), self.text(":")]);
+        for e in hs { doc = self.cat([doc, self.line(), self.sexpr(e.span)?]) }
+        self.group(self.nest(self.cat([doc, self.text(";")])))
+      }
+      StmtKind::Annot(e, s) => {
+        let sep = if self.src[e.span.end..s.span.start].contains('\n') {
+          self.hardline()
+        } else {
+          self.text(" ")
+        };
+        self.cat([self.text("@"), self.sexpr(e.span)?, sep, self.stmt(s)?])
+      }
+      StmtKind::DocComment(_, s) => {
+        let mut doc = self.nil();
+        for (tk, sp) in lex(self.src.as_bytes(), (stmt.span.start..s.span.start).into()) {
+          if tk != Tok::Comment { return None }
+          doc = self.cat([doc, self.comment(sp), self.hardline()])
+        }
+        self.cat([doc, self.stmt(s)?])
+      }
+      StmtKind::Do(_) => self.do_block(stmt.span)?,
+      &StmtKind::Import(sp, _) => self.text(format!("import {};", self.str(sp))),
+    })
+  }
+
+  /// Append the formatted statement to `out`, or the original text if the
+  /// statement contains comments that would otherwise be lost.
+  fn push_stmt(&self, out: &mut String, stmt: &Stmt, width: usize) {
+    let comments = lex(self.src.as_bytes(), stmt.span).iter()
+      .filter(|&&(tk, _)| tk == Tok::Comment).count();
+    self.comments.set(0);
+    match self.stmt(stmt) {
+      Some(doc) if self.comments.get() == comments => {
+        let mut buf = String::new();
+        doc.render_fmt(width, &mut buf).expect("impossible");
+        for (i, line) in buf.split('\n').enumerate() {
+          if i != 0 { out.push('\n') }
+          out.push_str(line.trim_end())
+        }
+      }
+      _ => out.push_str(self.str(stmt.span)),
+    }
+  }
+}
+
+/// Append the text between two statements (or at the start or end of the file) to `out`.
+/// This text contains only whitespace and comments; the comments are kept,
+/// and runs of blank lines are collapsed to one.
+fn push_gap(out: &mut String, text: &str, eof: bool) {
+  let mut lines = text.split('\n').collect::<Vec<_>>();
+  // The last line is the indentation of the next statement
+  if !eof && lines.len() > 1 { lines.pop(); }
+  let mut blank = false;
+  for (i, line) in lines.into_iter().enumerate() {
+    let line = line.trim();
+    if line.is_empty() {
+      blank |= i != 0;
+      continue
+    }
+    if !out.is_empty() {
+      out.push(if i == 0 {' '} else {'\n'});
+      if blank { out.push('\n') }
+    }
+    out.push_str(line);
+    blank = false;
+  }
+  if !out.is_empty() {
+    // Statements that were written on the same line stay on the same line
+    out.push(if eof || text.contains('\n') {'\n'} else {' '});
+    if blank && !eof { out.push('\n') }
+  }
+}
+
+/// The reasons a file cannot be formatted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FormatError {
+  /// The file has parse errors, so the statements of the AST do not cover the whole file.
+  Parse,
+  /// The file has an `input` statement, which reads the text of the file itself,
+  /// so reformatting it would change what the statement checks.
+  Input,
+}
+
+impl std::fmt::Display for FormatError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Parse => write!(f, "cannot format a file with parse errors"),
+      Self::Input => write!(f, "cannot format a file with an input statement"),
+    }
+  }
+}
+
+fn is_input(stmt: &Stmt) -> bool {
+  match &stmt.k {
+    StmtKind::Inout {out, ..} => !out,
+    StmtKind::Annot(_, s) | StmtKind::DocComment(_, s) => is_input(s),
+    _ => false,
+  }
+}
+
+/// Format an MM0 or MM1 file, given its parse, with lines of at most `width` characters
+/// where possible.
+pub fn format(ast: &Ast, width: usize) -> Result<String, FormatError> {
+  if !ast.errors.is_empty() { return Err(FormatError::Parse) }
+  if ast.stmts.iter().any(is_input) { return Err(FormatError::Input) }
+  let src: &str = &ast.source;
+  let arena = Arena::new();
+  let p = Printer { src, alloc: &arena, comments: Cell::new(0) };
+  let mut out = String::new();
+  let mut last = 0;
+  for stmt in &ast.stmts {
+    push_gap(&mut out, &src[last..stmt.span.start], false);
+    p.push_stmt(&mut out, stmt, width);
+    last = stmt.span.end;
+  }
+  push_gap(&mut out, &src[last..], true);
+  Ok(out)
+}
+
+/// Main entry point for `mm0-rs fmt` subcommand.
+///
+/// See the [module documentation](self) for the formatting rules.
+///
+/// # Arguments
+///
+/// `mm0-rs fmt <in.mm1> [out.mm1]`, where:
+///
+/// - `in.mm1` (or `in.mm0`) is the file to format
+/// - `out.mm1` is the output location, or stdout if omitted.
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let path = args.value_of("INPUT").expect("required arg");
+  let text = LinedString::from(fs::read_to_string(path)?);
+  let (_, ast) = mm1_parser::parse(Arc::new(text), None);
+  let out = format(&ast, WIDTH).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+    format!("{}: {}", path, e)))?;
+  match args.value_of("OUTPUT") {
+    None => io::stdout().write_all(out.as_bytes()),
+    Some(file) => fs::write(file, out),
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::wildcard_imports)]
+mod tests {
+  use super::*;
+
+  fn fmt(text: &str) -> Result<String, FormatError> {
+    let (_, ast) = mm1_parser::parse(Arc::new(text.to_owned().into()), None);
+    format(&ast, WIDTH)
+  }
+
+  fn tokens(text: &str) -> String { text.split_whitespace().collect() }
+
+  #[test]
+  fn idempotent() {
+    let src = include_str!("../../examples/peano.mm1");
+    let out = fmt(src).unwrap();
+    assert_eq!(fmt(&out).unwrap(), out);
+    assert_eq!(tokens(&out), tokens(src));
+    // the layout should not spread the file out much
+    let (lines, src_lines) = (out.lines().count(), src.lines().count());
+    assert!(10 * lines <= 11 * src_lines, "{} lines, from {}", lines, src_lines);
+  }
+
+  #[test]
+  fn short_forms() {
+    let src = "\
+      do {\n  \
+        (def (f x) @ match x [1 2] [_ 3])\n  \
+        (def (g x) (f x)) (def (h x) (g x))\n\
+      };\n\
+      sort nat; term z: nat;\n";
+    assert_eq!(fmt(src).unwrap(), src);
+    let src = "do { (def (long-name-f some-arg) @ match some-arg [1 (list 2 3 4)] [_ (list 5 6 7)]) };\n";
+    assert_eq!(fmt(src).unwrap(), "\
+      do {\n  \
+        (def (long-name-f some-arg) @ match some-arg\n    \
+          [1 (list 2 3 4)]\n    \
+          [_ (list 5 6 7)])\n\
+      };\n");
+  }
+
+  #[test]
+  fn comments() {
+    let src = "\
+      -- the naturals\n\
+      sort   nat;  -- a sort\n\n\n\
+      --| zero\n\
+      term z: nat;\n\
+      do {\n  \
+        -- a comment\n  \
+        (def x 1) -- x\n  \
+        (def (f a) (list a -- first\n    \
+          x))\n\
+      };\n";
+    let out = fmt(src).unwrap();
+    assert_eq!(fmt(&out).unwrap(), out);
+    assert_eq!(tokens(&out), tokens(src));
+    assert!(out.starts_with("-- the naturals\nsort nat; -- a sort\n\n--| zero\nterm z: nat;\n"), "{}", out);
+  }
+
+  #[test]
+  fn input_refused() {
+    let src = "strict sort string; def s: string; input string: s;\n";
+    assert_eq!(fmt(src), Err(FormatError::Input));
+    assert_eq!(fmt("sort nat"), Err(FormatError::Parse));
+  }
+}
@@ -0,0 +1,1180 @@
+//! Metamath importer, which produces an [`Environment`] object from a `.mm` file.
+//!
+//! The translation follows the same scheme as `mm0-hs from-mm`:
+//!
+//! * Each typecode used in a `$f` hypothesis becomes a sort. The `$j` commands
+//!   `syntax 'x';`, `syntax 'x' as 'y';` and `bound 'x';` are also understood, and are
+//!   used to declare syntax typecodes, provable typecodes, and `pure` sorts respectively.
+//! * Syntax axioms (`$a` statements with a syntax typecode) become `term`s.
+//!   Variables of a `pure` sort become bound variables, and all other arguments
+//!   depend on all the bound variables.
+//! * Other `$a` and `$p` statements become `axiom`s and `theorem`s. A regular variable
+//!   depends on every bound variable that it is not in a `$d` condition with.
+//!   Statements are parsed using the grammar given by the syntax axioms, and
+//!   the provable typecode is mapped to the sort it parses as.
+//! * Normal and compressed proofs are translated into proof terms, which are checked
+//!   for well-typedness and hypothesis matching, but not for disjoint variable conditions.
+//!   (Use `mm0-rs verify` on the result to check the translation.)
+//!
+//! Theorems that are applied to the same bound variable in two different positions
+//! ("bundled" theorems) are not supported, nor are `$[ $]` file inclusions.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
+use clap::ArgMatches;
+use crate::{Term, Thm, TermKind, ThmKind, AtomId, SortId, TermId, ThmId, Environment,
+  Modifiers, Type, Proof, ExprNode, StmtTrace, DeclKey, MAX_BOUND_VARS,
+  DocComment, BoxError, Span, FileRef, FileSpan, FrozenEnv};
+use crate::elab::{ElabError, Result, proof::{IDedup, ExprHash, ProofKind, ProofHash, build}};
+use crate::mmu::import::Dedup;
+
+/// The keywords of MM0 and MM1, which cannot be used as identifiers.
+const KEYWORDS: &[&str] = &["abstract", "axiom", "coercion", "def", "delimiter", "do",
+  "exit", "free", "import", "infixl", "infixr", "input", "local", "max", "notation",
+  "output", "prefix", "provable", "pub", "pure", "sort", "strict", "term", "theorem"];
+
+/// Convert a Metamath label or symbol into a valid MM0 identifier.
+fn mangle(s: &[u8]) -> String {
+  let mut out: String = s.iter().map(|&c|
+    if c.is_ascii_alphanumeric() || c == b'_' {c as char} else {'_'}).collect();
+  if out.is_empty() || out == "_" || out.starts_with(|c: char| c.is_ascii_digit()) ||
+    KEYWORDS.contains(&&*out) { out.insert(0, '_') }
+  out
+}
+
+/// A math symbol declared by `$c` or `$v`.
+#[derive(Copy, Clone, Debug)]
+enum Sym {
+  /// A constant, with its index
+  Const(usize),
+  /// A variable, with its index into [`Importer::vars`]
+  Var(usize),
+}
+
+/// A token in a math string, with variables resolved to their currently active type.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum MathTok {
+  /// A constant symbol
+  Const(usize),
+  /// A variable and its sort
+  Var(usize, SortId),
+}
+
+/// A variable declared by `$v`.
+#[derive(Debug)]
+struct Var {
+  /// The MM0 name of the variable
+  atom: AtomId,
+  /// True if the variable is in scope
+  active: bool,
+  /// The active `$f` hypothesis for this variable, and its sort
+  hyp: Option<(usize, SortId)>,
+}
+
+/// A parsed math expression.
+#[derive(Debug)]
+enum Tree {
+  /// A variable, given by index into [`Importer::vars`]
+  Var(usize),
+  /// A term constructor applied to arguments (in MM0 order)
+  App(TermId, Box<[Rc<Tree>]>),
+}
+
+/// A floating or essential hypothesis.
+#[derive(Debug)]
+enum Hyp {
+  /// `$f`: a variable and its sort
+  F(usize, SortId),
+  /// `$e`: the MM0 name of the hypothesis, the parsed statement, and the variables in it
+  E(AtomId, Rc<Tree>, Box<[usize]>),
+}
+
+/// An element of the grammar, the right hand side of a syntax axiom.
+#[allow(variant_size_differences)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum RuleSym {
+  /// A constant symbol
+  Const(usize),
+  /// A variable slot, which is filled by an expression of the given sort
+  Var(SortId),
+}
+
+/// A grammar rule derived from a syntax axiom.
+#[derive(Debug)]
+struct Rule {
+  /// The sort of the resulting expression
+  sort: SortId,
+  /// The symbols of the syntax axiom
+  syms: Box<[RuleSym]>,
+  /// The term constructor
+  term: TermId,
+  /// The term argument corresponding to each variable slot of `syms`
+  perm: Box<[usize]>,
+}
+
+/// A position in the frame of a theorem.
+#[derive(Copy, Clone, Debug)]
+enum Slot {
+  /// The `i`th argument of the MM0 theorem
+  Var(usize),
+  /// The `i`th hypothesis of the MM0 theorem
+  Hyp(usize),
+}
+
+/// The meaning of a Metamath label, when it appears in a proof.
+#[derive(Debug)]
+enum Label {
+  /// A `$f` or `$e` hypothesis, by index into [`Importer::hyps`]
+  Hyp(usize),
+  /// A syntax axiom, and the term argument for each element of its frame
+  Term(TermId, Box<[usize]>),
+  /// A syntax theorem, with the sorts of its frame, the result sort, and the
+  /// resulting expression (where `Ref(i)` refers to element `i` of the frame)
+  Syntax(Box<[SortId]>, SortId, ExprNode),
+  /// An axiom or theorem, and the MM0 argument or hypothesis for each element of its frame
+  Thm(ThmId, Box<[Slot]>),
+}
+
+/// The state to restore at the end of a `${ $}` block.
+#[derive(Debug)]
+struct Scope {
+  /// The variables declared in this block
+  vars: Vec<usize>,
+  /// The `$f` assignments made in this block, and the previous value
+  fvars: Vec<(usize, Option<(usize, SortId)>)>,
+  /// The number of active hypotheses at the start of the block
+  hyps: usize,
+  /// The number of disjoint variable pairs at the start of the block
+  dvs: usize,
+}
+
+/// An element on the proof stack.
+#[derive(Copy, Clone, Debug)]
+enum StackEl {
+  /// An expression (index into the [`Dedup`]) and its sort
+  Expr(usize, SortId),
+  /// A proof (index into the [`Dedup`]) and the index of its statement
+  Proof(usize, usize),
+}
+
+/// The context for running a proof.
+#[derive(Debug)]
+struct ProofCtx<'b> {
+  /// The proof under construction
+  de: Dedup<ProofHash>,
+  /// The argument index of each mandatory variable
+  vars: &'b HashMap<usize, usize>,
+  /// True for the arguments that are bound variables
+  bound: Vec<bool>,
+  /// The proof and statement indexes of each mandatory `$e` hypothesis
+  hyps: HashMap<usize, (usize, usize)>,
+  /// The proof stack
+  stack: Vec<StackEl>,
+}
+
+/// An item in the Earley parser.
+#[derive(Clone, Debug)]
+struct Item {
+  rule: usize,
+  dot: usize,
+  origin: usize,
+  /// The parsed variable slots so far, in reverse order
+  kids: Option<Rc<Kids>>,
+}
+
+/// A linked list of parsed subexpressions.
+#[derive(Debug)]
+struct Kids(Rc<Tree>, Option<Rc<Kids>>);
+
+impl Item {
+  fn advance(&self, kid: Option<Rc<Tree>>) -> Item {
+    Item {
+      rule: self.rule,
+      dot: self.dot + 1,
+      origin: self.origin,
+      kids: match kid {
+        None => self.kids.clone(),
+        Some(e) => Some(Rc::new(Kids(e, self.kids.clone()))),
+      }
+    }
+  }
+}
+
+/// The importer, which reads the input `.mm` file and builds an [`Environment`].
+#[derive(Debug)]
+pub struct Importer<'a> {
+  /// The input file name
+  file: &'a FileRef,
+  /// The input source text (as a byte slice)
+  source: &'a [u8],
+  /// The position in the input
+  idx: usize,
+  /// The environment under construction
+  env: Environment,
+  /// The declared math symbols
+  syms: HashMap<&'a [u8], Sym>,
+  /// The number of declared constants
+  consts: usize,
+  /// The declared variables
+  vars: Vec<Var>,
+  /// All hypotheses, and whether they are currently active
+  hyps: Vec<(Hyp, bool)>,
+  /// The active hypotheses, in order
+  active: Vec<usize>,
+  /// The active disjoint variable pairs
+  dvs: Vec<(usize, usize)>,
+  /// The enclosing `${ $}` blocks
+  scopes: Vec<Scope>,
+  /// The declared labels
+  labels: HashMap<&'a [u8], Label>,
+  /// The sort of each syntax typecode
+  sorts: HashMap<usize, SortId>,
+  /// The sort of each provable typecode
+  provable: HashMap<usize, SortId>,
+  /// The `$j syntax` declarations, with the target sort for provable typecodes
+  j_syntax: HashMap<&'a [u8], Option<Span>>,
+  /// The `$j bound` declarations
+  j_bound: HashSet<&'a [u8]>,
+  /// The grammar rules
+  rules: Vec<Rule>,
+  /// The grammar rules for each sort
+  rules_by_sort: HashMap<SortId, Vec<usize>>,
+  /// The MM0 names that have been used
+  names: HashSet<String>,
+  /// The last comment, which is used as documentation for the next statement
+  doc: Option<DocComment>,
+}
+
+fn is_ws(c: u8) -> bool { matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'\x0c') }
+
+impl<'a> Importer<'a> {
+  fn new(file: &'a FileRef, source: &'a [u8]) -> Self {
+    Importer {
+      file, source, idx: 0,
+      env: Environment::new(),
+      syms: HashMap::new(),
+      consts: 0,
+      vars: vec![],
+      hyps: vec![],
+      active: vec![],
+      dvs: vec![],
+      scopes: vec![],
+      labels: HashMap::new(),
+      sorts: HashMap::new(),
+      provable: HashMap::new(),
+      j_syntax: HashMap::new(),
+      j_bound: HashSet::new(),
+      rules: vec![],
+      rules_by_sort: HashMap::new(),
+      names: HashSet::new(),
+      doc: None,
+    }
+  }
+
+  fn span(&self, s: Span) -> &'a [u8] { &self.source[s.start..s.end] }
+
+  fn fspan(&self, s: Span) -> FileSpan {
+    FileSpan {file: self.file.clone(), span: s}
+  }
+
+  fn err(&self, msg: BoxError) -> ElabError {
+    ElabError::new_e(self.idx..self.idx, msg)
+  }
+
+  /// Get the next whitespace-delimited token, not skipping comments.
+  fn raw_token(&mut self) -> Option<Span> {
+    while self.source.get(self.idx).map_or(false, |&c| is_ws(c)) { self.idx += 1 }
+    let start = self.idx;
+    while self.source.get(self.idx).map_or(false, |&c| !is_ws(c)) { self.idx += 1 }
+    if start == self.idx { None } else { Some((start..self.idx).into()) }
+  }
+
+  /// Get the next token, skipping (and processing) comments.
+  fn token(&mut self) -> Result<Option<Span>> {
+    loop {
+      match self.raw_token() {
+        Some(sp) if self.span(sp) == b"$(" => self.comment(sp)?,
+        tk => return Ok(tk)
+      }
+    }
+  }
+
+  fn token_err(&mut self) -> Result<Span> {
+    self.token()?.ok_or_else(|| self.err("unexpected end of file".into()))
+  }
+
+  /// Parse a comment, starting after the `$(`. `$j` comments are processed, and other
+  /// comments are saved as documentation for the next statement.
+  fn comment(&mut self, start: Span) -> Result<()> {
+    let mut toks = vec![];
+    loop {
+      let sp = self.raw_token().ok_or_else(|| ElabError::new_e(start, "unclosed comment"))?;
+      match self.span(sp) {
+        b"$)" => break,
+        s if s.windows(2).any(|w| w == b"$(" || w == b"$)") =>
+          return Err(ElabError::new_e(sp, "comments may not contain '$(' or '$)'")),
+        _ => toks.push(sp)
+      }
+    }
+    match toks.first().map(|&sp| self.span(sp)) {
+      Some(b"$j") => {
+        let (lo, hi) = (toks[0].end, toks.last().map_or(toks[0].end, |sp| sp.end));
+        self.j_commands(lo, hi)
+      }
+      Some(b"$t") => {}
+      _ => if let (Some(lo), Some(hi)) = (toks.first(), toks.last()) {
+        let text = String::from_utf8_lossy(&self.source[lo.start..hi.end]);
+        self.doc = Some(text.into());
+      }
+    }
+    Ok(())
+  }
+
+  /// Process the commands in a `$j` comment (the text `source[lo..hi]`).
+  /// Unrecognized commands are ignored.
+  fn j_commands(&mut self, lo: usize, hi: usize) {
+    let mut cmd: Vec<Span> = vec![];
+    let mut i = lo;
+    while i < hi {
+      let c = self.source[i];
+      if is_ws(c) || c == b';' { i += 1 } else {
+        let start = i;
+        while i < hi && !is_ws(self.source[i]) && self.source[i] != b';' { i += 1 }
+        let mut sp = Span::from(start..i);
+        if sp.end - sp.start >= 2 && matches!(self.source[start], b'\'' | b'"') &&
+          self.source[i - 1] == self.source[start] {
+          sp = (start + 1..i - 1).into()
+        }
+        cmd.push(sp)
+      }
+      if i >= hi || self.source[i] == b';' {
+        match *cmd.iter().map(|&sp| self.span(sp)).collect::<Vec<_>>() {
+          [b"syntax", x] => {self.j_syntax.insert(x, None);}
+          [b"syntax", x, b"as", _] => {self.j_syntax.insert(x, Some(cmd[3]));}
+          [b"bound", x] => {self.j_bound.insert(x);}
+          _ => {}
+        }
+        cmd.clear()
+      }
+    }
+  }
+
+  /// Make a unique MM0 identifier from a Metamath label or symbol.
+  fn fresh_name(&mut self, s: &[u8]) -> AtomId {
+    let base = mangle(s);
+    let mut name = base.clone();
+    let mut n = 1;
+    while !self.names.insert(name.clone()) {
+      name = format!("{}_{}", base, n);
+      n += 1;
+    }
+    self.env.get_atom(name.as_bytes())
+  }
+
+  /// Get the sort for a syntax typecode, creating it if necessary.
+  fn sort_of(&mut self, c: usize, sp: Span) -> Result<SortId> {
+    if let Some(&s) = self.sorts.get(&c) { return Ok(s) }
+    let name = self.span(sp);
+    let mods = if self.j_bound.contains(name) {Modifiers::PURE} else {Modifiers::empty()};
+    let a = self.fresh_name(name);
+    let s = self.env.add_sort(a, self.fspan(sp), sp, mods, None)
+      .map_err(|e| e.into_elab_error(sp))?;
+    self.sorts.insert(c, s);
+    Ok(s)
+  }
+
+  fn is_syntax(&self, c: usize, name: &[u8]) -> bool {
+    self.sorts.contains_key(&c) || matches!(self.j_syntax.get(name), Some(None))
+  }
+
+  fn pure(&self, s: SortId) -> bool { self.env.sorts[s].mods.contains(Modifiers::PURE) }
+
+  /// Get the constant symbol at `sp`.
+  fn constant(&self, sp: Span) -> Result<usize> {
+    match self.syms.get(self.span(sp)) {
+      Some(&Sym::Const(c)) => Ok(c),
+      _ => Err(ElabError::new_e(sp, "expected a constant")),
+    }
+  }
+
+  /// Get the active variable at `sp`.
+  fn variable(&self, sp: Span) -> Result<usize> {
+    match self.syms.get(self.span(sp)) {
+      Some(&Sym::Var(v)) if self.vars[v].active => Ok(v),
+      _ => Err(ElabError::new_e(sp, "expected an active variable")),
+    }
+  }
+
+  /// Read a list of tokens up to (and not including) one of the terminators in `end`.
+  /// Returns the tokens and the terminator.
+  fn tokens_until(&mut self, end: &[&[u8]]) -> Result<(Vec<Span>, Span)> {
+    let mut toks = vec![];
+    loop {
+      let sp = self.token_err()?;
+      let s = self.span(sp);
+      if end.contains(&s) { return Ok((toks, sp)) }
+      if s.starts_with(b"$") {
+        return Err(ElabError::new_e(sp, format!("unexpected keyword '{}'",
+          String::from_utf8_lossy(s))))
+      }
+      toks.push(sp)
+    }
+  }
+
+  /// Read a math string, terminated by `$.` or `$=`.
+  fn math(&mut self) -> Result<(Vec<MathTok>, Span, Span)> {
+    let (toks, end) = self.tokens_until(&[b"$.", b"$="])?;
+    let (&tc, toks) = toks.split_first().ok_or_else(|| ElabError::new_e(end, "expected a typecode"))?;
+    let toks = toks.iter().map(|&sp| match self.syms.get(self.span(sp)) {
+      Some(&Sym::Const(c)) => Ok(MathTok::Const(c)),
+      Some(&Sym::Var(v)) => match self.vars[v] {
+        Var {active: true, hyp: Some((_, s)), ..} => Ok(MathTok::Var(v, s)),
+        Var {active: true, hyp: None, ..} =>
+          Err(ElabError::new_e(sp, "variable has no active $f hypothesis")),
+        _ => Err(ElabError::new_e(sp, "variable is not active")),
+      }
+      None => Err(ElabError::new_e(sp, "math symbol not declared")),
+    }).collect::<Result<_>>()?;
+    Ok((toks, tc, end))
+  }
+
+  fn run(&mut self) -> Result<()> {
+    while let Some(sp) = self.token()? {
+      match self.span(sp) {
+        b"${" => self.scopes.push(Scope {
+          vars: vec![], fvars: vec![], hyps: self.active.len(), dvs: self.dvs.len()
+        }),
+        b"$}" => {
+          let sc = self.scopes.pop().ok_or_else(|| ElabError::new_e(sp, "unmatched '$}'"))?;
+          for v in sc.vars { self.vars[v].active = false }
+          for (v, old) in sc.fvars.into_iter().rev() { self.vars[v].hyp = old }
+          for &h in &self.active[sc.hyps..] { self.hyps[h].1 = false }
+          self.active.truncate(sc.hyps);
+          self.dvs.truncate(sc.dvs);
+        }
+        b"$c" => for sp in self.tokens_until(&[b"$."])?.0 {
+          let s = self.span(sp);
+          if self.syms.contains_key(s) {
+            return Err(ElabError::new_e(sp, "math symbol already declared"))
+          }
+          self.syms.insert(s, Sym::Const(self.consts));
+          self.consts += 1;
+        },
+        b"$v" => for sp in self.tokens_until(&[b"$."])?.0 {
+          let s = self.span(sp);
+          let v = match self.syms.get(s) {
+            Some(Sym::Const(_)) => return Err(ElabError::new_e(sp, "math symbol already declared")),
+            Some(&Sym::Var(v)) if self.vars[v].active =>
+              return Err(ElabError::new_e(sp, "variable already active")),
+            Some(&Sym::Var(v)) => v,
+            None => {
+              let atom = self.fresh_name(s);
+              self.vars.push(Var {atom, active: false, hyp: None});
+              self.syms.insert(s, Sym::Var(self.vars.len() - 1));
+              self.vars.len() - 1
+            }
+          };
+          self.vars[v].active = true;
+          if let Some(sc) = self.scopes.last_mut() { sc.vars.push(v) }
+        },
+        b"$d" => {
+          let vs = self.tokens_until(&[b"$."])?.0.into_iter()
+            .map(|sp| self.variable(sp)).collect::<Result<Vec<_>>>()?;
+          for (i, &v1) in vs.iter().enumerate() {
+            for &v2 in &vs[i+1..] {
+              if v1 == v2 { return Err(ElabError::new_e(sp, "repeated variable in $d")) }
+              self.dvs.push((v1, v2))
+            }
+          }
+        }
+        b"$[" => return Err(ElabError::new_e(sp, "file inclusion is not supported")),
+        s if s.starts_with(b"$") => return Err(ElabError::new_e(sp, format!(
+          "unexpected keyword '{}'", String::from_utf8_lossy(s)))),
+        label => {
+          if self.labels.contains_key(label) {
+            return Err(ElabError::new_e(sp, "label already declared"))
+          }
+          let kw = self.token_err()?;
+          match self.span(kw) {
+            b"$f" => self.float(sp)?,
+            b"$e" => self.essential(sp)?,
+            b"$a" | b"$p" => self.assertion(sp)?,
+            _ => return Err(ElabError::new_e(kw, "expected $f, $e, $a, or $p")),
+          }
+        }
+      }
+    }
+    if !self.scopes.is_empty() {
+      return Err(self.err("unclosed '${' block".into()))
+    }
+    Ok(())
+  }
+
+  /// Parse a `$f` statement, after the `$f`.
+  fn float(&mut self, label: Span) -> Result<()> {
+    let (toks, end) = self.tokens_until(&[b"$."])?;
+    let (tc, v) = match *toks {
+      [tc, v] => (tc, v),
+      _ => return Err(ElabError::new_e(label.start..end.end, "expected '$f typecode var $.'")),
+    };
+    let c = self.constant(tc)?;
+    let v = self.variable(v)?;
+    let s = self.sort_of(c, tc)?;
+    let h = self.hyps.len();
+    self.hyps.push((Hyp::F(v, s), true));
+    self.active.push(h);
+    let old = self.vars[v].hyp.replace((h, s));
+    if let Some(sc) = self.scopes.last_mut() { sc.fvars.push((v, old)) }
+    self.labels.insert(self.span(label), Label::Hyp(h));
+    Ok(())
+  }
+
+  /// Parse a `$e` statement, after the `$e`.
+  fn essential(&mut self, label: Span) -> Result<()> {
+    let (toks, tc, end) = self.math()?;
+    if self.span(end) != b"$." { return Err(ElabError::new_e(end, "expected '$.'")) }
+    let c = self.constant(tc)?;
+    let (_, e) = self.parse_provable(c, tc, &toks)?;
+    let mut vs = vec![];
+    for &t in &toks { if let MathTok::Var(v, _) = t { if !vs.contains(&v) { vs.push(v) } } }
+    let a = self.fresh_name(self.span(label));
+    let h = self.hyps.len();
+    self.hyps.push((Hyp::E(a, e, vs.into()), true));
+    self.active.push(h);
+    self.labels.insert(self.span(label), Label::Hyp(h));
+    Ok(())
+  }
+
+  /// Parse a provable statement with typecode `c`, determining the sort it corresponds to
+  /// if this is the first use of the typecode.
+  fn parse_provable(&mut self, c: usize, tc: Span, toks: &[MathTok]) -> Result<(SortId, Rc<Tree>)> {
+    let err = || ElabError::new_e(tc, "could not parse statement");
+    let s = if let Some(&s) = self.provable.get(&c) { s } else {
+      let s = if let Some(&Some(y)) = self.j_syntax.get(self.span(tc)) {
+        let c2 = self.constant(y)?;
+        self.sort_of(c2, y)?
+      } else {
+        self.env.sorts.enum_iter().map(|(s, _)| s)
+          .find(|&s| self.parse(toks, s).is_some()).ok_or_else(err)?
+      };
+      self.env.sorts[s].mods |= Modifiers::PROVABLE;
+      self.provable.insert(c, s);
+      s
+    };
+    Ok((s, self.parse(toks, s).ok_or_else(err)?))
+  }
+
+  /// Parse a `$a` or `$p` statement, after the keyword.
+  fn assertion(&mut self, label: Span) -> Result<()> {
+    let start = label.start;
+    let (toks, tc, end) = self.math()?;
+    let proof = self.span(end) == b"$=";
+    let (proof, end) = if proof {
+      let (pf, end) = self.tokens_until(&[b"$."])?;
+      (Some(pf), end)
+    } else { (None, end) };
+    let c = self.constant(tc)?;
+    let full = (start..end.end).into();
+    let doc = self.doc.take();
+    if self.is_syntax(c, self.span(tc)) {
+      self.syntax(label, full, doc, c, tc, &toks, proof)
+    } else {
+      self.theorem(label, full, doc, c, tc, &toks, proof)
+    }
+  }
+
+  /// The mandatory `$f` hypotheses of a syntax statement.
+  fn syntax_frame(&self, label: Span, toks: &[MathTok]) -> Result<Vec<(usize, SortId)>> {
+    let mut frame = vec![];
+    for &h in &self.active {
+      match self.hyps[h].0 {
+        Hyp::F(v, s) => if toks.contains(&MathTok::Var(v, s)) { frame.push((v, s)) },
+        Hyp::E(..) => return Err(ElabError::new_e(label,
+          "syntax statements with $e hypotheses are not supported")),
+      }
+    }
+    Ok(frame)
+  }
+
+  /// Add a syntax axiom or theorem.
+  #[allow(clippy::too_many_arguments)]
+  fn syntax(&mut self, label: Span, full: Span, doc: Option<DocComment>,
+    c: usize, tc: Span, toks: &[MathTok], proof: Option<Vec<Span>>
+  ) -> Result<()> {
+    let sort = self.sort_of(c, tc)?;
+    let frame = self.syntax_frame(label, toks)?;
+    if let Some(proof) = proof {
+      let vars = frame.iter().enumerate().map(|(i, &(v, _))| (v, i)).collect();
+      let args: Vec<_> = frame.iter().map(|&(v, s)|
+        (Some(self.vars[v].atom), Type::Reg(s, 0))).collect();
+      let mut ctx = ProofCtx {
+        de: Dedup::new(&args), vars: &vars, bound: vec![false; args.len()],
+        hyps: HashMap::new(), stack: vec![]
+      };
+      let mand = self.active.iter().copied()
+        .filter(|&h| matches!(self.hyps[h].0, Hyp::F(v, _) if vars.contains_key(&v)))
+        .collect::<Vec<_>>();
+      self.run_proof(&mut ctx, &mand, &proof)?;
+      let e = match *ctx.stack {
+        [StackEl::Expr(e, s)] if s == sort => e,
+        _ => return Err(ElabError::new_e(label, "proof does not prove the statement")),
+      };
+      let e = to_node(&ctx.de, e).ok_or_else(||
+        ElabError::new_e(label, "syntax theorems with dummy variables are not supported"))?;
+      let sorts = frame.iter().map(|&(_, s)| s).collect();
+      self.labels.insert(self.span(label), Label::Syntax(sorts, sort, e));
+      return Ok(())
+    }
+    let nbound = frame.iter().filter(|&&(_, s)| self.pure(s)).count();
+    if nbound > MAX_BOUND_VARS {
+      return Err(ElabError::new_e(label,
+        format!("too many bound variables (max {})", MAX_BOUND_VARS)))
+    }
+    let mut args = Vec::with_capacity(frame.len());
+    let mut perm = vec![0; frame.len()];
+    for (k, &(v, s)) in frame.iter().enumerate() {
+      if self.pure(s) { perm[k] = args.len(); args.push((Some(self.vars[v].atom), Type::Bound(s))) }
+    }
+    for (k, &(v, s)) in frame.iter().enumerate() {
+      if !self.pure(s) {
+        perm[k] = args.len();
+        args.push((Some(self.vars[v].atom), Type::Reg(s, (1 << nbound) - 1)))
+      }
+    }
+    let mut syms = vec![];
+    let mut slots = vec![];
+    for &t in toks {
+      match t {
+        MathTok::Const(c) => syms.push(RuleSym::Const(c)),
+        MathTok::Var(v, s) => {
+          let k = frame.iter().position(|&(v2, _)| v == v2).expect("variable in frame");
+          if slots.contains(&perm[k]) {
+            return Err(ElabError::new_e(label, "repeated variable in syntax axiom"))
+          }
+          slots.push(perm[k]);
+          syms.push(RuleSym::Var(s))
+        }
+      }
+    }
+    if syms.is_empty() {
+      return Err(ElabError::new_e(label, "empty syntax axioms are not supported"))
+    }
+    let atom = self.fresh_name(self.span(label));
+    let term = self.env.add_term(Term {
+      atom,
+      span: self.fspan(label),
+      vis: Modifiers::empty(),
+      full,
+      doc,
+      args: args.into(),
+      ret: (sort, 0),
+      kind: TermKind::Term,
+    }).map_err(|e| e.into_elab_error(label))?;
+    self.rules_by_sort.entry(sort).or_default().push(self.rules.len());
+    self.rules.push(Rule {sort, syms: syms.into(), term, perm: slots.into()});
+    self.labels.insert(self.span(label), Label::Term(term, perm.into()));
+    Ok(())
+  }
+
+  /// Add an axiom or theorem.
+  #[allow(clippy::too_many_arguments)]
+  fn theorem(&mut self, label: Span, full: Span, doc: Option<DocComment>,
+    c: usize, tc: Span, toks: &[MathTok], proof: Option<Vec<Span>>
+  ) -> Result<()> {
+    let (_, stmt) = self.parse_provable(c, tc, toks)?;
+    let mut used = HashSet::new();
+    for &t in toks { if let MathTok::Var(v, _) = t { used.insert(v); } }
+    for &h in &self.active {
+      if let Hyp::E(_, _, ref vs) = self.hyps[h].0 { used.extend(vs.iter().copied()) }
+    }
+    let frame = self.active.iter().copied().filter(|&h| match self.hyps[h].0 {
+      Hyp::F(v, _) => used.contains(&v),
+      Hyp::E(..) => true,
+    }).collect::<Vec<_>>();
+    let fvars = frame.iter().filter_map(|&h| match self.hyps[h].0 {
+      Hyp::F(v, s) => Some((v, s)),
+      Hyp::E(..) => None,
+    }).collect::<Vec<_>>();
+    let bvars = fvars.iter().copied().filter(|&(_, s)| self.pure(s)).collect::<Vec<_>>();
+    if bvars.len() > MAX_BOUND_VARS {
+      return Err(ElabError::new_e(label,
+        format!("too many bound variables (max {})", MAX_BOUND_VARS)))
+    }
+    let dv = |v1, v2| self.dvs.contains(&(v1, v2)) || self.dvs.contains(&(v2, v1));
+    let mut args = Vec::with_capacity(fvars.len());
+    let mut vars = HashMap::new();
+    for &(v, s) in &bvars {
+      vars.insert(v, args.len());
+      args.push((Some(self.vars[v].atom), Type::Bound(s)))
+    }
+    for &(v, s) in &fvars {
+      if !self.pure(s) {
+        let deps = bvars.iter().enumerate()
+          .filter(|&(_, &(b, _))| !dv(v, b)).fold(0, |a, (i, _)| a | 1 << i);
+        vars.insert(v, args.len());
+        args.push((Some(self.vars[v].atom), Type::Reg(s, deps)))
+      }
+    }
+    let mut de = Dedup::new(&args);
+    let mut is = vec![];
+    let mut slots = vec![];
+    for &h in &frame {
+      match self.hyps[h].0 {
+        Hyp::F(v, _) => slots.push(Slot::Var(vars[&v])),
+        Hyp::E(a, ref e, _) => {
+          slots.push(Slot::Hyp(is.len()));
+          is.push((h, a, expr(&mut de, &vars, e)))
+        }
+      }
+    }
+    let ir = expr(&mut de, &vars, &stmt);
+    let (mut ids, heap) = build(&de);
+    let hyps = is.iter().map(|&(_, a, i)| (Some(a), ids[i].take())).collect();
+    let ret = ids[ir].take();
+    let (kind, vis) = if let Some(proof) = proof {
+      let mut ctx = ProofCtx {
+        de: de.map_proof(), vars: &vars,
+        bound: args.iter().map(|(_, ty)| ty.bound()).collect(),
+        hyps: HashMap::new(), stack: vec![]
+      };
+      let mut is2 = vec![];
+      for (i, &(h, _, e)) in is.iter().enumerate() {
+        let n = ctx.de.add(ProofHash::Hyp(i, e));
+        is2.push(n);
+        ctx.hyps.insert(h, (n, e));
+      }
+      self.run_proof(&mut ctx, &frame, &proof)?;
+      let ip = match *ctx.stack {
+        [StackEl::Proof(p, e)] if e == ir => p,
+        _ => return Err(ElabError::new_e(label, "proof does not prove the statement")),
+      };
+      let (mut ids, heap) = build(&ctx.de);
+      let hyps = is2.into_iter().map(|i| ids[i].take()).collect();
+      (ThmKind::Thm(Some(Proof {heap, hyps, head: ids[ip].take()})), Modifiers::PUB)
+    } else { (ThmKind::Axiom, Modifiers::empty()) };
+    let atom = self.fresh_name(self.span(label));
+    let thm = self.env.add_thm(Thm {
+      atom,
+      span: self.fspan(label),
+      vis,
+      full,
+      doc,
+      args: args.into(), heap, hyps, ret, kind
+    }).map_err(|e| e.into_elab_error(label))?;
+    self.labels.insert(self.span(label), Label::Thm(thm, slots.into()));
+    Ok(())
+  }
+
+  /// Run a normal or compressed proof.
+  fn run_proof(&self, ctx: &mut ProofCtx<'_>, frame: &[usize], proof: &[Span]) -> Result<()> {
+    let label = |sp: Span| match self.span(sp) {
+      b"?" => Err(ElabError::new_e(sp, "incomplete proofs are not supported")),
+      s => self.labels.get(s).ok_or_else(|| ElabError::new_e(sp, "unknown label")),
+    };
+    match proof.split_first() {
+      Some((&open, rest)) if self.span(open) == b"(" => {
+        let close = rest.iter().position(|&sp| self.span(sp) == b")")
+          .ok_or_else(|| ElabError::new_e(open, "unclosed '('"))?;
+        let labels = rest[..close].iter().map(|&sp| label(sp)).collect::<Result<Vec<_>>>()?;
+        let mut saved = vec![];
+        let mut n = 0;
+        for &sp in &rest[close+1..] {
+          for (i, &c) in self.span(sp).iter().enumerate() {
+            let sp1 = Span::from(sp.start + i..sp.start + i + 1);
+            match c {
+              b'U'..=b'Y' => n = n * 5 + usize::from(c - b'U' + 1),
+              b'A'..=b'T' => {
+                let i = n * 20 + usize::from(c - b'A');
+                n = 0;
+                if let Some(&h) = frame.get(i) {
+                  self.step(ctx, &Label::Hyp(h), sp1)?
+                } else if let Some(&l) = labels.get(i - frame.len()) {
+                  self.step(ctx, l, sp1)?
+                } else {
+                  let el = *saved.get(i - frame.len() - labels.len())
+                    .ok_or_else(|| ElabError::new_e(sp1, "step index out of range"))?;
+                  match el {
+                    StackEl::Expr(e, _) | StackEl::Proof(e, _) => {ctx.de.reuse(e);}
+                  }
+                  ctx.stack.push(el)
+                }
+              }
+              b'Z' => saved.push(*ctx.stack.last()
+                .ok_or_else(|| ElabError::new_e(sp1, "proof stack is empty"))?),
+              b'?' => return Err(ElabError::new_e(sp1, "incomplete proofs are not supported")),
+              _ => return Err(ElabError::new_e(sp1, "invalid character in compressed proof")),
+            }
+          }
+        }
+      }
+      _ => for &sp in proof { self.step(ctx, label(sp)?, sp)? }
+    }
+    Ok(())
+  }
+
+  /// Pop `n` elements from the proof stack.
+  fn pop(ctx: &mut ProofCtx<'_>, n: usize, sp: Span) -> Result<Vec<StackEl>> {
+    let i = ctx.stack.len().checked_sub(n)
+      .ok_or_else(|| ElabError::new_e(sp, "proof stack underflow"))?;
+    Ok(ctx.stack.split_off(i))
+  }
+
+  /// Check that a stack element is an expression of sort `s`.
+  fn expect_expr(&self, el: StackEl, s: SortId, sp: Span) -> Result<usize> {
+    match el {
+      StackEl::Expr(e, s2) if s == s2 => Ok(e),
+      StackEl::Expr(_, s2) => Err(ElabError::new_e(sp, format!(
+        "sort mismatch: expected {}, got {}", self.env.sorts[s].name, self.env.sorts[s2].name))),
+      StackEl::Proof(..) => Err(ElabError::new_e(sp, "expected an expression, got a proof")),
+    }
+  }
+
+  /// Returns true if expression `e` is a bound variable.
+  fn is_bound(ctx: &ProofCtx<'_>, e: usize) -> bool {
+    match ctx.de[e] {
+      ProofHash::Dummy(..) => true,
+      ProofHash::Ref(_, i) => ctx.bound.get(i).copied().unwrap_or(false),
+      _ => false,
+    }
+  }
+
+  /// Apply the proof step given by label `l`.
+  fn step(&self, ctx: &mut ProofCtx<'_>, label: &Label, sp: Span) -> Result<()> {
+    match *label {
+      Label::Hyp(h) => match self.hyps[h] {
+        (_, false) => return Err(ElabError::new_e(sp, "hypothesis is not active")),
+        (Hyp::F(v, sort), _) => {
+          let e = if let Some(&i) = ctx.vars.get(&v) {
+            ctx.de.reuse(i)
+          } else {
+            ctx.de.add(ProofHash::Dummy(self.vars[v].atom, sort))
+          };
+          ctx.stack.push(StackEl::Expr(e, sort))
+        }
+        (Hyp::E(..), _) => {
+          let &(p, e) = ctx.hyps.get(&h)
+            .ok_or_else(|| ElabError::new_e(sp, "hypothesis is not in the frame"))?;
+          ctx.stack.push(StackEl::Proof(ctx.de.reuse(p), e))
+        }
+      },
+      Label::Term(t, ref perm) => {
+        let td = &self.env.terms[t];
+        let els = Self::pop(ctx, perm.len(), sp)?;
+        let mut args = vec![0; perm.len()];
+        for (&k, el) in perm.iter().zip(els) {
+          let ty = td.args[k].1;
+          let e = self.expect_expr(el, ty.sort(), sp)?;
+          if ty.bound() && !Self::is_bound(ctx, e) {
+            return Err(ElabError::new_e(sp, "expected a bound variable"))
+          }
+          args[k] = e;
+        }
+        let e = ctx.de.add(ProofHash::Term(t, args.into()));
+        ctx.stack.push(StackEl::Expr(e, td.ret.0))
+      }
+      Label::Syntax(ref sorts, s, ref e) => {
+        let els = Self::pop(ctx, sorts.len(), sp)?;
+        let mut heap = sorts.iter().zip(els)
+          .map(|(&s, el)| self.expect_expr(el, s, sp).map(Some))
+          .collect::<Result<Vec<_>>>()?;
+        let e = ProofHash::subst(&mut ctx.de, &[], &mut heap, e);
+        ctx.stack.push(StackEl::Expr(e, s))
+      }
+      Label::Thm(t, ref slots) => {
+        let td = &self.env.thms[t];
+        let els = Self::pop(ctx, slots.len(), sp)?;
+        let mut args = vec![0; td.args.len()];
+        let mut hyps = vec![(0, 0); td.hyps.len()];
+        for (&slot, el) in slots.iter().zip(els) {
+          match slot {
+            Slot::Var(i) => {
+              let ty = td.args[i].1;
+              let e = self.expect_expr(el, ty.sort(), sp)?;
+              if ty.bound() && !Self::is_bound(ctx, e) {
+                return Err(ElabError::new_e(sp, "expected a bound variable"))
+              }
+              args[i] = e
+            }
+            Slot::Hyp(i) => match el {
+              StackEl::Proof(pf, stmt) => hyps[i] = (pf, stmt),
+              StackEl::Expr(..) =>
+                return Err(ElabError::new_e(sp, "expected a proof, got an expression")),
+            }
+          }
+        }
+        let mut bvs = HashSet::new();
+        for (&(_, ty), &e) in td.args.iter().zip(&args) {
+          if ty.bound() && !bvs.insert(e) {
+            return Err(ElabError::new_e(sp, "bundled theorem application is not supported"))
+          }
+        }
+        let mut heap = vec![None; td.heap.len()];
+        for (i, &e) in args.iter().enumerate() { heap[i] = Some(e) }
+        for (i, (_, h)) in td.hyps.iter().enumerate() {
+          if ProofHash::subst(&mut ctx.de, &td.heap, &mut heap, h) != hyps[i].1 {
+            return Err(ElabError::new_e(sp, format!("hypothesis {} does not match", i + 1)))
+          }
+        }
+        let res = ProofHash::subst(&mut ctx.de, &td.heap, &mut heap, &td.ret);
+        args.extend(hyps.iter().map(|&(p, _)| p));
+        let p = ctx.de.add(ProofHash::Thm(t, args.into(), res));
+        ctx.stack.push(StackEl::Proof(p, res))
+      }
+    }
+    Ok(())
+  }
+
+  /// Parse a math string as an expression of sort `sort`, using an Earley parser.
+  fn parse(&self, toks: &[MathTok], sort: SortId) -> Option<Rc<Tree>> {
+    struct Chart<'b> {
+      rules: &'b [Rule],
+      rules_by_sort: &'b HashMap<SortId, Vec<usize>>,
+      toks: &'b [MathTok],
+      sets: Vec<Vec<Item>>,
+      seen: Vec<HashSet<(usize, usize, usize)>>,
+      predicted: Vec<HashSet<SortId>>,
+    }
+    impl Chart<'_> {
+      fn add(&mut self, k: usize, it: Item) {
+        if self.seen[k].insert((it.rule, it.dot, it.origin)) { self.sets[k].push(it) }
+      }
+      fn predict(&mut self, k: usize, s: SortId) {
+        if !self.predicted[k].insert(s) { return }
+        for &r in self.rules_by_sort.get(&s).map_or(&[][..], |v| v) {
+          let ok = match self.rules[r].syms[0] {
+            RuleSym::Const(c) => self.toks.get(k) == Some(&MathTok::Const(c)),
+            RuleSym::Var(_) => k < self.toks.len(),
+          };
+          if ok { self.add(k, Item {rule: r, dot: 0, origin: k, kids: None}) }
+        }
+      }
+    }
+    if let [MathTok::Var(v, s)] = *toks {
+      if s == sort { return Some(Rc::new(Tree::Var(v))) }
+    }
+    let n = toks.len();
+    let mut ch = Chart {
+      rules: &self.rules, rules_by_sort: &self.rules_by_sort, toks,
+      sets: vec![vec![]; n + 1],
+      seen: vec![HashSet::new(); n + 1],
+      predicted: vec![HashSet::new(); n + 1],
+    };
+    ch.predict(0, sort);
+    for k in 0..=n {
+      let mut i = 0;
+      while let Some(it) = ch.sets[k].get(i).cloned() {
+        i += 1;
+        let rule = &self.rules[it.rule];
+        match rule.syms.get(it.dot) {
+          Some(&RuleSym::Const(c)) =>
+            if toks.get(k) == Some(&MathTok::Const(c)) { ch.add(k + 1, it.advance(None)) },
+          Some(&RuleSym::Var(s)) => {
+            if let Some(&MathTok::Var(v, s2)) = toks.get(k) {
+              if s == s2 { ch.add(k + 1, it.advance(Some(Rc::new(Tree::Var(v))))) }
+            }
+            ch.predict(k, s)
+          }
+          None => {
+            let mut slots = vec![];
+            let mut kids = &it.kids;
+            while let Some(k) = kids { slots.push(k.0.clone()); kids = &k.1 }
+            slots.reverse();
+            let mut args = slots.clone();
+            for (i, e) in slots.into_iter().enumerate() { args[rule.perm[i]] = e }
+            let e = Rc::new(Tree::App(rule.term, args.into()));
+            if it.origin == 0 && k == n && rule.sort == sort { return Some(e) }
+            let next = ch.sets[it.origin].iter()
+              .filter(|p| self.rules[p.rule].syms.get(p.dot) == Some(&RuleSym::Var(rule.sort)))
+              .map(|p| p.advance(Some(e.clone())))
+              .collect::<Vec<_>>();
+            for p in next { ch.add(k, p) }
+          }
+        }
+      }
+    }
+    None
+  }
+}
+
+/// Add a parsed expression to a [`Dedup`], using `vars` to map variables to argument indexes.
+fn expr(de: &mut Dedup<ExprHash>, vars: &HashMap<usize, usize>, e: &Tree) -> usize {
+  match *e {
+    Tree::Var(v) => de.add(ExprHash::Ref(ProofKind::Expr, vars[&v])),
+    Tree::App(t, ref es) => {
+      let ns = es.iter().map(|e| expr(de, vars, e)).collect();
+      de.add(ExprHash::App(t, ns))
+    }
+  }
+}
+
+/// Convert an expression in a [`Dedup`] to an [`ExprNode`], or return `None`
+/// if it contains dummy variables.
+fn to_node(de: &Dedup<ProofHash>, i: usize) -> Option<ExprNode> {
+  match de[i] {
+    ProofHash::Ref(_, j) => Some(ExprNode::Ref(j)),
+    ProofHash::Term(t, ref es) => Some(ExprNode::App(t,
+      es.iter().map(|&j| to_node(de, j)).collect::<Option<_>>()?)),
+    _ => None
+  }
+}
+
+/// Construct an [`Environment`] from a Metamath `.mm` file.
+pub fn elab(file: &FileRef, source: &[u8]) -> (Result<()>, Environment) {
+  let mut p = Importer::new(file, source);
+  (p.run(), p.env)
+}
+
+/// Write an MM0 specification file containing the sorts, terms, axioms and
+/// theorem statements of `env`.
+pub fn write_mm0(env: &FrozenEnv, mut w: impl Write) -> io::Result<()> {
+  fn binders(env: &FrozenEnv, w: &mut impl Write, args: &[(Option<AtomId>, Type)]) -> io::Result<()> {
+    let name = |a: Option<AtomId>| a.map_or("_", |a| env.data()[a].name().as_str());
+    let mut bvars = vec![];
+    for &(a, ty) in args {
+      match ty {
+        Type::Bound(s) => {
+          bvars.push(a);
+          write!(w, " {{{}: {}}}", name(a), env.sort(s).name)?
+        }
+        Type::Reg(s, deps) => {
+          write!(w, " ({}: {}", name(a), env.sort(s).name)?;
+          for (i, &b) in bvars.iter().enumerate() {
+            if deps & (1 << i) != 0 { write!(w, " {}", name(b))? }
+          }
+          write!(w, ")")?
+        }
+      }
+    }
+    Ok(())
+  }
+  fn expr(env: &FrozenEnv, strs: &[String], e: &ExprNode, paren: bool) -> String {
+    match *e {
+      ExprNode::Ref(i) => strs[i].clone(),
+      ExprNode::Dummy(a, _) => env.data()[a].name().to_string(),
+      ExprNode::App(t, ref es) => {
+        let mut s = env.data()[env.term(t).atom].name().to_string();
+        if es.is_empty() { return s }
+        for e in &**es { s.push(' '); s.push_str(&expr(env, strs, e, true)) }
+        if paren { format!("( {} )", s) } else { s }
+      }
+    }
+  }
+  writeln!(w, "delimiter $ ( ) $;")?;
+  for s in env.stmts() {
+    match *s {
+      StmtTrace::Sort(a) => {
+        let sd = env.sort(env.data()[a].sort().expect("expected a sort"));
+        writeln!(w, "\n{}{}sort {};",
+          if sd.mods.contains(Modifiers::PURE) {"pure "} else {""},
+          if sd.mods.contains(Modifiers::PROVABLE) {"provable "} else {""},
+          sd.name)?
+      }
+      StmtTrace::Decl(a) => match env.data()[a].decl().expect("expected a term/thm") {
+        DeclKey::Term(t) => {
+          let td = env.term(t);
+          write!(w, "\nterm {}", env.data()[a].name())?;
+          binders(env, &mut w, &td.args)?;
+          writeln!(w, ": {};", env.sort(td.ret.0).name)?
+        }
+        DeclKey::Thm(t) => {
+          let td = env.thm(t);
+          write!(w, "\n{} {}",
+            if let ThmKind::Axiom = td.kind {"axiom"} else {"theorem"},
+            env.data()[a].name())?;
+          binders(env, &mut w, &td.args)?;
+          let mut strs: Vec<String> = td.args.iter().map(|&(a, _)|
+            a.map_or_else(String::new, |a| env.data()[a].name().to_string())).collect();
+          for e in &td.heap[td.args.len()..] {
+            let s = expr(env, &strs, e, true);
+            strs.push(s)
+          }
+          for &(h, ref e) in &*td.hyps {
+            write!(w, "\n  ({}: $ {} $)",
+              h.map_or("_", |a| env.data()[a].name().as_str()), expr(env, &strs, e, false))?
+          }
+          writeln!(w, ":\n  $ {} $;", expr(env, &strs, &td.ret, false))?
+        }
+      },
+      StmtTrace::Global(_) | StmtTrace::OutputString(_) | StmtTrace::InputString(_) => {}
+    }
+  }
+  Ok(())
+}
+
+/// Main entry point for `mm0-rs from-mm` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs from-mm <in.mm> [out.mmb|out.mmu] [--spec out.mm0]`, where:
+///
+/// - `in.mm` is the Metamath database to import.
+/// - `out.mmb` (or `out.mmu`) is the proof file to write.
+/// - `out.mm0` is the MM0 specification file to write, containing the
+///   sorts, terms, axioms and theorem statements of the database.
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let path = args.value_of("INPUT").expect("required arg");
+  let path: FileRef = fs::canonicalize(path)?.into();
+  let file = crate::compiler::get_file(path.clone())?;
+  let (res, env) = elab(&path, &file);
+  if let Err(e) = res {
+    crate::compiler::print_error(&path, &e)?;
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "failed to import Metamath database"))
+  }
+  let env = FrozenEnv::new(env);
+  if let Some(spec) = args.value_of("spec") {
+    let mut w = BufWriter::new(File::create(spec)?);
+    write_mm0(&env, &mut w)?;
+    w.flush()?;
+  }
+  if let Some(out) = args.value_of("OUTPUT") {
+    crate::compiler::write_output(path, file.try_ascii().map(|fc| &**fc), &env, out)?
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use crate::compiler::{elab_test_file, test_file};
+  use crate::FrozenEnv;
+  use super::{elab, write_mm0};
+
+  const PROP_MM: &str = include_str!("../../examples/prop.mm");
+
+  fn import(text: &str) -> crate::elab::Result<FrozenEnv> {
+    let (res, env) = elab(&test_file("prop.mm", text.as_bytes()), text.as_bytes());
+    res.map(|()| FrozenEnv::new(env))
+  }
+
+  #[test]
+  fn round_trip() {
+    let env = import(PROP_MM).unwrap();
+    let mut mm0 = vec![];
+    write_mm0(&env, &mut mm0).unwrap();
+    let mm0 = String::from_utf8(mm0).unwrap();
+    assert!(mm0.contains("theorem id (ph: wff):\n  $ wi ph ph $;"), "{}", mm0);
+    assert!(mm0.contains("theorem idc (ph: wff):\n  $ wi ph ph $;"), "{}", mm0);
+    let spec = elab_test_file("prop.mm0", &mm0);
+    let mut mmu = vec![];
+    env.export_mmu(&mut mmu).unwrap();
+    crate::mmu::verify::verify(&mmu, &spec).unwrap();
+    let mmb = crate::mmb::export::tests::export(&env);
+    let mmb = mm0b_parser::BasicMmbFile::parse(&mmb).unwrap();
+    crate::mmb::verify::verify(&mmb, Some(&spec)).unwrap();
+  }
+
+  #[test]
+  fn malformed_compressed_proof() {
+    let msg = |from, to| {
+      let text = PROP_MM.replace(from, to);
+      assert_ne!(text, PROP_MM);
+      import(&text).unwrap_err().kind.msg()
+    };
+    assert_eq!(msg("AFADEE $.", "AFADEe $."), "invalid character in compressed proof");
+    // `F` is the only saved step, so `G` refers to nothing
+    assert_eq!(msg("AFADEE $.", "AFADEG $."), "step index out of range");
+    // without the final `ax-mp`, two steps are left on the stack
+    assert_eq!(msg("AFADEE $.", "AFADE $."), "proof does not prove the statement");
+  }
+}
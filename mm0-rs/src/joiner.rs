@@ -87,7 +87,10 @@ impl<W: Write> Joiner<W> {
   }
 }
 
-fn join_with_header(comments: bool, header: bool, mut w: impl Write, file: FileRef) -> io::Result<()> {
+/// Write the file `file` with its imports joined to `w`. If `comments` is true, each file is
+/// preceded by a comment with its name, and if `header` is also true, the output begins
+/// with a list of all the joined files.
+pub(crate) fn join_with_header(comments: bool, header: bool, mut w: impl Write, file: FileRef) -> io::Result<()> {
   let mut buf = vec![];
   if comments && header {
     let mut joiner = Joiner::new(comments, &mut buf);
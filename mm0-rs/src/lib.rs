@@ -9,10 +9,14 @@
 //!     -V, --version    Prints version information
 //!
 //! SUBCOMMANDS:
+//!     build      Build the targets of an mm0.toml project manifest
 //!     compile    Compile MM1 files into MMB
+//!     decompile  Decompile MMB files into MM1
+//!     from-mm    Import Metamath databases into MM0
 //!     help       Prints this message or the help of the given subcommand(s)
 //!     join       Join MM1/MM0 files with imports by concatenation
 //!     server     MM1 LSP server
+//!     verify     Verify MMB or MMU files against an MM0 specification
 //! ```
 //!
 //! [`mm0-rs/README.md`]: https://github.com/digama0/mm0/blob/master/mm0-rs/README.md
@@ -58,9 +62,13 @@ pub use mm0_deepsize::deep_size_0;
 
 #[cfg(feature = "server")]
 #[macro_use] pub mod server;
+pub mod build;
 pub mod compiler;
+pub mod debug_adapter;
 pub mod joiner;
 pub mod elab;
+pub mod from_mm;
+pub mod fmt;
 #[cfg(feature = "doc")]
 pub mod doc;
 /// Import and export functionality for MMB binary proof format
@@ -68,13 +76,13 @@ pub mod doc;
 /// See [`mm0-c/verifier.c`] for information on the MMB format.
 ///
 /// [`mm0-c/verifier.c`]: https://github.com/digama0/mm0/blob/master/mm0-c/verifier.c
-pub mod mmb { pub mod export; pub mod import; }
+pub mod mmb { pub mod decompile; pub mod export; pub mod import; pub mod verify; }
 /// Import and export functionality for MMU ascii proof format
 ///
 /// See [The `.mmu` file format] for information on the MMU format.
 ///
 /// [The `.mmu` file format]: https://github.com/digama0/mm0/blob/master/mm0-hs/README.md#the-mmu-file-format
-pub mod mmu { pub mod import; pub mod export; }
+pub mod mmu { pub mod import; pub mod export; pub mod verify; }
 #[cfg(feature = "mmc")]
 pub mod mmc;
 
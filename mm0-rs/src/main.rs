@@ -1,6 +1,13 @@
 
 use clap::clap_app;
 
+/// The values of `compile --message-format`. JSON output uses the LSP types,
+/// so it is only available with the `server` feature.
+#[cfg(feature = "server")]
+const MESSAGE_FORMATS: &[&str] = &["human", "json"];
+#[cfg(not(feature = "server"))]
+const MESSAGE_FORMATS: &[&str] = &["human"];
+
 fn main() -> std::io::Result<()> {
   let app = clap_app!(mm0_rs =>
     (name: "mm0-rs")
@@ -10,10 +17,21 @@ fn main() -> std::io::Result<()> {
     (@setting InferSubcommands)
     (@setting SubcommandRequiredElseHelp)
     (@setting VersionlessSubcommands)
+    (@subcommand build =>
+      (about: "Build the targets of an mm0.toml project manifest")
+      (@arg manifest: -m --manifest [FILE] "Sets the manifest file (default mm0.toml)")
+      (@arg force: -f --force "Rebuild all targets, even if they are up to date")
+      (@arg no_proofs: -n --("no-proofs") "Disable proof checking until (check-proofs #t)")
+      (@arg TARGETS: ... "The targets to build, or all targets if omitted"))
     (@subcommand compile =>
       (about: "Compile MM1 files into MMB")
       (@arg no_proofs: -n --("no-proofs") "Disable proof checking until (check-proofs #t)")
       (@arg quiet: -q --quiet "Hide diagnostic messages")
+      (@arg message_format: --("message-format") [FMT] possible_values(MESSAGE_FORMATS)
+        "Print diagnostics as human-readable text (the default) or JSON records")
+      (@arg cache: --cache [DIR] "Cache elaborated files in DIR, and reuse them if unchanged")
+      (@arg watch: -w --watch "Keep running, and recompile when the input or its imports change")
+      (@arg profile: --profile "Print the time spent elaborating each statement")
       (@arg output: -o --output [FILE] "Print 'output' commands to a file (use '-' to print to stdout)")
       (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
       (@arg OUTPUT: "Sets the output file (.mmb or .mmu)"))
@@ -23,6 +41,31 @@ fn main() -> std::io::Result<()> {
       (@arg bare: -b --("bare") "Don't add any comments")
       (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
       (@arg OUTPUT: "Sets the output file (.mm1 or .mm0), or stdin if omitted"))
+    (@subcommand verify =>
+      (about: "Verify MMB or MMU files against an MM0 specification")
+      (@arg INPUT: +required "Sets the specification file (.mm0)")
+      (@arg PROOF: +required "Sets the proof file to check (.mmb or .mmu)"))
+    (@subcommand from_mm =>
+      (name: "from-mm")
+      (about: "Import Metamath databases into MM0")
+      (@arg spec: -s --spec [FILE] "Write an MM0 specification to FILE")
+      (@arg INPUT: +required "Sets the input file (.mm)")
+      (@arg OUTPUT: "Sets the output file (.mmb or .mmu)"))
+    (@subcommand decompile =>
+      (about: "Decompile MMB files into MM1")
+      (@arg INPUT: +required "Sets the input file (.mmb)")
+      (@arg OUTPUT: "Sets the output file (.mm1), or stdout if omitted"))
+    (@subcommand fmt =>
+      (about: "Format MM0/MM1 files")
+      (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
+      (@arg OUTPUT: "Sets the output file (.mm1 or .mm0), or stdout if omitted"))
+    (@subcommand repl =>
+      (about: "Interactive MM1 lisp shell")
+      (@arg no_proofs: -n --("no-proofs") "Disable proof checking until (check-proofs #t)")
+      (@arg INPUT: "Elaborate the file (.mm1 or .mm0) and start in the resulting environment"))
+    (@subcommand debug_adapter =>
+      (name: "debug-adapter")
+      (about: "MM1 lisp debugger, using the Debug Adapter Protocol on stdin/stdout"))
     (@subcommand doc =>
       (about: "Build documentation pages")
       (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
@@ -44,11 +87,24 @@ fn main() -> std::io::Result<()> {
   let m = app.get_matches();
 
   match m.subcommand() {
+    ("build", Some(m)) => {
+      if m.is_present("no_proofs") { mm0_rs::set_check_proofs(false) }
+      mm0_rs::build::main(m)?
+    }
     ("compile", Some(m)) => {
       if m.is_present("no_proofs") { mm0_rs::set_check_proofs(false) }
       mm0_rs::compiler::main(m)?
     }
     ("join", Some(m)) => mm0_rs::joiner::main(m)?,
+    ("verify", Some(m)) => mm0_rs::mmb::verify::main(m)?,
+    ("decompile", Some(m)) => mm0_rs::mmb::decompile::main(m)?,
+    ("from-mm", Some(m)) => mm0_rs::from_mm::main(m)?,
+    ("fmt", Some(m)) => mm0_rs::fmt::main(m)?,
+    ("repl", Some(m)) => {
+      if m.is_present("no_proofs") { mm0_rs::set_check_proofs(false) }
+      mm0_rs::elab::repl::main(m)?
+    }
+    ("debug-adapter", Some(m)) => mm0_rs::debug_adapter::main(m)?,
     #[cfg(feature = "doc")]
     ("doc", Some(m)) => mm0_rs::doc::main(m)?,
     #[cfg(feature = "server")]
@@ -0,0 +1,109 @@
+//! A decompiler for MMB files.
+//!
+//! This reads an MMB file using the [`import`](super::import) module, and then prints every
+//! sort, term, definition and theorem using the names from the index, with theorem proofs
+//! rendered as `refine`-style proof terms. The result is an `.mm1` file that uses no notations,
+//! so all expressions are printed in prefix s-expression form, like `$ (wi a b) $`.
+
+use std::{fs, io};
+use clap::ArgMatches;
+use crate::elab::environment::{DeclKey, StmtTrace};
+use crate::{AtomId, Environment, FileRef, FormatEnv, LinedString};
+
+/// Replace any anonymous hypothesis names (which can occur if the MMB file has no index, or the
+/// index does not name some hypotheses) with fresh names `h1`, `h2` etc., so that they can be
+/// referred to in the proof. Names that are already used by a binder of the theorem are skipped.
+fn name_hyps(env: &mut Environment) {
+  for tid in 0..env.thms.len() {
+    let mut hyps = std::mem::take(&mut env.thms.0[tid].hyps);
+    let mut used: Vec<AtomId> = env.thms.0[tid].args.iter().map(|p| p.0)
+      .chain(hyps.iter().map(|p| p.0)).flatten().collect();
+    let mut n = 0;
+    for (h, _) in &mut *hyps {
+      if h.map_or(true, |h| h == AtomId::UNDER) {
+        let a = loop {
+          n += 1;
+          let name = format!("h{}", n);
+          match env.atoms.get(name.as_bytes()) {
+            Some(a) if used.contains(a) => {}
+            _ => break env.get_atom(name.as_bytes()),
+          }
+        };
+        used.push(a);
+        *h = Some(a)
+      }
+    }
+    env.thms.0[tid].hyps = hyps;
+  }
+}
+
+/// Write the contents of `env` to `w` as an MM1 file.
+pub fn decompile(env: &Environment, mut w: impl io::Write) -> io::Result<()> {
+  let source = LinedString::from(String::new());
+  let fe = FormatEnv {source: &source, env};
+  for s in &env.stmts {
+    let mut out = String::new();
+    fe.pretty(|p| match *s {
+      StmtTrace::Sort(a) => p.sort(env.data[a].sort.expect("sort not found")).render_fmt(80, &mut out),
+      StmtTrace::Decl(a) => match env.data[a].decl.expect("decl not found") {
+        DeclKey::Term(tid) => p.term_with_dummies(tid).render_fmt(80, &mut out),
+        DeclKey::Thm(tid) => p.thm(tid, true).render_fmt(80, &mut out),
+      },
+      StmtTrace::Global(_) | StmtTrace::OutputString(_) | StmtTrace::InputString(_) => Ok(()),
+    }).expect("impossible");
+    if !out.is_empty() { writeln!(w, "{}\n", out)? }
+  }
+  Ok(())
+}
+
+/// Main entry point for `mm0-rs decompile` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs decompile <in.mmb> [out.mm1]`, where:
+///
+/// - `in.mmb` is the MMB file to read
+/// - `out.mm1` is the output file, or stdout if omitted
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let path = args.value_of("INPUT").expect("required arg");
+  let path: FileRef = fs::canonicalize(path)?.into();
+  let file = crate::compiler::get_file(path.clone())?;
+  let (res, mut env) = super::import::elab(&path, &file);
+  if let Err(e) = res {
+    crate::compiler::print_error(&path, &e)?;
+    std::process::exit(1);
+  }
+  name_hyps(&mut env);
+  match args.value_of("OUTPUT") {
+    None => decompile(&env, io::stdout().lock()),
+    Some(out) => decompile(&env, io::BufWriter::new(fs::File::create(out)?)),
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::wildcard_imports)]
+mod tests {
+  use crate::compiler::elab_test_file;
+  use crate::mmb::export::tests::export;
+  use super::*;
+
+  #[test]
+  fn hyp_names_avoid_binders() {
+    let env = elab_test_file("hyps.mm1", "\
+      provable sort wff;\n\
+      term im: wff > wff > wff;\n\
+      theorem foo (h1 h3: wff) (a: $ h1 $) (h2: $ h3 $) (b: $ im h1 h3 $): $ h3 $ = 'h2;\n");
+    let buf = export(&env);
+    let (res, mut env) = crate::mmb::import::elab(&std::path::PathBuf::from("hyps.mmb").into(), &buf);
+    res.unwrap();
+    // simulate an MMB file whose index does not name `a` and `b`
+    let hyps = &mut env.thms.0[0].hyps;
+    hyps[0].0 = None;
+    hyps[2].0 = None;
+    name_hyps(&mut env);
+    let mut out = vec![];
+    decompile(&env, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("(h4: $ h1 $) (h2: $ h3 $) (h5: $ im h1 h3 $)"), "{}", out);
+  }
+}
@@ -597,6 +597,7 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
                   for (_, h) in &*td.hyps {
                     write_expr_proof(vec, &td.heap, &mut reorder, vars, h, false)?;
                     ProofCmd::Hyp.write_to(vec)?;
+                    reorder.idx += 1;
                   }
                   write_expr_proof(vec, &td.heap, &mut reorder, vars, &td.ret, false)?;
                   if let ThmKind::Axiom = td.kind {
@@ -631,6 +632,15 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
             }
           }
         }
+        StmtTrace::InputString(ref os) => {
+          let mut reorder = Reorder::new(0, os.heap.len(), |i| i);
+          for e in &*os.exprs {
+            write_expr_proof(vec, &os.heap, &mut reorder, &mut None, e, false)?;
+          }
+          vec.write_u8(0)?;
+          write_cmd_bytes(self, STMT_INPUT_STRING, vec)?;
+          vec.clear();
+        }
         StmtTrace::Global(_) |
         StmtTrace::OutputString(_) => {}
       }
@@ -711,4 +721,38 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
     }
     w.flush()
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::wildcard_imports)]
+pub(crate) mod tests {
+  use std::io::Cursor;
+  use mm0b_parser::BasicMmbFile;
+  use crate::compiler::elab_test_file;
+  use super::*;
+
+  /// Export `env` to an in-memory MMB file.
+  pub(crate) fn export(env: &FrozenEnv) -> Vec<u8> {
+    let mut report = |_: ErrorLevel, _: &str| {};
+    let mut w = Cursor::new(vec![]);
+    let mut ex = Exporter::new(std::path::PathBuf::from("test.mm1").into(), None, env, &mut report, &mut w);
+    ex.run(true).unwrap();
+    ex.finish().unwrap();
+    w.into_inner()
+  }
+
+  #[test]
+  fn axiom_hyps_are_on_the_heap() {
+    // Each hypothesis of an axiom takes a heap slot, so the later reference to the
+    // shared subterm `b -> b` has to skip over it.
+    let env = elab_test_file("ax.mm1", "\
+      provable sort wff;\n\
+      delimiter $ ( ) $;\n\
+      term im: wff > wff > wff;\n\
+      infixr im: $->$ prec 25;\n\
+      axiom ax (a b: wff): $ a $ > $ (b -> b) -> (b -> b) $ > $ b -> b $;\n");
+    let buf = export(&env);
+    let file = BasicMmbFile::parse(&buf).unwrap();
+    crate::mmb::verify::verify(&file, None).unwrap();
+  }
+}
@@ -2,7 +2,7 @@
 
 use std::rc::Rc;
 use crate::{Environment, Modifiers, AtomId, TermId,
-    Type, Term, Thm, TermKind, ThmKind, ExprNode, Expr, Proof};
+    Type, Term, Thm, TermKind, ThmKind, ExprNode, Expr, Proof, OutputString, StmtTrace};
 use crate::elab::proof::{IDedup, ProofKind, ProofHash, build};
 use crate::{FileRef, FileSpan, SliceExt};
 use mm0b_parser::{NumdStmtCmd, UnifyCmd, ProofCmd, BasicMmbFile,
@@ -365,6 +365,34 @@ fn parse_proof(
   Ok(Proof {heap, hyps, head: ids[ret].take()})
 }
 
+/// Parse the proof stream of an `input string` directive, which constructs a list of
+/// expressions (with no variables).
+fn parse_input_string(file: &BasicMmbFile<'_>, it: &mut ProofIter<'_>, span: FileSpan
+) -> Result<OutputString> {
+  use ParseError::StrError;
+  let mut heap = vec![];
+  let mut stack = vec![];
+  let mut pos = it.pos;
+  while let Some(e) = it.next() {
+    match e? {
+      ProofCmd::Term {tid, save} => {
+        let nargs = file.term(tid).ok_or(StrError("unknown term", pos))?.args().len();
+        let mid = stack.len().checked_sub(nargs).ok_or(StrError("stack underflow", pos))?;
+        let e = ExprNode::App(tid, stack.drain(mid..).collect());
+        stack.push(if save { heap.push(e); ExprNode::Ref(heap.len() - 1) } else { e })
+      }
+      ProofCmd::Ref(i) => {
+        let i = usize::try_from(i).expect("impossible");
+        if i >= heap.len() { return Err(StrError("reference out of range", pos)) }
+        stack.push(ExprNode::Ref(i))
+      }
+      _ => return Err(StrError("expected an expression", pos)),
+    }
+    pos = it.pos;
+  }
+  Ok(OutputString {span, heap: heap.into(), exprs: stack.into()})
+}
+
 fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
   use ParseError::StrError;
   let file = BasicMmbFile::parse(buf)?;
@@ -454,6 +482,11 @@ fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
           vis, heap, hyps: hyps.into_boxed_slice(), ret,
         }).map_err(|_| StrError("double add term", start))?;
       }
+      NumdStmtCmd::InputString => {
+        let fsp = FileSpan {file: fref.clone(), span: (start..pf.pos).into()};
+        let os = parse_input_string(&file, &mut pf, fsp)?;
+        env.stmts.push(StmtTrace::InputString(Box::new(os)));
+      }
     }
     start = it.pos;
   }
@@ -0,0 +1,837 @@
+//! A proof checker for MMB files.
+//!
+//! This is a port of the verifier in [`mm0-c/verifier.c`] to Rust. It replays the proof and
+//! unify streams of every declaration on a stack machine, and (optionally) checks that the public
+//! declarations match the statements of an elaborated `.mm0` file, in order.
+//!
+//! [`mm0-c/verifier.c`]: https://github.com/digama0/mm0/blob/master/mm0-c/verifier.c
+
+use std::collections::HashMap;
+use std::{fs, io};
+use clap::ArgMatches;
+use mm0b_parser::{BasicMmbFile, NumdStmtCmd, ProofCmd, UnifyCmd, ProofIter, UnifyIter,
+  ParseError, Arg, TYPE_BOUND_MASK, TYPE_DEPS_MASK};
+use crate::elab::environment::{DeclKey, ExprNode, StmtTrace, TermKind, ThmKind, Type};
+use crate::{AtomId, BoxError, ElabError, Environment, FileRef, FileSpan, FrozenEnv, Modifiers,
+  OutputString, SortId, TermId};
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+/// An error produced by the verifier. The underlying [`ParseError`] carries the byte offset in
+/// the MMB file (for [`ParseError::StrError`]), and `spec` points at the corresponding
+/// declaration in the `.mm0` file, if the failing statement is a public one.
+#[derive(Debug)]
+pub struct VerifyError {
+  /// The error in the MMB file.
+  pub err: ParseError,
+  /// The spec declaration that was being checked when the error occurred.
+  pub spec: Option<FileSpan>,
+}
+
+impl From<VerifyError> for ElabError {
+  fn from(VerifyError {err, spec}: VerifyError) -> Self {
+    let e = ElabError::from(err);
+    match spec {
+      None => e,
+      Some(fsp) =>
+        ElabError::with_info(e.pos, e.kind.msg().into(), vec![(fsp, "spec declaration".into())]),
+    }
+  }
+}
+
+/// An expression in the store. The `u64` is the type of the expression, in the same
+/// format as an [`Arg`]: the high bit is set for a bound variable, the next 7 bits are the
+/// sort, and the low 56 bits are the set of bound variables the expression depends on.
+#[derive(Debug)]
+enum Expr {
+  /// A variable (either a binder or a dummy)
+  Var(u64),
+  /// A term constructor applied to a list of arguments (given as indexes into the store)
+  App(u64, TermId, Box<[usize]>),
+}
+
+impl Expr {
+  fn ty(&self) -> u64 { match *self { Expr::Var(ty) | Expr::App(ty, _, _) => ty } }
+}
+
+/// A stack (or heap) element. All the `usize` fields are indexes into the store.
+#[derive(Clone, Copy, Debug)]
+enum StackEl {
+  /// An expression `e`
+  Expr(usize),
+  /// A proof `|- e`
+  Proof(usize),
+  /// A convertibility proof `e1 = e2`
+  Conv(usize, usize),
+  /// A convertibility obligation `e1 =?= e2`
+  CoConv(usize, usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum UnifyMode {
+  /// We are checking a definition, or processing an `Unfold` command
+  Def,
+  /// We are applying a theorem (`Thm`), and need to check the substitution is correct
+  Thm,
+  /// We are checking that a theorem header is correct
+  ThmEnd,
+}
+
+/// Returns true if a value with type `from` can be cast to a value of type `to`.
+/// This requires that the sorts be the same, and additionally if `to` is a
+/// name then so is `from`.
+fn sorts_compatible(from: u64, to: u64) -> bool {
+  let diff = from ^ to;
+  diff & !TYPE_DEPS_MASK == 0 ||
+  (diff & !TYPE_BOUND_MASK & !TYPE_DEPS_MASK == 0 && from & TYPE_BOUND_MASK != 0)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn type_sort(ty: u64) -> SortId { SortId(((ty >> 56) & 0x7F) as u8) }
+
+fn as_term(store: &[Expr], e: usize, pos: usize) -> Result<(TermId, &[usize])> {
+  if let Expr::App(_, t, ref args) = store[e] { Ok((t, args)) }
+  else { Err(ParseError::StrError("store type error", pos)) }
+}
+
+macro_rules! ensure {($e:expr, $msg:expr, $pos:expr) => {
+  if !$e { return Err(ParseError::StrError($msg, $pos)) }
+}}
+
+/// The state of the MMB verifier.
+#[derive(Debug)]
+struct Verifier<'a> {
+  /// The file being verified.
+  file: &'a BasicMmbFile<'a>,
+  /// The sorts that have been declared so far.
+  sorts: Vec<Modifiers>,
+  /// The number of terms that have been declared so far.
+  num_terms: u32,
+  /// The number of theorems that have been declared so far.
+  num_thms: u32,
+  /// The expression store, which is cleared at the start of each declaration.
+  store: Vec<Expr>,
+  /// The main stack.
+  stack: Vec<StackEl>,
+  /// The main heap.
+  heap: Vec<StackEl>,
+  /// The hypothesis stack.
+  hstack: Vec<usize>,
+  /// The unify stack.
+  ustack: Vec<usize>,
+  /// The unify heap.
+  uheap: Vec<usize>,
+  /// The bit for the next bound variable that is allocated.
+  next_bv: u64,
+}
+
+impl<'a> Verifier<'a> {
+  fn sort(&self, s: SortId) -> Option<Modifiers> { self.sorts.get(usize::from(s.0)).copied() }
+
+  fn alloc(&mut self, e: Expr) -> usize { (self.store.len(), self.store.push(e)).0 }
+
+  fn pop(&mut self, pos: usize) -> Result<StackEl> {
+    self.stack.pop().ok_or(ParseError::StrError("stack underflow", pos))
+  }
+
+  fn pop_expr(&mut self, pos: usize) -> Result<usize> {
+    if let StackEl::Expr(e) = self.pop(pos)? { Ok(e) }
+    else { Err(ParseError::StrError("bad stack slot", pos)) }
+  }
+
+  fn pop_proof(&mut self, pos: usize) -> Result<usize> {
+    if let StackEl::Proof(e) = self.pop(pos)? { Ok(e) }
+    else { Err(ParseError::StrError("bad stack slot", pos)) }
+  }
+
+  fn pop_conv(&mut self, pos: usize) -> Result<(usize, usize)> {
+    if let StackEl::Conv(e1, e2) = self.pop(pos)? { Ok((e1, e2)) }
+    else { Err(ParseError::StrError("bad stack slot", pos)) }
+  }
+
+  fn pop_coconv(&mut self, pos: usize) -> Result<(usize, usize)> {
+    if let StackEl::CoConv(e1, e2) = self.pop(pos)? { Ok((e1, e2)) }
+    else { Err(ParseError::StrError("bad stack slot", pos)) }
+  }
+
+  fn pop_ustack(&mut self, pos: usize) -> Result<usize> {
+    self.ustack.pop().ok_or(ParseError::StrError("unify stack underflow", pos))
+  }
+
+  /// Check that `e` has a provable sort.
+  fn provable(&self, e: usize) -> bool {
+    self.sort(type_sort(self.store[e].ty())).map_or(false, |m| m.contains(Modifiers::PROVABLE))
+  }
+
+  /// Given a list of binders, load the main heap and allocate all the variables.
+  /// Also perform binder validity checking.
+  fn load_args(&mut self, args: &[Arg], pos: usize) -> Result<()> {
+    self.store.clear();
+    self.stack.clear();
+    self.heap.clear();
+    self.hstack.clear();
+    self.next_bv = 1;
+    for &ty in args {
+      let ty = ty.into_inner();
+      let deps = ty & TYPE_DEPS_MASK;
+      let sort = self.sort(type_sort(ty)).ok_or(ParseError::StrError("bad binder sort", pos))?;
+      if ty & TYPE_BOUND_MASK != 0 {
+        ensure!(!sort.contains(Modifiers::STRICT), "bound variable in strict sort", pos);
+        ensure!(deps == self.next_bv, "bad binder deps", pos);
+        self.next_bv *= 2;
+      } else {
+        ensure!(deps & !(self.next_bv - 1) == 0, "bad binder deps", pos);
+      }
+      let e = self.alloc(Expr::Var(ty));
+      self.heap.push(StackEl::Expr(e))
+    }
+    Ok(())
+  }
+
+  /// Run a unify command stream, with `tgt` as the expression to be unified
+  /// (the unfolded definition for [`UnifyMode::Def`], the substituted theorem for
+  /// [`UnifyMode::Thm`], and the target statement for [`UnifyMode::ThmEnd`]).
+  fn run_unify(&mut self, mode: UnifyMode, mut it: UnifyIter<'_>, tgt: usize) -> Result<()> {
+    self.ustack.clear();
+    self.ustack.push(tgt);
+    let mut pos = it.pos;
+    while let Some(cmd) = it.next() {
+      match cmd? {
+        UnifyCmd::Ref(i) => {
+          let e = *self.uheap.get(u32_as_usize(i)).ok_or(ParseError::StrError("bad ref step", pos))?;
+          ensure!(e == self.pop_ustack(pos)?, "unify failure at ref", pos);
+        }
+        UnifyCmd::Term {tid, save} => {
+          let p = self.pop_ustack(pos)?;
+          let (t, args) = as_term(&self.store, p, pos)?;
+          ensure!(t == tid, "unify failure at term", pos);
+          self.ustack.extend(args.iter().rev().copied());
+          if save { self.uheap.push(p) }
+        }
+        UnifyCmd::Dummy(s) => {
+          ensure!(mode == UnifyMode::Def, "Dummy command not allowed in theorem statements", pos);
+          let p = self.pop_ustack(pos)?;
+          let ty = if let Expr::Var(ty) = self.store[p] { ty }
+            else { return Err(ParseError::StrError("store type error", pos)) };
+          ensure!(ty >> 56 == u64::from(0x80 | s.0), "unify failure at dummy", pos);
+          let deps = ty & TYPE_DEPS_MASK;
+          ensure!(self.uheap.iter().all(|&e| self.store[e].ty() & deps == 0),
+            "dummy disjoint variable violation", pos);
+          self.uheap.push(p)
+        }
+        UnifyCmd::Hyp => match mode {
+          UnifyMode::Thm => {
+            let e = self.pop_proof(pos)?;
+            self.ustack.push(e)
+          }
+          UnifyMode::ThmEnd => {
+            ensure!(self.ustack.is_empty(), "unfinished unify stack", pos);
+            let e = self.hstack.pop().ok_or(ParseError::StrError("hypothesis stack underflow", pos))?;
+            self.ustack.push(e)
+          }
+          UnifyMode::Def =>
+            return Err(ParseError::StrError("Hyp command not allowed in definition statements", pos)),
+        }
+      }
+      pos = it.pos;
+    }
+    if mode == UnifyMode::ThmEnd {
+      ensure!(self.hstack.is_empty(), "unfinished hypothesis stack", pos);
+    }
+    ensure!(self.ustack.is_empty(), "unfinished unify stack", pos);
+    Ok(())
+  }
+
+  /// Run a proof command stream. `def` is true if we are constructing a definition body,
+  /// and false if this is a theorem proof.
+  fn run_proof(&mut self, def: bool, it: &mut ProofIter<'_>) -> Result<()> {
+    let mut pos = it.pos;
+    while let Some(cmd) = it.next() {
+      match cmd? {
+        ProofCmd::Ref(i) => match *self.heap.get(u32_as_usize(i))
+          .ok_or(ParseError::StrError("bad ref step", pos))? {
+          StackEl::Conv(e1, e2) => {
+            let (f1, f2) = self.pop_coconv(pos)?;
+            ensure!(e1 == f1 && e2 == f2, "ConvRef unify error", pos);
+          }
+          s => self.stack.push(s),
+        }
+        ProofCmd::Dummy(s) => {
+          let sort = self.sort(s).ok_or(ParseError::StrError("bad dummy sort", pos))?;
+          ensure!(!sort.contains(Modifiers::STRICT), "dummy variable in strict sort", pos);
+          ensure!(self.next_bv >> 56 == 0, "too many bound variables", pos);
+          let e = self.alloc(Expr::Var(TYPE_BOUND_MASK | u64::from(s.0) << 56 | self.next_bv));
+          self.next_bv *= 2;
+          self.stack.push(StackEl::Expr(e));
+          self.heap.push(StackEl::Expr(e))
+        }
+        ProofCmd::Term {tid, save} => {
+          ensure!(tid.0 < self.num_terms, "term out of range", pos);
+          let td = self.file.term(tid).ok_or(ParseError::StrError("term out of range", pos))?;
+          let mid = self.stack.len().checked_sub(td.args().len())
+            .ok_or(ParseError::StrError("stack underflow", pos))?;
+          let mut bound = vec![];
+          let mut accum = u64::from(td.sort().0) << 56;
+          let mut args = Vec::with_capacity(td.args().len());
+          for (&s, &target) in self.stack[mid..].iter().zip(td.args()) {
+            let arg = if let StackEl::Expr(e) = s { e }
+              else { return Err(ParseError::StrError("bad stack slot", pos)) };
+            let target = target.into_inner();
+            let ty = self.store[arg].ty();
+            ensure!(sorts_compatible(ty, target), "type mismatch", pos);
+            let mut deps = ty & TYPE_DEPS_MASK;
+            if target & TYPE_BOUND_MASK != 0 {
+              bound.push(deps)
+            } else {
+              if def {
+                for (j, &d) in bound.iter().enumerate() {
+                  if target & (1 << j) != 0 { deps &= !d }
+                }
+              }
+              accum |= deps
+            }
+            args.push(arg)
+          }
+          if def {
+            let target = td.ret().into_inner() & TYPE_DEPS_MASK;
+            for (j, &d) in bound.iter().enumerate() {
+              if target & (1 << j) != 0 { accum |= d }
+            }
+          }
+          self.stack.truncate(mid);
+          let e = self.alloc(Expr::App(accum, tid, args.into_boxed_slice()));
+          self.stack.push(StackEl::Expr(e));
+          if save { self.heap.push(StackEl::Expr(e)) }
+        }
+        ProofCmd::Thm {tid, save} => {
+          ensure!(!def, "invalid opcode in def", pos);
+          ensure!(tid.0 < self.num_thms, "theorem out of range", pos);
+          let td = self.file.thm(tid).ok_or(ParseError::StrError("theorem out of range", pos))?;
+          let e = self.pop_expr(pos)?;
+          let mid = self.stack.len().checked_sub(td.args().len())
+            .ok_or(ParseError::StrError("stack underflow", pos))?;
+          self.uheap.clear();
+          let mut bound = vec![];
+          for (&s, &target) in self.stack[mid..].iter().zip(td.args()) {
+            let arg = if let StackEl::Expr(e) = s { e }
+              else { return Err(ParseError::StrError("bad stack slot", pos)) };
+            let target = target.into_inner();
+            let deps = self.store[arg].ty() & TYPE_DEPS_MASK;
+            if target & TYPE_BOUND_MASK != 0 {
+              bound.push(deps);
+              ensure!(self.uheap.iter().all(|&e| self.store[e].ty() & deps == 0),
+                "disjoint variable violation", pos);
+            } else {
+              ensure!(bound.iter().enumerate().all(|(j, &d)| target & (1 << j) != 0 || d & deps == 0),
+                "disjoint variable violation", pos);
+            }
+            self.uheap.push(arg)
+          }
+          self.stack.truncate(mid);
+          self.run_unify(UnifyMode::Thm, td.unify(), e)?;
+          self.stack.push(StackEl::Proof(e));
+          if save { self.heap.push(StackEl::Proof(e)) }
+        }
+        ProofCmd::Hyp => {
+          ensure!(!def, "invalid opcode in def", pos);
+          let e = self.pop_expr(pos)?;
+          ensure!(self.provable(e), "hypothesis should have provable sort", pos);
+          self.hstack.push(e);
+          self.heap.push(StackEl::Proof(e))
+        }
+        ProofCmd::Conv => {
+          let e2 = self.pop_proof(pos)?;
+          let e1 = self.pop_expr(pos)?;
+          self.stack.push(StackEl::Proof(e1));
+          self.stack.push(StackEl::CoConv(e1, e2))
+        }
+        ProofCmd::Refl => {
+          let (e1, e2) = self.pop_coconv(pos)?;
+          ensure!(e1 == e2, "Refl unify failure", pos);
+        }
+        ProofCmd::Sym => {
+          let (e1, e2) = self.pop_coconv(pos)?;
+          self.stack.push(StackEl::CoConv(e2, e1))
+        }
+        ProofCmd::Cong => {
+          let (e1, e2) = self.pop_coconv(pos)?;
+          let (t1, args1) = as_term(&self.store, e1, pos)?;
+          let (t2, args2) = as_term(&self.store, e2, pos)?;
+          ensure!(t1 == t2, "Cong unify error", pos);
+          let new = args1.iter().zip(args2).rev().map(|(&a1, &a2)| StackEl::CoConv(a1, a2))
+            .collect::<Vec<_>>();
+          self.stack.extend(new)
+        }
+        ProofCmd::Unfold => {
+          let e = self.pop_expr(pos)?;
+          let (e1, e2) = self.pop_coconv(pos)?;
+          let (t, args) = as_term(&self.store, e1, pos)?;
+          let td = self.file.term(t).ok_or(ParseError::StrError("term out of range", pos))?;
+          ensure!(td.def(), "Unfold: not a definition", pos);
+          self.uheap = args.to_vec();
+          self.run_unify(UnifyMode::Def, td.unify(), e)?;
+          self.stack.push(StackEl::CoConv(e, e2))
+        }
+        ProofCmd::ConvCut => {
+          let (e1, e2) = self.pop_coconv(pos)?;
+          self.stack.push(StackEl::Conv(e1, e2));
+          self.stack.push(StackEl::CoConv(e1, e2))
+        }
+        ProofCmd::ConvSave => {
+          let (e1, e2) = self.pop_conv(pos)?;
+          self.heap.push(StackEl::Conv(e1, e2))
+        }
+        ProofCmd::Save => match *self.stack.last().ok_or(ParseError::StrError("stack underflow", pos))? {
+          StackEl::CoConv(..) => return Err(ParseError::StrError("Can't save proof obligation", pos)),
+          s => self.heap.push(s),
+        }
+        ProofCmd::Sorry => return Err(ParseError::StrError("proof uses sorry", pos)),
+      }
+      pos = it.pos;
+    }
+    Ok(())
+  }
+}
+
+#[inline] fn u32_as_usize(n: u32) -> usize { mm0b_parser::u32_as_usize(n) }
+
+/// The state of the spec checker, which walks the statements of the `.mm0` file in
+/// parallel with the public declarations of the MMB file.
+#[derive(Debug)]
+struct Spec<'a> {
+  /// The elaborated `.mm0` file.
+  env: &'a FrozenEnv,
+  /// The remaining statements in the `.mm0` file.
+  stmts: std::slice::Iter<'a, StmtTrace>,
+  /// A map from MMB term IDs to the corresponding term in the spec, or `None` for local defs.
+  term_map: Vec<Option<TermId>>,
+}
+
+impl<'a> Spec<'a> {
+  /// Get the next sort, declaration or input in the spec.
+  fn next(&mut self, pos: usize) -> Result<&'a StmtTrace> {
+    self.stmts.find(|s| matches!(s,
+      StmtTrace::Sort(_) | StmtTrace::Decl(_) | StmtTrace::InputString(_)))
+      .ok_or(ParseError::StrError("declaration not in spec", pos))
+  }
+
+  fn check_name(&self, a: AtomId, name: Option<&str>, pos: usize) -> Result<()> {
+    if let Some(name) = name {
+      ensure!(self.env.data()[a].name().as_str() == name, "declaration name mismatch", pos);
+    }
+    Ok(())
+  }
+
+  fn check_args(args: &[Arg], spec: &[(Option<AtomId>, Type)], pos: usize) -> Result<()> {
+    ensure!(args.len() == spec.len(), "binder mismatch", pos);
+    for (&a, &(_, ty)) in args.iter().zip(spec) {
+      let ok = match ty {
+        Type::Bound(s) => a.bound() && a.sort() == s,
+        Type::Reg(s, deps) => !a.bound() && a.sort() == s && a.deps_unchecked() == deps,
+      };
+      ensure!(ok, "binder mismatch", pos);
+    }
+    Ok(())
+  }
+
+  /// Check that store expression `e` is equal to the spec expression `node`, in a context
+  /// where `heap` is the spec heap, whose first `nargs` elements are the variables.
+  /// (The variables are allocated first in the store, so their store index is the same as
+  /// the heap index.)
+  fn expr_eq(&self, store: &[Expr], heap: &[ExprNode], nargs: usize,
+    dummies: &mut HashMap<AtomId, usize>, e: usize, node: &ExprNode,
+  ) -> bool {
+    match *node {
+      ExprNode::Ref(i) if i < nargs => e == i,
+      ExprNode::Ref(i) => self.expr_eq(store, heap, nargs, dummies, e, &heap[i]),
+      ExprNode::Dummy(a, s) => {
+        if e < nargs { return false }
+        match store[e] {
+          Expr::Var(ty) if ty & TYPE_BOUND_MASK != 0 && type_sort(ty) == s => {}
+          _ => return false
+        }
+        match dummies.get(&a) {
+          Some(&e2) => e == e2,
+          None => !dummies.values().any(|&e2| e == e2) && dummies.insert(a, e).is_none()
+        }
+      }
+      ExprNode::App(t, ref args) => match store[e] {
+        Expr::App(_, t2, ref args2) =>
+          self.term_map.get(u32_as_usize(t2.0)) == Some(&Some(t)) &&
+          args.len() == args2.len() &&
+          args.iter().zip(&**args2).all(|(a, &e)| self.expr_eq(store, heap, nargs, dummies, e, a)),
+        Expr::Var(_) => false,
+      }
+    }
+  }
+}
+
+/// Verify an MMB file. If `spec` is provided, then it should be the result of elaborating
+/// an `.mm0` file, and the public declarations of the MMB file are checked against it.
+pub fn verify(file: &BasicMmbFile<'_>, spec: Option<&FrozenEnv>) -> std::result::Result<(), VerifyError> {
+  let mut v = Verifier {
+    file,
+    sorts: vec![],
+    num_terms: 0,
+    num_thms: 0,
+    store: vec![],
+    stack: vec![],
+    heap: vec![],
+    hstack: vec![],
+    ustack: vec![],
+    uheap: vec![],
+    next_bv: 1,
+  };
+  let mut spec = spec.map(|env| Spec {env, stmts: env.stmts().iter(), term_map: vec![]});
+  let mut it = file.proof();
+  let mut start = it.pos;
+  let mut cur_spec = None;
+  let mut go = || -> Result<()> {
+    while let Some(e) = it.next() {
+      let (stmt, mut pf) = e?;
+      let spec_stmt = match (&mut spec, stmt.is_local()) {
+        (Some(spec), false) => Some((spec.next(start)?, &*spec)),
+        _ => None
+      };
+      cur_spec = None;
+      match stmt {
+        NumdStmtCmd::Sort {sort_id} => {
+          ensure!(pf.is_null(), "Next statement incorrect", start);
+          let sd = file.sort(sort_id).ok_or(ParseError::StrError("Step sort overflow", start))?;
+          let mods = Modifiers::try_from(sd)
+            .map_err(|_| ParseError::StrError("bad sort modifiers", start))?;
+          if let Some((stmt, spec)) = spec_stmt {
+            let sort = match *stmt {
+              StmtTrace::Sort(a) => spec.env.data()[a].sort()
+                .ok_or(ParseError::StrError("statement mismatch", start))?,
+              _ => return Err(ParseError::StrError("statement mismatch", start))
+            };
+            let sd = spec.env.sort(sort);
+            cur_spec = Some(sd.span.clone());
+            ensure!(sort == sort_id, "statement mismatch", start);
+            spec.check_name(sd.atom, file.try_sort_name(sort_id), start)?;
+            ensure!(sd.mods == mods, "sort modifier mismatch", start);
+          }
+          v.sorts.push(mods);
+        }
+        NumdStmtCmd::TermDef {term_id, ..} => {
+          let td = file.term(term_id).ok_or(ParseError::StrError("Step term overflow", start))?;
+          let sort = v.sort(td.sort()).ok_or(ParseError::StrError("bad sort", start))?;
+          ensure!(!sort.contains(Modifiers::PURE), "term in pure sort", start);
+          let ret = td.ret();
+          ensure!(ret.into_inner() >> 56 == u64::from(td.sort().0), "bad return type", start);
+          let spec_term = if let Some((stmt, spec)) = spec_stmt {
+            let t = match *stmt {
+              StmtTrace::Decl(a) => match spec.env.data()[a].decl() {
+                Some(DeclKey::Term(t)) => t,
+                _ => return Err(ParseError::StrError("statement mismatch", start))
+              },
+              _ => return Err(ParseError::StrError("statement mismatch", start))
+            };
+            let tm = spec.env.term(t);
+            cur_spec = Some(tm.span.clone());
+            spec.check_name(tm.atom, file.try_term_name(term_id), start)?;
+            ensure!(matches!(tm.kind, TermKind::Def(_)) == td.def(), "statement mismatch", start);
+            Spec::check_args(td.args(), &tm.args, start)?;
+            ensure!(ret.sort() == tm.ret.0 && ret.deps_unchecked() == tm.ret.1,
+              "return type mismatch", start);
+            Some(t)
+          } else { None };
+          v.load_args(td.args_and_ret(), start)?;
+          v.heap.pop();
+          if td.def() {
+            v.run_proof(true, &mut pf)?;
+            let val = match *v.stack {
+              [StackEl::Expr(val)] => val,
+              [_] => return Err(ParseError::StrError("bad stack slot", pf.pos)),
+              _ => return Err(ParseError::StrError("stack has != one element", pf.pos)),
+            };
+            let ty = v.store[val].ty();
+            ensure!(sorts_compatible(ty, ret.into_inner()), "type mismatch", pf.pos);
+            ensure!(ty & TYPE_DEPS_MASK & !ret.into_inner() == 0,
+              "type has unaccounted dependencies", pf.pos);
+            v.uheap = (0..td.args().len()).collect();
+            v.run_unify(UnifyMode::Def, td.unify(), val)?;
+            if let (Some(t), Some(spec)) = (spec_term, &spec) {
+              if let TermKind::Def(Some(ref body)) = spec.env.term(t).kind {
+                ensure!(spec.expr_eq(&v.store, &body.heap, td.args().len(),
+                  &mut HashMap::new(), val, &body.head), "definition body mismatch", start);
+              }
+            }
+          } else {
+            ensure!(pf.is_null(), "Next statement incorrect", start);
+          }
+          if let Some(spec) = &mut spec { spec.term_map.push(spec_term) }
+          v.num_terms += 1;
+        }
+        NumdStmtCmd::Axiom {thm_id} | NumdStmtCmd::Thm {thm_id, ..} => {
+          let axiom = matches!(stmt, NumdStmtCmd::Axiom {..});
+          let td = file.thm(thm_id).ok_or(ParseError::StrError("Step theorem overflow", start))?;
+          let spec_thm = if let Some((stmt, spec)) = spec_stmt {
+            let t = match *stmt {
+              StmtTrace::Decl(a) => match spec.env.data()[a].decl() {
+                Some(DeclKey::Thm(t)) => t,
+                _ => return Err(ParseError::StrError("statement mismatch", start))
+              },
+              _ => return Err(ParseError::StrError("statement mismatch", start))
+            };
+            let th = spec.env.thm(t);
+            cur_spec = Some(th.span.clone());
+            spec.check_name(th.atom, file.try_thm_name(thm_id), start)?;
+            ensure!(matches!(th.kind, ThmKind::Axiom) == axiom, "statement mismatch", start);
+            Spec::check_args(td.args(), &th.args, start)?;
+            Some(t)
+          } else { None };
+          v.load_args(td.args(), start)?;
+          v.run_proof(false, &mut pf)?;
+          let val = match (&*v.stack, axiom) {
+            (&[StackEl::Expr(val)], true) | (&[StackEl::Proof(val)], false) => val,
+            ([_], _) => return Err(ParseError::StrError("bad stack slot", pf.pos)),
+            _ => return Err(ParseError::StrError("stack has != one element", pf.pos)),
+          };
+          ensure!(v.provable(val), "conclusion should have provable sort", pf.pos);
+          if let (Some(t), Some(spec)) = (spec_thm, &spec) {
+            let th = spec.env.thm(t);
+            let nargs = td.args().len();
+            let mut dummies = HashMap::new();
+            ensure!(v.hstack.len() == th.hyps.len() &&
+              v.hstack.iter().zip(&*th.hyps).all(|(&e, (_, h))|
+                spec.expr_eq(&v.store, &th.heap, nargs, &mut dummies, e, h)),
+              "hypothesis mismatch", start);
+            ensure!(spec.expr_eq(&v.store, &th.heap, nargs, &mut dummies, val, &th.ret),
+              "conclusion mismatch", start);
+          }
+          v.uheap = (0..td.args().len()).collect();
+          v.run_unify(UnifyMode::ThmEnd, td.unify(), val)?;
+          v.num_thms += 1;
+        }
+        NumdStmtCmd::InputString => {
+          v.load_args(&[], start)?;
+          v.run_proof(true, &mut pf)?;
+          let exprs = v.stack.iter().map(|s| match *s {
+            StackEl::Expr(e) => Ok(e),
+            _ => Err(ParseError::StrError("bad stack slot", pf.pos)),
+          }).collect::<Result<Vec<_>>>()?;
+          if let Some((stmt, spec)) = spec_stmt {
+            let os = match *stmt {
+              StmtTrace::InputString(ref os) => os,
+              _ => return Err(ParseError::StrError("statement mismatch", start))
+            };
+            cur_spec = Some(os.span.clone());
+            // Here we only check that the expressions match the spec. Checking that they
+            // evaluate to the text of the spec file requires unfolding the definitions,
+            // which is done afterwards by `verify_file`.
+            ensure!(exprs.len() == os.exprs.len() &&
+              exprs.iter().zip(&*os.exprs).all(|(&e, s)|
+                spec.expr_eq(&v.store, &os.heap, 0, &mut HashMap::new(), e, s)),
+              "input string mismatch", start);
+          }
+        }
+      }
+      start = it.pos;
+    }
+    let pos = it.after_end().unwrap_or(start);
+    ensure!(v.sorts.len() == usize::from(file.header.num_sorts), "not all sorts proved", pos);
+    ensure!(v.num_terms == file.header.num_terms.get(), "not all terms proved", pos);
+    ensure!(v.num_thms == file.header.num_thms.get(), "not all theorems proved", pos);
+    if let Some(spec) = &mut spec {
+      if let Some(stmt) = spec.stmts.find(|s| matches!(s,
+        StmtTrace::Sort(_) | StmtTrace::Decl(_) | StmtTrace::InputString(_))) {
+        cur_spec = Some(match *stmt {
+          StmtTrace::Sort(a) => spec.env.sort(spec.env.data()[a].sort().expect("sort")).span.clone(),
+          StmtTrace::Decl(a) => match spec.env.data()[a].decl().expect("decl") {
+            DeclKey::Term(t) => spec.env.term(t).span.clone(),
+            DeclKey::Thm(t) => spec.env.thm(t).span.clone(),
+          },
+          StmtTrace::InputString(ref os) => os.span.clone(),
+          _ => unreachable!()
+        });
+        return Err(ParseError::StrError("spec declaration missing from proof file", pos))
+      }
+    }
+    Ok(())
+  };
+  let res = go();
+  res.map_err(|err| VerifyError {err, spec: cur_spec})
+}
+
+/// Main entry point for `mm0-rs verify` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs verify <in.mm0> <in.mmb>`, where:
+///
+/// - `in.mm0` is the specification file. It is elaborated (as for `mm0-rs compile`),
+///   and the public declarations of `in.mmb` are checked against it.
+/// - `in.mmb` (or `in.mmu`) is the proof file to verify. The file extension is used to
+///   determine if this is a binary file; `.mmu` files are checked by
+///   [`mmu::verify`](crate::mmu::verify::verify).
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let spec = args.value_of("INPUT").expect("required arg");
+  let spec: FileRef = fs::canonicalize(spec)?.into();
+  let (_, env) = crate::compiler::elab_for_result(spec)?;
+  let env = env.unwrap_or_else(|| std::process::exit(1));
+  let path = args.value_of("PROOF").expect("required arg");
+  if !verify_file(&fs::canonicalize(path)?.into(), &env)? { std::process::exit(1) }
+  Ok(())
+}
+
+/// Translate the expression `e` in the `input string` directive of `spec` to the environment
+/// `env` of the proof file, matching terms by name. Returns `None` if a term is missing
+/// from `env`, or if `e` contains a dummy variable.
+fn input_expr_by_name(spec: &FrozenEnv, env: &Environment, e: &ExprNode) -> Option<ExprNode> {
+  Some(match *e {
+    ExprNode::Ref(i) => ExprNode::Ref(i),
+    ExprNode::Dummy(_, _) => return None,
+    ExprNode::App(t, ref es) => {
+      let t = env.term(*env.atoms.get(spec.data()[spec.term(t).atom].name())?)?;
+      ExprNode::App(t, es.iter().map(|e| input_expr_by_name(spec, env, e)).collect::<Option<_>>()?)
+    }
+  })
+}
+
+/// Verify the MMB or MMU file at `path` against `spec`, and print the error on failure.
+///
+/// `spec` is the result of elaborating an `.mm0` file. The file extension of `path` is used
+/// to determine if this is a binary file. Returns `false` if verification failed.
+///
+/// This also checks that each `input string` directive evaluates to the text of the MM0 file
+/// containing it, using the definitions in the proof file. MMU files do not record the
+/// expressions of the directive, so for these the expressions in `spec` are used.
+pub fn verify_file(path: &FileRef, spec: &FrozenEnv) -> io::Result<bool> {
+  let buf = crate::compiler::get_file(path.clone())?;
+  let mmu = path.has_extension("mmu");
+  let res = if mmu {
+    crate::mmu::verify::verify(&buf, spec)
+  } else {
+    BasicMmbFile::parse(&buf)
+      .map_err(|err| VerifyError {err, spec: None})
+      .and_then(|file| verify(&file, Some(spec)))
+      .map_err(Into::into)
+  };
+  if let Err(e) = res {
+    crate::compiler::print_error(path, &e)?;
+    return Ok(false)
+  }
+  let specs = spec.stmts().iter().filter_map(|s|
+    if let StmtTrace::InputString(os) = s { Some(os) } else { None });
+  if specs.clone().next().is_none() { return Ok(true) }
+  let (res, env) = if mmu {
+    crate::mmu::import::elab(path, &buf)
+  } else {
+    crate::mmb::import::elab(path, &buf)
+  };
+  if let Err(e) = res {
+    crate::compiler::print_error(path, &e)?;
+    return Ok(false)
+  }
+  // `verify` already checked that the directives line up with the spec
+  let mut inputs = env.stmts.iter().filter_map(|s|
+    if let StmtTrace::InputString(os) = s { Some(os) } else { None });
+  for spec_os in specs {
+    let res = if mmu {
+      let by_name = |es: &[ExprNode]| es.iter()
+        .map(|e| input_expr_by_name(spec, &env, e)).collect::<Option<Box<[_]>>>();
+      match (by_name(&spec_os.heap), by_name(&spec_os.exprs)) {
+        (Some(heap), Some(exprs)) => env.eval_string_directive(
+          &OutputString {span: spec_os.span.clone(), heap, exprs}),
+        _ => Err("term not found in proof file".into()),
+      }
+    } else {
+      env.eval_string_directive(inputs.next().expect("checked by verify"))
+    };
+    let text = crate::compiler::get_file(spec_os.span.file.clone())?;
+    let err = match res {
+      Ok(s) if s == text.ascii().as_bytes() => continue,
+      Ok(_) => "input string does not match the text of this file".to_owned(),
+      Err(e) => format!("could not evaluate input string: {}", BoxError::from(e)),
+    };
+    crate::compiler::print_error(&spec_os.span.file, &ElabError::new_e(spec_os.span.span, err))?;
+    return Ok(false)
+  }
+  Ok(true)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use std::fmt::Write;
+  use crate::compiler::{elab_test_file, test_file};
+  use crate::mmb::export::tests::export;
+  use mm0b_parser::{BasicMmbFile, NumdStmtCmd};
+  use super::{verify, verify_file};
+
+  const THM_MM1: &str = "provable sort wff;\nterm im: wff > wff > wff;\ninfixr im: $->$ prec 25;\n\
+    axiom ax (a b: wff): $ a -> b -> a $;\npub theorem t (a b: wff): $ a -> b -> a $ = 'ax;\n";
+
+  /// A string expression for `s`, as a balanced tree of `sadd` applications.
+  fn string_expr(s: &[u8], out: &mut String) {
+    match s {
+      [] => out.push_str("s0"),
+      [c] => write!(out, "(s1 (ch x{:x} x{:x}))", c >> 4, c & 15).unwrap(),
+      _ => {
+        let (l, r) = s.split_at(s.len() >> 1);
+        out.push_str("(sadd ");
+        string_expr(l, out);
+        out.push(' ');
+        string_expr(r, out);
+        out.push(')');
+      }
+    }
+  }
+
+  #[test]
+  fn input_string_mmb() {
+    let mut text = String::from("strict free sort hex;\n");
+    for i in 0..16 { writeln!(text, "term x{:x}: hex;", i).unwrap() }
+    text.push_str("strict free sort char;\nterm ch: hex > hex > char;\n\
+      strict free sort string;\nterm s0: string;\nterm s1: char > string;\n\
+      term sadd: string > string > string;\n");
+    let spec_text = format!("{}def input: string;\ninput string: input;\n", text);
+    let spec = elab_test_file("input.mm0", &spec_text);
+    let mmb = |s: &[u8]| {
+      let mut val = String::new();
+      string_expr(s, &mut val);
+      let env = elab_test_file("input.mm1",
+        &format!("delimiter $ ( ) $;\n{}def input: string = $ {} $;\ninput string: input;\n",
+          text, val));
+      test_file("input.mmb", &export(&env))
+    };
+    assert!(verify_file(&mmb(spec_text.as_bytes()), &spec).unwrap());
+    let bad = spec_text.replace("input:", "inpvt:");
+    assert!(!verify_file(&mmb(bad.as_bytes()), &spec).unwrap());
+  }
+
+  /// Export `THM_MM1` and return the buffer along with the position of the proof of `t`.
+  fn thm_mmb() -> (Vec<u8>, usize) {
+    let buf = export(&elab_test_file("thm.mm1", THM_MM1));
+    let file = BasicMmbFile::parse(&buf).unwrap();
+    let pos = file.proof().find_map(|d| match d.unwrap() {
+      (NumdStmtCmd::Thm { .. }, pr) => Some(pr.pos),
+      _ => None,
+    }).unwrap();
+    (buf, pos)
+  }
+
+  #[test]
+  fn corrupted_proof_rejected() {
+    let (buf, pos) = thm_mmb();
+    verify(&BasicMmbFile::parse(&buf).unwrap(), None).unwrap();
+    // 0x3f is not a proof command, and 0 ends the proof before anything is proved
+    for b in [0x3f, 0] {
+      let mut bad = buf.clone();
+      bad[pos] = b;
+      assert!(verify(&BasicMmbFile::parse(&bad).unwrap(), None).is_err());
+    }
+  }
+
+  #[test]
+  fn statement_mismatch_rejected() {
+    let (buf, _) = thm_mmb();
+    let file = BasicMmbFile::parse(&buf).unwrap();
+    let spec = |stmt| elab_test_file("thm.mm0", &THM_MM1.replace("pub ", "").replace(" = 'ax", "")
+      .replace("$ a -> b -> a $;", &format!("$ {} $;", stmt)));
+    verify(&file, Some(&spec("a -> b -> a"))).unwrap();
+    assert!(verify(&file, Some(&spec("a -> a -> a"))).is_err());
+  }
+}
@@ -133,14 +133,16 @@ enum DeclKind {
   LocalTheorem,
 }
 
+/// A deduplicating heap builder, used to construct the expression and proof heaps
+/// of a declaration.
 #[derive(Debug)]
-struct Dedup<H: NodeHash> {
+pub(crate) struct Dedup<H: NodeHash> {
   map: HashMap<Rc<H>, usize>,
   vec: Vec<(Rc<H>, bool)>,
 }
 
 impl<H: NodeHash> Dedup<H> {
-  fn new(args: &[(Option<AtomId>, Type)]) -> Dedup<H> {
+  pub(crate) fn new(args: &[(Option<AtomId>, Type)]) -> Dedup<H> {
     let vec: Vec<_> = (0..args.len())
       .map(|i| (Rc::new(H::REF(ProofKind::Expr, i)), true)).collect();
     Dedup {
@@ -149,7 +151,7 @@ impl<H: NodeHash> Dedup<H> {
     }
   }
 
-  fn add(&mut self, v: H) -> usize {
+  pub(crate) fn add(&mut self, v: H) -> usize {
     match self.map.entry(Rc::new(v)) {
       Entry::Vacant(e) => {
         let n = self.vec.len();
@@ -194,7 +196,7 @@ impl<H: NodeHash> IDedup<H> for Dedup<H> {
 }
 
 #[must_use] #[derive(Debug)]
-struct DedupIter<'a, H: NodeHash>(std::slice::Iter<'a, (Rc<H>, bool)>);
+pub(crate) struct DedupIter<'a, H: NodeHash>(std::slice::Iter<'a, (Rc<H>, bool)>);
 
 impl<'a, H: NodeHash> Iterator for DedupIter<'a, H> {
   type Item = (&'a H, bool);
@@ -214,7 +216,7 @@ impl<'a, H: NodeHash> IntoIterator for &'a Dedup<H> {
 }
 
 impl Dedup<ExprHash> {
-  fn map_proof(&self) -> Dedup<ProofHash> {
+  pub(crate) fn map_proof(&self) -> Dedup<ProofHash> {
     self.map_inj(ExprHash::to_proof)
   }
 }
@@ -246,6 +248,8 @@ impl<'a> Importer<'a> {
           Some(b"theorem") => self.decl(start, DeclKind::LocalTheorem)?,
           _ => return Err(self.err("expecting 'def' or 'theorem'".into()))
         }
+        // The expressions of `input` and `output` directives are not recorded in MMU files
+        Some(b"input" | b"output") => { self.ident_err()?; self.close_err()?; }
         _ => return Err(self.err("expecting command keyword".into()))
       }
     }
@@ -0,0 +1,768 @@
+//! MMU checker, which verifies an `.mmu` file against an `.mm0` specification.
+//!
+//! Unlike the [`Importer`](super::import::Importer), which trusts its input and only builds an
+//! [`Environment`](crate::Environment), this typechecks every declaration and replays every
+//! proof step, in the same way as `mm0-hs verify`. The public declarations are checked against
+//! the statements of the elaborated `.mm0` file, in order.
+
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+use mm1_parser::{whitespace, lisp_ident};
+use crate::elab::{ElabError, Result};
+use crate::elab::environment::{DeclKey, ExprNode, StmtTrace, TermKind, ThmKind, Type};
+use crate::{AtomId, FileSpan, FrozenEnv, Modifiers, Span, TermId};
+
+/// An s-expression in the `.mmu` file.
+#[derive(Debug)]
+enum SExpr {
+  /// An identifier
+  Atom(Span),
+  /// A parenthesized list
+  List(Span, Vec<SExpr>),
+}
+
+impl SExpr {
+  fn span(&self) -> Span { match *self { SExpr::Atom(sp) | SExpr::List(sp, _) => sp } }
+}
+
+/// The `.mmu` reader, which splits the file into s-expressions.
+#[derive(Debug)]
+struct Parser<'a> {
+  /// The input source text (as a byte slice)
+  source: &'a [u8],
+  /// The position in the input
+  idx: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn cur_opt(&self) -> Option<u8> { self.source.get(self.idx).copied() }
+
+  fn ws(&mut self) {
+    while let Some(c) = self.cur_opt() {
+      if whitespace(c) {self.idx += 1; continue}
+      if c == b'-' && self.source.get(self.idx + 1) == Some(&b'-') {
+        self.idx += 1;
+        while let Some(c) = self.cur_opt() {
+          self.idx += 1;
+          if c == b'\n' {break}
+        }
+      } else {break}
+    }
+  }
+
+  fn sexpr(&mut self) -> Result<SExpr> {
+    let start = self.idx;
+    if self.cur_opt() == Some(b'(') {
+      self.idx += 1;
+      self.ws();
+      let mut es = vec![];
+      loop {
+        match self.cur_opt() {
+          Some(b')') => {
+            self.idx += 1;
+            let sp = (start..self.idx).into();
+            self.ws();
+            return Ok(SExpr::List(sp, es))
+          }
+          None => return Err(ElabError::new_e(start..self.idx, "unclosed parenthesis")),
+          _ => es.push(self.sexpr()?),
+        }
+      }
+    }
+    while self.cur_opt().map_or(false, lisp_ident) {self.idx += 1}
+    if self.idx == start {
+      return Err(ElabError::new_e(start..=start, "expecting identifier or '('"))
+    }
+    let sp = (start..self.idx).into();
+    self.ws();
+    Ok(SExpr::Atom(sp))
+  }
+}
+
+/// A set of variables, given as indexes into the [`Ctx`].
+type VarSet = BTreeSet<usize>;
+
+/// An expression, in the context of a declaration.
+#[derive(PartialEq, Eq, Debug)]
+enum Expr {
+  /// A variable (an index into the [`Ctx`])
+  Var(usize),
+  /// A term constructor applied to a list of arguments
+  App(usize, Box<[Rc<Expr>]>),
+}
+
+impl Expr {
+  /// Substitute the variables of `self` with `subst`.
+  fn subst(&self, subst: &[Rc<Expr>]) -> Rc<Expr> {
+    match *self {
+      Expr::Var(i) => subst[i].clone(),
+      Expr::App(t, ref es) => Rc::new(Expr::App(t, es.iter().map(|e| e.subst(subst)).collect())),
+    }
+  }
+}
+
+/// A binder of a term or theorem.
+#[derive(Debug)]
+struct Binder {
+  /// The sort of the binder
+  sort: usize,
+  /// True if this is a bound variable
+  bound: bool,
+  /// For a regular variable, the indexes of the (earlier) bound variables it depends on
+  deps: Vec<usize>,
+}
+
+/// The data of a term or definition.
+#[derive(Debug)]
+struct TermData {
+  /// The binders
+  args: Vec<Binder>,
+  /// The return sort and dependencies
+  ret: (usize, Vec<usize>),
+  /// The dummy sorts and body, for a definition
+  def: Option<(Vec<usize>, Rc<Expr>)>,
+  /// The corresponding term in the spec, if this is a public declaration
+  spec: Option<TermId>,
+}
+
+/// The data of an axiom or theorem.
+#[derive(Debug)]
+struct ThmData {
+  /// The binders
+  args: Vec<Binder>,
+  /// The hypotheses
+  hyps: Vec<Rc<Expr>>,
+  /// The conclusion
+  ret: Rc<Expr>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Decl { Term(usize), Thm(usize) }
+
+/// A variable in the local context of a declaration.
+#[derive(Debug)]
+struct Var {
+  sort: usize,
+  bound: bool,
+  /// The variables this depends on (itself, if it is a bound variable)
+  fv: VarSet,
+}
+
+/// The local context of a declaration: the binders followed by the dummy variables.
+#[derive(Debug, Default)]
+struct Ctx<'a> {
+  vars: Vec<Var>,
+  names: HashMap<&'a [u8], usize>,
+}
+
+/// A typechecked expression, with its sort, whether it is a bound variable, and its
+/// free variables.
+type Typed = (Rc<Expr>, usize, bool, VarSet);
+
+/// The checker state.
+#[derive(Debug)]
+struct Checker<'a> {
+  /// The input source text (as a byte slice)
+  source: &'a [u8],
+  /// The elaborated `.mm0` file
+  env: &'a FrozenEnv,
+  /// The remaining statements in the `.mm0` file
+  stmts: std::slice::Iter<'a, StmtTrace>,
+  /// The spec declaration being checked, for error reporting
+  cur_spec: Option<FileSpan>,
+  sorts: Vec<Modifiers>,
+  sort_names: HashMap<&'a [u8], usize>,
+  terms: Vec<TermData>,
+  thms: Vec<ThmData>,
+  decls: HashMap<&'a [u8], Decl>,
+}
+
+macro_rules! ensure {
+  ($b:expr, $sp:expr, $msg:expr) => { if !$b { return Err(ElabError::new_e($sp, $msg)) } }
+}
+
+fn atom(e: &SExpr) -> Result<Span> {
+  match *e {
+    SExpr::Atom(sp) => Ok(sp),
+    SExpr::List(sp, _) => Err(ElabError::new_e(sp, "expecting identifier")),
+  }
+}
+
+fn list(e: &SExpr) -> Result<&[SExpr]> {
+  match e {
+    SExpr::List(_, es) => Ok(es),
+    SExpr::Atom(sp) => Err(ElabError::new_e(*sp, "expecting list")),
+  }
+}
+
+fn disjoint(a: &VarSet, b: &VarSet) -> bool { a.intersection(b).next().is_none() }
+
+impl<'a> Checker<'a> {
+  fn span(&self, sp: Span) -> &'a [u8] { &self.source[sp.start..sp.end] }
+
+  fn sort(&self, e: &SExpr) -> Result<usize> {
+    let sp = atom(e)?;
+    self.sort_names.get(self.span(sp)).copied()
+      .ok_or_else(|| ElabError::new_e(sp, "expecting sort"))
+  }
+
+  fn term(&self, sp: Span) -> Result<usize> {
+    match self.decls.get(self.span(sp)) {
+      Some(&Decl::Term(t)) => Ok(t),
+      _ => Err(ElabError::new_e(sp, "expecting term")),
+    }
+  }
+
+  fn var(&self, ctx: &Ctx<'a>, e: &SExpr) -> Result<usize> {
+    let sp = atom(e)?;
+    ctx.names.get(self.span(sp)).copied()
+      .ok_or_else(|| ElabError::new_e(sp, "unknown variable"))
+  }
+
+  fn push_var(&self, ctx: &mut Ctx<'a>, x: Span, sort: usize, bound: bool, deps: &[usize]) -> Result<()> {
+    let n = ctx.vars.len();
+    let name = self.span(x);
+    if name != b"_" {
+      ensure!(ctx.names.insert(name, n).is_none(), x, "duplicate variable");
+    }
+    let fv = if bound { std::iter::once(n).collect() } else { deps.iter().copied().collect() };
+    ctx.vars.push(Var {sort, bound, fv});
+    Ok(())
+  }
+
+  /// Parse a dependency list `(x y z)`, which must refer to bound variables in the context.
+  fn deps(&self, ctx: &Ctx<'a>, e: &SExpr) -> Result<Vec<usize>> {
+    list(e)?.iter().map(|x| {
+      let i = self.var(ctx, x)?;
+      ensure!(ctx.vars[i].bound, x.span(), "expecting bound variable");
+      Ok(i)
+    }).collect()
+  }
+
+  /// Parse the binder list of a declaration, adding the variables to the context.
+  fn binders(&self, ctx: &mut Ctx<'a>, e: &SExpr) -> Result<Vec<Binder>> {
+    let mut args = vec![];
+    for bi in list(e)? {
+      let (x, sort, bound, deps) = match *list(bi)? {
+        [ref x, ref s] => (atom(x)?, self.sort(s)?, true, vec![]),
+        [ref x, ref s, ref deps] => (atom(x)?, self.sort(s)?, false, self.deps(ctx, deps)?),
+        _ => return Err(ElabError::new_e(bi.span(), "expecting binder"))
+      };
+      if bound {
+        ensure!(!self.sorts[sort].contains(Modifiers::STRICT), bi.span(),
+          "bound variable in strict sort");
+      }
+      self.push_var(ctx, x, sort, bound, &deps)?;
+      args.push(Binder {sort, bound, deps});
+    }
+    Ok(args)
+  }
+
+  /// Parse the dummy list of a definition or theorem, adding the variables to the context.
+  fn dummies(&self, ctx: &mut Ctx<'a>, e: &SExpr) -> Result<Vec<usize>> {
+    list(e)?.iter().map(|d| match *list(d)? {
+      [ref x, ref s] => {
+        let sort = self.sort(s)?;
+        ensure!(!self.sorts[sort].contains(Modifiers::STRICT), d.span(),
+          "dummy variable in strict sort");
+        self.push_var(ctx, atom(x)?, sort, true, &[])?;
+        Ok(sort)
+      }
+      _ => Err(ElabError::new_e(d.span(), "expecting dummy variable"))
+    }).collect()
+  }
+
+  /// Typecheck an expression. If `def` is true, the free variables are computed exactly
+  /// (as is needed for definition bodies); otherwise all variables occurring in the
+  /// expression are included.
+  fn expr(&self, ctx: &Ctx<'a>, e: &SExpr, def: bool) -> Result<Typed> {
+    let es = match e {
+      SExpr::Atom(_) => {
+        let i = self.var(ctx, e)?;
+        let v = &ctx.vars[i];
+        return Ok((Rc::new(Expr::Var(i)), v.sort, v.bound, v.fv.clone()))
+      }
+      SExpr::List(_, es) => es,
+    };
+    let (head, es) = es.split_first().ok_or_else(|| ElabError::new_e(e.span(), "expecting term"))?;
+    let t = self.term(atom(head)?)?;
+    let td = &self.terms[t];
+    ensure!(td.args.len() == es.len(), e.span(), "incorrect number of term arguments");
+    let mut args = Vec::with_capacity(es.len());
+    let mut fvs = Vec::with_capacity(es.len());
+    for (bi, e) in td.args.iter().zip(es) {
+      let (e2, s, bound, fv) = self.expr(ctx, e, def)?;
+      ensure!(bi.sort == s, e.span(), "type mismatch");
+      ensure!(!bi.bound || bound, e.span(), "non-bound variable in BV slot");
+      args.push(e2);
+      fvs.push(fv);
+    }
+    let mut fv = VarSet::new();
+    if def {
+      for (bi, vs) in td.args.iter().zip(&fvs) {
+        if !bi.bound {
+          let bound: VarSet = bi.deps.iter().flat_map(|&j| fvs[j].iter().copied()).collect();
+          fv.extend(vs.difference(&bound))
+        }
+      }
+      for &j in &td.ret.1 { fv.extend(&fvs[j]) }
+    } else {
+      for vs in fvs { fv.extend(vs) }
+    }
+    Ok((Rc::new(Expr::App(t, args.into())), td.ret.0, false, fv))
+  }
+
+  fn provable(&self, ctx: &Ctx<'a>, e: &SExpr) -> Result<Rc<Expr>> {
+    let (e2, s, _, _) = self.expr(ctx, e, false)?;
+    ensure!(self.sorts[s].contains(Modifiers::PROVABLE), e.span(), "expression not provable");
+    Ok(e2)
+  }
+
+  /// Check the substitution `(e, _, bound, fv)` for binder `bi`, given the previous
+  /// substitutions `subst`. If `dv` is true, disjoint variable conditions are checked.
+  fn push_subst(subst: &mut Vec<(Rc<Expr>, bool, VarSet)>, binders: &[Binder], dv: bool,
+    (e, s, bound, fv): Typed, sp: Span,
+  ) -> Result<()> {
+    let bi = &binders[subst.len()];
+    ensure!(bi.sort == s, sp, "type mismatch");
+    if bi.bound {
+      ensure!(bound, sp, "non-bound variable in BV slot");
+      if dv {
+        ensure!(subst.iter().all(|(_, _, fv2)| disjoint(&fv, fv2)), sp,
+          "disjoint variable violation");
+      }
+    } else if dv {
+      for (j, (_, b, fv2)) in subst.iter().enumerate() {
+        ensure!(!*b || bi.deps.contains(&j) || disjoint(&fv, fv2), sp,
+          "disjoint variable violation");
+      }
+    }
+    subst.push((e, bi.bound, fv));
+    Ok(())
+  }
+
+  /// Check a conversion proof, returning the two sides, and the sort and boundedness
+  /// of the expressions.
+  fn conv(&self, ctx: &Ctx<'a>, e: &SExpr) -> Result<(Rc<Expr>, Rc<Expr>, usize, bool)> {
+    let es = match e {
+      SExpr::Atom(_) => {
+        let i = self.var(ctx, e)?;
+        let v = Rc::new(Expr::Var(i));
+        return Ok((v.clone(), v, ctx.vars[i].sort, ctx.vars[i].bound))
+      }
+      SExpr::List(_, es) => es,
+    };
+    let (head, es) = es.split_first().ok_or_else(|| ElabError::new_e(e.span(), "expecting term"))?;
+    let hsp = atom(head)?;
+    match self.span(hsp) {
+      b":sym" => match es {
+        [c] => self.conv(ctx, c).map(|(e1, e2, s, b)| (e2, e1, s, b)),
+        _ => Err(ElabError::new_e(e.span(), "expecting (:sym conv)")),
+      }
+      b":unfold" => {
+        let (t, args, ds, c) = match es {
+          [t, args, ds, c] => (self.term(atom(t)?)?, list(args)?, list(ds)?, c),
+          _ => return Err(ElabError::new_e(e.span(), "expecting (:unfold term (args) (dummies) conv)")),
+        };
+        let td = &self.terms[t];
+        let (dummies, val) = td.def.as_ref().ok_or_else(|| ElabError::new_e(hsp, "not a definition"))?;
+        ensure!(td.args.len() == args.len(), e.span(), "incorrect number of term arguments");
+        ensure!(dummies.len() == ds.len(), e.span(), "incorrect number of dummy arguments");
+        let binders: Vec<_> = td.args.iter().map(|bi| Binder {sort: bi.sort, bound: bi.bound, deps: bi.deps.clone()})
+          .chain(dummies.iter().map(|&sort| Binder {sort, bound: true, deps: vec![]})).collect();
+        let mut subst = vec![];
+        for e in args {
+          Self::push_subst(&mut subst, &binders, false, self.expr(ctx, e, false)?, e.span())?
+        }
+        for d in ds {
+          let i = self.var(ctx, d)?;
+          let v = &ctx.vars[i];
+          Self::push_subst(&mut subst, &binders, true,
+            (Rc::new(Expr::Var(i)), v.sort, v.bound, v.fv.clone()), d.span())?
+        }
+        let subst: Vec<_> = subst.into_iter().map(|(e, _, _)| e).collect();
+        let (e1, e2, s, b) = self.conv(ctx, c)?;
+        ensure!(e1 == val.subst(&subst), c.span(), "conversion proof mismatch");
+        Ok((Rc::new(Expr::App(t, subst[..args.len()].into())), e2, s, b))
+      }
+      b":conv" | b":let" => Err(ElabError::new_e(hsp, "invalid conversion step")),
+      _ => {
+        let t = self.term(hsp)?;
+        let td = &self.terms[t];
+        ensure!(td.args.len() == es.len(), e.span(), "incorrect number of term arguments");
+        let (mut l, mut r) = (Vec::with_capacity(es.len()), Vec::with_capacity(es.len()));
+        for (bi, c) in td.args.iter().zip(es) {
+          let (e1, e2, s, b) = self.conv(ctx, c)?;
+          ensure!(bi.sort == s, c.span(), "type mismatch");
+          ensure!(!bi.bound || b, c.span(), "non-bound variable in BV slot");
+          l.push(e1);
+          r.push(e2);
+        }
+        Ok((Rc::new(Expr::App(t, l.into())), Rc::new(Expr::App(t, r.into())), td.ret.0, false))
+      }
+    }
+  }
+
+  /// Check a proof, returning the statement it proves.
+  fn proof(&self, ctx: &Ctx<'a>, heap: &mut HashMap<&'a [u8], Rc<Expr>>, e: &SExpr) -> Result<Rc<Expr>> {
+    let es = match *e {
+      SExpr::Atom(sp) => return heap.get(self.span(sp)).cloned()
+        .ok_or_else(|| ElabError::new_e(sp, "unknown subproof")),
+      SExpr::List(_, ref es) => es,
+    };
+    let (head, es) = es.split_first().ok_or_else(|| ElabError::new_e(e.span(), "expecting proof"))?;
+    let hsp = atom(head)?;
+    match self.span(hsp) {
+      b":conv" => {
+        let (tgt, c, p) = match es {
+          [tgt, c, p] => (tgt, c, p),
+          _ => return Err(ElabError::new_e(e.span(), "expecting (:conv expr conv proof)")),
+        };
+        let tgt = self.provable(ctx, tgt)?;
+        let (e1, e2, _, _) = self.conv(ctx, c)?;
+        let p = self.proof(ctx, heap, p)?;
+        ensure!(e1 == tgt && e2 == p, c.span(), "conversion proof mismatch");
+        Ok(tgt)
+      }
+      b":let" => {
+        let (h, p1, p2) = match es {
+          [h, p1, p2] => (self.span(atom(h)?), p1, p2),
+          _ => return Err(ElabError::new_e(e.span(), "expecting (:let h proof proof)")),
+        };
+        let p1 = self.proof(ctx, heap, p1)?;
+        ensure!(!heap.contains_key(h), es[0].span(), "subproof name shadowing");
+        heap.insert(h, p1);
+        let res = self.proof(ctx, heap, p2);
+        heap.remove(h);
+        res
+      }
+      b":sym" | b":unfold" => Err(ElabError::new_e(hsp, "conversion step in proof position")),
+      name => {
+        let td = match self.decls.get(name) {
+          Some(&Decl::Thm(t)) => &self.thms[t],
+          _ => return Err(ElabError::new_e(hsp, "expecting theorem")),
+        };
+        let (args, ps) = es.split_first().ok_or_else(|| ElabError::new_e(e.span(), "expecting theorem arguments"))?;
+        let args = list(args)?;
+        ensure!(td.args.len() == args.len(), e.span(), "incorrect number of term arguments");
+        ensure!(td.hyps.len() == ps.len(), e.span(), "incorrect number of hypotheses");
+        let mut subst = vec![];
+        for e in args {
+          Self::push_subst(&mut subst, &td.args, true, self.expr(ctx, e, false)?, e.span())?
+        }
+        let subst: Vec<_> = subst.into_iter().map(|(e, _, _)| e).collect();
+        for (h, p) in td.hyps.iter().zip(ps) {
+          let e = self.proof(ctx, heap, p)?;
+          ensure!(e == h.subst(&subst), p.span(),
+            "substitution to hypothesis does not match theorem");
+        }
+        Ok(td.ret.subst(&subst))
+      }
+    }
+  }
+
+  /// Get the next sort, declaration or output in the spec.
+  fn next_spec(&mut self, sp: Span) -> Result<&'a StmtTrace> {
+    self.stmts.find(|s| !matches!(s, StmtTrace::Global(_)))
+      .ok_or_else(|| ElabError::new_e(sp, "declaration not in spec"))
+  }
+
+  fn check_name(&self, a: AtomId, x: Span) -> Result<()> {
+    ensure!(self.env.data()[a].name().as_str().as_bytes() == self.span(x), x, "declaration name mismatch");
+    Ok(())
+  }
+
+  fn check_args(args: &[Binder], spec: &[(Option<AtomId>, Type)], sp: Span) -> Result<()> {
+    ensure!(args.len() == spec.len(), sp, "binder mismatch");
+    for (bi, &(_, ty)) in args.iter().zip(spec) {
+      let ok = match ty {
+        Type::Bound(s) => bi.bound && bi.sort == usize::from(s.0),
+        Type::Reg(s, deps) => !bi.bound && bi.sort == usize::from(s.0) &&
+          Self::deps_mask(args, &bi.deps) == deps,
+      };
+      ensure!(ok, sp, "binder mismatch");
+    }
+    Ok(())
+  }
+
+  /// Convert a list of binder indexes to a bitmask, in which bound variable `i` is bit `i`.
+  fn deps_mask(args: &[Binder], deps: &[usize]) -> u64 {
+    deps.iter().map(|&j| 1 << args[..j].iter().filter(|bi| bi.bound).count()).fold(0, |a, b| a | b)
+  }
+
+  /// Check that expression `e` is equal to the spec expression `node`, in a context where
+  /// `heap` is the spec heap, whose first `nargs` elements are the variables.
+  fn expr_eq(&self, ctx: &Ctx<'a>, heap: &[ExprNode], nargs: usize,
+    dummies: &mut HashMap<AtomId, usize>, e: &Expr, node: &ExprNode,
+  ) -> bool {
+    match *node {
+      ExprNode::Ref(i) if i < nargs => *e == Expr::Var(i),
+      ExprNode::Ref(i) => self.expr_eq(ctx, heap, nargs, dummies, e, &heap[i]),
+      ExprNode::Dummy(a, s) => match *e {
+        Expr::Var(i) if i >= nargs && ctx.vars[i].sort == usize::from(s.0) =>
+          match dummies.get(&a) {
+            Some(&j) => i == j,
+            None => !dummies.values().any(|&j| i == j) && dummies.insert(a, i).is_none()
+          },
+        _ => false
+      },
+      ExprNode::App(t, ref args) => match *e {
+        Expr::App(t2, ref args2) =>
+          self.terms[t2].spec == Some(t) && args.len() == args2.len() &&
+          args.iter().zip(&**args2).all(|(a, e)| self.expr_eq(ctx, heap, nargs, dummies, e, a)),
+        Expr::Var(_) => false,
+      }
+    }
+  }
+
+  fn stmt(&mut self, e: &SExpr) -> Result<()> {
+    let es = list(e)?;
+    let (kw, mut es) = es.split_first().ok_or_else(|| ElabError::new_e(e.span(), "expecting command keyword"))?;
+    let mut kw = self.span(atom(kw)?);
+    let local = kw == b"local";
+    if local {
+      let (kw2, es2) = es.split_first().ok_or_else(|| ElabError::new_e(e.span(), "expecting 'def' or 'theorem'"))?;
+      kw = self.span(atom(kw2)?);
+      ensure!(kw == b"def" || kw == b"theorem", kw2.span(), "expecting 'def' or 'theorem'");
+      es = es2;
+    }
+    let spec = if local { None } else { Some(self.next_spec(e.span())?) };
+    self.cur_spec = None;
+    match kw {
+      b"sort" => {
+        let (x, mods) = es.split_first().ok_or_else(|| ElabError::new_e(e.span(), "expecting sort name"))?;
+        let x = atom(x)?;
+        let mut m = Modifiers::empty();
+        for md in mods {
+          m |= match self.span(atom(md)?) {
+            b"pure" => Modifiers::PURE,
+            b"strict" => Modifiers::STRICT,
+            b"provable" => Modifiers::PROVABLE,
+            b"free" => Modifiers::FREE,
+            _ => return Err(ElabError::new_e(md.span(), "expecting sort modifier")),
+          }
+        }
+        let n = self.sorts.len();
+        ensure!(self.sort_names.insert(self.span(x), n).is_none(), x, "sort redeclared");
+        self.sorts.push(m);
+        match spec {
+          Some(&StmtTrace::Sort(a)) => {
+            let sd = self.env.sort(self.env.data()[a].sort().expect("wf env"));
+            self.cur_spec = Some(sd.span.clone());
+            self.check_name(sd.atom, x)?;
+            ensure!(sd.mods == m, x, "sort modifier mismatch");
+          }
+          _ => return Err(ElabError::new_e(x, "statement mismatch")),
+        }
+      }
+      b"term" | b"def" => {
+        let def = kw == b"def";
+        let (x, args, ret, rest) = match *es {
+          [ref x, ref args, ref ret, ref rest @ ..] => (atom(x)?, args, ret, rest),
+          _ => return Err(ElabError::new_e(e.span(), "expecting term declaration")),
+        };
+        let mut ctx = Ctx::default();
+        let args = self.binders(&mut ctx, args)?;
+        let ret = match list(ret)? {
+          [s, deps] => (self.sort(s)?, self.deps(&ctx, deps)?),
+          _ => return Err(ElabError::new_e(ret.span(), "expecting return type")),
+        };
+        ensure!(!self.sorts[ret.0].contains(Modifiers::PURE), x, "term in pure sort");
+        let val = match (def, rest) {
+          (false, []) => None,
+          (true, [ds, val]) => {
+            let dummies = self.dummies(&mut ctx, ds)?;
+            let (val2, s, _, fv) = self.expr(&ctx, val, true)?;
+            ensure!(s == ret.0, val.span(), "type mismatch");
+            ensure!(fv.iter().all(|i| ret.1.contains(i)), val.span(), "unaccounted free variable");
+            Some((dummies, val2))
+          }
+          _ => return Err(ElabError::new_e(e.span(), "expecting term declaration")),
+        };
+        let t = if let Some(spec) = spec {
+          let t = match *spec {
+            StmtTrace::Decl(a) => match self.env.data()[a].decl() {
+              Some(DeclKey::Term(t)) => t,
+              _ => return Err(ElabError::new_e(x, "statement mismatch")),
+            },
+            _ => return Err(ElabError::new_e(x, "statement mismatch")),
+          };
+          let tm = self.env.term(t);
+          self.cur_spec = Some(tm.span.clone());
+          self.check_name(tm.atom, x)?;
+          ensure!(matches!(tm.kind, TermKind::Def(_)) == def, x, "statement mismatch");
+          Self::check_args(&args, &tm.args, x)?;
+          ensure!(ret.0 == usize::from(tm.ret.0.0) && Self::deps_mask(&args, &ret.1) == tm.ret.1, x,
+            "return type mismatch");
+          if let (TermKind::Def(Some(body)), Some((_, val))) = (&tm.kind, &val) {
+            ensure!(self.expr_eq(&ctx, &body.heap, args.len(), &mut HashMap::new(), val, &body.head),
+              x, "definition body mismatch");
+          }
+          Some(t)
+        } else { None };
+        ensure!(self.decls.insert(self.span(x), Decl::Term(self.terms.len())).is_none(), x,
+          "declaration redeclared");
+        self.terms.push(TermData {args, ret, def: val, spec: t});
+      }
+      b"axiom" | b"theorem" => {
+        let axiom = kw == b"axiom";
+        let (x, args, hyps, ret, rest) = match *es {
+          [ref x, ref args, ref hyps, ref ret, ref rest @ ..] => (atom(x)?, args, list(hyps)?, ret, rest),
+          _ => return Err(ElabError::new_e(e.span(), "expecting theorem declaration")),
+        };
+        let mut ctx = Ctx::default();
+        let args = self.binders(&mut ctx, args)?;
+        let mut heap = HashMap::new();
+        let hyps = hyps.iter().map(|h| if axiom { self.provable(&ctx, h) } else {
+          match *list(h)? {
+            [ref x, ref h] => {
+              let (x, h) = (self.span(atom(x)?), self.provable(&ctx, h)?);
+              if x != b"_" { heap.insert(x, h.clone()); }
+              Ok(h)
+            }
+            _ => Err(ElabError::new_e(h.span(), "expecting hypothesis")),
+          }
+        }).collect::<Result<Vec<_>>>()?;
+        let ret2 = self.provable(&ctx, ret)?;
+        let nargs = args.len();
+        match (axiom, rest) {
+          (true, []) => {}
+          (false, [ds, pf]) => {
+            self.dummies(&mut ctx, ds)?;
+            let e = self.proof(&ctx, &mut heap, pf)?;
+            ensure!(e == ret2, pf.span(), "theorem did not prove what it claimed");
+          }
+          _ => return Err(ElabError::new_e(e.span(), "expecting theorem declaration")),
+        }
+        if let Some(spec) = spec {
+          let t = match *spec {
+            StmtTrace::Decl(a) => match self.env.data()[a].decl() {
+              Some(DeclKey::Thm(t)) => t,
+              _ => return Err(ElabError::new_e(x, "statement mismatch")),
+            },
+            _ => return Err(ElabError::new_e(x, "statement mismatch")),
+          };
+          let th = self.env.thm(t);
+          self.cur_spec = Some(th.span.clone());
+          self.check_name(th.atom, x)?;
+          ensure!(matches!(th.kind, ThmKind::Axiom) == axiom, x, "statement mismatch");
+          Self::check_args(&args, &th.args, x)?;
+          let mut dummies = HashMap::new();
+          ensure!(hyps.len() == th.hyps.len() && hyps.iter().zip(&*th.hyps).all(|(e, (_, h))|
+            self.expr_eq(&ctx, &th.heap, nargs, &mut dummies, e, h)), x, "hypothesis mismatch");
+          ensure!(self.expr_eq(&ctx, &th.heap, nargs, &mut dummies, &ret2, &th.ret), ret.span(),
+            "conclusion mismatch");
+        }
+        ensure!(self.decls.insert(self.span(x), Decl::Thm(self.thms.len())).is_none(), x,
+          "declaration redeclared");
+        self.thms.push(ThmData {args, hyps, ret: ret2});
+      }
+      b"output" => match (es, spec) {
+        ([k], Some(StmtTrace::OutputString(os))) if self.span(atom(k)?) == b"string" =>
+          self.cur_spec = Some(os.span.clone()),
+        _ => return Err(ElabError::new_e(e.span(), "statement mismatch")),
+      },
+      b"input" => match (es, spec) {
+        ([k], Some(StmtTrace::InputString(os))) if self.span(atom(k)?) == b"string" =>
+          self.cur_spec = Some(os.span.clone()),
+        _ => return Err(ElabError::new_e(e.span(), "statement mismatch")),
+      },
+      _ => return Err(ElabError::new_e(e.span(), "expecting command keyword")),
+    }
+    Ok(())
+  }
+}
+
+/// Verify the `.mmu` file with contents `source` against `spec`, the result of
+/// elaborating an `.mm0` file. Returns the first error, which points into the `.mmu` file
+/// and (if a public declaration is at fault) has a related note pointing at the spec.
+pub fn verify(source: &[u8], spec: &FrozenEnv) -> Result<()> {
+  let mut p = Parser {source, idx: 0};
+  let mut c = Checker {
+    source,
+    env: spec,
+    stmts: spec.stmts().iter(),
+    cur_spec: None,
+    sorts: vec![],
+    sort_names: HashMap::new(),
+    terms: vec![],
+    thms: vec![],
+    decls: HashMap::new(),
+  };
+  let res = (|| {
+    p.ws();
+    while p.idx < source.len() { c.stmt(&p.sexpr()?)? }
+    if let Some(stmt) = c.stmts.find(|s| !matches!(s, StmtTrace::Global(_))) {
+      c.cur_spec = Some(match *stmt {
+        StmtTrace::Sort(a) => spec.sort(spec.data()[a].sort().expect("wf env")).span.clone(),
+        StmtTrace::Decl(a) => match spec.data()[a].decl().expect("wf env") {
+          DeclKey::Term(t) => spec.term(t).span.clone(),
+          DeclKey::Thm(t) => spec.thm(t).span.clone(),
+        },
+        StmtTrace::OutputString(ref os) | StmtTrace::InputString(ref os) => os.span.clone(),
+        StmtTrace::Global(_) => unreachable!(),
+      });
+      return Err(ElabError::new_e(source.len()..source.len(), "spec declaration missing from proof file"))
+    }
+    Ok(())
+  })();
+  res.map_err(|e| match c.cur_spec {
+    None => e,
+    Some(fsp) => ElabError::with_info(e.pos, e.kind.msg().into(), vec![(fsp, "spec declaration".into())]),
+  })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use crate::compiler::{elab_test_file, test_file};
+  use crate::mmb::verify::verify_file;
+  use super::verify;
+
+  const STRING_MM0: &str = include_str!("../../../examples/string.mm0");
+  const STRING_MMU: &str = include_str!("../../../examples/string.mmu");
+  const IM_MM0: &str = "delimiter $ ( ) $;\nstrict provable sort wff;\nterm im: wff > wff > wff;\n\
+    axiom ax_1 (a b: wff): $ im a (im b a) $;\ntheorem t (a b: wff): $ im a (im b a) $;\n";
+  const IM_MMU: &str = "(sort wff strict provable)\n(term im ((p wff ()) (q wff ())) (wff ()))\n\
+    (axiom ax_1 ((a wff ()) (b wff ())) () (im a (im b a)))\n\
+    (theorem t ((a wff ()) (b wff ())) () (im a (im b a)) () (ax_1 (a b)))\n";
+
+  fn msg(source: &str, spec: &str) -> String {
+    verify(source.as_bytes(), &elab_test_file("spec.mm0", spec)).unwrap_err().kind.msg()
+  }
+
+  #[test]
+  fn input_output_statements() {
+    let spec = elab_test_file("string.mm0", STRING_MM0);
+    verify(STRING_MMU.as_bytes(), &spec).unwrap();
+    // the spec has `input string` before the first `output string`
+    let swapped = STRING_MMU.replacen("(input string)\n(output string)",
+      "(output string)\n(input string)", 1);
+    assert_ne!(swapped, STRING_MMU);
+    assert_eq!(msg(&swapped, STRING_MM0), "statement mismatch");
+    let bad_kind = STRING_MMU.replacen("(input string)", "(input bytes)", 1);
+    assert_eq!(msg(&bad_kind, STRING_MM0), "statement mismatch");
+    let missing = STRING_MMU.replacen("(input string)", "", 1);
+    assert_eq!(msg(&missing, STRING_MM0), "statement mismatch");
+  }
+
+  #[test]
+  fn input_string_mmu() {
+    let spec = elab_test_file("string.mm0", STRING_MM0);
+    assert!(verify_file(&test_file("string.mmu", STRING_MMU.as_bytes()), &spec).unwrap());
+    // `sinput` is the string "input", which is part of the definition of `input`
+    let bad = STRING_MMU.replace("(_i (_n (_p (_u (_t (s0))))))", "(_i (_n (_p (_u (_u (s0))))))");
+    assert_ne!(bad, STRING_MMU);
+    assert!(!verify_file(&test_file("string.mmu", bad.as_bytes()), &spec).unwrap());
+  }
+
+  #[test]
+  fn wrong_proof_rejected() {
+    verify(IM_MMU.as_bytes(), &elab_test_file("im.mm0", IM_MM0)).unwrap();
+    // `ax_1 (b a)` proves `im b (im a b)` instead
+    let bad = IM_MMU.replace("(ax_1 (a b))", "(ax_1 (b a))");
+    assert_eq!(msg(&bad, IM_MM0), "theorem did not prove what it claimed");
+    let bad = IM_MMU.replace("(ax_1 (a b))", "(ax_1 (a))");
+    assert_eq!(msg(&bad, IM_MM0), "incorrect number of term arguments");
+  }
+}
@@ -5,14 +5,14 @@ use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}, Condvar};
 use std::collections::{VecDeque, HashMap, HashSet, hash_map::{Entry, DefaultHasher}};
 use std::hash::{Hash, Hasher};
 use std::thread::{ThreadId, self};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use futures::{FutureExt, future::BoxFuture};
 use futures::channel::oneshot::{Sender as FSender, channel};
 use futures::executor::ThreadPool;
 use futures::lock::Mutex as FMutex;
 use lsp_server::{Connection, ErrorCode, Message, Notification, ProtocolError,
   Request, RequestId, Response, ResponseError};
-use serde::ser::Serialize;
+use serde::Serialize;
 use serde_json::{from_value, to_value};
 use serde_repr::{Serialize_repr, Deserialize_repr};
 use serde::Deserialize;
@@ -21,14 +21,16 @@ use crossbeam::channel::{SendError, RecvError};
 use clap::ArgMatches;
 use crate::{ArcList, ArcString, BoxError, FileRef, FileSpan, Span,
   MutexExt, CondvarExt};
-use mm1_parser::{Ast, parse};
+use mm1_parser::{Ast, parse, ast::{Binder, Literal as AstLiteral, LocalKind, SExpr, SExprKind,
+  Stmt, StmtKind}};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
 use crate::compiler::FileContents;
-use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, LinedString, FrozenEnv,
-  FrozenLispKind, FrozenAtomData};
-use crate::elab::{ElabResult, ElaborateBuilder, GoalListener,
-  local_context::InferSort, proof::Subst,
+use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, Type, LinedString,
+  Thm, ThmKind, Proof, ProofNode,
+  FrozenEnv, FrozenLispKind, FrozenLispVal, FrozenAtomData};
+use crate::elab::{ElabErrorKind, ElabResult, ElaborateBuilder, GoalListener, GoalState,
+  local_context::InferSort, proof::Subst, profile::Profile,
   lisp::{print::FormatEnv, pretty::Pretty, LispKind, Proc, BuiltinProc},
   spans::Spans};
 
@@ -209,14 +211,14 @@ async fn elaborate(path: FileRef, start: Option<Position>,
   let source = text.clone();
 
   let mut deps = Vec::new();
-  let (ast, (cyc, toks, errors, env)) = if path.has_extension("mmb") {
+  let (ast, (cyc, toks, errors, env, profile)) = if path.has_extension("mmb") {
     let (error, env) = mmb_elab(&path, &text);
     let errors = if let Err(e) = error {vec![e]} else {vec![]};
-    (None, (None, vec![], errors, FrozenEnv::new(env)))
+    (None, (None, vec![], errors, FrozenEnv::new(env), None))
   } else if path.has_extension("mmu") {
     let (error, env) = mmu_elab(&path, &text);
     let errors = if let Err(e) = error {vec![e]} else {vec![]};
-    (None, (None, vec![], errors, FrozenEnv::new(env)))
+    (None, (None, vec![], errors, FrozenEnv::new(env), None))
   } else {
     let (idx, ast) = parse(text.ascii().clone(), old_ast);
     let ast = Arc::new(ast);
@@ -255,6 +257,8 @@ async fn elaborate(path: FileRef, start: Option<Position>,
             }
           })
         }),
+      profile: SERVER.options.ulock().profile.unwrap_or(false),
+      debugger: None,
     }.elab();
     (Some(ast.clone()), elab.await)
   };
@@ -328,7 +332,8 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     }
   }
   if !is_canceled {
-    *g = Some(FileCache::Ready {hash, source, ast, res: res.clone(), deps});
+    let profile = profile.map(Arc::new);
+    *g = Some(FileCache::Ready {hash, source, ast, res: res.clone(), deps, profile});
     drop(g);
     for d in file.downstream.ulock().iter() {
       log!("{:?} affects {:?}", path, d);
@@ -377,6 +382,7 @@ enum FileCache {
     ast: Option<Arc<Ast>>,
     res: ElabResult<u64>,
     deps: Vec<FileRef>,
+    profile: Option<Arc<Profile>>,
   }
 }
 
@@ -485,6 +491,22 @@ enum RequestType {
   DocumentSymbol(DocumentSymbolParams),
   References(ReferenceParams),
   DocumentHighlight(DocumentHighlightParams),
+  PrepareRename(TextDocumentPositionParams),
+  Rename(RenameParams),
+  SemanticTokens(SemanticTokensParams),
+  SemanticTokensRange(SemanticTokensRangeParams),
+  Goals(TextDocumentPositionParams),
+  WorkspaceSymbol(WorkspaceSymbolParams),
+  CodeAction(CodeActionParams),
+  SignatureHelp(SignatureHelpParams),
+  InlayHint(InlayHintParams),
+  Formatting(DocumentFormattingParams),
+  FoldingRange(FoldingRangeParams),
+  SelectionRange(SelectionRangeParams),
+  PrepareCallHierarchy(CallHierarchyPrepareParams),
+  IncomingCalls(CallHierarchyIncomingCallsParams),
+  OutgoingCalls(CallHierarchyOutgoingCallsParams),
+  CodeLens(CodeLensParams),
 }
 
 fn parse_request(Request {id, method, params}: Request) -> Result<Option<(RequestId, RequestType)>> {
@@ -496,6 +518,25 @@ fn parse_request(Request {id, method, params}: Request) -> Result<Option<(Reques
     "textDocument/documentSymbol"    => Some((id, RequestType::DocumentSymbol(from_value(params)?))),
     "textDocument/references"        => Some((id, RequestType::References(from_value(params)?))),
     "textDocument/documentHighlight" => Some((id, RequestType::DocumentHighlight(from_value(params)?))),
+    "textDocument/prepareRename"     => Some((id, RequestType::PrepareRename(from_value(params)?))),
+    "textDocument/rename"            => Some((id, RequestType::Rename(from_value(params)?))),
+    "textDocument/semanticTokens/full" =>
+      Some((id, RequestType::SemanticTokens(from_value(params)?))),
+    "textDocument/semanticTokens/range" =>
+      Some((id, RequestType::SemanticTokensRange(from_value(params)?))),
+    "mm0/goals"                      => Some((id, RequestType::Goals(from_value(params)?))),
+    "workspace/symbol"               => Some((id, RequestType::WorkspaceSymbol(from_value(params)?))),
+    "textDocument/codeAction"        => Some((id, RequestType::CodeAction(from_value(params)?))),
+    "textDocument/signatureHelp"     => Some((id, RequestType::SignatureHelp(from_value(params)?))),
+    "textDocument/inlayHint"         => Some((id, RequestType::InlayHint(from_value(params)?))),
+    "textDocument/formatting"        => Some((id, RequestType::Formatting(from_value(params)?))),
+    "textDocument/foldingRange"      => Some((id, RequestType::FoldingRange(from_value(params)?))),
+    "textDocument/selectionRange"    => Some((id, RequestType::SelectionRange(from_value(params)?))),
+    "textDocument/prepareCallHierarchy" =>
+      Some((id, RequestType::PrepareCallHierarchy(from_value(params)?))),
+    "callHierarchy/incomingCalls"    => Some((id, RequestType::IncomingCalls(from_value(params)?))),
+    "callHierarchy/outgoingCalls"    => Some((id, RequestType::OutgoingCalls(from_value(params)?))),
+    "textDocument/codeLens"          => Some((id, RequestType::CodeLens(from_value(params)?))),
     _ => None
   })
 }
@@ -583,6 +624,38 @@ impl RequestHandler {
         self.finish(references(file.clone(), doc.position, true,
           |range| DocumentHighlight { range, kind: None }).await)
       }
+      RequestType::PrepareRename(TextDocumentPositionParams {text_document: doc, position}) =>
+        self.finish(prepare_rename(doc.uri.into(), position).await),
+      RequestType::Rename(RenameParams {text_document_position: doc, new_name, ..}) =>
+        self.finish(rename(doc.text_document.uri.into(), doc.position, new_name).await),
+      RequestType::SemanticTokens(SemanticTokensParams {text_document: doc, ..}) =>
+        self.finish(semantic_tokens(doc.uri.into(), None).await),
+      RequestType::SemanticTokensRange(SemanticTokensRangeParams {text_document: doc, range, ..}) =>
+        self.finish(semantic_tokens(doc.uri.into(), Some(range)).await),
+      RequestType::Goals(TextDocumentPositionParams {text_document: doc, position}) =>
+        self.finish(goals(doc.uri.into(), position).await),
+      RequestType::WorkspaceSymbol(WorkspaceSymbolParams {query, ..}) =>
+        self.finish(Ok(workspace_symbol(&query))),
+      RequestType::CodeAction(CodeActionParams {text_document: doc, range, context, ..}) =>
+        self.finish(code_action(doc.uri.into(), range, context.diagnostics).await),
+      RequestType::SignatureHelp(SignatureHelpParams {text_document_position_params: doc, ..}) =>
+        self.finish(signature_help(doc.text_document.uri.into(), doc.position).await),
+      RequestType::InlayHint(InlayHintParams {text_document: doc, range}) =>
+        self.finish(inlay_hint(doc.uri.into(), range).await),
+      RequestType::Formatting(DocumentFormattingParams {text_document: doc, ..}) =>
+        self.finish(formatting(&doc.uri.into())),
+      RequestType::FoldingRange(FoldingRangeParams {text_document: doc, ..}) =>
+        self.finish(folding_range(&doc.uri.into())),
+      RequestType::SelectionRange(SelectionRangeParams {text_document: doc, positions, ..}) =>
+        self.finish(selection_range(&doc.uri.into(), positions)),
+      RequestType::PrepareCallHierarchy(CallHierarchyPrepareParams {text_document_position_params: doc, ..}) =>
+        self.finish(prepare_call_hierarchy(doc.text_document.uri.into(), doc.position).await),
+      RequestType::IncomingCalls(CallHierarchyIncomingCallsParams {item, ..}) =>
+        self.finish(incoming_calls(item).await),
+      RequestType::OutgoingCalls(CallHierarchyOutgoingCallsParams {item, ..}) =>
+        self.finish(outgoing_calls(item).await),
+      RequestType::CodeLens(CodeLensParams {text_document: doc, ..}) =>
+        self.finish(code_lens(doc.uri.into()).await),
     }
   }
 
@@ -779,7 +852,7 @@ async fn hover(path: FileRef, pos: Position) -> Result<Option<Hover>, ResponseEr
           ((sp, mk_doc(bp.doc())), None)
         }
       }
-      ObjectKind::Import(_) => return None,
+      ObjectKind::Import(_) | ObjectKind::InferredArgs(_) | ObjectKind::HaveType(_) => return None,
     }))() {
       let sp = r.0;
       out.push(r);
@@ -840,7 +913,9 @@ async fn definition<T>(path: FileRef, pos: Position,
       &ObjectKind::Thm(t) => res.push(thm(t)),
       ObjectKind::Var(_) |
       ObjectKind::Syntax(_) |
-      ObjectKind::RefineSyntax(_) => {}
+      ObjectKind::RefineSyntax(_) |
+      ObjectKind::InferredArgs(_) |
+      ObjectKind::HaveType(_) => {}
       ObjectKind::Expr(e) => {
         let head = e.uncons().next().unwrap_or(e);
         if let Some(DeclKey::Term(t)) = head.as_atom().and_then(|a| env.data()[a].decl()) {
@@ -944,7 +1019,9 @@ async fn document_symbol(path: FileRef) -> Result<DocumentSymbolResponse, Respon
                 FrozenLispKind::Syntax(_) => SymbolKind::Event,
                 FrozenLispKind::Undef => return None,
                 FrozenLispKind::Proc(_) => SymbolKind::Function,
+                FrozenLispKind::Vector(_) => SymbolKind::Array,
                 FrozenLispKind::AtomMap(_) |
+                FrozenLispKind::HashMap(_) |
                 FrozenLispKind::Annot(_, _) |
                 FrozenLispKind::Ref(_) => SymbolKind::Object,
               }))() {
@@ -954,12 +1031,258 @@ async fn document_symbol(path: FileRef) -> Result<DocumentSymbolResponse, Respon
           }
         }
       }
-      StmtTrace::OutputString(_) => {}
+      StmtTrace::OutputString(_) | StmtTrace::InputString(_) => {}
     }
   }
   Ok(DocumentSymbolResponse::Nested(res))
 }
 
+/// The declaration in `stmt`, looking through annotations and doc comments.
+fn stmt_decl(stmt: &Stmt) -> Option<&mm1_parser::ast::Decl> {
+  match &stmt.k {
+    StmtKind::Decl(d) => Some(d),
+    StmtKind::Annot(_, s) | StmtKind::DocComment(_, s) => stmt_decl(s),
+    _ => None,
+  }
+}
+
+/// The position at which to add dependencies to the return type of the definition
+/// containing `idx`.
+fn ret_deps_pos(ast: &Ast, idx: usize) -> Option<usize> {
+  let d = stmt_decl(ast.stmts.iter().find(|s| s.span.start <= idx && idx < s.span.end)?)?;
+  match &d.ty {
+    Some(mm1_parser::ast::Type::DepType(ty)) => Some(ty.span().end),
+    _ => None,
+  }
+}
+
+/// The position at which to add dependencies to the type of the binder `a` of the
+/// declaration whose name is at `id`, if it is a regular variable. There is none if the type
+/// is shared with other binders, as in `(ph ps: wff)`, because then it can't be changed
+/// for `a` alone.
+fn binder_deps_pos(ast: &Ast, id: Span, a: &[u8]) -> Option<usize> {
+  let d = ast.stmts.iter().filter_map(stmt_decl).find(|d| d.id == id)?;
+  let bi = d.bis.iter().find(|bi| bi.local.map_or(false, |l| ast.span(l) == a))?;
+  let ty = match &bi.ty {
+    Some(mm1_parser::ast::Type::DepType(ty)) if bi.kind == LocalKind::Reg => ty.span(),
+    _ => return None,
+  };
+  let shared = d.bis.iter().filter(|bi| bi.ty.as_ref().map(mm1_parser::ast::Type::span) == Some(ty));
+  if shared.count() > 1 { return None }
+  Some(ty.end)
+}
+
+/// Returns the files in the VFS that (transitively) import `path`.
+fn downstream_closure(path: &FileRef) -> HashSet<FileRef> {
+  let mut todo = vec![path.clone()];
+  let mut res = HashSet::new();
+  while let Some(f) = todo.pop() {
+    if let Some(vf) = SERVER.vfs.get(&f) {
+      for d in vf.downstream.ulock().iter() {
+        if res.insert(d.clone()) { todo.push(d.clone()) }
+      }
+    }
+  }
+  res
+}
+
+/// Returns the files in the VFS other than `path` which declare a sort, term or theorem
+/// called `name`, excluding those that import `path` since importing them would be a cycle.
+fn files_declaring(path: &FileRef, name: &[u8]) -> Vec<FileRef> {
+  let downstream = downstream_closure(path);
+  let files: Vec<_> = SERVER.vfs.0.ulock().iter().map(|(p, f)| (p.clone(), f.clone())).collect();
+  files.into_iter().filter(|(p, file)| p != path && !downstream.contains(p) &&
+    try_old(file).map_or(false, |(_, env)| env.get_atom(name).map_or(false, |a| {
+      let ad = &env.data()[a];
+      ad.sort().map(|s| &env.sort(s).span.file).into_iter()
+        .chain(ad.decl().map(|dk| match dk {
+          DeclKey::Term(t) => &env.term(t).span.file,
+          DeclKey::Thm(t) => &env.thm(t).span.file,
+        }))
+        .any(|f| f == p)
+    }))
+  ).map(|(p, _)| p).collect()
+}
+
+async fn code_action(path: FileRef, range: Range, diags: Vec<Diagnostic>
+) -> Result<Vec<CodeActionOrCommand>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "code action: nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let elab = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let errors = match elab {
+    ElabResult::Ok(_, Some(errors), _) => errors,
+    ElabResult::Canceled => return Err(response_err(ErrorCode::RequestCanceled, "")),
+    _ => return Ok(vec![]),
+  };
+  let (start, end) = match (text.to_idx(range.start), text.to_idx(range.end)) {
+    (Some(start), Some(end)) => (start, end),
+    _ => return Ok(vec![]),
+  };
+  let ast = match &*file.parsed.lock().await {
+    Some(FileCache::Ready {ast, ..}) => ast.clone(),
+    _ => None,
+  };
+  let mut res = vec![];
+  for e in &*errors {
+    if e.pos.end < start || end < e.pos.start { continue }
+    let msg = e.kind.msg();
+    let err_range = text.to_range(e.pos);
+    let diagnostics = Some(diags.iter()
+      .filter(|d| d.range == err_range && d.message == msg).cloned().collect());
+    let mut push = |title: String, edits: Vec<(Url, TextEdit)>| {
+      let mut changes = HashMap::<_, Vec<_>>::new();
+      for (url, edit) in edits { changes.entry(url).or_default().push(edit) }
+      res.push(CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: diagnostics.clone(),
+        edit: Some(WorkspaceEdit {changes: Some(changes), ..Default::default()}),
+        ..Default::default()
+      }))
+    };
+    let insert = |pos: Position, new_text: String| (path.url().clone(),
+      TextEdit {range: Range::new(pos, pos), new_text});
+    match &e.kind {
+      ElabErrorKind::UnknownIdent(_, name) => {
+        // Put the new import after the last import, or at the start of the file
+        let ast = if let Some(ast) = &ast {ast} else {continue};
+        let pos = ast.stmts.iter().rev().find(|s| matches!(s.k, StmtKind::Import(..)))
+          .map_or_else(Position::default, |s| Position::new(text.to_pos(s.span.end).line + 1, 0));
+        let dir = path.path().parent().unwrap_or_else(|| path.path());
+        for p in files_declaring(&path, name) {
+          let rel = pathdiff::diff_paths(p.path(), dir).unwrap_or_else(|| p.path().clone());
+          let rel = rel.to_string_lossy().replace('\\', "/");
+          push(format!("Import '{}' from \"{}\"", name, rel),
+            vec![insert(pos, format!("import \"{}\";\n", rel))])
+        }
+      }
+      ElabErrorKind::MissingDeps(_, vars) => {
+        let ast = if let Some(ast) = &ast {ast} else {continue};
+        let pos = if let Some(pos) = ret_deps_pos(ast, e.pos.start) {pos} else {continue};
+        let vars = vars.iter().map(|v| format!(" {}", v)).collect::<String>();
+        push(format!("Add dependencies on{}", vars), vec![insert(text.to_pos(pos), vars)])
+      }
+      ElabErrorKind::DisjointVars(_, fsp, missing) => {
+        // The fix is in the declaration of the applied theorem, which may be in another file
+        let ast = if fsp.file == path {ast.clone()} else {
+          let f = if let Some(f) = SERVER.vfs.get(&fsp.file) {f} else {continue};
+          let g = f.parsed.lock().await;
+          if let Some(FileCache::Ready {ast, ..}) = &*g {ast.clone()} else {None}
+        };
+        let ast = if let Some(ast) = ast {ast} else {continue};
+        let mut deps = HashMap::<_, Vec<_>>::new();
+        for (x, a) in &**missing { deps.entry(a).or_default().push(x) }
+        for (a, xs) in deps {
+          let pos = if let Some(pos) = binder_deps_pos(&ast, fsp.span, a) {pos} else {continue};
+          let xs = xs.iter().map(|x| format!(" {}", x)).collect::<String>();
+          let pos = ast.source.to_pos(pos);
+          push(format!("Add dependencies on{} to '{}' in '{}'", xs, a,
+              String::from_utf8_lossy(ast.span(fsp.span))),
+            vec![(fsp.file.url().clone(), TextEdit {range: Range::new(pos, pos), new_text: xs})])
+        }
+      }
+      ElabErrorKind::Hole(_, Some(name)) => {
+        if !matches!(&text[e.pos], b"?" | b"_") { continue }
+        push(format!("Replace hole with '{}'", name), vec![(path.url().clone(),
+          TextEdit {range: err_range, new_text: name.to_string()})])
+      }
+      _ => {}
+    }
+  }
+  Ok(res)
+}
+
+/// The maximum number of results returned by [`workspace_symbol`].
+const MAX_WORKSPACE_SYMBOLS: usize = 1000;
+
+/// Matches `query` as a case insensitive subsequence of `name`, returning a score
+/// (lower is better) which counts the characters skipped before and between the matches.
+fn fuzzy_match(query: &[u8], name: &[u8]) -> Option<usize> {
+  let (mut score, mut it, mut last) = (0, name.iter().enumerate(), None);
+  for q in query {
+    let (i, _) = it.by_ref().find(|(_, c)| c.eq_ignore_ascii_case(q))?;
+    score += last.map_or(i, |j| i - j - 1);
+    last = Some(i);
+  }
+  Some(score)
+}
+
+/// Searches the sorts, terms and theorems of all elaborated files for names fuzzy matching
+/// `query`. The query can be prefixed with `sort:`, `term:` or `thm:` to restrict the
+/// results to that kind of declaration.
+fn workspace_symbol(query: &str) -> Vec<SymbolInformation> {
+  let (kind, query) = match query.split_once(':') {
+    Some(("sort", q)) => (Some(SymbolKind::Class), q),
+    Some(("term", q)) => (Some(SymbolKind::Constructor), q),
+    Some(("thm", q)) => (Some(SymbolKind::Method), q),
+    _ => (None, query),
+  };
+  let query = query.trim().as_bytes();
+  let files: Vec<_> = SERVER.vfs.0.ulock().iter().map(|(p, f)| (p.clone(), f.clone())).collect();
+  let mut res = vec![];
+  for (path, file) in files {
+    let (text, env) = if let Some(old) = try_old(&file) {old} else {continue};
+    let text = if let Some(text) = text.try_ascii() {text.clone()} else {continue};
+    file_symbols(&path, &text, &env, kind, query, &mut res)
+  }
+  rank_symbols(res)
+}
+
+/// Pushes the declarations made in `path` (with contents `text`) that match `query` and `kind`
+/// onto `res`, together with their [`fuzzy_match`] scores.
+fn file_symbols(path: &FileRef, text: &LinedString, env: &FrozenEnv, kind: Option<SymbolKind>,
+  query: &[u8], res: &mut Vec<(usize, SymbolInformation)>
+) {
+  let fe = unsafe { env.format_env(text) };
+  let mut push = |fsp: &FileSpan, name: &ArcString, desc: String, sk| {
+    // Declarations are reported by the file that declares them, not the files importing them
+    if fsp.file != *path || kind.map_or(false, |k| k != sk) { return }
+    if let Some(score) = fuzzy_match(query, name) {
+      #[allow(deprecated)]
+      res.push((score, SymbolInformation {
+        name: String::from_utf8_lossy(name).into(),
+        kind: sk,
+        tags: None,
+        deprecated: None,
+        location: Location {uri: path.url().clone(), range: text.to_range(fsp.span)},
+        container_name: Some(desc),
+      }))
+    }
+  };
+  for s in env.stmts() {
+    match *s {
+      StmtTrace::Sort(a) => {
+        let ad = &env.data()[a];
+        let sd = env.sort(ad.sort().expect("env well formed"));
+        push(&sd.span, ad.name(), format!("{}", sd), SymbolKind::Class)
+      }
+      StmtTrace::Decl(a) => {
+        let ad = &env.data()[a];
+        match ad.decl().expect("env well formed") {
+          DeclKey::Term(t) => {
+            let td = env.term(t);
+            push(&td.span, ad.name(), format!("{}", fe.to(td)), SymbolKind::Constructor)
+          }
+          DeclKey::Thm(t) => {
+            let td = env.thm(t);
+            push(&td.span, ad.name(), format!("{}", fe.to(td)), SymbolKind::Method)
+          }
+        }
+      }
+      StmtTrace::Global(_) | StmtTrace::OutputString(_) | StmtTrace::InputString(_) => {}
+    }
+  }
+}
+
+/// Orders the matches by score, preferring shorter names, and keeps the first
+/// [`MAX_WORKSPACE_SYMBOLS`] of them.
+fn rank_symbols(mut res: Vec<(usize, SymbolInformation)>) -> Vec<SymbolInformation> {
+  res.sort_by(|(s1, a), (s2, b)| (s1, a.name.len(), &a.name).cmp(&(s2, b.name.len(), &b.name)));
+  res.into_iter().take(MAX_WORKSPACE_SYMBOLS).map(|(_, si)| si).collect()
+}
+
 #[derive(Serialize_repr, Deserialize_repr)]
 #[repr(u8)]
 enum TraceKind {Sort, Decl, Global}
@@ -1003,6 +1326,8 @@ fn make_completion_item(path: &FileRef, fe: FormatEnv<'_>, ad: &FrozenAtomData,
         FrozenLispKind::String(_) |
         FrozenLispKind::Bool(_) |
         FrozenLispKind::AtomMap(_) |
+        FrozenLispKind::Vector(_) |
+        FrozenLispKind::HashMap(_) |
         FrozenLispKind::Annot(_, _) |
         FrozenLispKind::Ref(_) => CompletionItemKind::Value,
         FrozenLispKind::Syntax(_) => CompletionItemKind::Event,
@@ -1084,6 +1409,243 @@ async fn completion_resolve(ci: CompletionItem) -> Result<CompletionItem, Respon
     .ok_or_else(|| response_err(ErrorCode::ContentModified, "completion missing"))
 }
 
+/// Scans `text`, which ends at the cursor, for the innermost unclosed lisp application.
+/// Returns the head, the `!` or `!!` prefix (or the empty string), and the index of the
+/// argument at the cursor. This is done lexically rather than with the parser, because
+/// the application is usually incomplete while it is being typed.
+fn enclosing_app(text: &[u8]) -> Option<(&[u8], &[u8], u32)> {
+  /// An open list. `at` is set for the implicit list opened by `@`, which
+  /// is closed by the close paren of the enclosing list.
+  #[derive(Default)]
+  struct Frame<'a> { at: bool, prefix: &'a [u8], head: Option<&'a [u8]>, args: u32 }
+  fn token<'a>(stack: &mut [Frame<'a>], tk: &'a [u8]) {
+    if let Some(f) = stack.last_mut() {
+      match f.head {
+        None if f.prefix.is_empty() && matches!(tk, b"!" | b"!!") => f.prefix = tk,
+        None => f.head = Some(tk),
+        Some(_) => f.args += 1,
+      }
+    }
+  }
+  let ident = |c| mm1_parser::lisp_ident(c) || c == b'#';
+  let mut stack = vec![];
+  let mut i = 0;
+  while let Some(&c) = text.get(i) {
+    match c {
+      b'(' | b'[' | b'{' => {
+        token(&mut stack, b"");
+        stack.push(Frame::default());
+        // Infix lists `{a + b}` have no head
+        if c == b'{' { token(&mut stack, b"") }
+        i += 1
+      }
+      b')' | b']' | b'}' => {
+        while stack.last().map_or(false, |f| f.at) { stack.pop(); }
+        stack.pop();
+        i += 1
+      }
+      b'$' | b'"' => {
+        // Skip the math string or string literal, unless the cursor is inside it
+        let mut j = i + 1;
+        loop {
+          match *text.get(j)? {
+            b'\\' if c == b'"' => j += 2,
+            d if d == c => break,
+            _ => j += 1,
+          }
+        }
+        token(&mut stack, b"");
+        i = j + 1
+      }
+      b'-' if text.get(i + 1) == Some(&b'-') =>
+        i += text[i..].iter().position(|&c| c == b'\n')?,
+      _ if ident(c) => {
+        let j = text[i..].iter().position(|&c| !ident(c)).map_or(text.len(), |n| i + n);
+        if text[i..j] == *b"@" {
+          token(&mut stack, b"");
+          stack.push(Frame {at: true, ..Frame::default()})
+        } else {
+          token(&mut stack, &text[i..j])
+        }
+        i = j
+      }
+      _ => i += 1,
+    }
+  }
+  let f = stack.pop()?;
+  let head = f.head.filter(|h| !h.is_empty())?;
+  // If the cursor is touching the last token then that argument is still being typed
+  let typing = text.last().map_or(false, |&c| !mm1_parser::whitespace(c) && !b"([{".contains(&c));
+  Some((head, f.prefix, if typing { f.args.checked_sub(1)? } else { f.args }))
+}
+
+/// Shows the binders and hypotheses of the term or theorem being applied at `pos`,
+/// with the argument at the cursor as the active parameter.
+async fn signature_help(path: FileRef, pos: Position) -> Result<Option<SignatureHelp>, ResponseError> {
+  fn utf16_len(s: &str) -> u32 { s.encode_utf16().count().try_into().expect("label too long") }
+
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "signature help nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let idx = if let Some(idx) = text.to_idx(pos) {idx} else {return Ok(None)};
+  let env = if let Some((_, env)) = try_old(&file) { env } else {
+    let env = elaborate(path, Some(Position::default()), Default::default(), Default::default())
+      .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+    match env.into_response_error()? {
+      None => return Ok(None),
+      Some((_, env)) => env
+    }
+  };
+  // Start scanning at the statement containing the cursor, if the last parse is up to date
+  let start = match &*file.parsed.lock().await {
+    Some(FileCache::Ready {ast: Some(ast), ..}) if Arc::ptr_eq(&ast.source, &text) =>
+      ast.last_checkpoint(idx).1,
+    _ => 0,
+  };
+  let (head, prefix, arg) =
+    if let Some(app) = enclosing_app(&text.as_bytes()[start..idx]) {app} else {return Ok(None)};
+  let fe = unsafe { env.format_env(&text) };
+  let a = if let Some(a) = env.get_atom(head) {a} else {return Ok(None)};
+  let (args, hyps, ret, doc) = match env.data()[a].decl() {
+    None => return Ok(None),
+    Some(DeclKey::Term(t)) => {
+      let td = &fe.terms[t];
+      (&td.args, None, Err(td.ret), &td.doc)
+    }
+    Some(DeclKey::Thm(t)) => {
+      let td = &fe.thms[t];
+      let mut heap = vec![];
+      fe.binders(&td.args, &mut heap, &mut vec![]);
+      for e in &td.heap[heap.len()..] {
+        let e = fe.expr_node(&heap, &mut None, e);
+        heap.push(e)
+      }
+      let hyps = td.hyps.iter().map(|(h, e)| (*h, fe.expr_node(&heap, &mut None, e))).collect::<Vec<_>>();
+      (&td.args, Some(hyps), Ok(fe.expr_node(&heap, &mut None, &td.ret)), &td.doc)
+    }
+  };
+
+  let mut label = format!("{}", fe.to(&a));
+  let mut params = vec![];
+  let mut push = |explicit: bool, s: String| {
+    label.push(' ');
+    let start = utf16_len(&label);
+    label += &s;
+    if explicit {
+      params.push(ParameterInformation {
+        label: ParameterLabel::LabelOffsets([start, utf16_len(&label)]),
+        documentation: None,
+      })
+    }
+  };
+  let mut bvars = vec![];
+  let deps = |bvars: &[Option<AtomId>], ds: u64| -> String {
+    bvars.iter().enumerate().filter(|&(i, _)| ds & (1 << i) != 0)
+      .map(|(_, x)| format!(" {}", fe.to(x))).collect()
+  };
+  for &(x, ty) in &**args {
+    // In a proof, theorem binders are only given explicitly with `!` or `!!`
+    let explicit = hyps.is_none() || match prefix {
+      b"!" => true,
+      b"!!" => ty.bound(),
+      _ => false,
+    };
+    push(explicit, match ty {
+      Type::Bound(s) => {
+        bvars.push(x);
+        format!("{{{}: {}}}", fe.to(&x), fe.to(&s))
+      }
+      Type::Reg(s, ds) => format!("({}: {}{})", fe.to(&x), fe.to(&s), deps(&bvars, ds)),
+    })
+  }
+  for (h, e) in hyps.iter().flatten() {
+    push(true, format!("({}: $ {} $)", fe.to(h), fe.pp(e, 80)))
+  }
+  label += &match ret {
+    Ok(e) => format!(": $ {} $", fe.pp(&e, 80)),
+    Err((s, ds)) => format!(": {}{}", fe.to(&s), deps(&bvars, ds)),
+  };
+  Ok(Some(SignatureHelp {
+    signatures: vec![SignatureInformation {
+      label,
+      documentation: doc.as_ref().map(|doc| Documentation::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: trim_margin(doc),
+      })),
+      parameters: Some(params),
+      active_parameter: None,
+    }],
+    active_signature: Some(0),
+    active_parameter: Some(arg),
+  }))
+}
+
+/// An object that can be referenced from several places, for `references` and `rename`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum RefKey {
+  Var(AtomId),
+  Sort(SortId),
+  Term(TermId),
+  Thm(ThmId),
+  Global(AtomId),
+}
+
+impl RefKey {
+  fn new(env: &FrozenEnv, k: &ObjectKind) -> Option<RefKey> {
+    match *k {
+      ObjectKind::Expr(ref e) => {
+        let a = e.uncons().next().unwrap_or(e).as_atom()?;
+        if let Some(DeclKey::Term(t)) = env.data()[a].decl() {
+          Some(RefKey::Term(t))
+        } else {
+          Some(RefKey::Var(a))
+        }
+      }
+      ObjectKind::Proof(ref p) => {
+        let a = p.uncons().next().unwrap_or(p).as_atom()?;
+        if let Some(DeclKey::Thm(t)) = env.data()[a].decl() {
+          Some(RefKey::Thm(t))
+        } else {
+          Some(RefKey::Var(a))
+        }
+      }
+      ObjectKind::Import(_) |
+      ObjectKind::Syntax(_) |
+      ObjectKind::RefineSyntax(_) |
+      ObjectKind::InferredArgs(_) |
+      ObjectKind::HaveType(_) => None,
+      ObjectKind::Var(a) => Some(RefKey::Var(a)),
+      ObjectKind::Sort(a) => Some(RefKey::Sort(a)),
+      ObjectKind::Term(a, _) => Some(RefKey::Term(a)),
+      ObjectKind::Thm(a) => Some(RefKey::Thm(a)),
+      ObjectKind::Global(a) => Some(RefKey::Global(a)),
+    }
+  }
+
+  /// Calls `f(sp, name_sp)` for each occurrence of this key in `spans`, where `sp` is the
+  /// span of the object and `name_sp` is the span to report for it.
+  fn find_in(self, env: &FrozenEnv, spans: &Spans<ObjectKind>, mut f: impl FnMut(Span, Span)) {
+    for &(sp, ref k) in spans {
+      let eq = match *k {
+        ObjectKind::Expr(_) if !matches!(self, RefKey::Term(_) | RefKey::Var(_)) => false,
+        ObjectKind::Proof(_) if !matches!(self, RefKey::Thm(_) | RefKey::Var(_)) => false,
+        _ => Some(self) == RefKey::new(env, k),
+      };
+      if eq { f(sp, if let ObjectKind::Term(_, sp2) = *k {sp2} else {sp}) }
+    }
+  }
+
+  /// The name of the referenced object.
+  fn name(self, env: &FrozenEnv) -> &ArcString {
+    match self {
+      RefKey::Var(a) | RefKey::Global(a) => env.data()[a].name(),
+      RefKey::Sort(s) => &env.sort(s).name,
+      RefKey::Term(t) => env.data()[env.term(t).atom].name(),
+      RefKey::Thm(t) => env.data()[env.thm(t).atom].name(),
+    }
+  }
+}
+
 async fn references<T>(
   path: FileRef, pos: Position, include_self: bool, f: impl Fn(Range) -> T + Send
 ) -> Result<Vec<T>, ResponseError> {
@@ -1091,14 +1653,6 @@ async fn references<T>(
     Some(x) => x,
     None => return Ok(vec![])
   }}}
-  #[derive(Copy, Clone, PartialEq, Eq)]
-  enum Key {
-    Var(AtomId),
-    Sort(SortId),
-    Term(TermId),
-    Thm(ThmId),
-    Global(AtomId),
-  }
 
   let file = SERVER.vfs.get(&path).ok_or_else(||
     response_err(ErrorCode::InvalidRequest, "references: nonexistent file"))?;
@@ -1109,60 +1663,732 @@ async fn references<T>(
   let env = or_none!(env.into_response_error()?).1;
   let spans = or_none!(env.find(idx));
 
-  let to_key = |k: &ObjectKind| match *k {
-    ObjectKind::Expr(ref e) => {
-      let a = e.uncons().next().unwrap_or(e).as_atom()?;
-      if let Some(DeclKey::Term(t)) = env.data()[a].decl() {
-        Some(Key::Term(t))
-      } else {
-        Some(Key::Var(a))
+  let mut res = vec![];
+  find_references(&env, spans, idx, include_self, |sp| res.push(f(text.to_range(sp))));
+  Ok(res)
+}
+
+/// Calls `f` on the name span of each reference to the objects at `idx`, which is in
+/// `spans`. Local variables are only searched for in `spans`, and other objects in all
+/// of `env`. The occurrences at `idx` itself are skipped unless `include_self` is true.
+fn find_references(env: &FrozenEnv, spans: &Spans<ObjectKind>, idx: usize, include_self: bool,
+  mut f: impl FnMut(Span)
+) {
+  for &(sp, ref k) in spans.find_pos(idx) {
+    let key = match RefKey::new(env, k) {Some(k) => k, None => continue};
+    match key {
+      RefKey::Global(a) if BuiltinProc::from_bytes(env.data()[a].name()).is_some() => continue,
+      _ => {}
+    }
+    let mut cont = |sp2, name_sp| if include_self || sp != sp2 { f(name_sp) };
+    if let RefKey::Var(_) = key {
+      key.find_in(env, spans, &mut cont)
+    } else {
+      for spans2 in env.spans() {
+        key.find_in(env, spans2, &mut cont)
       }
     }
-    ObjectKind::Proof(ref p) => {
-      let a = p.uncons().next().unwrap_or(p).as_atom()?;
-      if let Some(DeclKey::Thm(t)) = env.data()[a].decl() {
-        Some(Key::Thm(t))
-      } else {
-        Some(Key::Var(a))
+  }
+}
+
+/// Returns true if `name` has any global binding in `env`: a sort, a term or theorem,
+/// or a lisp definition. Renaming something to such a name would shadow or clash with it.
+fn name_in_use(env: &FrozenEnv, name: &[u8]) -> bool {
+  env.get_atom(name).map_or(false, |a| {
+    let ad = &env.data()[a];
+    ad.sort().is_some() || ad.decl().is_some() || ad.lisp().is_some()
+  })
+}
+
+/// Returns true if `s` is a valid name for a variable or declaration.
+fn is_valid_ident(s: &[u8]) -> bool {
+  matches!(s.split_first(), Some((&c, rest))
+    if mm1_parser::ident_start(c) && rest.iter().all(|&c| mm1_parser::ident_rest(c))) &&
+  s != b"_"
+}
+
+/// Finds the renameable object at `idx`, and returns it along with its name and the span
+/// of the name at `idx`.
+fn rename_target(env: &FrozenEnv, text: &LinedString, spans: &Spans<ObjectKind>, idx: usize
+) -> Option<(RefKey, ArcString, Span)> {
+  spans.find_pos(idx).find_map(|(sp, k)| {
+    let key = RefKey::new(env, k)?;
+    if let RefKey::Global(_) = key { return None }
+    let name = key.name(env).clone();
+    let sp = if let ObjectKind::Term(_, sp2) = *k {sp2} else {*sp};
+    // Only occurrences that spell out the name can be renamed, not notations
+    if sp.start <= idx && idx <= sp.end && text[sp] == *name { Some((key, name, sp)) } else { None }
+  })
+}
+
+async fn prepare_rename(path: FileRef, pos: Position
+) -> Result<Option<PrepareRenameResponse>, ResponseError> {
+  macro_rules! or_none {($e:expr)  => {match $e {
+    Some(x) => x,
+    None => return Ok(None)
+  }}}
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "prepareRename: nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let idx = or_none!(text.to_idx(pos));
+  let env = elaborate(path, Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let env = or_none!(env.into_response_error()?).1;
+  let spans = or_none!(env.find(idx));
+  let (_, _, sp) = or_none!(rename_target(&env, &text, spans, idx));
+  Ok(Some(PrepareRenameResponse::Range(text.to_range(sp))))
+}
+
+/// Elaborates `path` from the start and returns the resulting environment, if any.
+async fn get_env(path: FileRef) -> Result<Option<FrozenEnv>, ResponseError> {
+  let env = elaborate(path, Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  Ok(env.into_response_error()?.map(|p| p.1))
+}
+
+async fn rename(path: FileRef, pos: Position, new_name: String
+) -> Result<Option<WorkspaceEdit>, ResponseError> {
+  macro_rules! or_none {($e:expr)  => {match $e {
+    Some(x) => x,
+    None => return Ok(None)
+  }}}
+  let reject = |msg: String| Err(response_err(ErrorCode::InvalidRequest, msg));
+  if !is_valid_ident(new_name.as_bytes()) {
+    return reject(format!("'{}' is not a valid identifier", new_name))
+  }
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "rename: nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let idx = or_none!(text.to_idx(pos));
+  let env = or_none!(get_env(path.clone()).await?);
+  let spans = or_none!(env.find(idx));
+  let (key, name, _) = or_none!(rename_target(&env, &text, spans, idx));
+  let mut changes = HashMap::new();
+
+  let decl_file = match key {
+    RefKey::Var(_) => {
+      if name_in_use(&env, new_name.as_bytes()) ||
+        spans.lc.as_ref().zip(env.get_atom(new_name.as_bytes()))
+          .map_or(false, |(lc, a)| lc.vars.contains_key(&a)) {
+        return reject(format!("'{}' is already in use", new_name))
       }
+      let mut edits = vec![];
+      key.find_in(&env, spans, |_, sp| if text[sp] == *name {
+        edits.push(TextEdit {range: text.to_range(sp), new_text: new_name.clone()})
+      });
+      changes.insert(path.url().clone(), edits);
+      return Ok(Some(WorkspaceEdit {changes: Some(changes), ..Default::default()}))
     }
-    ObjectKind::Import(_) |
-    ObjectKind::Syntax(_) |
-    ObjectKind::RefineSyntax(_) => None,
-    ObjectKind::Var(a) => Some(Key::Var(a)),
-    ObjectKind::Sort(a) => Some(Key::Sort(a)),
-    ObjectKind::Term(a, _) => Some(Key::Term(a)),
-    ObjectKind::Thm(a) => Some(Key::Thm(a)),
-    ObjectKind::Global(a) => Some(Key::Global(a)),
+    RefKey::Sort(s) => env.sort(s).span.file.clone(),
+    RefKey::Term(t) => env.term(t).span.file.clone(),
+    RefKey::Thm(t) => env.thm(t).span.file.clone(),
+    RefKey::Global(_) => unreachable!(),
   };
 
-  let mut res = vec![];
-  for &(sp, ref k) in spans.find_pos(idx) {
-    let key = match to_key(k) {Some(k) => k, None => continue};
-    match key {
-      Key::Global(a) if BuiltinProc::from_bytes(env.data()[a].name()).is_some() => continue,
-      _ => {}
+  // The declaration and everything that (transitively) imports it
+  let mut files = vec![decl_file.clone()];
+  let mut seen: HashSet<_> = files.iter().cloned().collect();
+  while let Some(f) = files.pop() {
+    if let Some(vf) = SERVER.vfs.get(&f) {
+      for d in vf.downstream.ulock().iter() {
+        if seen.insert(d.clone()) { files.push(d.clone()) }
+      }
     }
-    let mut cont = |&(sp2, ref k2)| {
-      let eq = match *k2 {
-        ObjectKind::Expr(_) if !matches!(key, Key::Term(_) | Key::Var(_)) => false,
-        ObjectKind::Proof(_) if !matches!(key, Key::Thm(_) | Key::Var(_)) => false,
-        _ => Some(key) == to_key(k2),
-      };
-      if eq && (include_self || sp != sp2) {
-        let sp2 = if let ObjectKind::Term(_, sp2) = *k2 {sp2} else {sp2};
-        res.push(f(text.to_range(sp2)))
+  }
+  for f in seen {
+    let text = match SERVER.vfs.get(&f) {
+      Some(vf) => vf.text.ulock().1.clone(),
+      None => continue,
+    };
+    let text = match text.try_ascii() {
+      Some(text) if !f.has_extension("mmb") && !f.has_extension("mmu") => text.clone(),
+      _ if f.ptr_eq(&decl_file) => return reject(format!("cannot rename '{}' in {}", name, f)),
+      _ => continue,
+    };
+    let env = match get_env(f.clone()).await? {
+      Some(env) => env,
+      None => continue,
+    };
+    let ad = match env.get_atom(&name) {
+      Some(a) => &env.data()[a],
+      None => continue,
+    };
+    let key = match (key, ad.sort(), ad.decl()) {
+      (RefKey::Sort(_), Some(s), _) => RefKey::Sort(s),
+      (RefKey::Term(_), _, Some(DeclKey::Term(t))) => RefKey::Term(t),
+      (RefKey::Thm(_), _, Some(DeclKey::Thm(t))) => RefKey::Thm(t),
+      _ => continue,
+    };
+    if name_in_use(&env, new_name.as_bytes()) {
+      return reject(format!("'{}' is already declared in {}", new_name, f))
+    }
+    let mut edits = vec![];
+    for spans in env.spans() {
+      key.find_in(&env, spans, |_, sp| if text[sp] == *name {
+        edits.push(TextEdit {range: text.to_range(sp), new_text: new_name.clone()})
+      })
+    }
+    if !edits.is_empty() { changes.insert(f.url().clone(), edits); }
+  }
+  Ok(Some(WorkspaceEdit {changes: Some(changes), ..Default::default()}))
+}
+
+/// Collects the theorems applied in `proof`, in order of first use.
+fn proof_thms(proof: &Proof) -> Vec<ThmId> {
+  fn go(node: &ProofNode, seen: &mut HashSet<ThmId>, out: &mut Vec<ThmId>) {
+    match node {
+      ProofNode::Ref(_) |
+      ProofNode::Dummy(_, _) |
+      ProofNode::Term {..} |
+      ProofNode::Hyp(_, _) |
+      ProofNode::Refl(_) |
+      ProofNode::Sym(_) |
+      ProofNode::Cong {..} |
+      ProofNode::Unfold {..} => {}
+      ProofNode::Conv(p) => go(&p.2, seen, out),
+      &ProofNode::Thm {thm, ref args, ..} => {
+        if seen.insert(thm) { out.push(thm) }
+        for p in &**args { go(p, seen, out) }
       }
+    }
+  }
+  let (mut seen, mut out) = (HashSet::new(), vec![]);
+  for p in proof.heap.iter().chain(std::iter::once(&proof.head)) { go(p, &mut seen, &mut out) }
+  out
+}
+
+/// Builds the call hierarchy item for theorem `t`, if it is declared in a text file.
+fn call_item(env: &FrozenEnv, t: ThmId) -> Option<CallHierarchyItem> {
+  let td = env.thm(t);
+  let vf = SERVER.vfs.get(&td.span.file)?;
+  let text = vf.text.ulock().1.try_ascii()?.clone();
+  let fe = unsafe { env.format_env(&text) };
+  Some(CallHierarchyItem {
+    name: String::from_utf8_lossy(env.data()[td.atom].name()).into(),
+    kind: SymbolKind::Method,
+    tags: None,
+    detail: Some(format!("{}", fe.to(td))),
+    uri: td.span.file.url().clone(),
+    range: text.to_range(td.full),
+    selection_range: text.to_range(td.span.span),
+    data: Some(to_value((td.span.span.start, td.span.span.end)).expect("impossible")),
+  })
+}
+
+/// Finds the theorem declared at the span recorded in `item` (by [`call_item`]) in `file`.
+/// This does not look up the name, which may be shadowed or renamed in the importing files.
+fn item_thm(env: &FrozenEnv, item: &CallHierarchyItem, file: &FileRef) -> Option<ThmId> {
+  let (start, end) = from_value(item.data.clone()?).ok()?;
+  let span = Span {start, end};
+  env.thms().enum_iter().find(|(_, td)| td.span.span == span && td.span.file == *file).map(|(t, _)| t)
+}
+
+/// Returns true if `callee` is applied anywhere in the proof `p`.
+fn proof_uses(p: &FrozenLispVal, callee: AtomId) -> bool {
+  let mut u = p.uncons();
+  match u.next() {
+    None => false,
+    Some(head) => head.as_atom() == Some(callee) || u.any(|p| proof_uses(p, callee)),
+  }
+}
+
+/// The ranges in the proof of `caller` which refer to `callee`. If the proof does not
+/// mention it in the source (for example because it was generated by a tactic),
+/// these are the innermost recorded proof steps whose elaborated proof applies it.
+fn call_ranges(env: &FrozenEnv, text: &LinedString, caller: &Thm, callee: ThmId) -> Vec<Range> {
+  let spans = if let Some(spans) = Spans::find(env.spans(), caller.full.start) {spans} else {
+    return vec![]
+  };
+  let mut res = vec![];
+  RefKey::Thm(callee).find_in(env, spans, |_, sp| res.push(sp));
+  if res.is_empty() {
+    let a = env.thm(callee).atom;
+    let steps = spans.into_iter().filter(|(_, k)| matches!(k, ObjectKind::Proof(p) if proof_uses(p, a)))
+      .map(|&(sp, _)| sp).collect::<Vec<_>>();
+    res = steps.iter().copied().filter(|sp| !steps.iter().any(|sp2|
+      sp2 != sp && sp.start <= sp2.start && sp2.end <= sp.end)).collect();
+  }
+  res.into_iter().map(|sp| text.to_range(sp)).collect()
+}
+
+/// Finds the theorem at `pos`, as the root of a call hierarchy.
+async fn prepare_call_hierarchy(path: FileRef, pos: Position
+) -> Result<Option<Vec<CallHierarchyItem>>, ResponseError> {
+  macro_rules! or_none {($e:expr)  => {match $e {
+    Some(x) => x,
+    None => return Ok(None)
+  }}}
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "prepareCallHierarchy: nonexistent file"))?;
+  if path.has_extension("mmb") || path.has_extension("mmu") { return Ok(None) }
+  let text = file.text.ulock().1.ascii().clone();
+  let idx = or_none!(text.to_idx(pos));
+  let env = or_none!(get_env(path).await?);
+  let spans = or_none!(env.find(idx));
+  let t = or_none!(spans.find_pos(idx).find_map(|(_, k)| match RefKey::new(&env, k)? {
+    RefKey::Thm(t) => Some(t),
+    _ => None
+  }));
+  Ok(call_item(&env, t).map(|item| vec![item]))
+}
+
+/// Finds the theorems whose proofs use `item`, in the file declaring it and every file
+/// which (transitively) imports that file.
+async fn incoming_calls(item: CallHierarchyItem
+) -> Result<Option<Vec<CallHierarchyIncomingCall>>, ResponseError> {
+  let decl_file: FileRef = item.uri.clone().into();
+  let mut files = downstream_closure(&decl_file);
+  files.insert(decl_file.clone());
+  let mut res = vec![];
+  for f in files {
+    let vf = match SERVER.vfs.get(&f) {
+      Some(vf) => vf,
+      None => continue,
     };
-    if let Key::Var(_) = key {
-      spans.into_iter().for_each(&mut cont);
+    let text = match vf.text.ulock().1.try_ascii() {
+      Some(text) if !f.has_extension("mmb") && !f.has_extension("mmu") => text.clone(),
+      _ => continue,
+    };
+    let env = match get_env(f.clone()).await? {
+      Some(env) => env,
+      None => continue,
+    };
+    let callee = match item_thm(&env, &item, &decl_file) {
+      Some(t) => t,
+      None => continue,
+    };
+    for (t, td) in env.thms().enum_iter() {
+      // Callers are reported by the file that declares them, not the files importing them
+      if td.span.file != f { continue }
+      if let ThmKind::Thm(Some(proof)) = &td.kind {
+        if !proof_thms(proof).contains(&callee) { continue }
+        if let Some(from) = call_item(&env, t) {
+          res.push(CallHierarchyIncomingCall {from, from_ranges: call_ranges(&env, &text, td, callee)})
+        }
+      }
+    }
+  }
+  Ok(Some(res))
+}
+
+/// Finds the theorems used in the proof of `item`.
+async fn outgoing_calls(item: CallHierarchyItem
+) -> Result<Option<Vec<CallHierarchyOutgoingCall>>, ResponseError> {
+  macro_rules! or_none {($e:expr)  => {match $e {
+    Some(x) => x,
+    None => return Ok(None)
+  }}}
+  let path: FileRef = item.uri.clone().into();
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "outgoingCalls: nonexistent file"))?;
+  if path.has_extension("mmb") || path.has_extension("mmu") { return Ok(None) }
+  let text = file.text.ulock().1.ascii().clone();
+  let env = or_none!(get_env(path.clone()).await?);
+  let td = env.thm(or_none!(item_thm(&env, &item, &path)));
+  let proof = match &td.kind {
+    ThmKind::Thm(Some(proof)) => proof,
+    _ => return Ok(Some(vec![]))
+  };
+  Ok(Some(proof_thms(proof).into_iter().filter_map(|t| Some(CallHierarchyOutgoingCall {
+    to: call_item(&env, t)?,
+    from_ranges: call_ranges(&env, &text, td, t),
+  })).collect()))
+}
+
+/// Statements that are elaborated faster than this do not get a code lens.
+const MIN_CODE_LENS_TIME: Duration = Duration::from_millis(10);
+
+/// Shows the elaboration time of each slow statement above it, if profiling is enabled.
+async fn code_lens(path: FileRef) -> Result<Option<Vec<CodeLens>>, ResponseError> {
+  if !SERVER.options.ulock().profile.unwrap_or(false) { return Ok(None) }
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "codeLens: nonexistent file"))?;
+  if path.has_extension("mmb") || path.has_extension("mmu") { return Ok(None) }
+  elaborate(path, Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let (source, profile) = match &*file.parsed.lock().await {
+    Some(FileCache::Ready {source, profile: Some(profile), ..}) => (source.clone(), profile.clone()),
+    _ => return Ok(None)
+  };
+  let text = source.ascii();
+  Ok(Some(profile.stmts.iter().filter(|s| s.time >= MIN_CODE_LENS_TIME).map(|s| {
+    let pos = text.to_pos(s.span.start);
+    CodeLens {
+      range: Range::new(pos, pos),
+      command: Some(Command {
+        title: format!("elab {}", s.summary()),
+        command: String::new(),
+        arguments: None,
+      }),
+      data: None,
+    }
+  }).collect()))
+}
+
+/// Elaborates `path` up to the statement containing `pos`, and returns the proof state there.
+async fn goals(path: FileRef, pos: Position) -> Result<Option<GoalState>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "goals: nonexistent file"))?;
+  if path.has_extension("mmb") || path.has_extension("mmu") { return Ok(None) }
+  // Make sure the cached elaboration is up to date, then only redo the statement we need
+  if get_env(path.clone()).await?.is_none() { return Ok(None) }
+  let (ast, env) = match &*file.parsed.lock().await {
+    Some(FileCache::Ready {ast: Some(ast), res: ElabResult::Ok(_, _, env), ..}) =>
+      (ast.clone(), env.clone()),
+    _ => return Ok(None),
+  };
+  let idx = if let Some(idx) = ast.source.to_idx(pos) {idx} else {return Ok(None)};
+  // Run the elaboration on the thread pool, like the elaboration of whole files
+  let (send, recv) = channel();
+  SERVER.pool.spawn_ok(async move {
+    let res = Arc::new(Mutex::new(None));
+    crate::elab::Elaborator::elab_stmt_at(&ast, path, &env, idx, GoalListener::at(idx, {
+      let res = res.clone();
+      move |elab: &crate::elab::Elaborator, _| *res.ulock() = Some(elab.goal_state())
+    }));
+    drop(send.send(res.ulock().take()));
+  });
+  Ok(recv.await.unwrap_or(None))
+}
+
+/// The semantic token types reported by the server, in the order of [`TokenType::LEGEND`].
+#[derive(Copy, Clone)]
+enum TokenType { Sort, Term, Notation, Thm, Var, BoundVar }
+
+impl TokenType {
+  const LEGEND: [SemanticTokenType; 6] = [
+    SemanticTokenType::TYPE,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::OPERATOR,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PARAMETER,
+  ];
+}
+
+/// The modifier bit for the name of a sort, term or theorem in its own declaration.
+const TOKEN_MOD_DECLARATION: u32 = 1;
+
+/// Classifies the object `k` at span `sp`, returning the token type and modifiers.
+fn semantic_token(env: &FrozenEnv, path: &FileRef, text: &LinedString,
+  spans: &Spans<ObjectKind>, sp: Span, k: &ObjectKind
+) -> Option<(TokenType, u32)> {
+  let key = RefKey::new(env, k)?;
+  let is_name = text[sp] == **key.name(env);
+  // Expressions and proofs are only tokens when the span is just the head symbol
+  if matches!(k, ObjectKind::Expr(_) | ObjectKind::Proof(_)) && !is_name { return None }
+  let decl = |fsp: &FileSpan| if fsp.file == *path && fsp.span == sp {
+    TOKEN_MOD_DECLARATION
+  } else {0};
+  Some(match key {
+    RefKey::Sort(s) => (TokenType::Sort, decl(&env.sort(s).span)),
+    RefKey::Term(t) if is_name => (TokenType::Term, decl(&env.term(t).span)),
+    RefKey::Term(_) => (TokenType::Notation, 0),
+    RefKey::Thm(t) => (TokenType::Thm, decl(&env.thm(t).span)),
+    RefKey::Var(a) => match spans.lc.as_ref().and_then(|lc| lc.vars.get(&a)) {
+      Some((_, InferSort::Bound(_))) => (TokenType::BoundVar, 0),
+      _ => (TokenType::Var, 0),
+    },
+    RefKey::Global(_) => return None,
+  })
+}
+
+async fn semantic_tokens(path: FileRef, range: Option<Range>
+) -> Result<Option<SemanticTokens>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "semanticTokens: nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let env = if let Some((_, env)) = env.into_response_error()? {env} else {return Ok(None)};
+  let (start, end) = match range {
+    Some(r) => (text.to_idx(r.start).unwrap_or(0), text.to_idx(r.end).unwrap_or_else(|| text.len())),
+    None => (0, text.len()),
+  };
+  let mut toks = vec![];
+  for spans in env.spans() {
+    let stmt = spans.stmt();
+    if stmt.end < start || end < stmt.start { continue }
+    for &(sp, ref k) in spans {
+      if sp.end <= start || end <= sp.start { continue }
+      if let Some(tk) = semantic_token(&env, &path, &text, spans, sp, k) { toks.push((sp, tk)) }
+    }
+  }
+  Ok(Some(SemanticTokens {result_id: None, data: encode_tokens(&text, toks)}))
+}
+
+/// Sorts the tokens and encodes them relative to the previous token, as the protocol requires.
+/// Tokens overlapping the previous token or spanning multiple lines are dropped.
+fn encode_tokens(text: &LinedString, mut toks: Vec<(Span, (TokenType, u32))>) -> Vec<SemanticToken> {
+  toks.sort_by_key(|&(sp, _)| sp.start);
+  let (mut data, mut last, mut last_end) = (vec![], Position::default(), 0);
+  for (sp, (ty, mods)) in toks {
+    let Range {start: p, end: q} = text.to_range(sp);
+    if sp.start < last_end || p.line != q.line { continue }
+    data.push(SemanticToken {
+      delta_line: p.line - last.line,
+      delta_start: if p.line == last.line {p.character - last.character} else {p.character},
+      length: q.character - p.character,
+      token_type: ty as u32,
+      token_modifiers_bitset: mods,
+    });
+    last = p;
+    last_end = sp.end;
+  }
+  data
+}
+
+/// The parameters of a `textDocument/inlayHint` request. Inlay hints were added in LSP 3.17,
+/// which is newer than the `lsp_types` version we use (0.90), so this, the types below
+/// and the `inlayHintProvider` capability are declared by hand. They can be replaced by
+/// the `lsp_types` definitions when it is upgraded.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InlayHintParams {
+  text_document: TextDocumentIdentifier,
+  range: Range,
+}
+
+#[derive(Serialize_repr, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum InlayHintKind { Type = 1, Parameter = 2 }
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct InlayHint {
+  position: Position,
+  label: String,
+  kind: InlayHintKind,
+  padding_left: bool,
+}
+
+/// Prints an expression on one line as an s-expression, for use in an inlay hint.
+/// Unassigned metavariables are printed as `_`.
+fn hint_expr(fe: FormatEnv<'_>, e: &FrozenLispVal) -> String {
+  fn go(fe: FormatEnv<'_>, e: &FrozenLispVal, out: &mut String) {
+    use std::fmt::Write;
+    let mut u = e.uncons();
+    if let Some(head) = u.next() {
+      out.push('(');
+      go(fe, head, out);
+      for e in u { out.push(' '); go(fe, e, out) }
+      out.push(')')
+    } else if let Some(a) = e.as_atom() {
+      write!(out, "{}", fe.to(&a)).expect("impossible")
     } else {
-      for spans2 in env.spans() {
-        spans2.into_iter().for_each(&mut cont);
+      out.push('_')
+    }
+  }
+  let mut out = String::new();
+  go(fe, e, &mut out);
+  out
+}
+
+/// Shows the binders inferred by `refine` after each theorem application, and the inferred
+/// statements of `have` steps after the subproof name.
+async fn inlay_hint(path: FileRef, range: Range) -> Result<Option<Vec<InlayHint>>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "inlayHint: nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let env = elaborate(path, Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let env = if let Some((_, env)) = env.into_response_error()? {env} else {return Ok(None)};
+  let start = text.to_idx(range.start).unwrap_or(0);
+  let end = text.to_idx(range.end).unwrap_or_else(|| text.len());
+  Ok(Some(file_hints(&env, &text, start, end)))
+}
+
+/// The inlay hints of the file `text` elaborated to `env`, which end between `start` and `end`.
+fn file_hints(env: &FrozenEnv, text: &LinedString, start: usize, end: usize) -> Vec<InlayHint> {
+  let fe = unsafe { env.format_env(text) };
+  let mut res = vec![];
+  for spans in env.spans() {
+    let stmt = spans.stmt();
+    if stmt.end < start || end < stmt.start { continue }
+    for &(sp, ref k) in spans.hints() {
+      if sp.end < start || end < sp.end { continue }
+      let position = text.to_pos(sp.end);
+      match k {
+        ObjectKind::InferredArgs(args) => for (x, e) in &**args {
+          res.push(InlayHint {
+            position,
+            label: format!("{} := {}", fe.to(x), hint_expr(fe, e)),
+            kind: InlayHintKind::Parameter,
+            padding_left: true,
+          })
+        },
+        ObjectKind::HaveType(e) => res.push(InlayHint {
+          position,
+          label: format!(": {}", hint_expr(fe, e)),
+          kind: InlayHintKind::Type,
+          padding_left: false,
+        }),
+        _ => {}
       }
     }
   }
-  Ok(res)
+  res
+}
+
+/// Formats the whole document, returning a single edit replacing it if anything changed.
+/// Files with parse errors are left alone.
+fn formatting(path: &FileRef) -> Result<Option<Vec<TextEdit>>, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "formatting nonexistent file"))?;
+  if path.has_extension("mmb") || path.has_extension("mmu") { return Ok(None) }
+  let text = file.text.ulock().1.ascii().clone();
+  let ast = parse(text.clone(), None).1;
+  let new_text = if let Ok(s) = crate::fmt::format(&ast, crate::fmt::WIDTH) {s} else {return Ok(None)};
+  if new_text == text.as_str() { return Ok(Some(vec![])) }
+  let range = Range::new(Position::default(), text.to_pos(text.len()));
+  Ok(Some(vec![TextEdit { range, new_text }]))
+}
+
+/// Adds a folding range for every multi-line lisp expression in `e`.
+fn sexpr_folds(text: &LinedString, e: &SExpr, res: &mut Vec<FoldingRange>) {
+  let (start, end) = (text.to_pos(e.span.start).line, text.to_pos(e.span.end).line);
+  if start == end { return }
+  if res.last().map_or(true, |r| r.start_line != start) {
+    res.push(FoldingRange {
+      start_line: start, start_character: None,
+      end_line: end, end_character: None, kind: None,
+    })
+  }
+  match &e.k {
+    SExprKind::List(es) => for e in es { sexpr_folds(text, e, res) },
+    SExprKind::DottedList(es, r) => {
+      for e in es { sexpr_folds(text, e, res) }
+      sexpr_folds(text, r, res)
+    }
+    SExprKind::DocComment(_, e) => sexpr_folds(text, e, res),
+    _ => {}
+  }
+}
+
+/// Adds folding ranges for a statement, its doc comment, and the lisp expressions in it.
+fn stmt_folds(text: &LinedString, stmt: &Stmt, res: &mut Vec<FoldingRange>) {
+  let (start, end) = (text.to_pos(stmt.span.start).line, text.to_pos(stmt.span.end).line);
+  if start == end { return }
+  if res.last().map_or(true, |r| r.start_line != start) {
+    res.push(FoldingRange {
+      start_line: start, start_character: None,
+      end_line: end, end_character: None, kind: None,
+    })
+  }
+  match &stmt.k {
+    StmtKind::DocComment(_, s) => {
+      let end = text.to_pos(s.span.start).line;
+      if start + 1 < end {
+        res.push(FoldingRange {
+          start_line: start, start_character: None,
+          end_line: end - 1, end_character: None, kind: Some(FoldingRangeKind::Comment),
+        })
+      }
+      stmt_folds(text, s, res)
+    }
+    StmtKind::Annot(e, s) => { sexpr_folds(text, e, res); stmt_folds(text, s, res) }
+    StmtKind::Decl(d) => if let Some(e) = &d.val { sexpr_folds(text, e, res) },
+    StmtKind::Inout {hs: es, ..} | StmtKind::Do(es) => for e in es { sexpr_folds(text, e, res) },
+    _ => {}
+  }
+}
+
+/// Computes folding ranges for statements, `do` blocks and nested s-expressions.
+fn folding_range(path: &FileRef) -> Result<Option<Vec<FoldingRange>>, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "folding range nonexistent file"))?;
+  if path.has_extension("mmb") || path.has_extension("mmu") { return Ok(None) }
+  let text = file.text.ulock().1.ascii().clone();
+  let ast = parse(text.clone(), None).1;
+  let mut res = vec![];
+  for stmt in &ast.stmts { stmt_folds(&text, stmt, &mut res) }
+  Ok(Some(res))
+}
+
+/// Pushes `sp` to `out` if it contains `idx` (and is not already the last entry).
+fn push_span(sp: Span, idx: usize, out: &mut Vec<Span>) -> bool {
+  let contained = sp.start <= idx && idx <= sp.end;
+  if contained && out.last() != Some(&sp) { out.push(sp) }
+  contained
+}
+
+/// Pushes the spans of `e` and its subexpressions containing `idx`, outermost first.
+fn sexpr_spans(e: &SExpr, idx: usize, out: &mut Vec<Span>) {
+  if !push_span(e.span, idx, out) { return }
+  match &e.k {
+    SExprKind::List(es) => for e in es { sexpr_spans(e, idx, out) },
+    SExprKind::DottedList(es, r) => {
+      for e in es { sexpr_spans(e, idx, out) }
+      sexpr_spans(r, idx, out)
+    }
+    SExprKind::DocComment(_, e) => sexpr_spans(e, idx, out),
+    _ => {}
+  }
+}
+
+/// Pushes the spans of the binder groups, names and types in `bis` containing `idx`.
+fn binder_spans(bis: &[Binder], idx: usize, out: &mut Vec<Span>) {
+  for bi in bis {
+    if push_span(bi.span, idx, out) {
+      if let Some(x) = bi.local { push_span(x, idx, out); }
+      if let Some(ty) = &bi.ty { push_span(ty.span(), idx, out); }
+    }
+  }
+}
+
+/// Pushes the spans of `stmt` and its parts containing `idx`, outermost first.
+fn stmt_spans(stmt: &Stmt, idx: usize, out: &mut Vec<Span>) {
+  if !push_span(stmt.span, idx, out) { return }
+  match &stmt.k {
+    &StmtKind::Sort(id, _) => { push_span(id, idx, out); }
+    StmtKind::Decl(d) => {
+      push_span(d.id, idx, out);
+      binder_spans(&d.bis, idx, out);
+      if let Some(ty) = &d.ty { push_span(ty.span(), idx, out); }
+      if let Some(e) = &d.val { sexpr_spans(e, idx, out) }
+    }
+    StmtKind::SimpleNota(n) => { push_span(n.id, idx, out); push_span(n.c.fmla.0, idx, out); }
+    &StmtKind::Coercion {id, from, to} =>
+      for sp in [id, from, to] { push_span(sp, idx, out); },
+    StmtKind::Notation(n) => {
+      push_span(n.id, idx, out);
+      binder_spans(&n.bis, idx, out);
+      if let Some(ty) = &n.ty { push_span(ty.span(), idx, out); }
+      for lit in &n.lits {
+        match *lit {
+          AstLiteral::Const(c, _) => push_span(c.fmla.0, idx, out),
+          AstLiteral::Var(x) => push_span(x, idx, out),
+        };
+      }
+    }
+    StmtKind::Inout {hs: es, ..} | StmtKind::Do(es) => for e in es { sexpr_spans(e, idx, out) },
+    StmtKind::Annot(e, s) => { sexpr_spans(e, idx, out); stmt_spans(s, idx, out) }
+    StmtKind::DocComment(_, s) => stmt_spans(s, idx, out),
+    &StmtKind::Import(sp, _) => { push_span(sp, idx, out); }
+    StmtKind::Delimiter(_) => {}
+  }
+}
+
+/// Computes the chain of enclosing syntax elements at each position, innermost first.
+fn selection_range(path: &FileRef, positions: Vec<Position>
+) -> Result<Option<Vec<SelectionRange>>, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "selection range nonexistent file"))?;
+  if path.has_extension("mmb") || path.has_extension("mmu") { return Ok(None) }
+  let text = file.text.ulock().1.ascii().clone();
+  let ast = parse(text.clone(), None).1;
+  Ok(Some(positions.into_iter().map(|pos| {
+    let mut spans = vec![];
+    if let Some(idx) = text.to_idx(pos) {
+      for stmt in &ast.stmts { stmt_spans(stmt, idx, &mut spans) }
+    }
+    let mut res = SelectionRange { range: Range::new(pos, pos), parent: None };
+    for sp in spans {
+      let parent = if res.range.start == res.range.end { None } else { Some(Box::new(res)) };
+      res = SelectionRange { range: text.to_range(sp), parent }
+    }
+    res
+  }).collect()))
 }
 
 struct Server {
@@ -1365,6 +2591,7 @@ struct ServerOptions {
   syntax_docs: Option<bool>,
   log_errors: Option<bool>,
   report_upstream_errors: Option<bool>,
+  profile: Option<bool>,
 }
 
 impl std::default::Default for ServerOptions {
@@ -1376,6 +2603,7 @@ impl std::default::Default for ServerOptions {
       syntax_docs: None,
       log_errors: None,
       report_upstream_errors: None,
+      profile: None,
     }
   }
 }
@@ -1414,21 +2642,50 @@ fn send_config_request() -> Result<()> {
 impl Server {
   fn new() -> Result<Server> {
     let (conn, _iot) = Connection::stdio();
-    let params = from_value(conn.initialize(
-      to_value(ServerCapabilities {
-        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::Incremental)),
-        hover_provider: Some(true.into()),
-        completion_provider: Some(CompletionOptions {
-          resolve_provider: Some(true),
-          ..Default::default()
-        }),
-        definition_provider: Some(OneOf::Left(true)),
-        document_symbol_provider: Some(OneOf::Left(true)),
-        references_provider: Some(OneOf::Left(true)),
-        document_highlight_provider: Some(OneOf::Left(true)),
+    let mut caps = to_value(ServerCapabilities {
+      text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::Incremental)),
+      hover_provider: Some(true.into()),
+      completion_provider: Some(CompletionOptions {
+        resolve_provider: Some(true),
         ..Default::default()
-      })?
-    )?)?;
+      }),
+      definition_provider: Some(OneOf::Left(true)),
+      document_symbol_provider: Some(OneOf::Left(true)),
+      workspace_symbol_provider: Some(OneOf::Left(true)),
+      code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+        code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+        work_done_progress_options: Default::default(),
+        resolve_provider: None,
+      })),
+      signature_help_provider: Some(SignatureHelpOptions {
+        trigger_characters: Some(vec![" ".into()]),
+        ..Default::default()
+      }),
+      references_provider: Some(OneOf::Left(true)),
+      document_highlight_provider: Some(OneOf::Left(true)),
+      document_formatting_provider: Some(OneOf::Left(true)),
+      folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+      selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+      call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+      code_lens_provider: Some(CodeLensOptions {resolve_provider: Some(false)}),
+      rename_provider: Some(OneOf::Right(RenameOptions {
+        prepare_provider: Some(true),
+        work_done_progress_options: Default::default(),
+      })),
+      semantic_tokens_provider: Some(SemanticTokensOptions {
+        legend: SemanticTokensLegend {
+          token_types: TokenType::LEGEND.to_vec(),
+          token_modifiers: vec![SemanticTokenModifier::DECLARATION],
+        },
+        range: Some(true),
+        full: Some(SemanticTokensFullOptions::Bool(true)),
+        ..Default::default()
+      }.into()),
+      ..Default::default()
+    })?;
+    // `lsp_types` 0.90 does not support inlay hints (see `InlayHintParams`)
+    caps["inlayHintProvider"] = true.into();
+    let params = from_value(conn.initialize(caps)?)?;
     Ok(Server {
       caps: Mutex::new(ClientCapabilities::new(params)),
       conn,
@@ -1590,3 +2847,297 @@ pub fn main(args: &ArgMatches<'_>) {
   std::mem::take(&mut *server.reqs.ulock());
   std::mem::take(&mut *server.vfs.0.ulock());
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+  use std::fmt::Write;
+  use crate::{LinedString, Span};
+  use crate::compiler::elab_test_file;
+  use lsp_types::{CallHierarchyItem, FoldingRangeKind, SymbolKind};
+  use super::{InlayHintKind, MAX_WORKSPACE_SYMBOLS, TokenType, binder_deps_pos, call_ranges,
+    encode_tokens, enclosing_app, file_hints, file_symbols, find_references, fuzzy_match, item_thm,
+    name_in_use, proof_thms, rank_symbols, ret_deps_pos, stmt_folds, stmt_spans};
+
+  const TEXT: &str = "delimiter $ ( ) $;\nprovable sort wff;\nterm im: wff > wff > wff;\n\
+    infixr im: $->$ prec 25;\naxiom ax (a b: wff): $ a -> b -> a $;\n\
+    theorem t1 (a: wff): $ a -> a -> a $ = '(! ax a a);\n\
+    theorem t2 (a b: wff): $ a -> b -> a $ = 'ax;\ndo { (def foo 1) };\n";
+
+  /// The references to the object at the `n`th occurrence of `pat` in `TEXT`, as
+  /// `(line, text)` pairs.
+  fn refs(pat: &str, n: usize, include_self: bool) -> Vec<(usize, &'static str)> {
+    let env = elab_test_file("refs.mm1", TEXT);
+    let idx = TEXT.match_indices(pat).nth(n).unwrap().0;
+    let mut res = vec![];
+    find_references(&env, env.find(idx).unwrap(), idx, include_self, |sp| {
+      res.push((TEXT[..sp.start].matches('\n').count(), &TEXT[sp.start..sp.end]))
+    });
+    res
+  }
+
+  #[test]
+  fn references() {
+    // theorems are found in declarations and proofs of all statements
+    let ax = vec![(4, "ax"), (5, "ax"), (6, "ax")];
+    assert_eq!(refs("ax a a", 0, true), ax);
+    assert_eq!(refs("ax;", 0, false), ax[..2]);
+    // local variables only in their own statement
+    assert_eq!(refs("a b", 1, true), [(6, "a"), (6, "a"), (6, "a")]);
+    assert_eq!(refs("b -> a $ =", 0, true), [(6, "b"), (6, "b")]);
+    // terms are also found through their notations
+    assert_eq!(refs("im:", 1, true), [(2, "im"), (3, "im"),
+      (4, "a -> b -> a"), (4, "b -> a"), (5, "a -> a -> a"), (5, "a -> a"),
+      (6, "a -> b -> a"), (6, "b -> a")]);
+    assert_eq!(refs("wff;", 0, true).len(), 7);
+    assert_eq!(refs("foo", 0, true), [(7, "foo")]);
+  }
+
+  #[test]
+  fn rename_collisions() {
+    let env = elab_test_file("names.mm1", TEXT);
+    for name in ["wff", "im", "ax", "t1", "foo"] {
+      assert!(name_in_use(&env, name.as_bytes()), "{}", name);
+    }
+    // `a` is only a local variable, and `bar` is not used at all
+    assert!(!name_in_use(&env, b"a"));
+    assert!(!name_in_use(&env, b"bar"));
+  }
+
+  #[test]
+  fn semantic_token_deltas() {
+    let text: LinedString = String::from("ab cd\nef (gh\nij)\n  kl").into();
+    let sp = |start, end| Span {start, end};
+    let toks = vec![
+      (sp(19, 21), (TokenType::Var, 0)),
+      (sp(3, 5), (TokenType::Thm, 1)),
+      (sp(0, 2), (TokenType::Sort, 0)),
+      (sp(1, 4), (TokenType::Term, 0)),
+      (sp(6, 8), (TokenType::BoundVar, 0)),
+      (sp(9, 16), (TokenType::Notation, 0)),
+    ];
+    let data = encode_tokens(&text, toks).into_iter().map(|t| (t.delta_line, t.delta_start,
+      t.length, t.token_type, t.token_modifiers_bitset)).collect::<Vec<_>>();
+    // `(1, 4)` overlaps the first token, and `(9, 16)` spans two lines
+    assert_eq!(data, [(0, 0, 2, 0, 0), (0, 3, 2, 3, 1), (1, 0, 2, 5, 0), (2, 2, 2, 4, 0)]);
+  }
+
+  #[test]
+  fn fuzzy_scores() {
+    assert_eq!(fuzzy_match(b"", b"anything"), Some(0));
+    assert_eq!(fuzzy_match(b"ax", b"ax_mp"), Some(0));
+    // case insensitive, counting the skipped characters before and between the matches
+    assert_eq!(fuzzy_match(b"Mp", b"ax_mp"), Some(3));
+    assert_eq!(fuzzy_match(b"axmp", b"ax_mp"), Some(1));
+    assert_eq!(fuzzy_match(b"aim", b"a1i_imp"), Some(3));
+    // the matches must be in order
+    assert_eq!(fuzzy_match(b"pm", b"ax_mp"), None);
+    assert_eq!(fuzzy_match(b"axx", b"ax_mp"), None);
+  }
+
+  #[test]
+  fn workspace_symbols() {
+    let mut text = String::from("provable sort wff;\n");
+    for i in 0..MAX_WORKSPACE_SYMBOLS + 100 { writeln!(text, "term t{}: wff;", i).unwrap() }
+    text += "axiom t_ax: $ t0 $;\n";
+    let env = elab_test_file("symbols.mm1", &text);
+    let text = LinedString::from(text);
+    let path = env.sort(crate::SortId(0)).span.file.clone();
+    let symbols = |kind, query: &str| {
+      let mut res = vec![];
+      file_symbols(&path, &text, &env, kind, query.as_bytes(), &mut res);
+      res
+    };
+    // An empty query matches everything, but only the first results are returned
+    let all = symbols(None, "");
+    assert_eq!(all.len(), MAX_WORKSPACE_SYMBOLS + 102);
+    let ranked = rank_symbols(all);
+    assert_eq!(ranked.len(), MAX_WORKSPACE_SYMBOLS);
+    let names = ranked.iter().map(|si| &*si.name).collect::<Vec<_>>();
+    assert_eq!(names[..11], ["t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", "t8", "t9", "t10"]);
+    // better matches come first, and the kind prefix filters the results
+    let ranked = rank_symbols(symbols(None, "tax"));
+    assert_eq!(ranked.iter().map(|si| &*si.name).collect::<Vec<_>>(), ["t_ax"]);
+    let ranked = rank_symbols(symbols(Some(SymbolKind::Method), "t"));
+    assert_eq!(ranked.iter().map(|si| (&*si.name, si.kind)).collect::<Vec<_>>(),
+      [("t_ax", SymbolKind::Method)]);
+    let ranked = rank_symbols(symbols(Some(SymbolKind::Class), "f"));
+    assert_eq!(ranked[0].name, "wff");
+    assert_eq!(ranked[0].container_name.as_deref(), Some("provable sort wff;"));
+  }
+
+  #[test]
+  fn enclosing_apps() {
+    fn app(text: &str) -> Option<(&str, &[u8], u32)> {
+      enclosing_app(text.as_bytes())
+        .map(|(head, prefix, n)| (std::str::from_utf8(head).unwrap(), prefix, n))
+    }
+    assert_eq!(app("(ax_mp "), Some(("ax_mp", &b""[..], 0)));
+    assert_eq!(app("(ax_mp h1 "), Some(("ax_mp", &b""[..], 1)));
+    // the argument being typed is the active one
+    assert_eq!(app("(ax_mp h1 h2"), Some(("ax_mp", &b""[..], 1)));
+    assert_eq!(app("(ax_mp"), None);
+    assert_eq!(app("(! ax_mp $ a $ "), Some(("ax_mp", &b"!"[..], 1)));
+    assert_eq!(app("'(!! ax_mp x "), Some(("ax_mp", &b"!!"[..], 1)));
+    // closed and nested lists count as one argument
+    assert_eq!(app("(a (b c) [d e] "), Some(("a", &b""[..], 2)));
+    assert_eq!(app("(a (b c "), Some(("b", &b""[..], 1)));
+    assert_eq!(app("(a {b + c} "), Some(("a", &b""[..], 1)));
+    // `@` opens a list that is closed with the enclosing one
+    assert_eq!(app("(a @ b "), Some(("b", &b""[..], 0)));
+    assert_eq!(app("(a (b @ c d) "), Some(("a", &b""[..], 1)));
+    // parens in math strings, string literals and comments are skipped
+    assert_eq!(app("(a $ ( $ \"(\\\"\" -- (\n"), Some(("a", &b""[..], 2)));
+    // there is no application to show while the cursor is in a math string
+    assert_eq!(app("(a $ (b "), None);
+    assert_eq!(app("(a) "), None);
+    assert_eq!(app("{a + "), None);
+  }
+
+  const FOLD_TEXT: &str = "--| A doc\n--| comment\ntheorem foo (a: wff):\n  $ a $ =\n\
+    '(focus\n    (exact a));\nsort s;\ndo {\n  (def x [1\n    2])\n};\n";
+
+  #[test]
+  fn folds() {
+    let text = LinedString::from(String::from(FOLD_TEXT));
+    let ast = mm1_parser::parse(text.clone().into(), None).1;
+    let mut res = vec![];
+    for stmt in &ast.stmts { stmt_folds(&text, stmt, &mut res) }
+    let res = res.into_iter().map(|r| (r.start_line, r.end_line, r.kind)).collect::<Vec<_>>();
+    // One fold per start line, so the list `[1 2]` is covered by the fold of `(def x ...)`,
+    // and nothing for the one line `sort` statement
+    assert_eq!(res, [(0, 5, None), (0, 1, Some(FoldingRangeKind::Comment)), (2, 5, None),
+      (4, 5, None), (7, 10, None), (8, 9, None)]);
+  }
+
+  #[test]
+  fn selection_spans() {
+    let ast = mm1_parser::parse(std::sync::Arc::new(String::from(FOLD_TEXT).into()), None).1;
+    let spans = |pat: &str| {
+      let idx = FOLD_TEXT.find(pat).unwrap();
+      let mut out = vec![];
+      for stmt in &ast.stmts { stmt_spans(stmt, idx, &mut out) }
+      out.into_iter().map(|sp| &FOLD_TEXT[sp.start..sp.end]).collect::<Vec<_>>()
+    };
+    let doc = &FOLD_TEXT[..=FOLD_TEXT.find(";\n").unwrap()];
+    let thm = &doc[doc.find("theorem").unwrap()..];
+    assert_eq!(spans("wff"), [doc, thm, "(a: wff)", "wff"]);
+    assert_eq!(spans("exact"),
+      [doc, thm, "'(focus\n    (exact a))", "(focus\n    (exact a))", "(exact a)", "exact"]);
+    assert_eq!(spans("2]"), ["do {\n  (def x [1\n    2])\n};", "(def x [1\n    2])",
+      "[1\n    2]", "2"]);
+    assert_eq!(spans("s;"), ["sort s;", "s"]);
+    assert_eq!(spans("-| A"), [doc]);
+  }
+
+  const CALL_TEXT: &str = "delimiter $ ( ) $;\nprovable sort wff;\nterm im: wff > wff > wff;\n\
+    infixr im: $->$ prec 25;\naxiom ax (a b: wff): $ a -> b -> a $;\n\
+    axiom mp (a b: wff): $ a $ > $ a -> b $ > $ b $;\n\
+    theorem t1 (a: wff): $ a -> a -> a $ = '(! ax a a);\n\
+    theorem t2 (a: wff): $ (a -> a -> a) -> a -> a -> a $ = '(mp t1 ax);\n\
+    do { (def (mk x) (list 'mp x 'ax)) };\n\
+    theorem t3 (a: wff): $ (a -> a -> a) -> a -> a -> a $ = (focus (refine (mk 't1)));\n";
+
+  #[test]
+  fn calls() {
+    let env = elab_test_file("calls.mm1", CALL_TEXT);
+    let text = LinedString::from(String::from(CALL_TEXT));
+    let thm = |name: &str| match env.data()[env.get_atom(name.as_bytes()).unwrap()].decl() {
+      Some(crate::DeclKey::Thm(t)) => t,
+      _ => unreachable!(),
+    };
+    let uses = |caller| match &env.thm(thm(caller)).kind {
+      crate::ThmKind::Thm(Some(proof)) => proof_thms(proof),
+      _ => unreachable!(),
+    };
+    assert_eq!(uses("t1"), [thm("ax")]);
+    assert_eq!(uses("t2"), [thm("mp"), thm("t1"), thm("ax")]);
+    let ranges = |caller, callee| call_ranges(&env, &text, env.thm(thm(caller)), thm(callee))
+      .into_iter().map(|r| &CALL_TEXT[text.to_idx(r.start).unwrap()..text.to_idx(r.end).unwrap()])
+      .collect::<Vec<_>>();
+    assert_eq!(ranges("t2", "ax"), ["ax"]);
+    assert_eq!(ranges("t3", "t1"), ["t1"]);
+    // `ax` is only used by the proof produced by `mk`, so the innermost step using it is reported
+    assert_eq!(uses("t3"), [thm("mp"), thm("t1"), thm("ax")]);
+    assert_eq!(ranges("t3", "ax"), ["(focus (refine (mk 't1)))"]);
+    assert_eq!(ranges("t1", "t2"), [] as [&str; 0]);
+  }
+
+  #[test]
+  fn call_items() {
+    let env = elab_test_file("items.mm1", CALL_TEXT);
+    let (t, td) = env.thms().enum_iter().nth(2).unwrap();
+    let item = |name: &str, data| CallHierarchyItem {
+      name: name.into(), kind: SymbolKind::Method, tags: None, detail: None,
+      uri: td.span.file.url().clone(), range: Default::default(),
+      selection_range: Default::default(), data: Some(data),
+    };
+    let data = serde_json::to_value((td.span.span.start, td.span.span.end)).unwrap();
+    // The theorem is found by its span, whatever the name in the item says
+    assert_eq!(item_thm(&env, &item("t1", data.clone()), &td.span.file), Some(t));
+    assert_eq!(item_thm(&env, &item("t2", data.clone()), &td.span.file), Some(t));
+    let other = crate::compiler::test_file("other.mm1", b"");
+    assert_eq!(item_thm(&env, &item("t1", data), &other), None);
+    assert_eq!(item_thm(&env, &item("t1", serde_json::json!([0, 1])), &td.span.file), None);
+  }
+
+  #[test]
+  fn dependency_fixes() {
+    use crate::elab::ElabErrorKind;
+    let errors = |text: &str| {
+      let path = crate::compiler::test_file("deps.mm1", text.as_bytes());
+      crate::compiler::elab_keep(Some(path), None).unwrap().2
+    };
+    let text = "delimiter $ ( ) $;\nprovable sort wff; sort set;\n\
+      term al {x: set} (a: wff x): wff;\n\
+      --| A definition\ndef d {x: set} (a: wff x): wff = $ a $;\n\
+      axiom ax {x: set} (a: wff): $ al x a $;\n\
+      theorem t {x: set} (a: wff x): $ al x a $ = 'ax;\n";
+    let ast = mm1_parser::parse(std::sync::Arc::new(String::from(text).into()), None).1;
+    let mut edits = vec![];
+    for e in errors(text) {
+      match &e.kind {
+        ElabErrorKind::MissingDeps(_, vars) =>
+          edits.push((ret_deps_pos(&ast, e.pos.start).unwrap(), vars[0].to_string())),
+        ElabErrorKind::DisjointVars(_, fsp, missing) => for (x, a) in &**missing {
+          edits.push((binder_deps_pos(&ast, fsp.span, a).unwrap(), x.to_string()))
+        },
+        _ => panic!("unexpected error {}", e.kind.msg()),
+      }
+    }
+    edits.sort();
+    let mut fixed = String::from(text);
+    for (pos, x) in edits.into_iter().rev() { fixed.insert_str(pos, &format!(" {}", x)) }
+    assert!(fixed.contains("): wff x = $ a $;") && fixed.contains("axiom ax {x: set} (a: wff x):"));
+    assert!(errors(&fixed).is_empty());
+
+    // the type can't be changed if it is shared with another binder
+    let ast = mm1_parser::parse(std::sync::Arc::new(
+      String::from("axiom ax {x: set} (a b: wff) (c: wff): $ a $;").into()), None).1;
+    let id = Span {start: 6, end: 8};
+    assert_eq!(binder_deps_pos(&ast, id, b"a"), None);
+    assert_eq!(binder_deps_pos(&ast, id, b"c"), Some(36));
+    assert_eq!(binder_deps_pos(&ast, id, b"x"), None);
+  }
+
+  #[test]
+  fn inlay_hints() {
+    let text = "delimiter $ ( ) $;\nprovable sort wff;\nterm im: wff > wff > wff;\n\
+      infixr im: $->$ prec 25;\naxiom ax (a b: wff): $ a -> b -> a $;\n\
+      theorem t1 (a: wff): $ a -> a -> a $ = 'ax;\n\
+      theorem t2 (a: wff): $ a -> a -> a $ = (focus (have 'h '(! ax a a)) (refine 'h));\n";
+    let env = elab_test_file("hints.mm1", text);
+    let end_of = |pat: &str| text.find(pat).unwrap() + pat.len();
+    let text = LinedString::from(String::from(text));
+    let hints = |start, end| file_hints(&env, &text, start, end).into_iter()
+      .map(|h| (text.to_idx(h.position).unwrap(), h.label, h.kind)).collect::<Vec<_>>();
+    // the binders inferred for `ax`, and the statement of `h`
+    let all = hints(0, text.len());
+    assert_eq!(all, [
+      (end_of("= 'ax"), "a := a".into(), InlayHintKind::Parameter),
+      (end_of("= 'ax"), "b := a".into(), InlayHintKind::Parameter),
+      (end_of("(have 'h"), ": (im a (im a a))".into(), InlayHintKind::Type),
+    ]);
+    assert_eq!(hints(end_of("= 'ax") + 1, text.len()), all[2..]);
+  }
+}
@@ -117,7 +117,7 @@ syn match mm0_lisp_if contained
 syn match mm0_lisp_keyword contained
   \ "\%(def\|fn\|let\|letrec\|begin\|focus\|set-merge-strategy\)[a-zA-Z0-9_!%&*/:<=>?\^~+\-.]\@!"
 syn match mm0_lisp_predef contained
-  \ "\%(+\|\*\|-\|<=\?\|>=\?\|==\?\|->string\|string-\%(>atom\|append\)\|display\|error\|print\|apply\|min\|max\|not\|or\|list\|cons\|hd\|tl\|lookup\|insert\|async\|set-timeout\|goal\|goal-type\|infer-type\|pp\|[gs]et-goals\|to-expr\|refine\|stat\|have\|get-decl\|\%(pair\|null\|int\|bool\|atom\|string\|fn\|number\|ref\|atom-map\|def\|goal\|mvar\)?\|\%(ref\|get\|set\|atom-map\|insert\|mvar\|add-\%(decl\|term\|thm\)\)!\|refine-extra-args\)[a-zA-Z0-9_!%&*/:<=>?\^~+\-.]\@!"
+  \ "\%(+\|\*\|-\|<=\?\|>=\?\|==\?\|->string\|string-\%(>atom\|append\|split\|index-of\|replace\|upcase\|downcase\)\|number->string\|format\|display\|error\|print\|apply\|for-each\|min\|max\|not\|or\|list\|cons\|hd\|tl\|lookup\|insert\|vec-\%(len\|get\|>list\)\|async\|set-timeout\|goal\|goal-type\|infer-type\|pp\|[gs]et-goals\|to-expr\|refine\|stat\|have\|get-decl\|\%(pair\|null\|int\|bool\|atom\|string\|fn\|number\|ref\|atom-map\|hash-map\|vec\|def\|goal\|mvar\)?\|\%(ref\|get\|set\|atom-map\|hash-map\|vec\|vec-set\|vec-push\|insert\|mvar\|add-\%(decl\|term\|thm\)\)!\|refine-extra-args\)[a-zA-Z0-9_!%&*/:<=>?\^~+\-.]\@!"
 syn cluster mm0_lisp add=mm0_lisp_if,mm0_lisp_keyword,mm0_lisp_predef
 syn match mm0_lisp_quote "'\s\@!" contained
 syn match mm0_lisp_antiquote ",\s\@!" contained
@@ -1,4 +1,5 @@
-import { commands, window, workspace, ExtensionContext, TextDocument, EndOfLine } from 'vscode';
+import { commands, debug, window, workspace, ExtensionContext, TextDocument, EndOfLine,
+	DebugAdapterExecutable } from 'vscode';
 
 import {
 	LanguageClient,
@@ -56,7 +57,13 @@ export function activate(context: ExtensionContext) {
 		commands.registerCommand('metamath-zero.shutdownServer',
 		  () => client.stop().then(() => {}, () => {})),
 		commands.registerCommand('metamath-zero.restartServer',
-			() => client.stop().then(startClient, startClient))
+			() => client.stop().then(startClient, startClient)),
+		// The debugger runs 'mm0-rs debug-adapter' using the same executable as the server
+		debug.registerDebugAdapterDescriptorFactory('metamath-zero', {
+			createDebugAdapterDescriptor: () => new DebugAdapterExecutable(
+				workspace.getConfiguration('metamath-zero').get<string>('executablePath') || 'mm0-rs',
+				['debug-adapter'])
+		})
 	);
 }
 